@@ -0,0 +1,91 @@
+# Computes the swap-in-potentiam address and control block test
+# vectors of swap-in-potentiam.md, independently of the Rust
+# implementation: plain-Python secp256k1 arithmetic, KeyAgg of
+# BIP-327, the tapleaf/TapBranch/TapTweak hashes of BIP-341 and the
+# bech32m encoding of BIP-350.
+#
+# Usage: python3 doc/test_vectors.py <alice> <bob> [<alice> <bob> ...]
+# where <alice> and <bob> are 33-byte compressed public keys in hex.
+# Prints, per key pair:
+#   Q        the internal public key, KeyAgg([P[0], P[1]])
+#   coop/tl  the 2-of-2 and timelock tapleaf scripts
+#   hc/ht    their tapleaf hashes
+#   r, t, S  the Merkle root, the TapTweak and the output key
+#   cb_coop/cb_tl  the control blocks of the two tapleaf paths
+#   spk, bc/tb/bcrt  the scriptPubKey and the addresses
+import hashlib
+p = 2**256 - 2**32 - 977
+n = 0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141
+G = (0x79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798,
+     0x483ADA7726A3C4655DA4FBFC0E1108A8FD17B448A68554199C47D08FFB10D4B8)
+def add(P1, P2):
+    if P1 is None: return P2
+    if P2 is None: return P1
+    if P1[0] == P2[0] and P1[1] != P2[1]: return None
+    if P1 == P2: lam = 3*P1[0]*P1[0]*pow(2*P1[1], p-2, p) % p
+    else: lam = (P2[1]-P1[1])*pow(P2[0]-P1[0], p-2, p) % p
+    x = (lam*lam - P1[0] - P2[0]) % p
+    return (x, (lam*(P1[0]-x) - P1[1]) % p)
+def mul(P, k):
+    R = None
+    for i in range(256):
+        if (k >> i) & 1: R = add(R, P)
+        P = add(P, P)
+    return R
+def lift(b):
+    x = int.from_bytes(b[1:], 'big'); y2 = (pow(x,3,p)+7) % p
+    y = pow(y2, (p+1)//4, p); assert y*y % p == y2
+    if (y & 1) != (b[0] & 1): y = p - y
+    return (x, y)
+def ser(P): return bytes([2 + (P[1] & 1)]) + P[0].to_bytes(32, 'big')
+def xb(P): return P[0].to_bytes(32, 'big')
+def th(tag, m):
+    t = hashlib.sha256(tag.encode()).digest(); return hashlib.sha256(t+t+m).digest()
+def keyagg(pks):
+    L = th('KeyAgg list', b''.join(pks)); pk2 = next((k for k in pks if k != pks[0]), None)
+    Q = None
+    for k in pks:
+        a = 1 if k == pk2 else int.from_bytes(th('KeyAgg coefficient', L+k), 'big') % n
+        Q = add(Q, mul(lift(k), a))
+    return Q
+CH = 'qpzry9x8gf2tvdw0s3jn54khce6mua7l'
+def polymod(v):
+    g = [0x3b6a57b2,0x26508e6d,0x1ea119fa,0x3d4233dd,0x2a1462b3]; c = 1
+    for x in v:
+        b = c >> 25; c = (c & 0x1ffffff) << 5 ^ x
+        for i in range(5): c ^= g[i] if (b >> i) & 1 else 0
+    return c
+def bech32m(hrp, ver, prog):
+    acc = bits = 0; d = [ver]
+    for b in prog:
+        acc = acc << 8 | b; bits += 8
+        while bits >= 5: bits -= 5; d.append(acc >> bits & 31)
+    if bits: d.append(acc << (5-bits) & 31)
+    e = [ord(c) >> 5 for c in hrp] + [0] + [ord(c) & 31 for c in hrp]
+    pm = polymod(e + d + [0]*6) ^ 0x2bc830a3
+    return hrp + '1' + ''.join(CH[c] for c in d + [pm >> 5*(5-i) & 31 for i in range(6)])
+def sip(a_hex, b_hex):
+    A = bytes.fromhex(a_hex); B = bytes.fromhex(b_hex)
+    P0, P1 = sorted([A[1:], B[1:]])
+    Q = keyagg([b'\x02'+P0, b'\x02'+P1])
+    coop = b'\x20'+P0+b'\xad\x20'+P1+b'\xac'
+    tl = bytes.fromhex('03c00f00b275') + b'\x20' + A[1:] + b'\xac'
+    hc = th('TapLeaf', b'\xc0'+bytes([len(coop)])+coop)
+    ht = th('TapLeaf', b'\xc0'+bytes([len(tl)])+tl)
+    r = th('TapBranch', min(hc,ht)+max(hc,ht))
+    # ApplyTweak with is_xonly_t: negate Q if its Y is odd.
+    Qe = Q if Q[1] % 2 == 0 else (Q[0], p-Q[1])
+    t = th('TapTweak', xb(Q)+r)
+    S = add(Qe, mul(G, int.from_bytes(t,'big')))
+    par = S[1] & 1
+    return dict(Q=ser(Q).hex(), coop=coop.hex(), tl=tl.hex(), hc=hc.hex(), ht=ht.hex(),
+                r=r.hex(), t=t.hex(), S=ser(S).hex(),
+                cb_coop=(bytes([0xc0|par])+xb(Q)+ht).hex(),
+                cb_tl=(bytes([0xc0|par])+xb(Q)+hc).hex(),
+                spk=('5120'+xb(S).hex()),
+                bc=bech32m('bc',1,xb(S)), tb=bech32m('tb',1,xb(S)), bcrt=bech32m('bcrt',1,xb(S)))
+if __name__ == '__main__':
+    import sys
+    for a, b in zip(sys.argv[1::2], sys.argv[2::2]):
+        for k, v in sip(a, b).items(): print(k, '=', v)
+        print()
@@ -1,8 +1,10 @@
 /*!
 The `address` module contains simple interfaces to *only*
 derive a swap-in-potentiam address from the user public key
-`alice` and some fixed LSP node ID `bob`.
+`alice` and some fixed LSP node ID `bob`, and the anchor
+output address of `bob`.
 */
+use std::collections::HashMap;
 use secp256k1::PublicKey;
 use secp256k1::Secp256k1;
 use secp256k1::Verification;
@@ -11,21 +13,48 @@ use super::bip327;
 use super::bip340;
 use super::bip341;
 use super::bip350;
+use super::psbt;
 use super::scripts;
 
-fn get_root_hash( alice: &PublicKey
-		, bob: &PublicKey
-		) -> [u8; 32]
+/* The 2-of-2 tapleaf is leaf 0, the timelock tapleaf
+is leaf 1.
+*/
+const COOPERATIVE_LEAF: usize = 0;
+const ALICE_RECOVERY_LEAF: usize = 1;
 
+fn get_taptree( alice: &PublicKey
+	      , bob: &PublicKey
+	      ) -> bip341::TapTree
 {
 	let coop_tapleaf_path = scripts::tapleaf_cooperative(alice, bob);
 	let recov_tapleaf_path = scripts::tapleaf_alice_recovery(alice);
 
-	let taptree = bip341::TapTree::new_two_leaves(
+	bip341::TapTree::new_two_leaves(
 		bip341::TAPROOT_TAPLEAF_VERSION, coop_tapleaf_path,
 		bip341::TAPROOT_TAPLEAF_VERSION, recov_tapleaf_path
-	);
-	taptree.to_hash()
+	)
+}
+
+fn get_root_hash( alice: &PublicKey
+		, bob: &PublicKey
+		) -> [u8; 32]
+
+{
+	get_taptree(alice, bob).into_hash()
+}
+
+/* The public keys given to KeyAgg are P[0] and P[1],
+in that order, converted to even Y coordinates.
+*/
+fn get_keyagg_pubkeys( alice: &PublicKey
+		     , bob: &PublicKey
+		     ) -> Vec<PublicKey> {
+	let scripts::P0P1{p0, p1} = scripts::P0P1::new(*alice, *bob);
+	let even_y = |p: &PublicKey| {
+		bip340::lift_x(&bip340::xbytes(p))
+		.expect("x coordinate of a valid point")
+	};
+	vec!(even_y(&p0), even_y(&p1))
 }
 
 fn get_aggkey_and_tweak<C>( secp256k1: &Secp256k1<C>
@@ -36,7 +65,7 @@ fn get_aggkey_and_tweak<C>( secp256k1: &Secp256k1<C>
 {
 	let root_hash = get_root_hash(alice, bob);
 
-	let pks = vec!(alice.clone(), bob.clone());
+	let pks = get_keyagg_pubkeys(alice, bob);
 	let aggkey = bip327::key_agg(secp256k1, &pks);
 
 	let xonly_aggkey = aggkey.get_xonly_pubkey();
@@ -127,7 +156,94 @@ fn derive_taproot_scriptpubkey<C>( secp256k1: &Secp256k1<C>
 	let mut buf = Vec::new();
 	buf.extend_from_slice(&[0x51, 0x20]);
 	buf.extend_from_slice(&xonly_pubkey);
-	return Some(buf);
+	Some(buf)
+}
+
+/**
+`ScriptPubKeyMatcher` recognizes the `scriptPubKey`s of
+swap-in-potentiam outputs for a fixed `bob` and a set of
+candidate `alice` public keys.
+
+This derives the Taproot x-only public key for each
+`alice` once, when it is added, and indexes them, so
+that each `scriptPubKey` checked afterwards is a single
+lookup.
+This is useful when rescanning the blockchain for
+the outputs of a wallet.
+*/
+pub
+struct ScriptPubKeyMatcher {
+	bob: PublicKey,
+	index: HashMap<[u8; 32], PublicKey>
+}
+
+impl ScriptPubKeyMatcher {
+	/// `new` creates a matcher for the given `bob` (LSP
+	/// node ID) with no `alice` keys yet.
+	pub
+	fn new(bob: &PublicKey) -> Self {
+		ScriptPubKeyMatcher{
+			bob: *bob,
+			index: HashMap::new()
+		}
+	}
+
+	/// `add_alice` adds a candidate `alice` public key.
+	///
+	/// Returns false (and does not add the key) on the
+	/// negligible-probability edge case that the Taproot
+	/// address is the point at infinity.
+	pub
+	fn add_alice<C>( &mut self
+		       , secp256k1: &Secp256k1<C>
+		       , alice: &PublicKey
+		       ) -> bool
+		where C: Verification
+	{
+		match derive_taproot_xonly_pubkey(secp256k1, alice, &self.bob) {
+			None => false,
+			Some(xonly_pubkey) => {
+				self.index.insert(xonly_pubkey, *alice);
+				true
+			}
+		}
+	}
+
+	/// `match_scriptpubkey` returns the `alice` public key
+	/// whose swap-in-potentiam output has the given
+	/// `scriptPubKey`, or None if it is not the
+	/// `scriptPubKey` of a swap-in-potentiam output of any
+	/// added `alice` and this matcher's `bob`.
+	pub
+	fn match_scriptpubkey(&self, script_pubkey: &[u8]) -> Option<&PublicKey> {
+		if script_pubkey.len() != 34
+		|| script_pubkey[0] != 0x51 // SegWit v1
+		|| script_pubkey[1] != 0x20 // Push 32 bytes
+		{
+			return None;
+		}
+		let xonly_pubkey: [u8; 32] = script_pubkey[2..34].try_into()
+		.expect("constant array indices");
+		self.index.get(&xonly_pubkey)
+	}
+
+	/// The `bob` this matcher was created with.
+	pub
+	fn bob(&self) -> &PublicKey {
+		&self.bob
+	}
+
+	/// The number of `alice` public keys added.
+	pub
+	fn len(&self) -> usize {
+		self.index.len()
+	}
+
+	/// Whether no `alice` public keys have been added.
+	pub
+	fn is_empty(&self) -> bool {
+		self.index.is_empty()
+	}
 }
 
 /**
@@ -139,9 +255,15 @@ Generally, `alice` is any public key whose private key
 is derivable by the client (using any derivation scheme),
 and `bob` is the Lightning Network node ID of the LSP.
 
-The operation may fail (return None) on the edge
-case that the Taproot address becomes the point
-at infinity.
+`network` may be `Network::Custom` to use an arbitrary
+human-readable part.
+
+The operation may fail (return None) if the
+human-readable part of a `Network::Custom` is not
+valid for BIP-173 (1 to 83 US-ASCII characters, with
+the resulting address at most 90 characters), or on
+the edge case that the Taproot address becomes the
+point at infinity.
 The probability of that happening should be
 negligibly low (to a cryptographer, i.e.
 universe heat death is more likely to come
@@ -158,12 +280,297 @@ fn derive_taproot_address<C>( secp256k1: &Secp256k1<C>
 	let program = derive_taproot_xonly_pubkey(
 		secp256k1, alice, bob
 	)?;
+	bip350::encode_segwit(
+		network,
+		1,
+		&program
+	)
+}
+
+/**
+`anchor_witness_script` returns the [BOLT 3][] anchor
+output witness script of the LSP node ID `bob`,
+`<bob> OP_CHECKSIG OP_IFDUP OP_NOTIF OP_16
+OP_CHECKSEQUENCEVERIFY OP_ENDIF`.
+
+Unlike in the swap-in-potentiam address, `bob` is used
+as the plain 33-byte public key here, which may have an
+odd Y coordinate; it must *not* be converted to its
+even-Y x-only form, or the anchor cannot be spent by
+`bob`.
+
+[BOLT 3]: https://github.com/lightning/bolts/blob/master/03-transactions.md#to_local_anchor-and-to_remote_anchor-output-option_anchors
+*/
+pub
+fn anchor_witness_script(bob: &PublicKey) -> Vec<u8> {
+	scripts::anchor(bob)
+}
+
+/**
+`derive_anchor_scriptpubkey` generates the SegWit v0
+P2WSH `scriptPubKey` of the anchor output of the LSP
+node ID `bob`, which every transaction funding a 0-conf
+Lightning operation from swap-in-potentiam outputs must
+have, with 330 satoshis.
+*/
+pub
+fn derive_anchor_scriptpubkey(bob: &PublicKey) -> Vec<u8> {
+	scripts::p2wsh(&scripts::anchor(bob))
+}
+
+/**
+`derive_anchor_address` generates the P2WSH address,
+returned as a `String`, of the anchor output of the LSP
+node ID `bob`.
+
+This fails (returns None) if the human-readable part of
+a `Network::Custom` is not valid.
+*/
+pub
+fn derive_anchor_address( network: Network
+			, bob: &PublicKey
+			) -> Option<String>
+{
+	let script_pubkey = derive_anchor_scriptpubkey(bob);
+	bip350::encode_segwit(
+		network,
+		0,
+		&script_pubkey[2..34]
+	)
+}
+
+/**
+`decode_segwit_address` parses a BIP-173 or BIP-350
+SegWit address, such as one pasted in by the user or
+a change address given by the LSP, returning the
+network, the SegWit version, and the witness program.
+
+The witness program does *not* include the version
+nor the push opcode, so for a pay-to-Taproot address
+it is the 32-byte x-only public key.
+
+This fails (returns None) if the address is not a
+valid SegWit address of a known network, including if
+it uses the wrong checksum variant for its version,
+mixes upper and lower case, or has a witness program
+of invalid length.
+*/
+pub
+fn decode_segwit_address(address: &str) -> Option<(Network, u8, Vec<u8>)> {
+	bip350::decode_segwit(address)
+}
+
+/**
+`decode_segwit_address_for_network` parses a BIP-173 or
+BIP-350 SegWit address like `decode_segwit_address`,
+but only accepts addresses for the given `network`,
+returning the SegWit version and the witness program.
+
+Use this for signet (which `decode_segwit_address`
+cannot distinguish from testnet) and for
+`Network::Custom` human-readable parts.
+*/
+pub
+fn decode_segwit_address_for_network( network: &Network
+				    , address: &str
+				    ) -> Option<(u8, Vec<u8>)> {
+	bip350::decode_segwit_for_network(network, address)
+}
+
+fn get_control_block<C>( secp256k1: &Secp256k1<C>
+		       , alice: &PublicKey
+		       , bob: &PublicKey
+		       , leaf: usize
+		       ) -> Option<[u8; 65]>
+	where C: Verification
+{
+	let (aggkey, _) = get_aggkey_and_tweak(secp256k1, alice, bob);
+	let internal_pubkey = aggkey.get_xonly_pubkey();
+	let control_block = bip341::taproot_control_block(
+		secp256k1,
+		&internal_pubkey,
+		get_taptree(alice, bob),
+		leaf
+	)?;
 	Some(
-		bip350::encode_segwit(
-			network,
-			1,
-			&program
-		).expect("can only fail if version is invalid, but version is hardcoded as 1")
+		control_block.try_into()
+		.expect("two-leaf tree always has a 65-byte control block")
+	)
+}
+
+/**
+`control_block_cooperative` generates the 65-byte control
+block for spending the swap-in-potentiam output of the
+given `alice` and `bob` public keys via the 2-of-2
+tapleaf path, i.e. with the script from
+`tapleaf_cooperative`.
+
+The control block is the byte `0xC0` or `0xC1`
+depending on the sign of the Y coordinate of the
+output public key, followed by the X coordinate of the
+internal public key `Q`, followed by the tapleaf hash
+of the timelock tapleaf.
+
+The operation may fail (return None) on the edge
+case that the Taproot address becomes the point
+at infinity.
+The probability of that happening should be
+negligibly low (to a cryptographer, i.e.
+universe heat death is more likely to come
+before you get that case).
+*/
+pub
+fn control_block_cooperative<C>( secp256k1: &Secp256k1<C>
+			       , alice: &PublicKey
+			       , bob: &PublicKey
+			       ) -> Option<[u8; 65]>
+	where C: Verification
+{
+	get_control_block(secp256k1, alice, bob, COOPERATIVE_LEAF)
+}
+
+/**
+`control_block_alice_recovery` generates the 65-byte
+control block for spending the swap-in-potentiam output
+of the given `alice` and `bob` public keys via the
+timelock tapleaf path, i.e. with the script from
+`tapleaf_alice_recovery`.
+
+The control block is the byte `0xC0` or `0xC1`
+depending on the sign of the Y coordinate of the
+output public key, followed by the X coordinate of the
+internal public key `Q`, followed by the tapleaf hash
+of the 2-of-2 tapleaf.
+
+The operation may fail (return None) on the edge
+case that the Taproot address becomes the point
+at infinity.
+The probability of that happening should be
+negligibly low (to a cryptographer, i.e.
+universe heat death is more likely to come
+before you get that case).
+*/
+pub
+fn control_block_alice_recovery<C>( secp256k1: &Secp256k1<C>
+				  , alice: &PublicKey
+				  , bob: &PublicKey
+				  ) -> Option<[u8; 65]>
+	where C: Verification
+{
+	get_control_block(secp256k1, alice, bob, ALICE_RECOVERY_LEAF)
+}
+
+/**
+`fill_psbt_input` fills in the [BIP-371][] fields of the
+input at `input_index` of `psbt`, which must spend the
+swap-in-potentiam output of the given `alice` and `bob`
+public keys:
+
+* `PSBT_IN_TAP_INTERNAL_KEY`, the MuSig2 aggregate of
+  `P[0]` and `P[1]`.
+* `PSBT_IN_TAP_MERKLE_ROOT`, the root hash of the two
+  tapleaves.
+* `PSBT_IN_TAP_LEAF_SCRIPT`, for each of the 2-of-2
+  and timelock tapleaves, with their control blocks.
+
+`PSBT_IN_TAP_BIP32_DERIVATION` is not filled in, as the
+origins of the keys are only known to their owners.
+
+Fails (returns None) if the input does not exist, if it
+has a `PSBT_IN_WITNESS_UTXO` whose `scriptPubKey` is not
+that of the swap-in-potentiam address, or on the
+negligible-probability edge case where the Taproot
+address is the point at infinity.
+The PSBT is unchanged on failure.
+
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+pub
+fn fill_psbt_input<C>( secp256k1: &Secp256k1<C>
+		     , psbt: &mut psbt::Psbt
+		     , input_index: usize
+		     , alice: &PublicKey
+		     , bob: &PublicKey
+		     ) -> Option<()>
+	where C: Verification
+{
+	let (aggkey, tweak) = get_aggkey_and_tweak(secp256k1, alice, bob);
+	let internal_pubkey = aggkey.get_xonly_pubkey();
+	let root_hash = get_root_hash(alice, bob);
+	let output_pubkey = aggkey.apply_tweak(secp256k1, tweak, true)?
+			    .get_xonly_pubkey();
+	let mut script_pubkey = vec!(0x51, 0x20);
+	script_pubkey.extend_from_slice(&output_pubkey);
+
+	if input_index >= psbt.inputs.len() {
+		return None;
+	}
+	if let Some(utxo) = psbt.witness_utxo(input_index) {
+		if utxo.script_pubkey != script_pubkey {
+			return None;
+		}
+	}
+
+	let leaves = [ ( scripts::tapleaf_cooperative(alice, bob)
+		       , get_control_block(secp256k1, alice, bob, COOPERATIVE_LEAF)?
+		       )
+		     , ( scripts::tapleaf_alice_recovery(alice)
+		       , get_control_block(secp256k1, alice, bob, ALICE_RECOVERY_LEAF)?
+		       )
+		     ];
+
+	psbt.set_tap_internal_key(input_index, &internal_pubkey)?;
+	psbt.set_tap_merkle_root(input_index, &root_hash)?;
+	for (script, control_block) in leaves {
+		psbt.set_tap_leaf_script(input_index, &psbt::TapLeafScript{
+			control_block: control_block.to_vec(),
+			script,
+			leaf_version: bip341::TAPROOT_TAPLEAF_VERSION
+		})?;
+	}
+	Some(())
+}
+
+/**
+`keypath_session_context` creates the BIP-327 session
+context for a keypath spend of the swap-in-potentiam
+output of the given `alice` and `bob` public keys.
+
+`aggnonce` is the result of `bip327::nonce_agg` over the
+`pubnonce`s of Alice and Bob, and `msg` is the BIP-341
+signature message of the input being spent.
+
+The session uses `P[0]` and `P[1]`, with even Y
+coordinates, as the public keys, and the single
+x-only `TapTweak` tweak, so the aggregate signature is
+valid for the keypath of the address generated by
+`derive_taproot_address`.
+Signers must call `bip327::nonce_gen` and `bip327::sign`
+with their even-Y public key and the corresponding
+secret key; a Bob whose node ID starts with `0x03`
+has to negate its node secret key before signing.
+
+Returns `None` if the `aggnonce` is invalid, or on the
+negligible-probability edge case where the tweaked key
+is the point at infinity.
+*/
+pub
+fn keypath_session_context<C>( secp256k1: &Secp256k1<C>
+			     , alice: &PublicKey
+			     , bob: &PublicKey
+			     , aggnonce: &[u8; 66]
+			     , msg: &[u8]
+			     ) -> Option<bip327::SessionContext>
+	where C: Verification
+{
+	let (_, tweak) = get_aggkey_and_tweak(secp256k1, alice, bob);
+	let pks = get_keyagg_pubkeys(alice, bob);
+	bip327::SessionContext::new(
+		secp256k1,
+		aggnonce,
+		&pks,
+		&[(tweak, true)],
+		msg
 	)
 }
 
@@ -193,4 +600,437 @@ mod tests {
 			    ).expect("you can see it is hex right there come on")
 			  );
 	}
+
+	fn check_internal_pubkey(a_s: &str, b_s: &str, q_s: &str) {
+		let s_ctx = Secp256k1::new();
+		let (aggkey, _) = get_aggkey_and_tweak( &s_ctx
+						      , &point_txt(a_s)
+						      , &point_txt(b_s)
+						      );
+		assert_eq!( aggkey.get_plain_pubkey()
+			  , point_txt(q_s)
+			  );
+	}
+
+	#[test]
+	fn test_testvector_internal_pubkey() {
+		/* swap-in-potentiam.md
+		 * Internal Public Key Derivation Test Vectors
+		 */
+		check_internal_pubkey( "02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"
+				     , "03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"
+				     , "026962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c69"
+				     );
+		check_internal_pubkey( "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+				     , "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+				     , "02f89c20245de19bd2889af0b0b4bad84bfa99e7e181ac8e9549aeebfcbb10fb1b"
+				     );
+		check_internal_pubkey( "038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236"
+				     , "03de2848d46044aec16ea7b73233f2709f15b9bfeb720dd5d5ae595cfa51e01f15"
+				     , "0359774215a479bd01274044024c52dcd5e37e50f5d3596cc374eaf5035ebc884d"
+				     );
+	}
+
+	fn check_address(a_s: &str, b_s: &str, spk_s: &str, addresses: [&str; 3]) {
+		let s_ctx = Secp256k1::new();
+		let a = point_txt(a_s);
+		let b = point_txt(b_s);
+		assert_eq!( derive_taproot_scriptpubkey(&s_ctx, &a, &b)
+			  , Some(hex::decode(spk_s).unwrap())
+			  );
+		let networks = [Network::Mainnet, Network::Testnet, Network::Regtest];
+		for (network, address) in networks.into_iter().zip(addresses) {
+			assert_eq!( derive_taproot_address(&s_ctx, network, &a, &b)
+				  , Some(address.to_string())
+				  );
+		}
+	}
+
+	#[test]
+	fn test_testvector_address() {
+		/* swap-in-potentiam.md
+		 * Test Vectors For Address Generation
+		 */
+		check_address( "02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"
+			     , "03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"
+			     , "51208e65ee023bbe8850816b833690b565c5edb5c7570724d65958620ea7f6846da5"
+			     , ["bc1p3ej7uq3mh6y9pqttsvmfpdt9chkmt36hqujdvk2cvg820a5ydkjswamvn6"
+			       , "tb1p3ej7uq3mh6y9pqttsvmfpdt9chkmt36hqujdvk2cvg820a5ydkjse4drf4"
+			       , "bcrt1p3ej7uq3mh6y9pqttsvmfpdt9chkmt36hqujdvk2cvg820a5ydkjs5v89u0"
+			       ]
+			     );
+		check_address( "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+			     , "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+			     , "5120a3e57251565298e58c86dddf4919a14645c34bf0cbe98d6fc635f7eb60efd2ba"
+			     , ["bc1p50jhy52k22vwtryxmh05jxdpgezuxjlse05c6m7xxhm7kc8062aqpyydak"
+			       , "tb1p50jhy52k22vwtryxmh05jxdpgezuxjlse05c6m7xxhm7kc8062aqkvjz8e"
+			       , "bcrt1p50jhy52k22vwtryxmh05jxdpgezuxjlse05c6m7xxhm7kc8062aqm4cyjr"
+			       ]
+			     );
+		check_address( "038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236"
+			     , "03de2848d46044aec16ea7b73233f2709f15b9bfeb720dd5d5ae595cfa51e01f15"
+			     , "5120fccde9d2266a2e68c99f0aab5adafea5f97d8a33f81b0525de8f7cb40c08ce7d"
+			     , ["bc1plnx7n53xdghx3jvlp2444kh75huhmz3nlqds2fw73a7tgrqgee7sngun35"
+			       , "tb1plnx7n53xdghx3jvlp2444kh75huhmz3nlqds2fw73a7tgrqgee7syq2utm"
+			       , "bcrt1plnx7n53xdghx3jvlp2444kh75huhmz3nlqds2fw73a7tgrqgee7sfeq67p"
+			       ]
+			     );
+	}
+
+	#[test]
+	fn test_keypath_musig2() {
+		use secp256k1::schnorr::Signature;
+		use secp256k1::Message;
+		use secp256k1::SecretKey;
+		use secp256k1::XOnlyPublicKey;
+
+		let s_ctx = Secp256k1::new();
+		/* Internal Public Key Derivation Test Vector 1;
+		 * B has an odd Y coordinate.
+		 */
+		let a = SecretKey::from_slice(&[0xde, 0xad, 0xbe, 0xef].repeat(8))
+		.unwrap();
+		let mut b_buf = [0; 32];
+		b_buf[0] = 0x12;
+		b_buf[1] = 0x34;
+		let b = SecretKey::from_slice(&b_buf).unwrap();
+		let alice = PublicKey::from_secret_key(&s_ctx, &a);
+		let bob = PublicKey::from_secret_key(&s_ctx, &b);
+		assert_eq!(alice, point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"));
+		assert_eq!(bob, point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"));
+		/* Bob has to sign with the even-Y key.  */
+		let b = b.negate();
+		let bob_even = PublicKey::from_secret_key(&s_ctx, &b);
+
+		let msg = [0x42; 32];
+		let (a_secnonce, a_pubnonce) = bip327::nonce_gen(
+			&s_ctx, [0x01; 32], Some(&a), &alice, None, Some(&msg), None
+		).unwrap();
+		let (b_secnonce, b_pubnonce) = bip327::nonce_gen(
+			&s_ctx, [0x02; 32], Some(&b), &bob_even, None, Some(&msg), None
+		).unwrap();
+		let aggnonce = bip327::nonce_agg(&[a_pubnonce, b_pubnonce])
+		.unwrap();
+
+		let session_ctx = keypath_session_context( &s_ctx
+							 , &alice
+							 , &bob
+							 , &aggnonce
+							 , &msg
+							 ).unwrap();
+		let a_psig = bip327::sign(&s_ctx, a_secnonce, &a, &session_ctx)
+		.unwrap();
+		let b_psig = bip327::sign(&s_ctx, b_secnonce, &b, &session_ctx)
+		.unwrap();
+		assert!(bip327::partial_sig_verify_internal(&s_ctx, &a_psig, &a_pubnonce, &alice, &session_ctx));
+		assert!(bip327::partial_sig_verify_internal(&s_ctx, &b_psig, &b_pubnonce, &bob_even, &session_ctx));
+		let sig = bip327::partial_sig_agg(&[a_psig, b_psig], &session_ctx)
+		.unwrap();
+
+		let output_key = derive_taproot_xonly_pubkey(&s_ctx, &alice, &bob)
+		.unwrap();
+		assert!(s_ctx.verify_schnorr( &Signature::from_slice(&sig).unwrap()
+					    , &Message::from_digest(msg)
+					    , &XOnlyPublicKey::from_slice(&output_key).unwrap()
+					    ).is_ok());
+	}
+
+	#[test]
+	fn test_decode_segwit_address() {
+		let s_ctx = Secp256k1::new();
+		/* swap-in-potentiam.md
+		 * Address Generation Test Vector 1
+		 */
+		let a = point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let address = derive_taproot_address(&s_ctx, Network::Testnet, &a, &b)
+		.expect("Should succeed");
+		let program = derive_taproot_xonly_pubkey(&s_ctx, &a, &b)
+		.expect("Should succeed");
+		assert_eq!( decode_segwit_address(&address)
+			  , Some((Network::Testnet, 1, program.to_vec()))
+			  );
+		assert_eq!( decode_segwit_address(&address.to_uppercase())
+			  , Some((Network::Testnet, 1, program.to_vec()))
+			  );
+		assert_eq!(decode_segwit_address(&address[1..]), None);
+
+		let address = derive_taproot_address(&s_ctx, Network::Signet, &a, &b)
+		.expect("Should succeed");
+		assert_eq!( decode_segwit_address_for_network(&Network::Signet, &address)
+			  , Some((1, program.to_vec()))
+			  );
+		let custom = Network::Custom("sipregtest".to_string());
+		let address = derive_taproot_address(&s_ctx, custom.clone(), &a, &b)
+		.expect("Should succeed");
+		assert!(address.starts_with("sipregtest1p"));
+		assert_eq!( decode_segwit_address_for_network(&custom, &address)
+			  , Some((1, program.to_vec()))
+			  );
+		assert_eq!(decode_segwit_address(&address), None);
+		assert_eq!(derive_taproot_address(&s_ctx, Network::Custom("".to_string()), &a, &b), None);
+	}
+
+	#[test]
+	fn test_testvector_anchor() {
+		/* BOLT 3 Appendix C, the to_local_anchor and
+		 * to_remote_anchor outputs of the commitment
+		 * transactions with anchors, whose funding keys
+		 * have even and odd Y coordinates.
+		 */
+		let vectors = [
+			( "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb"
+			, "00202b1b5854183c12d3316565972c4668929d314d81c5dcdbb21cb45fe8a9a8114f"
+			, "bc1q9vd4s4qc8sfdxvt9vktjc3ngj2wnznvpchwdhvsuk30732dgz98sf7k83x"
+			, "tb1q9vd4s4qc8sfdxvt9vktjc3ngj2wnznvpchwdhvsuk30732dgz98s7kqgtf"
+			),
+			( "030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1"
+			, "0020e9e86e4823faa62e222ebc858a226636856158f07e69898da3b0d1af0ddb3994"
+			, "bc1qa85xujprl2nzug3whjzc5gnxx6zkzk8s0e5cnrdrkrg67rwm8x2q6mma0z"
+			, "tb1qa85xujprl2nzug3whjzc5gnxx6zkzk8s0e5cnrdrkrg67rwm8x2qdndj4d"
+			)
+		];
+		for (b_s, spk, mainnet, testnet) in vectors {
+			let b = point_txt(b_s);
+			assert_eq!(hex::encode(derive_anchor_scriptpubkey(&b)), spk);
+			assert_eq!( derive_anchor_address(Network::Mainnet, &b).as_deref()
+				  , Some(mainnet)
+				  );
+			assert_eq!( derive_anchor_address(Network::Testnet, &b).as_deref()
+				  , Some(testnet)
+				  );
+			assert_eq!( decode_segwit_address(mainnet)
+				  , Some((Network::Mainnet, 0, hex::decode(&spk[4..]).unwrap()))
+				  );
+		}
+
+		/* The odd-Y key is not interchangeable with its
+		 * even-Y negation.
+		 */
+		let b = point_txt("030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1");
+		let even = point_txt("020e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1");
+		assert_ne!(derive_anchor_scriptpubkey(&b), derive_anchor_scriptpubkey(&even));
+		assert_eq!(anchor_witness_script(&b)[1..34], b.serialize());
+	}
+
+	/* The address agrees with the P2TR address of the
+	 * `bitcoin` crate, from the same internal public key
+	 * and Merkle root.
+	 */
+	#[cfg(feature = "bitcoin")]
+	#[test]
+	fn test_bitcoin_p2tr() {
+		use super::super::bitcoin_interop;
+
+		let s_ctx = Secp256k1::new();
+		let keys = [ ( "02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"
+			     , "03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"
+			     )
+			   , ( "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+			     , "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+			     )
+			   , ( "038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236"
+			     , "03de2848d46044aec16ea7b73233f2709f15b9bfeb720dd5d5ae595cfa51e01f15"
+			     )
+			   ];
+		for (a_s, b_s) in keys {
+			let a = point_txt(a_s);
+			let b = point_txt(b_s);
+			let (aggkey, _) = get_aggkey_and_tweak(&s_ctx, &a, &b);
+			let internal_key = bitcoin_interop::to_xonly_pubkey(&aggkey.get_xonly_pubkey())
+			.unwrap();
+			let merkle_root = bitcoin_interop::to_tap_node_hash(&get_root_hash(&a, &b));
+			for network in [Network::Mainnet, Network::Testnet, Network::Signet, Network::Regtest] {
+				let expected = bitcoin::Address::p2tr( &s_ctx
+								     , internal_key
+								     , Some(merkle_root)
+								     , bitcoin_interop::to_network(&network).unwrap()
+								     );
+				let address = derive_taproot_address(&s_ctx, network.clone(), &a, &b)
+				.unwrap();
+				assert_eq!(address, expected.to_string());
+				/* Testnet and signet addresses are the same,
+				so compare the scriptPubKeys.
+				*/
+				assert_eq!( bitcoin_interop::to_address(&network, &address)
+					    .map(|a| a.script_pubkey())
+					  , Some(expected.script_pubkey())
+					  );
+				assert_eq!( bitcoin_interop::to_script_buf(
+						&derive_taproot_scriptpubkey(&s_ctx, &a, &b).unwrap()
+					    )
+					  , expected.script_pubkey()
+					  );
+			}
+		}
+	}
+
+	#[test]
+	fn test_scriptpubkey_matcher() {
+		let s_ctx = Secp256k1::new();
+		let bob = point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let alices = [ point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae")
+			     , point_txt("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5")
+			     , point_txt("038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236")
+			     ];
+		let other_bob = point_txt("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+
+		let mut matcher = ScriptPubKeyMatcher::new(&bob);
+		assert!(matcher.is_empty());
+		for alice in alices.iter() {
+			assert!(matcher.add_alice(&s_ctx, alice));
+		}
+		assert_eq!(matcher.len(), 3);
+		assert_eq!(*matcher.bob(), bob);
+
+		for alice in alices.iter() {
+			let spk = derive_taproot_scriptpubkey(&s_ctx, alice, &bob)
+			.expect("Should succeed");
+			assert_eq!(matcher.match_scriptpubkey(&spk), Some(alice));
+
+			/* Same alice, different bob.  */
+			let spk = derive_taproot_scriptpubkey(&s_ctx, alice, &other_bob)
+			.expect("Should succeed");
+			assert_eq!(matcher.match_scriptpubkey(&spk), None);
+		}
+
+		/* swap-in-potentiam.md
+		 * Address Generation Test Vector 1
+		 */
+		let spk = hex::decode("51208e65ee023bbe8850816b833690b565c5edb5c7570724d65958620ea7f6846da5")
+		.expect("Test input must be hex");
+		assert_eq!(matcher.match_scriptpubkey(&spk), Some(&alices[0]));
+		/* Not a P2TR scriptPubKey, same 32 bytes.  */
+		let mut spk_v0 = spk.clone();
+		spk_v0[0] = 0x00;
+		assert_eq!(matcher.match_scriptpubkey(&spk_v0), None);
+		assert_eq!(matcher.match_scriptpubkey(&spk[0..33]), None);
+		assert_eq!(matcher.match_scriptpubkey(&[]), None);
+	}
+
+	#[test]
+	fn test_fill_psbt_input() {
+		let s_ctx = Secp256k1::new();
+		/* swap-in-potentiam.md
+		 * Address Generation Test Vector 1
+		 */
+		let a = point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let spk = hex::decode("51208e65ee023bbe8850816b833690b565c5edb5c7570724d65958620ea7f6846da5")
+		.expect("Test input must be hex");
+
+		let mut witness_utxo = 100000u64.to_le_bytes().to_vec();
+		witness_utxo.push(spk.len() as u8);
+		witness_utxo.extend_from_slice(&spk);
+		let mut global = psbt::Map::new();
+		global.insert(psbt::PSBT_GLOBAL_TX_VERSION, Vec::new(), vec!(2, 0, 0, 0));
+		global.insert(psbt::PSBT_GLOBAL_INPUT_COUNT, Vec::new(), vec!(1));
+		global.insert(psbt::PSBT_GLOBAL_OUTPUT_COUNT, Vec::new(), vec!(0));
+		global.insert(psbt::PSBT_GLOBAL_VERSION, Vec::new(), vec!(2, 0, 0, 0));
+		let mut input = psbt::Map::new();
+		input.insert(psbt::PSBT_IN_PREVIOUS_TXID, Vec::new(), vec!(0x11; 32));
+		input.insert(psbt::PSBT_IN_OUTPUT_INDEX, Vec::new(), vec!(1, 0, 0, 0));
+		input.insert(psbt::PSBT_IN_WITNESS_UTXO, Vec::new(), witness_utxo);
+		let mut p = psbt::Psbt{global, inputs: vec!(input), outputs: Vec::new()};
+
+		/* Wrong Bob.  */
+		let other_bob = point_txt("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+		let orig = p.clone();
+		assert_eq!(fill_psbt_input(&s_ctx, &mut p, 0, &a, &other_bob), None);
+		assert_eq!(p, orig);
+		assert_eq!(fill_psbt_input(&s_ctx, &mut p, 1, &a, &b), None);
+
+		assert_eq!(fill_psbt_input(&s_ctx, &mut p, 0, &a, &b), Some(()));
+		let p = psbt::Psbt::deserialize(&p.serialize()).expect("Should parse");
+		assert_eq!( hex::encode(p.tap_internal_key(0).unwrap())
+			  , "6962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c69"
+			  );
+		assert_eq!( hex::encode(p.tap_merkle_root(0).unwrap())
+			  , "9a7de09467b643aa9a636cb77488e60d822845ff38db30f8f486903fd552783b"
+			  );
+		let leaves = p.tap_leaf_scripts(0).unwrap();
+		assert_eq!(leaves.len(), 2);
+		assert_eq!(leaves[0].script, scripts::tapleaf_cooperative(&a, &b));
+		assert_eq!(leaves[0].leaf_version, 0xC0);
+		assert_eq!(hex::encode(&leaves[0].control_block), "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c69b15e91cdc464dfdb2f19faa6fcb96d5fb58773452f6afcfe251ed1cf83f1576a");
+		assert_eq!(leaves[1].script, scripts::tapleaf_alice_recovery(&a));
+		assert_eq!(hex::encode(&leaves[1].control_block), "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c696a3d6cef744256400f4a7ae5e5f72b04cb8668c117d47d6df325e5c13e8acef3");
+	}
+
+	fn check_control_block( a_s: &str
+			      , b_s: &str
+			      , cb_s: &str
+			      , f: fn( &Secp256k1<secp256k1::All>
+				     , &PublicKey
+				     , &PublicKey
+				     ) -> Option<[u8; 65]>
+			      ) {
+		let s_ctx = Secp256k1::new();
+		let a = point_txt(a_s);
+		let b = point_txt(b_s);
+		let control_block = f(&s_ctx, &a, &b)
+		.expect("Should succeed");
+		assert_eq!(hex::encode(control_block), cb_s);
+
+		/* The parity bit must match the output public key.  */
+		let (aggkey, tweak) = get_aggkey_and_tweak(&s_ctx, &a, &b);
+		let aggkey = aggkey.apply_tweak(&s_ctx, tweak, true)
+		.expect("Should succeed");
+		let parity = if bip340::has_even_y(&aggkey.get_plain_pubkey()) { 0xC0 } else { 0xC1 };
+		assert_eq!(control_block[0], parity);
+	}
+
+	#[test]
+	fn test_testvector_control_block_cooperative() {
+		/* swap-in-potentiam.md
+		 * Test Vectors For Control Block Of 2-of-2 Tapleaf Path
+		 */
+		check_control_block( "02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"
+				   , "03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"
+				   , "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c69b15e91cdc464dfdb2f19faa6fcb96d5fb58773452f6afcfe251ed1cf83f1576a"
+				   , control_block_cooperative
+				   );
+		check_control_block( "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+				   , "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+				   , "c0f89c20245de19bd2889af0b0b4bad84bfa99e7e181ac8e9549aeebfcbb10fb1b84227987deb98d26d0b8b665a159bc959b2f451ad8451bfa6bf34b25b979d9f4"
+				   , control_block_cooperative
+				   );
+		check_control_block( "038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236"
+				   , "03de2848d46044aec16ea7b73233f2709f15b9bfeb720dd5d5ae595cfa51e01f15"
+				   , "c059774215a479bd01274044024c52dcd5e37e50f5d3596cc374eaf5035ebc884daa7a24068eade1dff691d8d198aeeb5eca481cf937ab461d531eeb2bb1fd394d"
+				   , control_block_cooperative
+				   );
+		check_control_block( "022f8bde4d1a07209355b4a7250a5c5128e88b84bddc619ab7cba8d569b240efe4"
+				   , "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556"
+				   , "c1b9de2c615be15e40c666c1c8e7a2d47aa6ad93fc2a33b446291ed8883003bfc59d554f7390ca4fce20c4b638d9619c57c2abe23f45ac762a481dcb7028af5496"
+				   , control_block_cooperative
+				   );
+	}
+
+	#[test]
+	fn test_testvector_control_block_alice_recovery() {
+		/* swap-in-potentiam.md
+		 * Test Vectors For Control Block Of Timelock Tapleaf Path
+		 */
+		check_control_block( "02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"
+				   , "03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"
+				   , "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c696a3d6cef744256400f4a7ae5e5f72b04cb8668c117d47d6df325e5c13e8acef3"
+				   , control_block_alice_recovery
+				   );
+		check_control_block( "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+				   , "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+				   , "c0f89c20245de19bd2889af0b0b4bad84bfa99e7e181ac8e9549aeebfcbb10fb1bc9bcb65c1015db6b44e5c2977606cf7a5d8f13d226531c6d01133d0810bed17b"
+				   , control_block_alice_recovery
+				   );
+		check_control_block( "038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236"
+				   , "03de2848d46044aec16ea7b73233f2709f15b9bfeb720dd5d5ae595cfa51e01f15"
+				   , "c059774215a479bd01274044024c52dcd5e37e50f5d3596cc374eaf5035ebc884d3a0941de1e532cb3db21839462a6c10d2ab195ee73da09ff37cda2a01c8df72d"
+				   , control_block_alice_recovery
+				   );
+		check_control_block( "022f8bde4d1a07209355b4a7250a5c5128e88b84bddc619ab7cba8d569b240efe4"
+				   , "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556"
+				   , "c1b9de2c615be15e40c666c1c8e7a2d47aa6ad93fc2a33b446291ed8883003bfc5c46a5731331877af9659651233d202bcbc9d41a8ae842d704d3adbde76e4cd82"
+				   , control_block_alice_recovery
+				   );
+	}
 }
@@ -0,0 +1,931 @@
+/*!
+The `api` module contains the request, response and
+error types of the `c=.sip.*` [LSPS0][] JSON-RPC
+methods of the swap-in-potentiam API:
+
+* `c=.sip.sign_psbt_bob`
+* `c=.sip.get_sip_info`
+* `c=.sip.intend_to_fund_channel`
+* `c=.sip.sign_funding_bob`
+* `c=.sip.sign_funding_alice`
+
+The requests and responses are the `params` and
+`result` objects of the JSON-RPC messages, and are
+(de)serialized with `serde`.
+Deserializing only checks the syntax of the objects;
+e.g. pubkeys are checked to have a valid prefix, but
+not to be on the curve.
+
+Each error enum has a `code`, and converts into the
+JSON-RPC `ErrorObject` returned to the client.
+
+[LSPS0]: https://github.com/BitcoinAndLightningLayerSpecs/lsp/tree/main/LSPS0
+*/
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use super::lsps0;
+use super::promise::PromiseError;
+use super::sip_info::FeerateError;
+use super::sip_info::SipOffchainInfo;
+use super::tx::OutPoint;
+
+pub use super::sign_psbt_bob::SignPsbtBobError;
+
+/* `serde` `with` modules for hexadecimal strings.  */
+mod hex_array {
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+
+	pub
+	fn serialize<S, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		serializer.serialize_str(&hex::encode(bytes))
+	}
+
+	pub
+	fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+		where D: Deserializer<'de>
+	{
+		let s = String::deserialize(deserializer)?;
+		let mut rv = [0u8; N];
+		hex::decode_to_slice(&s, &mut rv)
+		.map_err(|_| D::Error::custom("invalid hexadecimal string"))?;
+		Ok(rv)
+	}
+}
+
+mod hex_arrays {
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+	use serde::ser::SerializeSeq;
+
+	pub
+	fn serialize<S, const N: usize>(arrays: &[[u8; N]], serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		let mut seq = serializer.serialize_seq(Some(arrays.len()))?;
+		for bytes in arrays.iter() {
+			seq.serialize_element(&hex::encode(bytes))?;
+		}
+		seq.end()
+	}
+
+	pub
+	fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<Vec<[u8; N]>, D::Error>
+		where D: Deserializer<'de>
+	{
+		let strings = Vec::<String>::deserialize(deserializer)?;
+		let mut rv = Vec::with_capacity(strings.len());
+		for s in strings.iter() {
+			let mut bytes = [0u8; N];
+			hex::decode_to_slice(s, &mut bytes)
+			.map_err(|_| D::Error::custom("invalid hexadecimal string"))?;
+			rv.push(bytes);
+		}
+		Ok(rv)
+	}
+}
+
+mod hex_bytes {
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+
+	pub
+	fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		serializer.serialize_str(&hex::encode(bytes))
+	}
+
+	pub
+	fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+		where D: Deserializer<'de>
+	{
+		let s = String::deserialize(deserializer)?;
+		hex::decode(s)
+		.map_err(|_| D::Error::custom("invalid hexadecimal string"))
+	}
+}
+
+/* `<LSPS0 pubkey>`: a compressed public key, which must
+start with 02 or 03.
+*/
+mod pubkey {
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+
+	pub
+	fn serialize<S>(bytes: &[u8; 33], serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		super::hex_array::serialize(bytes, serializer)
+	}
+
+	pub
+	fn deserialize<'de, D>(deserializer: D) -> Result<[u8; 33], D::Error>
+		where D: Deserializer<'de>
+	{
+		let rv: [u8; 33] = super::hex_array::deserialize(deserializer)?;
+		if rv[0] != 0x02 && rv[0] != 0x03 {
+			return Err(D::Error::custom("invalid <LSPS0 pubkey>"));
+		}
+		Ok(rv)
+	}
+}
+
+/**
+`ErrorObject` is the `error` object of a JSON-RPC
+response.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub
+struct ErrorObject {
+	pub code: i64,
+	pub message: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data: Option<serde_json::Value>
+}
+
+impl ErrorObject {
+	fn new(code: i64, message: &str) -> ErrorObject {
+		ErrorObject{code, message: message.to_string(), data: None}
+	}
+}
+
+/**
+`SignPsbtBobRequest` is the `params` of
+`c=.sip.sign_psbt_bob`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct SignPsbtBobRequest {
+	/// The Base64 PSBT.
+	pub psbt: String
+}
+
+/**
+`SignPsbtBobResponse` is the `result` of
+`c=.sip.sign_psbt_bob`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct SignPsbtBobResponse {
+	/// The Base64 PSBT, with Bob's signatures added.
+	pub signed_psbt: String
+}
+
+impl From<SignPsbtBobError> for ErrorObject {
+	fn from(e: SignPsbtBobError) -> Self {
+		let message = match e {
+			SignPsbtBobError::InvalidPsbt => "invalid_psbt",
+			SignPsbtBobError::UnsupportedPsbtVersion(_) => "unsupported_psbt_version",
+			SignPsbtBobError::UtxoNotValid => "utxo_not_valid",
+			SignPsbtBobError::StoreFailure => "Internal error"
+		};
+		ErrorObject::new(e.code(), message)
+	}
+}
+
+/**
+`GetSipInfoRequest` is the `params` of
+`c=.sip.get_sip_info`.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct GetSipInfoRequest {
+	/// The optional `token`, whose meaning is up to
+	/// the LSP.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub token: Option<String>
+}
+
+/**
+`GetSipInfoResponse` is the `result` of
+`c=.sip.get_sip_info`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct GetSipInfoResponse {
+	pub sip_offchain_info: SipOffchainInfo
+}
+
+/**
+`GetSipInfoError` is an error of `c=.sip.get_sip_info`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum GetSipInfoError {
+	/// `0conf_sip_disabled`: the LSP does not
+	/// currently support 0-conf Lightning operations with
+	/// this client.
+	ZeroconfSipDisabled
+}
+
+impl GetSipInfoError {
+	/// `code` returns the JSON-RPC error `code`.
+	pub
+	fn code(&self) -> i64 {
+		match self {
+			GetSipInfoError::ZeroconfSipDisabled => 1100
+		}
+	}
+}
+
+impl From<GetSipInfoError> for ErrorObject {
+	fn from(e: GetSipInfoError) -> Self {
+		let message = match e {
+			GetSipInfoError::ZeroconfSipDisabled => "0conf_sip_disabled"
+		};
+		ErrorObject::new(e.code(), message)
+	}
+}
+
+/**
+`IntendToFundChannelRequest` is the `params` of
+`c=.sip.intend_to_fund_channel`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct IntendToFundChannelRequest {
+	#[serde(with = "hex_array")]
+	pub temporary_channel_id: [u8; 32],
+	pub sip_offchain_info: SipOffchainInfo,
+	/// The [BIP-327][] pubnonces of Alice, one per
+	/// input of the funding transaction.
+	///
+	/// [BIP-327]: https://github.com/bitcoin/bips/blob/master/bip-0327.mediawiki
+	#[serde(with = "hex_arrays")]
+	pub alice_pubnonces: Vec<[u8; 66]>
+}
+
+/**
+`IntendToFundChannelResponse` is the `result` of
+`c=.sip.intend_to_fund_channel`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct IntendToFundChannelResponse {
+	/// The pubnonces of Bob, in the same order as the
+	/// `alice_pubnonces`.
+	#[serde(with = "hex_arrays")]
+	pub bob_pubnonces: Vec<[u8; 66]>
+}
+
+/**
+`IntendToFundChannelError` is an error of
+`c=.sip.intend_to_fund_channel`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum IntendToFundChannelError {
+	/// `duplicate_channel_id`: the
+	/// `temporary_channel_id` is already the ID of a
+	/// channel of the LSP.
+	DuplicateChannelId,
+	/// `invalid_or_unknown_promise`: the `promise` was
+	/// not returned by the LSP, or has expired.
+	InvalidOrUnknownPromise,
+	/// `too_many_operations`: there are too many
+	/// 0-conf channel fundings in progress.
+	TooManyOperations,
+	/// `invalid_pubnonces_length`: `alice_pubnonces`
+	/// is empty or too long.
+	InvalidPubnoncesLength
+}
+
+impl IntendToFundChannelError {
+	/// `code` returns the JSON-RPC error `code`.
+	pub
+	fn code(&self) -> i64 {
+		match self {
+			IntendToFundChannelError::DuplicateChannelId => 1,
+			IntendToFundChannelError::InvalidOrUnknownPromise => 2,
+			IntendToFundChannelError::TooManyOperations => 3,
+			IntendToFundChannelError::InvalidPubnoncesLength => 4
+		}
+	}
+}
+
+impl From<PromiseError> for IntendToFundChannelError {
+	fn from(_: PromiseError) -> Self {
+		IntendToFundChannelError::InvalidOrUnknownPromise
+	}
+}
+
+impl From<IntendToFundChannelError> for ErrorObject {
+	fn from(e: IntendToFundChannelError) -> Self {
+		let message = match e {
+			IntendToFundChannelError::DuplicateChannelId => "duplicate_channel_id",
+			IntendToFundChannelError::InvalidOrUnknownPromise => "invalid_or_unknown_promise",
+			IntendToFundChannelError::TooManyOperations => "too_many_operations",
+			IntendToFundChannelError::InvalidPubnoncesLength => "invalid_pubnonces_length"
+		};
+		ErrorObject::new(e.code(), message)
+	}
+}
+
+/**
+`FundingInput` is an element of the `inputs` of
+`c=.sip.sign_funding_bob`: a swap-in-potentiam output
+of Alice, with the LSP as Bob.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct FundingInput {
+	#[serde(with = "lsps0::outpoint")]
+	pub prev_out: OutPoint,
+	#[serde(with = "pubkey")]
+	pub alice_pubkey: [u8; 33],
+	#[serde(with = "lsps0::sat")]
+	pub amount_sat: u64
+}
+
+/**
+`ChangeOutput` is the `change` of
+`c=.sip.sign_funding_bob`: a swap-in-potentiam output to
+Alice, with the LSP as Bob.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct ChangeOutput {
+	#[serde(with = "lsps0::sat")]
+	pub amount_sat: u64,
+	#[serde(with = "pubkey")]
+	pub alice_pubkey: [u8; 33]
+}
+
+/**
+`FundingOutput` is the `funding` of
+`c=.sip.sign_funding_bob`: the channel funding output.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct FundingOutput {
+	#[serde(with = "lsps0::sat")]
+	pub amount_sat: u64,
+	#[serde(with = "hex_bytes")]
+	pub output_script: Vec<u8>
+}
+
+/**
+`OutputKind` is an output of the funding transaction,
+in its `order`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum OutputKind {
+	/// `f`: the channel funding output.
+	Funding,
+	/// `a`: the anchor output of Bob.
+	Anchor,
+	/// `c`: the change output of Alice.
+	Change
+}
+
+impl OutputKind {
+	fn to_char(self) -> char {
+		match self {
+			OutputKind::Funding => 'f',
+			OutputKind::Anchor => 'a',
+			OutputKind::Change => 'c'
+		}
+	}
+}
+
+/**
+`Order` is the `order` of the outputs of the funding
+transaction: exactly one `Funding` and one `Anchor`,
+and at most one `Change`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub
+struct Order(Vec<OutputKind>);
+
+impl Order {
+	/// `parse` parses an `order` string, e.g. `"fca"`,
+	/// returning None if it is invalid.
+	pub
+	fn parse(s: &str) -> Option<Order> {
+		let mut rv = Vec::new();
+		for c in s.chars() {
+			let kind = match c {
+				'f' => OutputKind::Funding,
+				'a' => OutputKind::Anchor,
+				'c' => OutputKind::Change,
+				_ => return None
+			};
+			if rv.contains(&kind) {
+				return None;
+			}
+			rv.push(kind);
+		}
+		if !rv.contains(&OutputKind::Funding) || !rv.contains(&OutputKind::Anchor) {
+			return None;
+		}
+		Some(Order(rv))
+	}
+
+	/// `outputs` returns the outputs, in order.
+	pub
+	fn outputs(&self) -> &[OutputKind] {
+		&self.0
+	}
+
+	/// `index` returns the index of the output of the
+	/// given kind, if it is in the order.
+	pub
+	fn index(&self, kind: OutputKind) -> Option<usize> {
+		self.0.iter().position(|k| *k == kind)
+	}
+
+	/// `has_change` returns true if there is a change output.
+	pub
+	fn has_change(&self) -> bool {
+		self.0.contains(&OutputKind::Change)
+	}
+}
+
+impl fmt::Display for Order {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for kind in self.0.iter() {
+			write!(f, "{}", kind.to_char())?;
+		}
+		Ok(())
+	}
+}
+
+impl TryFrom<String> for Order {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Self, String> {
+		Order::parse(&s).ok_or_else(|| format!("invalid order {:?}", s))
+	}
+}
+
+impl From<Order> for String {
+	fn from(order: Order) -> Self {
+		order.to_string()
+	}
+}
+
+/**
+`SignFundingBobRequest` is the `params` of
+`c=.sip.sign_funding_bob`.
+
+Deserializing checks that `order` has a change output
+exactly when `change` is present.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawSignFundingBobRequest")]
+pub
+struct SignFundingBobRequest {
+	#[serde(with = "hex_array")]
+	pub temporary_channel_id: [u8; 32],
+	pub current_blockheight: u32,
+	pub inputs: Vec<FundingInput>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub change: Option<ChangeOutput>,
+	pub funding: FundingOutput,
+	pub order: Order,
+	#[serde(rename = "nLockTime")]
+	pub n_lock_time: u32
+}
+
+/* The JSON object before validation.  */
+#[derive(Deserialize)]
+struct RawSignFundingBobRequest {
+	#[serde(with = "hex_array")]
+	temporary_channel_id: [u8; 32],
+	current_blockheight: u32,
+	inputs: Vec<FundingInput>,
+	#[serde(default)]
+	change: Option<ChangeOutput>,
+	funding: FundingOutput,
+	order: Order,
+	#[serde(rename = "nLockTime")]
+	n_lock_time: u32
+}
+
+impl TryFrom<RawSignFundingBobRequest> for SignFundingBobRequest {
+	type Error = &'static str;
+
+	fn try_from(raw: RawSignFundingBobRequest) -> Result<Self, &'static str> {
+		if raw.order.has_change() != raw.change.is_some() {
+			return Err("order does not match change");
+		}
+		Ok(SignFundingBobRequest{ temporary_channel_id: raw.temporary_channel_id
+					, current_blockheight: raw.current_blockheight
+					, inputs: raw.inputs
+					, change: raw.change
+					, funding: raw.funding
+					, order: raw.order
+					, n_lock_time: raw.n_lock_time
+					})
+	}
+}
+
+/**
+`SignFundingBobResponse` is the `result` of
+`c=.sip.sign_funding_bob`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct SignFundingBobResponse {
+	/// The [BIP-327][] partial signatures of Bob, in
+	/// the same order as the `inputs`.
+	///
+	/// [BIP-327]: https://github.com/bitcoin/bips/blob/master/bip-0327.mediawiki
+	#[serde(with = "hex_arrays")]
+	pub bob_signatures: Vec<[u8; 32]>
+}
+
+/**
+`SignFundingBobError` is an error of
+`c=.sip.sign_funding_bob`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum SignFundingBobError {
+	/// `unrecognized_temporary_channel_id`: there is no
+	/// 0-conf funding in progress with the
+	/// `temporary_channel_id` that can be signed.
+	UnrecognizedTemporaryChannelId,
+	/// `invalid_inputs_length`: the length of `inputs`
+	/// does not match the number of pubnonces.
+	InvalidInputsLength,
+	/// `invalid_prev_out`: one or more of the inputs
+	/// cannot be spent by the funding transaction.
+	InvalidPrevOut,
+	/// `insufficient_confirms`: one or more of the
+	/// inputs does not have enough confirmations.
+	InsufficientConfirms,
+	/// `deadline_too_near`: one or more of the inputs
+	/// has a deadline lower than the smallest
+	/// `max_deadline`.
+	DeadlineTooNear,
+	/// `fee_too_small`: the fee of the funding
+	/// transaction is negative or too low.
+	FeeTooSmall,
+	/// `blockheight_disagreement`: the
+	/// `current_blockheight` is too far from that of the
+	/// LSP, which is given.
+	BlockheightDisagreement{current_blockheight: u32}
+}
+
+impl SignFundingBobError {
+	/// `code` returns the JSON-RPC error `code`.
+	pub
+	fn code(&self) -> i64 {
+		match self {
+			SignFundingBobError::UnrecognizedTemporaryChannelId => 1201,
+			SignFundingBobError::InvalidInputsLength => 1202,
+			SignFundingBobError::InvalidPrevOut => 1203,
+			SignFundingBobError::InsufficientConfirms => 1204,
+			SignFundingBobError::DeadlineTooNear => 1205,
+			SignFundingBobError::FeeTooSmall => 1206,
+			SignFundingBobError::BlockheightDisagreement{..} => 1207
+		}
+	}
+}
+
+impl From<FeerateError> for SignFundingBobError {
+	fn from(e: FeerateError) -> Self {
+		match e {
+			FeerateError::NoInputs => SignFundingBobError::InvalidInputsLength,
+			FeerateError::InsufficientConfirms => SignFundingBobError::InsufficientConfirms,
+			FeerateError::DeadlineTooNear => SignFundingBobError::DeadlineTooNear
+		}
+	}
+}
+
+impl From<SignFundingBobError> for ErrorObject {
+	fn from(e: SignFundingBobError) -> Self {
+		let message = match e {
+			SignFundingBobError::UnrecognizedTemporaryChannelId => "unrecognized_temporary_channel_id",
+			SignFundingBobError::InvalidInputsLength => "invalid_inputs_length",
+			SignFundingBobError::InvalidPrevOut => "invalid_prev_out",
+			SignFundingBobError::InsufficientConfirms => "insufficient_confirms",
+			SignFundingBobError::DeadlineTooNear => "deadline_too_near",
+			SignFundingBobError::FeeTooSmall => "fee_too_small",
+			SignFundingBobError::BlockheightDisagreement{..} => "blockheight_disagreement"
+		};
+		let mut rv = ErrorObject::new(e.code(), message);
+		if let SignFundingBobError::BlockheightDisagreement{current_blockheight} = e {
+			rv.data = Some(serde_json::json!({
+				"current_blockheight": current_blockheight
+			}));
+		}
+		rv
+	}
+}
+
+/**
+`SignFundingAliceRequest` is the `params` of
+`c=.sip.sign_funding_alice`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct SignFundingAliceRequest {
+	#[serde(with = "hex_array")]
+	pub temporary_channel_id: [u8; 32],
+	/// The aggregate [BIP-340][] signatures of the
+	/// inputs, in the same order as the `inputs` of
+	/// `c=.sip.sign_funding_bob`.
+	///
+	/// [BIP-340]: https://github.com/bitcoin/bips/blob/master/bip-0340.mediawiki
+	#[serde(with = "hex_arrays")]
+	pub aggregate_signatures: Vec<[u8; 64]>
+}
+
+/**
+`SignFundingAliceResponse` is the `result` of
+`c=.sip.sign_funding_alice`, an empty object.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub
+struct SignFundingAliceResponse {
+}
+
+/**
+`SignFundingAliceError` is an error of
+`c=.sip.sign_funding_alice`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum SignFundingAliceError {
+	/// `unrecognized_temporary_channel_id`: there is no
+	/// 0-conf funding in progress with the
+	/// `temporary_channel_id` that is waiting for the
+	/// signatures.
+	UnrecognizedTemporaryChannelId,
+	/// `invalid_alice_signatures`: the number of
+	/// signatures is wrong, or one is invalid.
+	InvalidAliceSignatures
+}
+
+impl SignFundingAliceError {
+	/// `code` returns the JSON-RPC error `code`.
+	pub
+	fn code(&self) -> i64 {
+		match self {
+			SignFundingAliceError::UnrecognizedTemporaryChannelId => 1301,
+			SignFundingAliceError::InvalidAliceSignatures => 1302
+		}
+	}
+}
+
+impl From<SignFundingAliceError> for ErrorObject {
+	fn from(e: SignFundingAliceError) -> Self {
+		let message = match e {
+			SignFundingAliceError::UnrecognizedTemporaryChannelId => "unrecognized_temporary_channel_id",
+			SignFundingAliceError::InvalidAliceSignatures => "invalid_alice_signatures"
+		};
+		ErrorObject::new(e.code(), message)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::de::DeserializeOwned;
+	use super::*;
+
+	/* Checks that `json` deserializes to a `T`, and
+	 * that it serializes back to the same JSON value.
+	 */
+	fn round_trip<T>(json: &str) -> T
+		where T: Serialize + DeserializeOwned
+	{
+		let value: serde_json::Value = serde_json::from_str(json).unwrap();
+		let t: T = serde_json::from_str(json).unwrap();
+		assert_eq!(serde_json::to_value(&t).unwrap(), value);
+		t
+	}
+
+	const SIP_OFFCHAIN_INFO: &str = r#"{
+		"min_confirmations": 3,
+		"onchain_fee_schedule": [
+			{
+				"max_deadline": 288,
+				"min_feerate": 50000
+			},
+			{
+				"max_deadline": 576,
+				"min_feerate": 25000
+			},
+			{
+				"max_deadline": 1008,
+				"min_feerate": 10000
+			}
+		],
+		"min_offchain_size_sat": "100000000",
+		"max_offchain_size_sat": "10000000000",
+		"valid_until": "2024-01-18T14:42:24.000Z",
+		"promise": "arbitrary-string-9999"
+	}"#;
+
+	/* The examples of the specification.  */
+	#[test]
+	fn test_sign_psbt_bob() {
+		let req: SignPsbtBobRequest = round_trip(r#"{
+			"psbt": "cHNidP8BAAAA"
+		}"#);
+		assert_eq!(req.psbt, "cHNidP8BAAAA");
+		let resp: SignPsbtBobResponse = round_trip(r#"{
+			"signed_psbt": "cHNidP8BSSSSAAAA"
+		}"#);
+		assert_eq!(resp.signed_psbt, "cHNidP8BSSSSAAAA");
+	}
+
+	#[test]
+	fn test_get_sip_info() {
+		let req: GetSipInfoRequest = round_trip(r#"{
+			"token": "SWAPINPOTENTIAMISAWESOME"
+		}"#);
+		assert_eq!(req.token.as_deref(), Some("SWAPINPOTENTIAMISAWESOME"));
+		let req: GetSipInfoRequest = round_trip("{}");
+		assert_eq!(req.token, None);
+
+		let resp: GetSipInfoResponse = round_trip(&format!(
+			r#"{{ "sip_offchain_info": {} }}"#, SIP_OFFCHAIN_INFO
+		));
+		assert_eq!(resp.sip_offchain_info.min_confirmations, 3);
+		assert_eq!(resp.sip_offchain_info.onchain_fee_schedule.len(), 3);
+	}
+
+	#[test]
+	fn test_intend_to_fund_channel() {
+		let req: IntendToFundChannelRequest = round_trip(&format!(r#"{{
+			"temporary_channel_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			"sip_offchain_info": {},
+			"alice_pubnonces": [
+				"023456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef01203210fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543",
+				"023456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef01203210fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543"
+			]
+		}}"#, SIP_OFFCHAIN_INFO));
+		assert_eq!(req.temporary_channel_id[0..2], [0x01, 0x23]);
+		assert_eq!(req.alice_pubnonces.len(), 2);
+		assert_eq!(req.alice_pubnonces[0][0], 0x02);
+		assert_eq!(req.alice_pubnonces[0][33], 0x03);
+
+		let resp: IntendToFundChannelResponse = round_trip(r#"{
+			"bob_pubnonces": [
+				"03456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef01230210fedcba9876543210fedcba9876543210fedcba9876543210fedcba98765432",
+				"03456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef01230210fedcba9876543210fedcba9876543210fedcba9876543210fedcba98765432"
+			]
+		}"#);
+		assert_eq!(resp.bob_pubnonces.len(), 2);
+
+		/* Pubnonces of the wrong length.  */
+		assert!(serde_json::from_str::<IntendToFundChannelResponse>(r#"{
+			"bob_pubnonces": ["03456789abcdef"]
+		}"#).is_err());
+	}
+
+	const SIGN_FUNDING_BOB: &str = r#"{
+		"temporary_channel_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+		"current_blockheight": 424691,
+		"inputs": [
+			{
+				"prev_out": "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210:2",
+				"alice_pubkey": "02fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210",
+				"amount_sat": "19920"
+			},
+			{
+				"prev_out": "9876543219fedcba9876543219fedcba9876543219fedcba9876543219fedcba:1",
+				"alice_pubkey": "039876543219fedcba9876543219fedcba9876543219fedcba9876543219fedcba",
+				"amount_sat": "19920"
+			}
+		],
+		"change": {
+			"amount_sat": "99999",
+			"alice_pubkey": "039876543219fedcba9876543219fedcba9876543219fedcba9876543219fedcba"
+		},
+		"funding": {
+			"amount_sat": "100000",
+			"output_script": "00204321098765fedcba4321098765fedcba4321098765fedcba4321098765fedcba"
+		},
+		"order": "cfa",
+		"nLockTime": 655454
+	}"#;
+
+	#[test]
+	fn test_sign_funding_bob() {
+		let req: SignFundingBobRequest = round_trip(SIGN_FUNDING_BOB);
+		assert_eq!(req.current_blockheight, 424691);
+		assert_eq!(req.inputs.len(), 2);
+		assert_eq!(req.inputs[0].prev_out.txid[0], 0x10);
+		assert_eq!(req.inputs[0].prev_out.vout, 2);
+		assert_eq!(req.inputs[1].amount_sat, 19920);
+		assert_eq!(req.change.as_ref().unwrap().amount_sat, 99999);
+		assert_eq!(req.funding.output_script.len(), 34);
+		assert_eq!( req.order.outputs()
+			  , &[OutputKind::Change, OutputKind::Funding, OutputKind::Anchor]
+			  );
+		assert_eq!(req.order.index(OutputKind::Funding), Some(1));
+		assert_eq!(req.n_lock_time, 655454);
+
+		/* Without change.  */
+		let mut value: serde_json::Value = serde_json::from_str(SIGN_FUNDING_BOB).unwrap();
+		value.as_object_mut().unwrap().remove("change");
+		value["order"] = serde_json::json!("af");
+		let req: SignFundingBobRequest = round_trip(&value.to_string());
+		assert_eq!(req.change, None);
+		assert_eq!(req.order.index(OutputKind::Change), None);
+
+		/* Invalid requests.  */
+		let changes: [fn(&mut serde_json::Value); 7] = [
+			|v| v["order"] = serde_json::json!("fa"),
+			|v| { v.as_object_mut().unwrap().remove("change"); },
+			|v| v["inputs"][0]["amount_sat"] = serde_json::json!(19920),
+			|v| v["inputs"][0]["prev_out"] = serde_json::json!("fedcba98:2"),
+			|v| v["inputs"][1]["alice_pubkey"] = serde_json::json!(
+				"049876543219fedcba9876543219fedcba9876543219fedcba9876543219fedcba"
+			),
+			|v| v["funding"]["output_script"] = serde_json::json!("0020z"),
+			|v| v["temporary_channel_id"] = serde_json::json!("0123456789abcdef")
+		];
+		for change in changes {
+			let mut value: serde_json::Value = serde_json::from_str(SIGN_FUNDING_BOB).unwrap();
+			change(&mut value);
+			assert!(serde_json::from_str::<SignFundingBobRequest>(&value.to_string()).is_err());
+		}
+
+		let resp: SignFundingBobResponse = round_trip(r#"{
+			"bob_signatures": [
+				"faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450",
+				"aebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450f"
+			]
+		}"#);
+		assert_eq!(resp.bob_signatures[1][0], 0xae);
+	}
+
+	#[test]
+	fn test_order() {
+		for s in ["fa", "af", "fca", "cfa", "acf"] {
+			assert_eq!(Order::parse(s).unwrap().to_string(), s);
+		}
+		for s in ["", "f", "a", "fc", "ffa", "faa", "fcca", "fab", "FA"] {
+			assert_eq!(Order::parse(s), None, "{}", s);
+		}
+	}
+
+	#[test]
+	fn test_sign_funding_alice() {
+		let req: SignFundingAliceRequest = round_trip(r#"{
+			"temporary_channel_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+			"aggregate_signatures": [
+				"ebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450fa",
+				"faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450faebdc9182736450"
+			]
+		}"#);
+		assert_eq!(req.aggregate_signatures.len(), 2);
+		let _: SignFundingAliceResponse = round_trip("{ }");
+	}
+
+	#[test]
+	fn test_errors() {
+		let codes: Vec<(ErrorObject, i64, &str)> = vec!(
+			(SignPsbtBobError::InvalidPsbt.into(), 1001, "invalid_psbt"),
+			(SignPsbtBobError::UnsupportedPsbtVersion(0).into(), 1002, "unsupported_psbt_version"),
+			(SignPsbtBobError::UtxoNotValid.into(), 1004, "utxo_not_valid"),
+			(GetSipInfoError::ZeroconfSipDisabled.into(), 1100, "0conf_sip_disabled"),
+			(IntendToFundChannelError::DuplicateChannelId.into(), 1, "duplicate_channel_id"),
+			(IntendToFundChannelError::from(PromiseError::Expired).into(), 2, "invalid_or_unknown_promise"),
+			(IntendToFundChannelError::TooManyOperations.into(), 3, "too_many_operations"),
+			(IntendToFundChannelError::InvalidPubnoncesLength.into(), 4, "invalid_pubnonces_length"),
+			(SignFundingBobError::UnrecognizedTemporaryChannelId.into(), 1201, "unrecognized_temporary_channel_id"),
+			(SignFundingBobError::InvalidInputsLength.into(), 1202, "invalid_inputs_length"),
+			(SignFundingBobError::from(FeerateError::NoInputs).into(), 1202, "invalid_inputs_length"),
+			(SignFundingBobError::InvalidPrevOut.into(), 1203, "invalid_prev_out"),
+			(SignFundingBobError::from(FeerateError::InsufficientConfirms).into(), 1204, "insufficient_confirms"),
+			(SignFundingBobError::from(FeerateError::DeadlineTooNear).into(), 1205, "deadline_too_near"),
+			(SignFundingBobError::FeeTooSmall.into(), 1206, "fee_too_small"),
+			(SignFundingAliceError::UnrecognizedTemporaryChannelId.into(), 1301, "unrecognized_temporary_channel_id"),
+			(SignFundingAliceError::InvalidAliceSignatures.into(), 1302, "invalid_alice_signatures")
+		);
+		for (e, code, message) in codes {
+			assert_eq!(e.code, code);
+			assert_eq!(e.message, message);
+			assert_eq!(e.data, None);
+		}
+
+		let e: ErrorObject = SignFundingBobError::BlockheightDisagreement{current_blockheight: 424690}.into();
+		assert_eq!( serde_json::to_value(&e).unwrap()
+			  , serde_json::json!({
+				"code": 1207,
+				"message": "blockheight_disagreement",
+				"data": {"current_blockheight": 424690}
+			  })
+			  );
+	}
+}
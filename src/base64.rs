@@ -0,0 +1,104 @@
+/* RFC 4648 Base64, with the standard alphabet and
+mandatory padding, as used by BIP-174 for PSBTs.
+*/
+
+const TABLE: &[u8; 64] =
+b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate)
+fn encode(data: &[u8]) -> String {
+	let mut rv = String::with_capacity(data.len().div_ceil(3) * 4);
+	for chunk in data.chunks(3) {
+		let mut b = [0u8; 3];
+		b[0..chunk.len()].copy_from_slice(chunk);
+		let n = ((b[0] as u32) << 16)
+		      | ((b[1] as u32) << 8)
+		      | (b[2] as u32);
+		for i in 0..4 {
+			if i <= chunk.len() {
+				let v = (n >> (18 - 6 * i)) & 0x3F;
+				rv.push(TABLE[v as usize] as char);
+			} else {
+				rv.push('=');
+			}
+		}
+	}
+	rv
+}
+
+fn decode_char(c: u8) -> Option<u32> {
+	match c {
+		b'A'..=b'Z' => Some((c - b'A') as u32),
+		b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+		b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+		b'+' => Some(62),
+		b'/' => Some(63),
+		_ => None
+	}
+}
+
+/* Fails (returns None) on characters outside the
+alphabet, missing or misplaced padding, or non-zero
+padding bits, so that each byte string has exactly
+one valid encoding.
+*/
+pub(crate)
+fn decode(s: &str) -> Option<Vec<u8>> {
+	let s = s.as_bytes();
+	if !s.len().is_multiple_of(4) {
+		return None;
+	}
+	let mut rv = Vec::with_capacity(s.len() / 4 * 3);
+	let num_chunks = s.len() / 4;
+	for (ci, chunk) in s.chunks(4).enumerate() {
+		let pad = chunk.iter().rev().take_while(|c| **c == b'=').count();
+		if pad > 2 || (pad > 0 && ci != num_chunks - 1) {
+			return None;
+		}
+		let mut n: u32 = 0;
+		for c in &chunk[0..4 - pad] {
+			n = (n << 6) | decode_char(*c)?;
+		}
+		n <<= 6 * pad;
+		let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+		let len = 3 - pad;
+		if bytes[len..].iter().any(|b| *b != 0) {
+			return None;
+		}
+		rv.extend_from_slice(&bytes[0..len]);
+	}
+	Some(rv)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/* RFC 4648 section 10.  */
+	#[test]
+	fn test_rfc4648() {
+		let vectors = [ ("", "")
+			      , ("f", "Zg==")
+			      , ("fo", "Zm8=")
+			      , ("foo", "Zm9v")
+			      , ("foob", "Zm9vYg==")
+			      , ("fooba", "Zm9vYmE=")
+			      , ("foobar", "Zm9vYmFy")
+			      ];
+		for (data, b64) in vectors {
+			assert_eq!(encode(data.as_bytes()), b64);
+			assert_eq!(decode(b64), Some(data.as_bytes().to_vec()));
+		}
+	}
+
+	#[test]
+	fn test_invalid() {
+		for s in [ "Zg", "Zg=", "Zg===", "Z===", "Zh==", "Zm9=", "Zg==Zm9v"
+			 , "Zm9v\n", "Zm-v", "Zm_v", "Zm9vYmF\u{e9}"
+			 ] {
+			assert_eq!(decode(s), None, "{}", s);
+		}
+		let all: Vec<u8> = (0..=255).collect();
+		assert_eq!(decode(&encode(&all)), Some(all));
+	}
+}
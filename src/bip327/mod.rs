@@ -1,11 +1,37 @@
+/*!
+The `bip327` module implements the BIP-327 MuSig2
+multi-signature scheme: key aggregation and tweaking,
+nonce generation and aggregation, partial signing,
+partial signature verification, and partial signature
+aggregation.
+
+Swap-in-potentiam uses this to spend via the keypath,
+with Alice and Bob as the two signers.
+The `address` module provides the session context for
+that specific case; this module is the general
+algorithm, following the BIP-327 pseudocode closely.
+
+Nonces are the dangerous part of MuSig2.
+A `SecNonce` is consumed by `sign`, so that the type
+system prevents signing twice with the same nonce, and
+`nonce_gen` requires the caller to provide fresh
+randomness; this crate has no randomness source of its
+own.
+*/
+use secp256k1::constants::GENERATOR_X;
 use secp256k1::PublicKey;
 use secp256k1::Scalar;
 use secp256k1::Secp256k1;
 use secp256k1::SecretKey;
 use secp256k1::Verification;
+use super::bip340::has_even_y;
+use super::bip340::lift_x;
 use super::bip340::tagged_hash;
+use super::bip340::xbytes;
+use super::scalars::scalar_from_bytes_mod_n;
 use super::scalars::scalar_negate;
 use super::scalars::scalar_plus;
+use super::scalars::scalar_times;
 
 /* Type for the gacc field of KeyAggContext*/
 #[derive(Debug, PartialEq)]
@@ -23,7 +49,7 @@ impl Gacc {
 	}
 	fn times_scalar(&self, a: &Scalar) -> Scalar {
 		match self {
-			Self::PlusOne => a.clone(),
+			Self::PlusOne => *a,
 			Self::MinusOne => scalar_negate(a)
 		}
 	}
@@ -33,21 +59,32 @@ impl Gacc {
 			  ) -> PublicKey
 			where C: Verification {
 		match self {
-			Self::PlusOne => p.clone(),
+			Self::PlusOne => *p,
 			Self::MinusOne => p.negate(secp256k1)
 		}
 	}
 }
 
-pub(crate) struct KeyAggContext {
+/** The BIP-327 `KeyAggContext`, the aggregate public
+key `Q` together with the accumulated tweak `tacc` and
+sign `gacc`.
+
+Create it with `key_agg`, then tweak it with
+`apply_tweak`.
+*/
+pub struct KeyAggContext {
 	q: PublicKey,
 	tacc: Scalar,
 	gacc: Gacc
 }
 
 impl KeyAggContext {
-	/* BIP-327 ApplyTweak.  */
-	pub(crate)
+	/// BIP-327 ApplyTweak.
+	///
+	/// Returns `None` if the tweak is not less than the
+	/// curve order, or if the tweaked key would be the
+	/// point at infinity.
+	pub
 	fn apply_tweak<C>( &self
 			 , secp256k1: &Secp256k1<C>
 			 , tweak: [u8; 32]
@@ -84,44 +121,29 @@ impl KeyAggContext {
 			}
 		)
 	}
-	/* BIP-327 GetXonlyPubKey */
-	pub(crate)
+	/// BIP-327 GetXonlyPubKey
+	pub
 	fn get_xonly_pubkey(&self) -> [u8; 32] {
-		let KeyAggContext{q: q, tacc: _, gacc: _} = self;
-		let ser = q.serialize();
-		ser[1..33].try_into().expect("constant bounds")
+		xbytes(&self.q)
+	}
+	/// BIP-327 GetPlainPubKey
+	pub
+	fn get_plain_pubkey(&self) -> PublicKey {
+		self.q
 	}
 }
 
-fn has_even_y(q: &PublicKey) -> bool {
-	let ser = q.serialize();
-	return ser[0] == 0x02;
-}
-
-/* BIP-327 KeyAgg */
-pub(crate) fn key_agg<C>( secp256k1: &Secp256k1<C>
-			, pk: &[PublicKey]
-			) -> KeyAggContext
+/** BIP-327 KeyAgg */
+pub
+fn key_agg<C>( secp256k1: &Secp256k1<C>
+	     , pk: &[PublicKey]
+	     ) -> KeyAggContext
 	where C: Verification
 {
-	let l = hash_keys(pk);
-
-	let pk2 = get_second_key(pk);
-
 	let mut a = Vec::new();
 
 	for pk_prime in pk {
-		/* KeyAggCoeffInternal */
-		if pk2 < pk.len() && pk_prime == &pk[pk2] {
-			a.push(Scalar::ONE);
-		} else {
-			let mut buf = Vec::new();
-			buf.extend_from_slice(&l);
-			buf.extend_from_slice(&pk_prime.serialize());
-			let hash = tagged_hash("KeyAgg coefficient", &buf);
-			a.push(Scalar::from_be_bytes(hash)
-			.expect("unlikely scalar greater than n"));
-		}
+		a.push(key_agg_coeff(pk, pk_prime));
 	}
 	assert!(a.len() == pk.len());
 
@@ -134,11 +156,11 @@ pub(crate) fn key_agg<C>( secp256k1: &Secp256k1<C>
 		).expect("Unlikely key cancellation");
 	}
 
-	return KeyAggContext{
-		q: q,
+	KeyAggContext{
+		q,
 		tacc: Scalar::ZERO,
 		gacc: Gacc::PlusOne
-	};
+	}
 }
 
 fn hash_keys(pk: &[PublicKey]) -> [u8; 32] {
@@ -158,7 +180,487 @@ fn get_second_key(pk: &[PublicKey]) -> usize {
 			return i;
 		}
 	}
-	return pk.len();
+	pk.len()
+}
+
+/* BIP-327 KeyAggCoeff */
+fn key_agg_coeff(pk: &[PublicKey], pk_prime: &PublicKey) -> Scalar {
+	let pk2 = get_second_key(pk);
+	/* KeyAggCoeffInternal */
+	if pk2 < pk.len() && pk_prime == &pk[pk2] {
+		return Scalar::ONE;
+	}
+	let l = hash_keys(pk);
+	let mut buf = Vec::new();
+	buf.extend_from_slice(&l);
+	buf.extend_from_slice(&pk_prime.serialize());
+	scalar_from_bytes_mod_n(tagged_hash("KeyAgg coefficient", &buf))
+}
+
+/* Points that may be the point at infinity are
+represented as `None`.
+*/
+fn point_add( a: Option<PublicKey>
+	    , b: Option<PublicKey>
+	    ) -> Option<PublicKey> {
+	match (a, b) {
+		(None, b) => b,
+		(a, None) => a,
+		/* combine only fails on the point at infinity */
+		(Some(a), Some(b)) => a.combine(&b).ok()
+	}
+}
+fn point_mul<C>( secp256k1: &Secp256k1<C>
+	       , p: &Option<PublicKey>
+	       , k: &Scalar
+	       ) -> Option<PublicKey>
+			where C: Verification {
+	/* mul_tweak only fails on a 0 scalar */
+	p.as_ref()?.mul_tweak(secp256k1, k).ok()
+}
+fn generator() -> PublicKey {
+	lift_x(&GENERATOR_X).expect("G is on the curve")
+}
+/* BIP-327 cpoint_ext */
+fn cpoint_ext(buf: &[u8]) -> Result<Option<PublicKey>, ()> {
+	if buf.iter().all(|b| *b == 0) {
+		return Ok(None);
+	}
+	PublicKey::from_slice(buf).map(Some).map_err(|_| ())
+}
+/* BIP-327 cbytes_ext */
+fn cbytes_ext(p: &Option<PublicKey>) -> [u8; 33] {
+	match p {
+		None => [0; 33],
+		Some(p) => p.serialize()
+	}
+}
+
+/** BIP-327 blames a specific signer when it is given an
+invalid public nonce or partial signature.
+The contained value is the index of the signer whose
+contribution was invalid.
+*/
+#[derive(Debug, PartialEq)]
+pub struct InvalidContribution(pub usize);
+
+/** A BIP-327 `secnonce`, the secret counterpart of a
+66-byte `pubnonce`.
+
+This is deliberately neither `Clone` nor `Copy`, and
+`sign` consumes it: a `secnonce` MUST NOT be used to
+sign more than once, as that leaks the secret key.
+*/
+pub struct SecNonce {
+	buf: [u8; 97]
+}
+
+/** BIP-327 NonceGen.
+
+`rand` MUST be 32 bytes freshly drawn uniformly at
+random for every call; this is the `rand'` of BIP-327.
+All of the other arguments are optional extra inputs
+that make the nonce more robust against a bad `rand`,
+except for `pk`, which is the public key of the signer
+that will later call `sign` with the returned
+`SecNonce`.
+
+Returns the `secnonce` and the 66-byte `pubnonce`, or
+`None` in the negligible-probability case that a nonce
+is 0.
+*/
+pub
+fn nonce_gen<C>( secp256k1: &Secp256k1<C>
+	       , rand: [u8; 32]
+	       , sk: Option<&SecretKey>
+	       , pk: &PublicKey
+	       , aggpk: Option<&[u8; 32]>
+	       , msg: Option<&[u8]>
+	       , extra_in: Option<&[u8]>
+	       ) -> Option<(SecNonce, [u8; 66])>
+	where C: Verification
+{
+	let rand = match sk {
+		None => rand,
+		Some(sk) => {
+			let aux = tagged_hash("MuSig/aux", &rand);
+			let sk = sk.secret_bytes();
+			let mut buf: [u8; 32] = [0; 32];
+			for i in 0..32 {
+				buf[i] = sk[i] ^ aux[i];
+			}
+			buf
+		}
+	};
+	let pk = pk.serialize();
+	let aggpk: &[u8] = match aggpk {
+		None => &[],
+		Some(aggpk) => aggpk
+	};
+	let mut msg_prefixed = Vec::new();
+	match msg {
+		None => {
+			msg_prefixed.push(0);
+		},
+		Some(msg) => {
+			msg_prefixed.push(1);
+			msg_prefixed.extend_from_slice(&(msg.len() as u64).to_be_bytes());
+			msg_prefixed.extend_from_slice(msg);
+		}
+	}
+	let extra_in = extra_in.unwrap_or(&[]);
+
+	let mut buf = Vec::new();
+	buf.extend_from_slice(&rand);
+	buf.push(pk.len() as u8);
+	buf.extend_from_slice(&pk);
+	buf.push(aggpk.len() as u8);
+	buf.extend_from_slice(aggpk);
+	buf.extend_from_slice(&msg_prefixed);
+	buf.extend_from_slice(&(extra_in.len() as u32).to_be_bytes());
+	buf.extend_from_slice(extra_in);
+
+	let nonce_hash = |i: u8| {
+		let mut buf = buf.clone();
+		buf.push(i);
+		scalar_from_bytes_mod_n(tagged_hash("MuSig/nonce", &buf))
+	};
+	let k1 = nonce_hash(0);
+	let k2 = nonce_hash(1);
+	if k1 == Scalar::ZERO || k2 == Scalar::ZERO {
+		return None;
+	}
+
+	let g = Some(generator());
+	let r1 = point_mul(secp256k1, &g, &k1)?;
+	let r2 = point_mul(secp256k1, &g, &k2)?;
+
+	let mut secnonce: [u8; 97] = [0; 97];
+	secnonce[0..32].copy_from_slice(&k1.to_be_bytes());
+	secnonce[32..64].copy_from_slice(&k2.to_be_bytes());
+	secnonce[64..97].copy_from_slice(&pk);
+
+	let mut pubnonce: [u8; 66] = [0; 66];
+	pubnonce[0..33].copy_from_slice(&r1.serialize());
+	pubnonce[33..66].copy_from_slice(&r2.serialize());
+
+	Some((SecNonce{buf: secnonce}, pubnonce))
+}
+
+/** BIP-327 NonceAgg.
+
+Aggregates the 66-byte `pubnonce`s of all signers into
+a 66-byte `aggnonce`.
+On failure, returns the index of the signer that gave
+an invalid `pubnonce`.
+*/
+pub
+fn nonce_agg(pubnonces: &[[u8; 66]]) -> Result<[u8; 66], InvalidContribution> {
+	let mut aggnonce: [u8; 66] = [0; 66];
+	for j in 0..2 {
+		let mut r_j = None;
+		for (i, pubnonce) in pubnonces.iter().enumerate() {
+			let r_ij = PublicKey::from_slice(
+				&pubnonce[(j * 33)..((j + 1) * 33)]
+			).map_err(|_| InvalidContribution(i))?;
+			r_j = point_add(r_j, Some(r_ij));
+		}
+		aggnonce[(j * 33)..((j + 1) * 33)].copy_from_slice(
+			&cbytes_ext(&r_j)
+		);
+	}
+	Ok(aggnonce)
+}
+
+/** The BIP-327 session context, i.e. everything the
+signers need to agree on before signing: the aggregate
+nonce, the public keys in order, the tweaks, and the
+message.
+
+On creation, the BIP-327 `GetSessionValues` are
+computed and cached.
+*/
+pub struct SessionContext {
+	pubkeys: Vec<PublicKey>,
+	keyagg: KeyAggContext,
+	b: Scalar,
+	r: PublicKey,
+	e: Scalar
+}
+
+impl SessionContext {
+	/// Creates the session context and computes the
+	/// BIP-327 `GetSessionValues`.
+	///
+	/// `tweaks` are the tweaks to apply in order, each
+	/// paired with its `is_xonly_t` flag.
+	///
+	/// Returns `None` if the `aggnonce` is invalid, or any
+	/// of the tweaks is invalid.
+	pub
+	fn new<C>( secp256k1: &Secp256k1<C>
+		 , aggnonce: &[u8; 66]
+		 , pubkeys: &[PublicKey]
+		 , tweaks: &[([u8; 32], bool)]
+		 , msg: &[u8]
+		 ) -> Option<Self>
+			where C: Verification {
+		let mut keyagg = key_agg(secp256k1, pubkeys);
+		for (tweak, is_xonly_t) in tweaks {
+			keyagg = keyagg.apply_tweak(
+				secp256k1, *tweak, *is_xonly_t
+			)?;
+		}
+		let q = keyagg.get_xonly_pubkey();
+
+		let b = {
+			let mut buf = Vec::new();
+			buf.extend_from_slice(aggnonce);
+			buf.extend_from_slice(&q);
+			buf.extend_from_slice(msg);
+			scalar_from_bytes_mod_n(
+				tagged_hash("MuSig/noncecoef", &buf)
+			)
+		};
+
+		let r1 = cpoint_ext(&aggnonce[0..33]).ok()?;
+		let r2 = cpoint_ext(&aggnonce[33..66]).ok()?;
+		let r_prime = point_add(r1, point_mul(secp256k1, &r2, &b));
+		let r = r_prime.unwrap_or_else(generator);
+
+		let e = {
+			let mut buf = Vec::new();
+			buf.extend_from_slice(&xbytes(&r));
+			buf.extend_from_slice(&q);
+			buf.extend_from_slice(msg);
+			scalar_from_bytes_mod_n(
+				tagged_hash("BIP0340/challenge", &buf)
+			)
+		};
+
+		Some(SessionContext{
+			pubkeys: pubkeys.to_vec(),
+			keyagg,
+			b,
+			r,
+			e
+		})
+	}
+
+	/* BIP-327 GetSessionKeyAggCoeff */
+	fn get_session_key_agg_coeff(&self, p: &PublicKey) -> Option<Scalar> {
+		if !self.pubkeys.contains(p) {
+			return None;
+		}
+		Some(key_agg_coeff(&self.pubkeys, p))
+	}
+
+	/* g * gacc, where g is the sign of Q.  */
+	fn g_gacc(&self) -> Gacc {
+		let g = if has_even_y(&self.keyagg.q) {
+			Gacc::PlusOne
+		} else {
+			Gacc::MinusOne
+		};
+		g.times(&self.keyagg.gacc)
+	}
+}
+
+/** BIP-327 Sign.
+
+Consumes the `secnonce`, which must have been generated
+by `nonce_gen` for the public key of `sk`.
+
+Returns the 32-byte partial signature, or `None` if the
+`secnonce` does not match `sk`, the public key of `sk`
+is not one of the session public keys, or the partial
+signature fails to verify.
+*/
+pub
+fn sign<C>( secp256k1: &Secp256k1<C>
+	  , secnonce: SecNonce
+	  , sk: &SecretKey
+	  , session_ctx: &SessionContext
+	  ) -> Option<[u8; 32]>
+	where C: Verification
+{
+	let SecNonce{buf: secnonce} = secnonce;
+	let k1_prime = Scalar::from_be_bytes(
+		secnonce[0..32].try_into().expect("constant bounds")
+	).ok()?;
+	let k2_prime = Scalar::from_be_bytes(
+		secnonce[32..64].try_into().expect("constant bounds")
+	).ok()?;
+	if k1_prime == Scalar::ZERO || k2_prime == Scalar::ZERO {
+		return None;
+	}
+	let (k1, k2) = if has_even_y(&session_ctx.r) {
+		(k1_prime, k2_prime)
+	} else {
+		(scalar_negate(&k1_prime), scalar_negate(&k2_prime))
+	};
+
+	let d_prime = Scalar::from(*sk);
+	let g = Some(generator());
+	let p = point_mul(secp256k1, &g, &d_prime)?;
+	if p.serialize()[..] != secnonce[64..97] {
+		return None;
+	}
+	let a = session_ctx.get_session_key_agg_coeff(&p)?;
+	let d = session_ctx.g_gacc().times_scalar(&d_prime);
+
+	let SessionContext{b, e, ..} = session_ctx;
+	/* s = k1 + b*k2 + e*a*d */
+	let s = scalar_plus(
+		&scalar_plus(&k1, &scalar_times(b, &k2)),
+		&scalar_times(e, &scalar_times(&a, &d))
+	);
+	let psig = s.to_be_bytes();
+
+	let mut pubnonce: [u8; 66] = [0; 66];
+	pubnonce[0..33].copy_from_slice(
+		&point_mul(secp256k1, &g, &k1_prime)?.serialize()
+	);
+	pubnonce[33..66].copy_from_slice(
+		&point_mul(secp256k1, &g, &k2_prime)?.serialize()
+	);
+	if !partial_sig_verify_internal( secp256k1
+				       , &psig
+				       , &pubnonce
+				       , &p
+				       , session_ctx
+				       ) {
+		return None;
+	}
+
+	Some(psig)
+}
+
+/** BIP-327 PartialSigVerify.
+
+Verifies the partial signature `psig` of the signer at
+index `i`, given the `pubnonce`s and public keys of all
+signers, the tweaks, and the message.
+*/
+pub
+fn partial_sig_verify<C>( secp256k1: &Secp256k1<C>
+			, psig: &[u8; 32]
+			, pubnonces: &[[u8; 66]]
+			, pubkeys: &[PublicKey]
+			, tweaks: &[([u8; 32], bool)]
+			, msg: &[u8]
+			, i: usize
+			) -> bool
+	where C: Verification
+{
+	if i >= pubnonces.len() || i >= pubkeys.len() {
+		return false;
+	}
+	let aggnonce = match nonce_agg(pubnonces) {
+		Err(_) => return false,
+		Ok(aggnonce) => aggnonce
+	};
+	let session_ctx = match SessionContext::new(
+		secp256k1, &aggnonce, pubkeys, tweaks, msg
+	) {
+		None => return false,
+		Some(session_ctx) => session_ctx
+	};
+	partial_sig_verify_internal( secp256k1
+				   , psig
+				   , &pubnonces[i]
+				   , &pubkeys[i]
+				   , &session_ctx
+				   )
+}
+
+/** BIP-327 PartialSigVerifyInternal.
+
+Verifies the partial signature `psig` of the signer
+with the given `pubnonce` and public key `pk`, for an
+already-created session context.
+*/
+pub
+fn partial_sig_verify_internal<C>( secp256k1: &Secp256k1<C>
+				 , psig: &[u8; 32]
+				 , pubnonce: &[u8; 66]
+				 , pk: &PublicKey
+				 , session_ctx: &SessionContext
+				 ) -> bool
+	where C: Verification
+{
+	let s = match Scalar::from_be_bytes(*psig) {
+		Err(_) => return false,
+		Ok(s) => s
+	};
+	let r1 = match PublicKey::from_slice(&pubnonce[0..33]) {
+		Err(_) => return false,
+		Ok(r1) => r1
+	};
+	let r2 = match PublicKey::from_slice(&pubnonce[33..66]) {
+		Err(_) => return false,
+		Ok(r2) => r2
+	};
+	let a = match session_ctx.get_session_key_agg_coeff(pk) {
+		None => return false,
+		Some(a) => a
+	};
+	let SessionContext{b, r, e, ..} = session_ctx;
+
+	/* Re* = R*1 + b*R*2, negated if R has odd Y */
+	let re_prime = point_add( Some(r1)
+				, point_mul(secp256k1, &Some(r2), b)
+				);
+	let re = if has_even_y(r) {
+		re_prime
+	} else {
+		re_prime.map(|p| p.negate(secp256k1))
+	};
+
+	/* s*G == Re* + e*a*g'*P */
+	let g = Some(generator());
+	let lhs = point_mul(secp256k1, &g, &s);
+	let eag = session_ctx.g_gacc().times_scalar(
+		&scalar_times(e, &a)
+	);
+	let rhs = point_add(re, point_mul(secp256k1, &Some(*pk), &eag));
+
+	lhs == rhs
+}
+
+/** BIP-327 PartialSigAgg.
+
+Aggregates the 32-byte partial signatures of all signers
+into a 64-byte BIP-340 Schnorr signature.
+On failure, returns the index of the signer that gave
+an invalid partial signature.
+
+This does *not* verify the individual partial
+signatures; use `partial_sig_verify` for that.
+*/
+pub
+fn partial_sig_agg( psigs: &[[u8; 32]]
+		  , session_ctx: &SessionContext
+		  ) -> Result<[u8; 64], InvalidContribution> {
+	let mut s = Scalar::ZERO;
+	for (i, psig) in psigs.iter().enumerate() {
+		let s_i = Scalar::from_be_bytes(*psig)
+		.map_err(|_| InvalidContribution(i))?;
+		s = scalar_plus(&s, &s_i);
+	}
+	let SessionContext{keyagg, r, e, ..} = session_ctx;
+	let g = if has_even_y(&keyagg.q) {
+		Gacc::PlusOne
+	} else {
+		Gacc::MinusOne
+	};
+	/* s = s1 + ... + su + e*g*tacc */
+	s = scalar_plus(&s, &g.times_scalar(&scalar_times(e, &keyagg.tacc)));
+
+	let mut sig: [u8; 64] = [0; 64];
+	sig[0..32].copy_from_slice(&xbytes(r));
+	sig[32..64].copy_from_slice(&s.to_be_bytes());
+	Ok(sig)
 }
 
 #[cfg(test)]
@@ -280,4 +782,332 @@ mod tests {
 		 * *sigh*
 		 */
 	}
+
+	#[test]
+	fn test_apply_tweak_error() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0327/vectors/key_agg_vectors.json */
+		let s_ctx = Secp256k1::new();
+		/* Tweak is out of range */
+		let ctx = key_agg_txt(&[ "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"
+				       , "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"
+				       ]);
+		assert!(ctx.apply_tweak(&s_ctx, hex_arr("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"), true).is_none());
+		/* Intermediate tweaking result is point at infinity */
+		let ctx = key_agg_txt(&["03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9"]);
+		assert!(ctx.apply_tweak(&s_ctx, hex_arr("252E4BD67410A76CDF933D30EAA1608214037F1B105A013ECCD3C5C184A6110B"), false).is_none());
+	}
+
+	fn hex_arr<const N: usize>(s: &str) -> [u8; N] {
+		hex::decode(s)
+		.expect("Test input must be hex")
+		.try_into()
+		.expect("Test input must be the correct length")
+	}
+
+	fn secnonce_txt(s: &str) -> SecNonce {
+		SecNonce{buf: hex_arr(s)}
+	}
+
+	fn sk_txt(s: &str) -> SecretKey {
+		SecretKey::from_slice(&hex::decode(s).expect("Test input must be hex"))
+		.expect("Test input must be a valid secret key")
+	}
+
+	#[test]
+	fn test_nonce_gen() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0327/vectors/nonce_gen_vectors.json */
+		let s_ctx = Secp256k1::new();
+
+		let (secnonce, pubnonce) = nonce_gen( &s_ctx
+						    , hex_arr("0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F")
+						    , Some(&sk_txt("0202020202020202020202020202020202020202020202020202020202020202"))
+						    , &point_txt("024D4B6CD1361032CA9BD2AEB9D900AA4D45D9EAD80AC9423374C451A7254D0766")
+						    , Some(&hex_arr("0707070707070707070707070707070707070707070707070707070707070707"))
+						    , Some(&hex::decode("0101010101010101010101010101010101010101010101010101010101010101").unwrap())
+						    , Some(&hex::decode("0808080808080808080808080808080808080808080808080808080808080808").unwrap())
+						    ).unwrap();
+		assert_eq!( secnonce.buf
+			  , hex_arr("B114E502BEAA4E301DD08A50264172C84E41650E6CB726B410C0694D59EFFB6495B5CAF28D045B973D63E3C99A44B807BDE375FD6CB39E46DC4A511708D0E9D2024D4B6CD1361032CA9BD2AEB9D900AA4D45D9EAD80AC9423374C451A7254D0766")
+			  );
+		assert_eq!( pubnonce
+			  , hex_arr("02F7BE7089E8376EB355272368766B17E88E7DB72047D05E56AA881EA52B3B35DF02C29C8046FDD0DED4C7E55869137200FBDBFE2EB654267B6D7013602CAED3115A")
+			  );
+
+		let (secnonce, pubnonce) = nonce_gen( &s_ctx
+						    , hex_arr("0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F")
+						    , None
+						    , &point_txt("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9")
+						    , None
+						    , None
+						    , None
+						    ).unwrap();
+		assert_eq!( secnonce.buf
+			  , hex_arr("89BDD787D0284E5E4D5FC572E49E316BAB7E21E3B1830DE37DFE80156FA41A6D0B17AE8D024C53679699A6FD7944D9C4A366B514BAF43088E0708B1023DD289702F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9")
+			  );
+		assert_eq!( pubnonce
+			  , hex_arr("02C96E7CB1E8AA5DAC64D872947914198F607D90ECDE5200DE52978AD5DED63C000299EC5117C2D29EDEE8A2092587C3909BE694D5CFF0667D6C02EA4059F7CD9786")
+			  );
+	}
+
+	#[test]
+	fn test_nonce_agg() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0327/vectors/nonce_agg_vectors.json */
+		let pnonces: Vec<[u8; 66]> = [ "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E66603BA47FBC1834437B3212E89A84D8425E7BF12E0245D98262268EBDCB385D50641"
+					     , "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833"
+					     , "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E6660279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798"
+					     , "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60379BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798"
+					     , "04FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833"
+					     , "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B831"
+					     , "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A602FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30"
+					     ].iter().map(|s| hex_arr(s)).collect();
+		let agg = |ixs: &[usize]| {
+			let ps: Vec<[u8; 66]> = ixs.iter().map(|i| pnonces[*i]).collect();
+			nonce_agg(&ps)
+		};
+
+		assert_eq!( agg(&[0, 1])
+			  , Ok(hex_arr("035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B024725377345BDE0E9C33AF3C43C0A29A9249F2F2956FA8CFEB55C8573D0262DC8"))
+			  );
+		/* Sum of second points encoded in the nonces is point at infinity */
+		assert_eq!( agg(&[2, 3])
+			  , Ok(hex_arr("035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B000000000000000000000000000000000000000000000000000000000000000000"))
+			  );
+		/* Public nonce from signer 1 is invalid due wrong tag */
+		assert_eq!(agg(&[0, 4]), Err(InvalidContribution(1)));
+		/* Public nonce from signer 0 is invalid because X is not on the curve */
+		assert_eq!(agg(&[5, 1]), Err(InvalidContribution(0)));
+		/* Public nonce from signer 0 is invalid because X exceeds field size */
+		assert_eq!(agg(&[6, 1]), Err(InvalidContribution(0)));
+	}
+
+	fn msg_sign_verify() -> Vec<u8> {
+		hex::decode("F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF")
+		.expect("")
+	}
+
+	#[test]
+	fn test_sign_verify() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0327/vectors/sign_verify_vectors.json */
+		let s_ctx = Secp256k1::new();
+		let sk = sk_txt("7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671");
+		let pubkeys = [ point_txt("03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9")
+			      , point_txt("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9")
+			      , point_txt("02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661")
+			      ];
+		/* pubkeys[3] in the vectors is not a valid point,
+		 * so cannot even be represented as a PublicKey.
+		 */
+		assert!(PublicKey::from_slice(&hex::decode("020000000000000000000000000000000000000000000000000000000000000007").unwrap()).is_err());
+		let secnonces = [ "508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F703935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9"
+				, "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9"
+				];
+		let pnonces: Vec<[u8; 66]> = [ "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480"
+					     , "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798"
+					     , "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046"
+					     , "0237C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0387BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480"
+					     , "0200000000000000000000000000000000000000000000000000000000000000090287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480"
+					     ].iter().map(|s| hex_arr(s)).collect();
+		let aggnonces: Vec<[u8; 66]> = [ "028465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD61037496A3CC86926D452CAFCFD55D25972CA1675D549310DE296BFF42F72EEEA8C9"
+					       , "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+					       , "048465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD61037496A3CC86926D452CAFCFD55D25972CA1675D549310DE296BFF42F72EEEA8C9"
+					       , "028465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD61020000000000000000000000000000000000000000000000000000000000000009"
+					       , "028465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD6102FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30"
+					       ].iter().map(|s| hex_arr(s)).collect();
+		let msg = msg_sign_verify();
+
+		let pks = |ixs: &[usize]| -> Vec<PublicKey> {
+			ixs.iter().map(|i| pubkeys[*i]).collect()
+		};
+		let pns = |ixs: &[usize]| -> Vec<[u8; 66]> {
+			ixs.iter().map(|i| pnonces[*i]).collect()
+		};
+
+		let check_valid = | key_indices: &[usize]
+				  , nonce_indices: &[usize]
+				  , aggnonce_index: usize
+				  , signer_index: usize
+				  , expected: &str
+				  | {
+			let expected: [u8; 32] = hex_arr(expected);
+			assert_eq!( nonce_agg(&pns(nonce_indices))
+				  , Ok(aggnonces[aggnonce_index])
+				  );
+			let session_ctx = SessionContext::new( &s_ctx
+							     , &aggnonces[aggnonce_index]
+							     , &pks(key_indices)
+							     , &[]
+							     , &msg
+							     ).unwrap();
+			assert_eq!( sign(&s_ctx, secnonce_txt(secnonces[0]), &sk, &session_ctx)
+				  , Some(expected)
+				  );
+			assert!(partial_sig_verify( &s_ctx
+						  , &expected
+						  , &pns(nonce_indices)
+						  , &pks(key_indices)
+						  , &[]
+						  , &msg
+						  , signer_index
+						  ));
+		};
+		check_valid(&[0, 1, 2], &[0, 1, 2], 0, 0, "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB");
+		check_valid(&[1, 0, 2], &[1, 0, 2], 0, 1, "9FF2F7AAA856150CC8819254218D3ADEEB0535269051897724F9DB3789513A52");
+		check_valid(&[1, 2, 0], &[1, 2, 0], 0, 2, "FA23C359F6FAC4E7796BB93BC9F0532A95468C539BA20FF86D7C76ED92227900");
+		/* Both halves of aggregate nonce correspond to point at infinity */
+		check_valid(&[0, 1], &[0, 3], 1, 0, "AE386064B26105404798F75DE2EB9AF5EDA5387B064B83D049CB7C5E08879531");
+
+		/* The signer's pubkey is not in the list of pubkeys */
+		let session_ctx = SessionContext::new(&s_ctx, &aggnonces[0], &pks(&[1, 2]), &[], &msg).unwrap();
+		assert_eq!(sign(&s_ctx, secnonce_txt(secnonces[0]), &sk, &session_ctx), None);
+		/* Aggregate nonce is invalid due wrong tag, 0x04, in the first half */
+		assert!(SessionContext::new(&s_ctx, &aggnonces[2], &pks(&[1, 2, 0]), &[], &msg).is_none());
+		/* Aggregate nonce is invalid because the second half does not correspond to an X coordinate */
+		assert!(SessionContext::new(&s_ctx, &aggnonces[3], &pks(&[1, 2, 0]), &[], &msg).is_none());
+		/* Aggregate nonce is invalid because second half exceeds field size */
+		assert!(SessionContext::new(&s_ctx, &aggnonces[4], &pks(&[1, 2, 0]), &[], &msg).is_none());
+		/* Secnonce is invalid which may indicate nonce reuse */
+		let session_ctx = SessionContext::new(&s_ctx, &aggnonces[0], &pks(&[0, 1, 2]), &[], &msg).unwrap();
+		assert_eq!(sign(&s_ctx, secnonce_txt(secnonces[1]), &sk, &session_ctx), None);
+
+		let check_verify_fail = | psig: &str
+					, nonce_indices: &[usize]
+					, signer_index: usize
+					| {
+			assert!(!partial_sig_verify( &s_ctx
+						   , &hex_arr(psig)
+						   , &pns(nonce_indices)
+						   , &pks(&[0, 1, 2])
+						   , &[]
+						   , &msg
+						   , signer_index
+						   ));
+		};
+		/* Wrong signature (which is equal to the negation of valid signature) */
+		check_verify_fail("FED54434AD4CFE953FC527DC6A5E5BE8F6234907B7C187559557CE87A0541C46", &[0, 1, 2], 0);
+		/* Wrong signer */
+		check_verify_fail("012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB", &[0, 1, 2], 1);
+		/* Signature exceeds group size */
+		check_verify_fail("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141", &[0, 1, 2], 0);
+		/* Invalid pubnonce */
+		check_verify_fail("012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB", &[4, 1, 2], 0);
+	}
+
+	#[test]
+	fn test_tweak() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0327/vectors/tweak_vectors.json */
+		let s_ctx = Secp256k1::new();
+		let sk = sk_txt("7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671");
+		let secnonce = "508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F703935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9";
+		let aggnonce: [u8; 66] = hex_arr("028465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD61037496A3CC86926D452CAFCFD55D25972CA1675D549310DE296BFF42F72EEEA8C9");
+		let msg = msg_sign_verify();
+		/* Key and nonce indices are always [1, 2, 0],
+		 * with the signer at index 2.
+		 */
+		let pubkeys = [ point_txt("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9")
+			      , point_txt("02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659")
+			      , point_txt("03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9")
+			      ];
+		let pubnonces: Vec<[u8; 66]> = [ "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798"
+					       , "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046"
+					       , "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480"
+					       ].iter().map(|s| hex_arr(s)).collect();
+		let tweaks: Vec<[u8; 32]> = [ "E8F791FF9225A2AF0102AFFF4A9A723D9612A682A25EBE79802B263CDFCD83BB"
+					    , "AE2EA797CC0FE72AC5B97B97F3C6957D7E4199A167A58EB08BCAFFDA70AC0455"
+					    , "F52ECBC565B3D8BEA2DFD5B75A4F457E54369809322E4120831626F290FA87E0"
+					    , "1969AD73CC177FA0B4FCED6DF1F7BF9907E665FDE9BA196A74FED0A3CF5AEF9D"
+					    , "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"
+					    ].iter().map(|s| hex_arr(s)).collect();
+		assert_eq!(nonce_agg(&pubnonces), Ok(aggnonce));
+
+		let check_valid = | tweak_indices: &[usize]
+				  , is_xonly: &[bool]
+				  , expected: &str
+				  | {
+			let ts: Vec<([u8; 32], bool)> = tweak_indices.iter()
+				.zip(is_xonly.iter())
+				.map(|(i, x)| (tweaks[*i], *x))
+				.collect();
+			let expected: [u8; 32] = hex_arr(expected);
+			let session_ctx = SessionContext::new(&s_ctx, &aggnonce, &pubkeys, &ts, &msg)
+			.unwrap();
+			assert_eq!( sign(&s_ctx, secnonce_txt(secnonce), &sk, &session_ctx)
+				  , Some(expected)
+				  );
+			assert!(partial_sig_verify(&s_ctx, &expected, &pubnonces, &pubkeys, &ts, &msg, 2));
+		};
+		/* A single x-only tweak */
+		check_valid(&[0], &[true], "E28A5C66E61E178C2BA19DB77B6CF9F7E2F0F56C17918CD13135E60CC848FE91");
+		/* A single plain tweak */
+		check_valid(&[0], &[false], "38B0767798252F21BF5702C48028B095428320F73A4B14DB1E25DE58543D2D2D");
+		/* A plain tweak followed by an x-only tweak */
+		check_valid(&[0, 1], &[false, true], "408A0A21C4A0F5DACAF9646AD6EB6FECD7F7A11F03ED1F48DFFF2185BC2C2408");
+		/* Four tweaks: plain, plain, x-only, x-only. */
+		check_valid(&[0, 1, 2, 3], &[false, false, true, true], "45ABD206E61E3DF2EC9E264A6FEC8292141A633C28586388235541F9ADE75435");
+		/* Four tweaks: x-only, plain, x-only, plain. */
+		check_valid(&[0, 1, 2, 3], &[true, false, true, false], "B255FDCAC27B40C7CE7848E2D3B7BF5EA0ED756DA81565AC804CCCA3E1D5D239");
+
+		/* Tweak is invalid because it exceeds group size */
+		assert!(SessionContext::new(&s_ctx, &aggnonce, &pubkeys, &[(tweaks[4], false)], &msg).is_none());
+	}
+
+	#[test]
+	fn test_sig_agg() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0327/vectors/sig_agg_vectors.json */
+		let s_ctx = Secp256k1::new();
+		let pubkeys = [ point_txt("03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9")
+			      , point_txt("02D2DC6F5DF7C56ACF38C7FA0AE7A759AE30E19B37359DFDE015872324C7EF6E05")
+			      , point_txt("03C7FB101D97FF930ACD0C6760852EF64E69083DE0B06AC6335724754BB4B0522C")
+			      , point_txt("02352433B21E7E05D3B452B81CAE566E06D2E003ECE16D1074AABA4289E0E3D581")
+			      ];
+		let tweaks: Vec<[u8; 32]> = [ "B511DA492182A91B0FFB9A98020D55F260AE86D7ECBD0399C7383D59A5F2AF7C"
+					    , "A815FE049EE3C5AAB66310477FBC8BCCCAC2F3395F59F921C364ACD78A2F48DC"
+					    , "75448A87274B056468B977BE06EB1E9F657577B7320B0A3376EA51FD420D18A8"
+					    ].iter().map(|s| hex_arr(s)).collect();
+		let psigs: Vec<[u8; 32]> = [ "B15D2CD3C3D22B04DAE438CE653F6B4ECF042F42CFDED7C41B64AAF9B4AF53FB"
+					   , "6193D6AC61B354E9105BBDC8937A3454A6D705B6D57322A5A472A02CE99FCB64"
+					   , "9A87D3B79EC67228CB97878B76049B15DBD05B8158D17B5B9114D3C226887505"
+					   , "66F82EA90923689B855D36C6B7E032FB9970301481B99E01CDB4D6AC7C347A15"
+					   , "4F5AEE41510848A6447DCD1BBC78457EF69024944C87F40250D3EF2C25D33EFE"
+					   , "DDEF427BBB847CC027BEFF4EDB01038148917832253EBC355FC33F4A8E2FCCE4"
+					   , "97B890A26C981DA8102D3BC294159D171D72810FDF7C6A691DEF02F0F7AF3FDC"
+					   , "53FA9E08BA5243CBCB0D797C5EE83BC6728E539EB76C2D0BF0F971EE4E909971"
+					   , "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"
+					   ].iter().map(|s| hex_arr(s)).collect();
+		let msg = hex::decode("599C67EA410D005B9DA90817CF03ED3B1C868E4DA4EDF00A5880B0082C237869")
+		.expect("");
+
+		let agg = | key_indices: &[usize]
+			  , tweak_indices: &[usize]
+			  , is_xonly: &[bool]
+			  , aggnonce: &str
+			  , psig_indices: &[usize]
+			  | {
+			let pks: Vec<PublicKey> = key_indices.iter().map(|i| pubkeys[*i]).collect();
+			let ts: Vec<([u8; 32], bool)> = tweak_indices.iter()
+				.zip(is_xonly.iter())
+				.map(|(i, x)| (tweaks[*i], *x))
+				.collect();
+			let ps: Vec<[u8; 32]> = psig_indices.iter().map(|i| psigs[*i]).collect();
+			let session_ctx = SessionContext::new(&s_ctx, &hex_arr(aggnonce), &pks, &ts, &msg)
+			.unwrap();
+			partial_sig_agg(&ps, &session_ctx)
+		};
+
+		assert_eq!( agg(&[0, 1], &[], &[], "0341432722C5CD0268D829C702CF0D1CBCE57033EED201FD335191385227C3210C03D377F2D258B64AADC0E16F26462323D701D286046A2EA93365656AFD9875982B", &[0, 1])
+			  , Ok(hex_arr("041DA22223CE65C92C9A0D6C2CAC828AAF1EEE56304FEC371DDF91EBB2B9EF0912F1038025857FEDEB3FF696F8B99FA4BB2C5812F6095A2E0004EC99CE18DE1E"))
+			  );
+		assert_eq!( agg(&[0, 2], &[], &[], "0224AFD36C902084058B51B5D36676BBA4DC97C775873768E58822F87FE437D792028CB15929099EEE2F5DAE404CD39357591BA32E9AF4E162B8D3E7CB5EFE31CB20", &[2, 3])
+			  , Ok(hex_arr("1069B67EC3D2F3C7C08291ACCB17A9C9B8F2819A52EB5DF8726E17E7D6B52E9F01800260A7E9DAC450F4BE522DE4CE12BA91AEAF2B4279219EF74BE1D286ADD9"))
+			  );
+		assert_eq!( agg(&[0, 2], &[0], &[false], "0208C5C438C710F4F96A61E9FF3C37758814B8C3AE12BFEA0ED2C87FF6954FF186020B1816EA104B4FCA2D304D733E0E19CEAD51303FF6420BFD222335CAA402916D", &[4, 5])
+			  , Ok(hex_arr("5C558E1DCADE86DA0B2F02626A512E30A22CF5255CAEA7EE32C38E9A71A0E9148BA6C0E6EC7683B64220F0298696F1B878CD47B107B81F7188812D593971E0CC"))
+			  );
+		assert_eq!( agg(&[0, 3], &[0, 1, 2], &[true, false, true], "02B5AD07AFCD99B6D92CB433FBD2A28FDEB98EAE2EB09B6014EF0F8197CD58403302E8616910F9293CF692C49F351DB86B25E352901F0E237BAFDA11F1C1CEF29FFD", &[6, 7])
+			  , Ok(hex_arr("839B08820B681DBA8DAF4CC7B104E8F2638F9388F8D7A555DC17B6E6971D7426CE07BF6AB01F1DB50E4E33719295F4094572B79868E440FB3DEFD3FAC1DB589E"))
+			  );
+		/* Partial signature is invalid because it exceeds group size */
+		assert_eq!( agg(&[0, 3], &[0, 1, 2], &[true, false, true], "02B5AD07AFCD99B6D92CB433FBD2A28FDEB98EAE2EB09B6014EF0F8197CD58403302E8616910F9293CF692C49F351DB86B25E352901F0E237BAFDA11F1C1CEF29FFD", &[7, 8])
+			  , Err(InvalidContribution(1))
+			  );
+	}
 }
@@ -21,10 +21,18 @@ pub fn lift_x(x: &[u8; 32]) -> Option<PublicKey> {
 	buf.extend_from_slice(x);
 	PublicKey::from_slice(&buf).ok()
 }
+pub fn has_even_y(p: &PublicKey) -> bool {
+	let ser = p.serialize();
+	ser[0] == 0x02
+}
+/* BIP-340 bytes(P), i.e. the X coordinate only.  */
+pub fn xbytes(p: &PublicKey) -> [u8; 32] {
+	let ser = p.serialize();
+	ser[1..33].try_into().expect("constant bounds")
+}
 
 #[cfg(test)]
 mod test {
-	use hex;
 	use super::*;
 
 	#[test]
@@ -1,9 +1,12 @@
-use secp256k1::PublicKey;
 use secp256k1::Scalar;
 use secp256k1::Secp256k1;
 use secp256k1::Verification;
+use super::bip340::has_even_y;
 use super::bip340::lift_x;
 use super::bip340::tagged_hash;
+use super::tx::OutPoint;
+use super::tx::Transaction;
+use super::tx::TxOut;
 
 pub(crate)
 struct TapLeaf {
@@ -29,7 +32,6 @@ impl TapTree {
 	fn new_two_leaves( version0: u8, script0: Vec<u8>
 			 , version1: u8, script1: Vec<u8>
 			 ) -> Self {
-		use TapTree::TapTreeLeaf;
 		use TapTree::TapTreeBranch;
 		let left = Self::new_from_script(version0, script0);
 		let right = Self::new_from_script(version1, script1);
@@ -44,9 +46,9 @@ impl TapTree {
 	own tweak-the-public-key code.
 	*/
 	pub(crate)
-	fn to_hash(self) -> [u8; 32] {
+	fn into_hash(self) -> [u8; 32] {
 		let (_, h) = taproot_tree_helper(self);
-		return h;
+		h
 	}
 }
 
@@ -59,6 +61,7 @@ struct Info {
 	path: Vec<u8>
 }
 
+pub(crate)
 fn load_compactsize(buf: &mut Vec<u8>, s: usize) {
 	if s <= 0xFC {
 		buf.push(s as u8);
@@ -88,18 +91,56 @@ fn load_compactsize(buf: &mut Vec<u8>, s: usize) {
 	}
 }
 
+/* Inverse of load_compactsize: reads a compactsize
+from `buf` starting at `*pos`, advancing `*pos` past it.
+
+Fails (returns None) if `buf` is too short, or if the
+value is not minimally encoded.
+*/
+pub(crate)
+fn read_compactsize(buf: &[u8], pos: &mut usize) -> Option<u64> {
+	let first = *buf.get(*pos)?;
+	let (len, min) = match first {
+		0xFD => (2, 0xFD),
+		0xFE => (4, 0x10000),
+		0xFF => (8, 0x100000000),
+		_ => {
+			*pos += 1;
+			return Some(first as u64);
+		}
+	};
+	let bytes = buf.get(*pos + 1..*pos + 1 + len)?;
+	let mut s: u64 = 0;
+	for (i, b) in bytes.iter().enumerate() {
+		s |= (*b as u64) << (8 * i);
+	}
+	if s < min {
+		return None;
+	}
+	*pos += 1 + len;
+	Some(s)
+}
+
+/* The tagged hash "TapLeaf" of the leaf version and
+the serialized script.
+*/
+pub(crate)
+fn tapleaf_hash(version: u8, script: &[u8]) -> [u8; 32] {
+	let mut buf = Vec::new();
+	buf.push(version);
+	/* ser_script  */
+	load_compactsize(&mut buf, script.len());
+	buf.extend_from_slice(script);
+
+	tagged_hash("TapLeaf", &buf)
+}
+
 fn taproot_tree_helper(script_tree: TapTree) -> (Vec<Info>, [u8; 32]) {
 	use TapTree::TapTreeLeaf;
 	use TapTree::TapTreeBranch;
 	match script_tree {
 		TapTreeLeaf(TapLeaf{version, script}) => {
-			let mut buf = Vec::new();
-			buf.push(version);
-			/* ser_script  */
-			load_compactsize(&mut buf, script.len());
-			buf.extend_from_slice(&script);
-
-			let h = tagged_hash("TapLeaf", &buf);
+			let h = tapleaf_hash(version, &script);
 
 			( vec!(Info{leaf: TapLeaf{version, script}, path: Vec::new()})
 			, h
@@ -162,7 +203,7 @@ fn taproot_tweak_pubkey<C>( s_ctx: &Secp256k1<C>
 	let capital_p = lift_x(pubkey)?;
 
 	// Q = point_add(P, point_mul(G, t))
-	let capital_q = capital_p.mul_tweak(s_ctx, &t).ok()?;
+	let capital_q = capital_p.add_exp_tweak(s_ctx, &t).ok()?;
 
 	let capital_q_ser = capital_q.serialize();
 	let capital_q_x = capital_q_ser[1..33].try_into().expect("constant array indices");
@@ -172,36 +213,568 @@ fn taproot_tweak_pubkey<C>( s_ctx: &Secp256k1<C>
 		 );
 	Some(rv)
 }
-// TODO: factor out this common code in BIP-327 and BIP-341
-fn has_even_y(q: &PublicKey) -> bool {
-	let ser = q.serialize();
-	return ser[0] == 0x02;
-}
 
+/* The control block for spending the `script_num`th
+leaf (in the order returned by taproot_tree_helper,
+i.e. depth-first, left to right) of the given tree.
+This is the last witness item of BIP-341
+taproot_sign_script.
+*/
 pub(crate)
-fn taproot_output_script<C>( s_ctx: &Secp256k1<C>
+fn taproot_control_block<C>( s_ctx: &Secp256k1<C>
 			   , internal_pubkey: &[u8; 32]
-			   , script_tree: Option<TapTree>
+			   , script_tree: TapTree
+			   , script_num: usize
 			   ) -> Option<Vec<u8>>
 				where C: Verification {
-	let h = match script_tree {
-		None => { Vec::new() },
-		Some(t) => {
-			let (_, h) = taproot_tree_helper(t);
-			h.to_vec()
-		}
+	let (mut info, h) = taproot_tree_helper(script_tree);
+	if script_num >= info.len() {
+		return None;
+	}
+	let Info{leaf: TapLeaf{version, script: _}, path} = info.swap_remove(script_num);
+	let (output_pubkey_y_parity, _) = taproot_tweak_pubkey( s_ctx
+							      , internal_pubkey
+							      , &h
+							      )?;
+	let parity = match output_pubkey_y_parity {
+		Bit::Bit0 => 0,
+		Bit::Bit1 => 1
 	};
-	let (_, output_pubkey) = taproot_tweak_pubkey( s_ctx
-						     , internal_pubkey
-						     , &h
-						     )?;
 	let mut buf = Vec::new();
-	buf.extend_from_slice(&[ 0x51 // SegWit v1
-			       , 0x20 // Push 32 bytes
-			       ]);
-	buf.extend_from_slice(&output_pubkey);
-	return Some(buf);
+	buf.push(version + parity);
+	buf.extend_from_slice(internal_pubkey);
+	buf.extend_from_slice(&path);
+	Some(buf)
 }
 
 pub(crate)
 const TAPROOT_TAPLEAF_VERSION: u8 = 0xC0;
+
+pub(crate)
+const SIGHASH_DEFAULT: u8 = 0x00;
+pub(crate)
+const SIGHASH_ALL: u8 = 0x01;
+pub(crate)
+const SIGHASH_NONE: u8 = 0x02;
+pub(crate)
+const SIGHASH_SINGLE: u8 = 0x03;
+pub(crate)
+const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/* The only hash_type values BIP-341 accepts.  */
+pub(crate)
+fn is_valid_hash_type(hash_type: u8) -> bool {
+	matches!(hash_type, 0x00..=0x03 | 0x81..=0x83)
+}
+
+fn sha256(buf: &[u8]) -> [u8; 32] {
+	hashes::sha2::sha256::hash(buf).into_bytes()
+}
+
+fn ser_outpoint(buf: &mut Vec<u8>, outpoint: &OutPoint) {
+	buf.extend_from_slice(&outpoint.txid);
+	buf.extend_from_slice(&outpoint.vout.to_le_bytes());
+}
+
+fn ser_script(buf: &mut Vec<u8>, script: &[u8]) {
+	load_compactsize(buf, script.len());
+	buf.extend_from_slice(script);
+}
+
+fn ser_txout(buf: &mut Vec<u8>, txout: &TxOut) {
+	buf.extend_from_slice(&txout.value.to_le_bytes());
+	ser_script(buf, &txout.script_pubkey);
+}
+
+/* The ext_flag = 1 extension of the signature message
+for tapscript, from BIP-342.
+key_version is always 0x00.
+*/
+pub(crate)
+struct ScriptPath<'a> {
+	pub(crate) tapleaf_hash: &'a [u8; 32],
+	pub(crate) codesep_pos: u32
+}
+
+/* BIP-341 SigMsg(hash_type, ext_flag), plus the BIP-342
+extension if `script_path` is given.
+
+`prevouts` are the outputs being spent by each input of
+`tx`, in the same order.
+`annex`, if given, must include the 0x50 prefix.
+
+Fails (returns None) if the hash_type is invalid, if
+`input_index` is not an input of `tx`, if `prevouts`
+does not match the inputs of `tx`, or if hash_type is
+SIGHASH_SINGLE without a corresponding output.
+*/
+pub(crate)
+fn signature_message( tx: &Transaction
+		    , input_index: usize
+		    , prevouts: &[TxOut]
+		    , hash_type: u8
+		    , annex: Option<&[u8]>
+		    , script_path: Option<ScriptPath>
+		    ) -> Option<Vec<u8>> {
+	if !is_valid_hash_type(hash_type) {
+		return None;
+	}
+	if input_index >= tx.input.len() || prevouts.len() != tx.input.len() {
+		return None;
+	}
+	let output_type = hash_type & 0x03;
+	let anyonecanpay = (hash_type & SIGHASH_ANYONECANPAY) != 0;
+	if output_type == SIGHASH_SINGLE && input_index >= tx.output.len() {
+		return None;
+	}
+
+	let mut buf = Vec::new();
+
+	// Control
+	buf.push(hash_type);
+
+	// Transaction data
+	buf.extend_from_slice(&tx.version.to_le_bytes());
+	buf.extend_from_slice(&tx.lock_time.to_le_bytes());
+	if !anyonecanpay {
+		let mut prevouts_buf = Vec::new();
+		let mut amounts_buf = Vec::new();
+		let mut scriptpubkeys_buf = Vec::new();
+		let mut sequences_buf = Vec::new();
+		for (txin, prevout) in tx.input.iter().zip(prevouts.iter()) {
+			ser_outpoint(&mut prevouts_buf, &txin.prevout);
+			amounts_buf.extend_from_slice(&prevout.value.to_le_bytes());
+			ser_script(&mut scriptpubkeys_buf, &prevout.script_pubkey);
+			sequences_buf.extend_from_slice(&txin.sequence.to_le_bytes());
+		}
+		buf.extend_from_slice(&sha256(&prevouts_buf));
+		buf.extend_from_slice(&sha256(&amounts_buf));
+		buf.extend_from_slice(&sha256(&scriptpubkeys_buf));
+		buf.extend_from_slice(&sha256(&sequences_buf));
+	}
+	if output_type != SIGHASH_NONE && output_type != SIGHASH_SINGLE {
+		let mut outputs_buf = Vec::new();
+		for txout in tx.output.iter() {
+			ser_txout(&mut outputs_buf, txout);
+		}
+		buf.extend_from_slice(&sha256(&outputs_buf));
+	}
+
+	// Data about this input
+	let ext_flag = if script_path.is_some() { 1 } else { 0 };
+	let annex_present = if annex.is_some() { 1 } else { 0 };
+	buf.push(ext_flag * 2 + annex_present);
+	if anyonecanpay {
+		let txin = &tx.input[input_index];
+		let prevout = &prevouts[input_index];
+		ser_outpoint(&mut buf, &txin.prevout);
+		buf.extend_from_slice(&prevout.value.to_le_bytes());
+		ser_script(&mut buf, &prevout.script_pubkey);
+		buf.extend_from_slice(&txin.sequence.to_le_bytes());
+	} else {
+		buf.extend_from_slice(&(input_index as u32).to_le_bytes());
+	}
+	if let Some(annex) = annex {
+		let mut annex_buf = Vec::new();
+		ser_script(&mut annex_buf, annex);
+		buf.extend_from_slice(&sha256(&annex_buf));
+	}
+
+	// Data about this output
+	if output_type == SIGHASH_SINGLE {
+		let mut output_buf = Vec::new();
+		ser_txout(&mut output_buf, &tx.output[input_index]);
+		buf.extend_from_slice(&sha256(&output_buf));
+	}
+
+	// BIP-342 extension
+	if let Some(ScriptPath{tapleaf_hash, codesep_pos}) = script_path {
+		buf.extend_from_slice(tapleaf_hash);
+		buf.push(0x00); // key_version
+		buf.extend_from_slice(&codesep_pos.to_le_bytes());
+	}
+
+	Some(buf)
+}
+
+/* The message that is actually signed, i.e.
+hash_TapSighash(0x00 || SigMsg(hash_type, ext_flag)).
+*/
+pub(crate)
+fn signature_hash( tx: &Transaction
+		 , input_index: usize
+		 , prevouts: &[TxOut]
+		 , hash_type: u8
+		 , annex: Option<&[u8]>
+		 , script_path: Option<ScriptPath>
+		 ) -> Option<[u8; 32]> {
+	let mut buf = Vec::new();
+	buf.push(0x00); // epoch
+	buf.extend(signature_message( tx
+				    , input_index
+				    , prevouts
+				    , hash_type
+				    , annex
+				    , script_path
+				    )?);
+	Some(tagged_hash("TapSighash", &buf))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::tx::OutPoint;
+	use super::super::tx::TxIn;
+
+	/* Only handles non-SegWit serialization, which is all
+	 * the test vectors need.
+	 */
+	fn tx_txt(tx_s: &str) -> Transaction {
+		let buf = hex::decode(tx_s)
+		.expect("Test input must be hex");
+		let mut pos = 0;
+		let mut take = |n: usize| -> &[u8] {
+			pos += n;
+			&buf[pos - n..pos]
+		};
+		let version = u32::from_le_bytes(take(4).try_into().unwrap());
+		let n_in = take(1)[0];
+		let mut input = Vec::new();
+		for _ in 0..n_in {
+			let txid = take(32).try_into().unwrap();
+			let vout = u32::from_le_bytes(take(4).try_into().unwrap());
+			let script_len = take(1)[0] as usize;
+			let script_sig = take(script_len).to_vec();
+			let sequence = u32::from_le_bytes(take(4).try_into().unwrap());
+			input.push(TxIn{
+				prevout: OutPoint{txid, vout},
+				script_sig,
+				sequence,
+				witness: Vec::new()
+			});
+		}
+		let n_out = take(1)[0];
+		let mut output = Vec::new();
+		for _ in 0..n_out {
+			let value = u64::from_le_bytes(take(8).try_into().unwrap());
+			let script_len = take(1)[0] as usize;
+			let script_pubkey = take(script_len).to_vec();
+			output.push(TxOut{value, script_pubkey});
+		}
+		let lock_time = u32::from_le_bytes(take(4).try_into().unwrap());
+		Transaction{version, lock_time, input, output}
+	}
+
+	fn txout_txt(script_pubkey_s: &str, value: u64) -> TxOut {
+		TxOut{
+			value,
+			script_pubkey: hex::decode(script_pubkey_s)
+			.expect("Test input must be hex")
+		}
+	}
+
+	/* https://github.com/bitcoin/bips/blob/master/bip-0341/wallet-test-vectors.json
+	 * keyPathSpending
+	 */
+	fn wallet_vector_tx() -> (Transaction, Vec<TxOut>) {
+		let tx = tx_txt(concat!(
+			"02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b5596311",
+			"5f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6de",
+			"f61273e127517d44759b6dafdd990000000000fffffffff8e1f5833843336892",
+			"28c5d28eac13366be082dc57441760d957275419a418420000000000ffffffff",
+			"f0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b",
+			"0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd",
+			"3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa",
+			"29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b",
+			"88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c940100000000",
+			"00000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7ea",
+			"dfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a9966772",
+			"0b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b0000000019",
+			"76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb000000",
+			"0020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab",
+			"962b0065cd1d"
+		));
+		let prevouts = vec!(
+			txout_txt("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343", 420000000),
+			txout_txt("5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3", 462000000),
+			txout_txt("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac", 294000000),
+			txout_txt("5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e", 504000000),
+			txout_txt("512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605", 630000000),
+			txout_txt("00147dd65592d0ab2fe0d0257d571abf032cd9db93dc", 378000000),
+			txout_txt("512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831", 672000000),
+			txout_txt("5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5", 546000000),
+			txout_txt("512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220", 588000000)
+		);
+		(tx, prevouts)
+	}
+
+	fn check_keypath( input_index: usize
+			, hash_type: u8
+			, sig_msg_s: &str
+			, sig_hash_s: &str
+			) {
+		let (tx, prevouts) = wallet_vector_tx();
+		let sig_msg = signature_message( &tx
+					       , input_index
+					       , &prevouts
+					       , hash_type
+					       , None
+					       , None
+					       )
+		.expect("Should succeed");
+		/* The vectors' sigMsg includes the 0x00 epoch.  */
+		assert_eq!(format!("00{}", hex::encode(sig_msg)), sig_msg_s);
+		let sig_hash = signature_hash( &tx
+					     , input_index
+					     , &prevouts
+					     , hash_type
+					     , None
+					     , None
+					     )
+		.expect("Should succeed");
+		assert_eq!(hex::encode(sig_hash), sig_hash_s);
+	}
+
+	#[test]
+	fn test_signature_hash_keypath() {
+		/* https://github.com/bitcoin/bips/blob/master/bip-0341/wallet-test-vectors.json
+		 * keyPathSpending
+		 */
+		check_keypath( 0
+			     , 0x03
+			     , concat!(
+				"0003020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036ea",
+				"eb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d",
+				"1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b",
+				"1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281",
+				"d8e6b05e400e6c3a957e0000000000d0418f0e9a36245b9a50ec87f8bf5be5bc",
+				"ae434337b87139c3a5b1f56e33cba0"
+			       )
+			     , "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555"
+			     );
+		check_keypath( 1
+			     , 0x83
+			     , concat!(
+				"0083020000000065cd1d00d7b7cab57b1393ace2d064f4d4a2cb8af6def61273",
+				"e127517d44759b6dafdd9900000000808f891b00000000225120147c9c57132f",
+				"6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3ffffffffffce",
+				"f8fb4ca7efc5433f591ecfc57391811ce1e186a3793024def5c884cba51d"
+			       )
+			     , "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d"
+			     );
+		check_keypath( 3
+			     , 0x01
+			     , concat!(
+				"0001020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036ea",
+				"eb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d",
+				"1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b",
+				"1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281",
+				"d8e6b05e400e6c3a957ea2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3c",
+				"c37dcbad7f90d4eb4bc50003000000"
+			       )
+			     , "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669"
+			     );
+		check_keypath( 4
+			     , 0x00
+			     , concat!(
+				"0000020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036ea",
+				"eb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d",
+				"1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b",
+				"1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281",
+				"d8e6b05e400e6c3a957ea2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3c",
+				"c37dcbad7f90d4eb4bc50004000000"
+			       )
+			     , "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef"
+			     );
+		check_keypath( 6
+			     , 0x02
+			     , concat!(
+				"0002020000000065cd1de3b33bb4ef3a52ad1fffb555c0d82828eb22737036ea",
+				"eb02a235d82b909c4c3f58a6964a4f5f8f0b642ded0a8a553be7622a719da71d",
+				"1f5befcefcdee8e0fde623ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b",
+				"1e720cfbb38fbdd52e2118959c7221ab5ce9e26c3cd67b22c24f8baa54bac281",
+				"d8e6b05e400e6c3a957e0006000000"
+			       )
+			     , "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85"
+			     );
+		check_keypath( 7
+			     , 0x82
+			     , concat!(
+				"0082020000000065cd1d00e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb6",
+				"77a632a74ef7eadfd4eabf00000000804c8b2000000000225120712447206d7a",
+				"5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5ffffffff"
+			       )
+			     , "cd292de50313804dabe4685e83f923d2969577191a3e1d2882220dca88cbeb10"
+			     );
+		check_keypath( 8
+			     , 0x81
+			     , concat!(
+				"0081020000000065cd1da2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3c",
+				"c37dcbad7f90d4eb4bc500a778eb6a263dc090464cd125c466b5a99667720b1c",
+				"110468831d058aa1b82af101000000002b0c230000000022512077e30a5522dd",
+				"9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220ffffffff"
+			       )
+			     , "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2"
+			     );
+	}
+
+	/* `SighashCache::taproot_signature_hash` of the
+	 * `bitcoin` crate, for cross-checking.
+	 */
+	#[cfg(feature = "bitcoin")]
+	fn bitcoin_signature_hash( tx: &Transaction
+				 , input_index: usize
+				 , prevouts: &[TxOut]
+				 , hash_type: u8
+				 , annex: Option<&[u8]>
+				 , script_path: Option<&ScriptPath>
+				 ) -> [u8; 32] {
+		use bitcoin::hashes::Hash;
+		use bitcoin::sighash::Annex;
+		use bitcoin::sighash::Prevouts;
+		use bitcoin::sighash::SighashCache;
+		use bitcoin::sighash::TapSighashType;
+		use super::super::bitcoin_interop;
+
+		let tx = bitcoin_interop::to_transaction(tx).unwrap();
+		let prevouts: Vec<bitcoin::TxOut> = prevouts.iter().map(bitcoin_interop::to_txout).collect();
+		SighashCache::new(&tx).taproot_signature_hash(
+			input_index,
+			&Prevouts::All(&prevouts),
+			annex.map(|annex| Annex::new(annex).unwrap()),
+			script_path.map(|p| ( bitcoin_interop::to_tap_leaf_hash(p.tapleaf_hash)
+					    , p.codesep_pos
+					    )),
+			TapSighashType::from_consensus_u8(hash_type).unwrap()
+		).unwrap().to_byte_array()
+	}
+
+	/* Same transaction as the wallet test vectors, but
+	 * there are no official vectors for script path and
+	 * annex.
+	 * The expected values were computed with
+	 * `SighashCache::taproot_signature_hash` of rust-bitcoin
+	 * 0.31; with the `bitcoin` feature, each case is
+	 * recomputed with it, see `bitcoin_signature_hash`.
+	 * The tapleaf hash is the timelock tapleaf hash from
+	 * swap-in-potentiam.md Address Generation Test Vector 1,
+	 * and the annex, when present, is 50deadbeef.
+	 */
+	fn check_extended( input_index: usize
+			 , hash_type: u8
+			 , annex: Option<&[u8]>
+			 , script_path: Option<(&str, u32)>
+			 , sig_hash_s: &str
+			 ) {
+		let (tx, prevouts) = wallet_vector_tx();
+		let tapleaf_hash: Option<[u8; 32]> = script_path.map(|(h, _)| {
+			hex::decode(h).expect("Test input must be hex")
+			.try_into().expect("Test input must be 32 bytes")
+		});
+		let script_path = match (&tapleaf_hash, script_path) {
+			(Some(tapleaf_hash), Some((_, codesep_pos))) => Some(ScriptPath{
+				tapleaf_hash,
+				codesep_pos
+			}),
+			_ => None
+		};
+		#[cfg(feature = "bitcoin")]
+		assert_eq!( hex::encode(bitcoin_signature_hash( &tx, input_index, &prevouts
+							      , hash_type, annex, script_path.as_ref()
+							      ))
+			  , sig_hash_s
+			  );
+		let sig_hash = signature_hash( &tx
+					     , input_index
+					     , &prevouts
+					     , hash_type
+					     , annex
+					     , script_path
+					     )
+		.expect("Should succeed");
+		assert_eq!(hex::encode(sig_hash), sig_hash_s);
+	}
+
+	#[test]
+	fn test_signature_hash_extended() {
+		let leaf = "b15e91cdc464dfdb2f19faa6fcb96d5fb58773452f6afcfe251ed1cf83f1576a";
+		let annex = hex::decode("50deadbeef").expect("Test input must be hex");
+
+		check_extended( 1, 0x00, None, Some((leaf, 0xFFFFFFFF))
+			      , "28a196fb2d9e520634d38e5b216fe163a0f4b198f56c93afd6f00c0bd83441c4"
+			      );
+		check_extended( 1, 0x01, None, Some((leaf, 0xFFFFFFFF))
+			      , "e33778deab0c4805aa4410f6e49fd0393081521309afe55c42f74ce24264e40a"
+			      );
+		check_extended( 3, 0x02, None, Some((leaf, 0xFFFFFFFF))
+			      , "dd9e8ae6a321e8972f7565942087a46f427138b3255a6bcc171e214907d9d820"
+			      );
+		check_extended( 1, 0x03, None, Some((leaf, 0xFFFFFFFF))
+			      , "8379e9a472ed0375b8652867093af20dbc2c57ccc0b4937c8437700c013cc2e6"
+			      );
+		check_extended( 4, 0x81, None, Some((leaf, 0xFFFFFFFF))
+			      , "bcd30edf4e44755998eb04183d6c0d3d03af2f491948bc1de5e8d7aa8ed78664"
+			      );
+		check_extended( 6, 0x82, None, Some((leaf, 0xFFFFFFFF))
+			      , "6c4b3b1883fbe4dcf38b0aaaaef9d660a221919522269b0e2183411deee0f96c"
+			      );
+		check_extended( 0, 0x83, None, Some((leaf, 7))
+			      , "15f240306ba0fc933d95fff1d722eb99ba8cd718fbb4123c03da238126774e64"
+			      );
+		check_extended( 7, 0x00, Some(&annex), Some((leaf, 0xFFFFFFFF))
+			      , "fd6e9b44ed1e9115813d2f9cd4e47eedc90118e6f10ecadaa58320ed31011caf"
+			      );
+		check_extended( 1, 0x83, Some(&annex), Some((leaf, 3))
+			      , "c878ab0f1bf4ca0c1cdd7cf6d53eeed6de5894056b38dca0aa987463c302ae2c"
+			      );
+		check_extended( 0, 0x03, Some(&annex), None
+			      , "a87a6b625015ffaa8575cf45555b0f993727ee612e09d57886aa77d5f0a11fee"
+			      );
+		check_extended( 2, 0x00, Some(&annex), None
+			      , "a0808cdf469f9d4bf77674f6ffae836a9ba1aa0fba172157a65300182bcb368c"
+			      );
+	}
+
+	#[test]
+	fn test_compactsize() {
+		for s in [ 0usize, 1, 0xFC, 0xFD, 0xFFFF, 0x10000
+			 , 0xFFFFFFFF, 0x100000000, usize::MAX
+			 ] {
+			let mut buf = vec!(0xAA);
+			load_compactsize(&mut buf, s);
+			buf.push(0xBB);
+			let mut pos = 1;
+			assert_eq!(read_compactsize(&buf, &mut pos), Some(s as u64));
+			assert_eq!(pos, buf.len() - 1);
+			/* Truncated.  */
+			let mut pos = 1;
+			assert_eq!(read_compactsize(&buf[0..buf.len() - 2], &mut pos), None);
+			assert_eq!(pos, 1);
+		}
+		/* Not minimally encoded.  */
+		for buf in [ &[0xFD, 0xFC, 0x00][..]
+			   , &[0xFE, 0xFF, 0xFF, 0x00, 0x00][..]
+			   , &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00][..]
+			   ] {
+			let mut pos = 0;
+			assert_eq!(read_compactsize(buf, &mut pos), None);
+		}
+	}
+
+	#[test]
+	fn test_signature_hash_failures() {
+		let (tx, prevouts) = wallet_vector_tx();
+		/* Undefined hash types.  */
+		for hash_type in [0x04, 0x80, 0x84, 0xFF] {
+			assert!(signature_hash(&tx, 0, &prevouts, hash_type, None, None).is_none());
+		}
+		/* Input index out of range.  */
+		assert!(signature_hash(&tx, 9, &prevouts, SIGHASH_DEFAULT, None, None).is_none());
+		/* Prevouts do not match the inputs.  */
+		assert!(signature_hash(&tx, 0, &prevouts[0..8], SIGHASH_DEFAULT, None, None).is_none());
+		/* SIGHASH_SINGLE without a corresponding output.  */
+		assert!(signature_hash(&tx, 2, &prevouts, SIGHASH_SINGLE, None, None).is_none());
+		assert!(signature_hash(&tx, 2, &prevouts, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, None, None).is_none());
+		assert!(signature_hash(&tx, 2, &prevouts, SIGHASH_ALL, None, None).is_some());
+	}
+}
@@ -11,13 +11,23 @@ const TABLE: [char; 32] =
 const BECH32_CONSTANT: u32 = 0x1;
 /* BIP-350 */
 const BECH32M_CONSTANT: u32 = 0x2bc830a3;
-fn network_to_hrp(n: Network) -> String {
+fn network_to_hrp(n: &Network) -> String {
 	match n {
 		Network::Mainnet => "bc".to_string(),
 		Network::Testnet => "tb".to_string(),
-		Network::Regtest => "bcrt".to_string()
+		Network::Regtest => "bcrt".to_string(),
+		Network::Signet => "tb".to_string(),
+		Network::Custom(hrp) => hrp.to_ascii_lowercase()
 	}
 }
+/* BIP-173: the HRP is 1 to 83 US-ASCII characters in
+the range 33 to 126.
+*/
+fn is_valid_hrp(hrp: &str) -> bool {
+	!hrp.is_empty()
+	&& hrp.len() <= 83
+	&& hrp.chars().all(|c| ('!'..='~').contains(&c))
+}
 /* BIP-173 bech32_hrp_expand function.  */
 fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
 	let mut buf = Vec::new();
@@ -33,9 +43,10 @@ fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
 	However, we do need some external code to ensure that
 	the input HRP string *is* indeed US-ASCII.
 
-	Fortunately, for our specific use-case, we only need a
-	BECH32m *en*coder, not a decoder, and we know the HRP
-	is indeed US-ASCII (see network_to_hrp func above).
+	When encoding, encode_segwit checks the HRP with
+	is_valid_hrp (custom HRPs come from the caller).
+	When decoding, decode_bech32 checks that the entire
+	input is US-ASCII before calling this.
 	*/
 	for mut c in hrp.chars() {
 		c.make_ascii_lowercase();
@@ -56,9 +67,9 @@ fn bech32_polymod(values: &[u8]) -> u32 {
 	for v in values {
 		let b = chk >> 25;
 		chk = ((chk & 0x1ffffff) << 5) ^ (*v as u32);
-		for i in 0..=4 {
+		for (i, g) in GEN.iter().enumerate() {
 			if ((b >> i) & 1) == 1 {
-				chk = chk ^ GEN[i];
+				chk ^= g;
 			}
 		}
 	}
@@ -78,8 +89,8 @@ fn bech32_create_checksum( constant: u32
 	let polymod = bech32_polymod(&values) ^ constant;
 
 	let mut rv: [u8; 6] = [0,0,0,0,0,0];
-	for i in 0..=5 {
-		rv[i] = ((polymod >> (5 * (5 - i))) & 0x1F) as u8;
+	for (i, c) in rv.iter_mut().enumerate() {
+		*c = ((polymod >> (5 * (5 - i))) & 0x1F) as u8;
 	}
 	rv
 }
@@ -92,17 +103,17 @@ fn u8_to_u5(u8arr: &[u8]) -> Vec<u8> {
 
 	for byte in u8arr {
 		word = (word << 8) | (*byte as u16);
-		bitoff = bitoff + 8;
+		bitoff += 8;
 
 		while bitoff >= 0 {
 			let u5 = ((word >> bitoff) & 0x1F) as u8;
 			rv.push(u5);
-			bitoff = bitoff - 5;
+			bitoff -= 5;
 		}
 	}
 	if bitoff > -5 {
-		word = word << 8;
-		bitoff = bitoff + 8;
+		word <<= 8;
+		bitoff += 8;
 
 		let u5 = ((word >> bitoff) & 0x1F) as u8;
 		rv.push(u5);
@@ -117,11 +128,10 @@ fn u5_to_bech32(u5arr: &[u8]) -> String {
 		.collect()
 }
 
-fn encode_segwit_core( n: Network
+fn encode_segwit_core( hrp: String
 		     , version: u8
 		     , program: &[u8]
 		     ) -> String {
-	let hrp = network_to_hrp(n);
 	let constant = if version == 0 {
 		BECH32_CONSTANT
 	} else {
@@ -148,7 +158,8 @@ nor the OP_PUSH opcode.
 It is, strictly, the pushed program in the SegWit
 template.
 
-This will fail only if `version` is invalid.
+This will fail only if `version` is invalid, or if
+the HRP of a `Network::Custom` is invalid.
 'version' must be between 0 to 16, inclusive.
 
 If 'version' is 0, then the BIP-173 "bech32" encoding
@@ -165,13 +176,205 @@ fn encode_segwit( n: Network
 	if version > 16 {
 		return None;
 	}
-	Some(encode_segwit_core(n, version, program))
+	let hrp = network_to_hrp(&n);
+	if !is_valid_hrp(&hrp) {
+		return None;
+	}
+	let address = encode_segwit_core(hrp, version, program);
+	/* BIP-173: at most 90 characters.  */
+	if address.len() > 90 {
+		return None;
+	}
+	Some(address)
+}
+
+fn hrp_to_network(hrp: &str) -> Option<Network> {
+	match hrp {
+		"bc" => Some(Network::Mainnet),
+		"tb" => Some(Network::Testnet),
+		"bcrt" => Some(Network::Regtest),
+		_ => None
+	}
+}
+
+fn bech32_to_u5(c: char) -> Option<u8> {
+	TABLE.iter()
+		.position(|t| *t == c)
+		.map(|i| i as u8)
+}
+
+/* BIP-173 bech32_verify_checksum, except it returns the
+constant that the checksum matched, if any.
+*/
+fn bech32_verify_checksum( hrp: &str
+			 , data_u5: &[u8]
+			 ) -> Option<u32> {
+	let mut values = Vec::new();
+	values.extend(bech32_hrp_expand(hrp));
+	values.extend(data_u5);
+
+	match bech32_polymod(&values) {
+		BECH32_CONSTANT => Some(BECH32_CONSTANT),
+		BECH32M_CONSTANT => Some(BECH32M_CONSTANT),
+		_ => None
+	}
+}
+
+/* Inverse of u8_to_u5.
+BIP-173 requires that any incomplete group at the end
+is at most 4 bits, all of them 0.
+*/
+fn u5_to_u8(u5arr: &[u8]) -> Option<Vec<u8>> {
+	let mut rv: Vec<u8> = Vec::new();
+
+	let mut bits: usize = 0;
+	let mut word: u16 = 0;
+
+	for u5 in u5arr {
+		word = (word << 5) | (*u5 as u16);
+		bits += 5;
+
+		if bits >= 8 {
+			bits -= 8;
+			rv.push(((word >> bits) & 0xFF) as u8);
+		}
+	}
+	if bits >= 5 || (word & ((1 << bits) - 1)) != 0 {
+		return None;
+	}
+
+	Some(rv)
+}
+
+/* BIP-173 bech32 decoding, returning the lowercased HRP,
+the data part (without the checksum) as 5-bit values,
+and the checksum constant.
+*/
+fn decode_bech32(s: &str) -> Option<(String, Vec<u8>, u32)> {
+	/* BIP-173: overall length at most 90 characters,
+	US-ASCII in the range 33 to 126.
+	*/
+	if s.len() > 90 {
+		return None;
+	}
+	if !s.chars().all(|c| ('!'..='~').contains(&c)) {
+		return None;
+	}
+	/* BIP-173: must not mix upper and lower case.  */
+	let has_lower = s.chars().any(|c| c.is_ascii_lowercase());
+	let has_upper = s.chars().any(|c| c.is_ascii_uppercase());
+	if has_lower && has_upper {
+		return None;
+	}
+	let s = s.to_ascii_lowercase();
+
+	/* BIP-173: the separator is the *last* '1', the HRP
+	is at least 1 character, and the data part is at
+	least 6 characters (the checksum).
+	*/
+	let pos = s.rfind('1')?;
+	if pos < 1 || pos + 7 > s.len() {
+		return None;
+	}
+	let hrp = &s[0..pos];
+	let data_u5 = s[pos + 1..].chars()
+		.map(bech32_to_u5)
+		.collect::<Option<Vec<u8>>>()?;
+
+	let constant = bech32_verify_checksum(hrp, &data_u5)?;
+
+	Some(( hrp.to_string()
+	     , data_u5[0..data_u5.len() - 6].to_vec()
+	     , constant
+	     ))
+}
+
+/* Checks everything except the HRP, returning it
+together with the SegWit version and the program.
+*/
+fn decode_segwit_core(address: &str) -> Option<(String, u8, Vec<u8>)> {
+	let (hrp, data_u5, constant) = decode_bech32(address)?;
+
+	if data_u5.is_empty() {
+		return None;
+	}
+	let version = data_u5[0];
+	if version > 16 {
+		return None;
+	}
+	/* BIP-350: version 0 uses bech32, all others use
+	bech32m.
+	*/
+	let expected_constant = if version == 0 {
+		BECH32_CONSTANT
+	} else {
+		BECH32M_CONSTANT
+	};
+	if constant != expected_constant {
+		return None;
+	}
+
+	let program = u5_to_u8(&data_u5[1..])?;
+	if program.len() < 2 || program.len() > 40 {
+		return None;
+	}
+	/* BIP-141: version 0 programs are P2WPKH or P2WSH.  */
+	if version == 0 && program.len() != 20 && program.len() != 32 {
+		return None;
+	}
+
+	Some((hrp, version, program))
+}
+
+/** Inverse of `encode_segwit`, returning the network,
+the SegWit version, and the program.
+
+As signet and testnet share the same HRP, signet
+addresses are returned as `Network::Testnet`, and
+addresses with any HRP other than mainnet, testnet, or
+regtest fail.
+Use `decode_segwit_for_network` for those.
+
+This will fail if the address is not a valid BIP-173
+or BIP-350 SegWit address of a known network:
+* Invalid characters, mixed case, or longer than 90
+  characters.
+* Wrong checksum, including a "bech32" checksum on a
+  non-0 version or a "bech32m" checksum on version 0.
+* Version above 16.
+* Program shorter than 2 or longer than 40 bytes, or
+  neither 20 nor 32 bytes for version 0.
+* Unknown HRP.
+*/
+pub(crate)
+fn decode_segwit(address: &str) -> Option<(Network, u8, Vec<u8>)> {
+	let (hrp, version, program) = decode_segwit_core(address)?;
+	let network = hrp_to_network(&hrp)?;
+
+	Some((network, version, program))
+}
+
+/** Like `decode_segwit`, but only accepts addresses
+for the given network, returning the SegWit version
+and the program.
+This works for any network, including signet and
+`Network::Custom`.
+*/
+pub(crate)
+fn decode_segwit_for_network( n: &Network
+			    , address: &str
+			    ) -> Option<(u8, Vec<u8>)> {
+	let (hrp, version, program) = decode_segwit_core(address)?;
+	if hrp != network_to_hrp(n) {
+		return None;
+	}
+
+	Some((version, program))
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use hex;
 
 	fn test_segwit( n: Network
 		      , v: u8
@@ -233,4 +436,183 @@ mod tests {
 			   , "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
 			   );
 	}
+
+	fn test_decode( n: Network
+		      , v: u8
+		      , program: &str
+		      , address: &str
+		      ) {
+		let program = hex::decode(program)
+		.expect("program should be hex");
+		assert_eq!(
+			decode_segwit(address),
+			Some((n, v, program))
+		);
+	}
+
+	#[test]
+	fn test_decode_valid() {
+		/* BIP-350 valid SegWit addresses.  */
+		test_decode( Network::Mainnet
+			   , 0
+			   , "751e76e8199196d454941c45d1b3a323f1433bd6"
+			   , "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"
+			   );
+		test_decode( Network::Testnet
+			   , 0
+			   , "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"
+			   , "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7"
+			   );
+		test_decode( Network::Mainnet
+			   , 1
+			   , "751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6"
+			   , "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y"
+			   );
+		test_decode( Network::Mainnet
+			   , 16
+			   , "751e"
+			   , "BC1SW50QGDZ25J"
+			   );
+		test_decode( Network::Mainnet
+			   , 2
+			   , "751e76e8199196d454941c45d1b3a323"
+			   , "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs"
+			   );
+		test_decode( Network::Testnet
+			   , 0
+			   , "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"
+			   , "tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy"
+			   );
+		test_decode( Network::Testnet
+			   , 1
+			   , "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"
+			   , "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c"
+			   );
+		test_decode( Network::Mainnet
+			   , 1
+			   , "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+			   , "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+			   );
+		test_decode( Network::Regtest
+			   , 0
+			   , "751e76e8199196d454941c45d1b3a323f1433bd6"
+			   , "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"
+			   );
+	}
+
+	#[test]
+	fn test_decode_bip173_invalid() {
+		/* BIP-173 invalid SegWit addresses.  */
+		for address in [ "tc1qw508d6qejxtdg4y5r3zarvary0c5xw7kg3g4ty"
+			       , "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"
+			       , "BC13W508D6QEJXTDG4Y5R3ZARVARY0C5XW7KN40WF2"
+			       , "bc1rw5uspcuh"
+			       , "bc10w508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kw5rljs90"
+			       , "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P"
+			       , "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7"
+			       , "bc1zw508d6qejxtdg4y5r3zarvaryvqyzf3du"
+			       , "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3pjxtptv"
+			       , "bc1gmk9yu"
+			       ] {
+			assert_eq!(decode_segwit(address), None, "{}", address);
+		}
+		/* BIP-173 valid SegWit addresses with non-0
+		 * versions, which BIP-350 makes invalid.
+		 */
+		for address in [ "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7k7grplx"
+			       , "BC1SW50QA3JX3S"
+			       , "bc1zw508d6qejxtdg4y5r3zarvaryvg6kdaj"
+			       ] {
+			assert_eq!(decode_segwit(address), None, "{}", address);
+		}
+	}
+
+	#[test]
+	fn test_decode_bip350_invalid() {
+		/* BIP-350 invalid SegWit addresses.  */
+		for address in [ "tc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq5zuyut"
+			       , "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"
+			       , "tb1z0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqglt7rf"
+			       , "BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL"
+			       , "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh"
+			       , "tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47"
+			       , "bc1p38j9r5y49hruaue7wxjce0updqjuyyx0kh56v8s25huc6995vvpql3jow4"
+			       , "BC130XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ7ZWS8R"
+			       , "bc1pw5dgrnzv"
+			       , "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav"
+			       , "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P"
+			       , "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq47Zagq"
+			       , "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v07qwwzcrf"
+			       , "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vpggkg4j"
+			       , "bc1gmk9yu"
+			       ] {
+			assert_eq!(decode_segwit(address), None, "{}", address);
+		}
+	}
+
+	#[test]
+	fn test_signet_and_custom() {
+		let program = hex::decode("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+		.expect("program should be hex");
+
+		/* Signet addresses are the same as testnet.  */
+		let signet = encode_segwit(Network::Signet, 1, &program)
+		.expect("valid");
+		assert_eq!(Some(signet.clone()), encode_segwit(Network::Testnet, 1, &program));
+		assert_eq!( decode_segwit_for_network(&Network::Signet, &signet)
+			  , Some((1, program.clone()))
+			  );
+		assert_eq!( decode_segwit_for_network(&Network::Testnet, &signet)
+			  , Some((1, program.clone()))
+			  );
+		assert_eq!( decode_segwit(&signet)
+			  , Some((Network::Testnet, 1, program.clone()))
+			  );
+		assert_eq!(decode_segwit_for_network(&Network::Mainnet, &signet), None);
+
+		/* Custom HRPs.  */
+		let custom = Network::Custom("sip".to_string());
+		let address = encode_segwit(custom.clone(), 1, &program)
+		.expect("valid");
+		assert!(address.starts_with("sip1p"));
+		assert_eq!( decode_segwit_for_network(&custom, &address)
+			  , Some((1, program.clone()))
+			  );
+		assert_eq!( decode_segwit_for_network(&Network::Custom("SIP".to_string()), &address.to_uppercase())
+			  , Some((1, program.clone()))
+			  );
+		assert_eq!(decode_segwit(&address), None);
+		assert_eq!(decode_segwit_for_network(&Network::Regtest, &address), None);
+		/* A custom HRP that happens to be regtest.  */
+		let address = encode_segwit(Network::Custom("bcrt".to_string()), 1, &program)
+		.expect("valid");
+		assert_eq!( decode_segwit(&address)
+			  , Some((Network::Regtest, 1, program.clone()))
+			  );
+
+		/* Invalid custom HRPs.  */
+		assert_eq!(encode_segwit(Network::Custom("".to_string()), 1, &program), None);
+		assert_eq!(encode_segwit(Network::Custom("a b".to_string()), 1, &program), None);
+		assert_eq!(encode_segwit(Network::Custom("\u{e9}".to_string()), 1, &program), None);
+		/* Too long for the 90-character limit.  */
+		assert_eq!(encode_segwit(Network::Custom("x".repeat(31)), 1, &program), None);
+	}
+
+	#[test]
+	fn test_decode_roundtrip() {
+		for (n, v, program) in [ (Network::Mainnet, 1, vec!(0x5A; 32))
+				       , (Network::Testnet, 0, vec!(0xA5; 20))
+				       , (Network::Regtest, 16, vec!(0x00; 40))
+				       , (Network::Regtest, 3, vec!(0xFF; 2))
+				       ] {
+			let address = encode_segwit(n.clone(), v, &program)
+			.expect("valid version");
+			let (n2, v2, program2) = decode_segwit(&address)
+			.expect("should round trip");
+			assert_eq!(encode_segwit(n2.clone(), v2, &program2), Some(address));
+			assert_eq!(n2, n);
+			assert_eq!(v2, v);
+			assert_eq!(program2, program);
+		}
+	}
 }
@@ -0,0 +1,303 @@
+/*!
+The `bitcoin_interop` module, enabled by the `bitcoin`
+feature, converts between the raw values that the rest
+of this crate takes and returns, and the types of the
+[`bitcoin`][] crate:
+
+* `scriptPubKey`s and scripts, as `ScriptBuf`.
+* X-only public keys, as `XOnlyPublicKey`.
+* Addresses, as `Address`.
+* Control blocks, as `taproot::ControlBlock`.
+* Tapleaf hashes and Taproot Merkle roots, as
+  `TapLeafHash` and `TapNodeHash`.
+* Networks, outpoints, outputs and transactions.
+
+The conversions from the `bitcoin` types always succeed;
+the ones to them fail (return None) if the raw value is
+not valid for the type.
+
+[`bitcoin`]: https://docs.rs/bitcoin/0.31
+*/
+use bitcoin::Address;
+use bitcoin::ScriptBuf;
+use bitcoin::TapLeafHash;
+use bitcoin::TapNodeHash;
+use bitcoin::consensus;
+use bitcoin::hashes::Hash;
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::taproot::ControlBlock;
+use std::str::FromStr;
+use super::Network;
+use super::tx::OutPoint;
+use super::tx::Transaction;
+use super::tx::TxOut;
+
+/**
+`to_network` converts a `Network` to a `bitcoin::Network`.
+Fails for `Network::Custom`.
+*/
+pub
+fn to_network(network: &Network) -> Option<bitcoin::Network> {
+	match network {
+		Network::Mainnet => Some(bitcoin::Network::Bitcoin),
+		Network::Testnet => Some(bitcoin::Network::Testnet),
+		Network::Regtest => Some(bitcoin::Network::Regtest),
+		Network::Signet => Some(bitcoin::Network::Signet),
+		Network::Custom(_) => None
+	}
+}
+
+/**
+`from_network` converts a `bitcoin::Network` to a
+`Network`.
+*/
+pub
+fn from_network(network: bitcoin::Network) -> Network {
+	match network {
+		bitcoin::Network::Bitcoin => Network::Mainnet,
+		bitcoin::Network::Testnet => Network::Testnet,
+		bitcoin::Network::Signet => Network::Signet,
+		bitcoin::Network::Regtest => Network::Regtest
+	}
+}
+
+/** `to_script_buf` converts a script to a `ScriptBuf`.  */
+pub
+fn to_script_buf(script: &[u8]) -> ScriptBuf {
+	ScriptBuf::from_bytes(script.to_vec())
+}
+
+/** `from_script` converts a `bitcoin::Script` to a script.  */
+pub
+fn from_script(script: &bitcoin::Script) -> Vec<u8> {
+	script.to_bytes()
+}
+
+/**
+`to_xonly_pubkey` converts an x-only public key, such as
+that of `address::derive_taproot_xonly_pubkey`, to an
+`XOnlyPublicKey`.
+*/
+pub
+fn to_xonly_pubkey(xonly: &[u8; 32]) -> Option<XOnlyPublicKey> {
+	XOnlyPublicKey::from_slice(xonly).ok()
+}
+
+/** `from_xonly_pubkey` converts an `XOnlyPublicKey`.  */
+pub
+fn from_xonly_pubkey(xonly: &XOnlyPublicKey) -> [u8; 32] {
+	xonly.serialize()
+}
+
+/**
+`to_address` parses an address, such as that of
+`address::derive_taproot_address`, as an `Address` of
+the given network.
+Fails for `Network::Custom`.
+As testnet and signet addresses are the same, the
+`Address` of a signet address reports testnet.
+*/
+pub
+fn to_address(network: &Network, address: &str) -> Option<Address> {
+	let network = to_network(network)?;
+	Address::from_str(address).ok()?
+	.require_network(network).ok()
+}
+
+/** `from_address` converts an `Address` to its string.  */
+pub
+fn from_address(address: &Address) -> String {
+	address.to_string()
+}
+
+/**
+`to_control_block` converts a control block, such as
+that of `address::control_block_cooperative`, to a
+`taproot::ControlBlock`.
+*/
+pub
+fn to_control_block(control_block: &[u8]) -> Option<ControlBlock> {
+	ControlBlock::decode(control_block).ok()
+}
+
+/** `from_control_block` serializes a `taproot::ControlBlock`.  */
+pub
+fn from_control_block(control_block: &ControlBlock) -> Vec<u8> {
+	control_block.serialize()
+}
+
+/**
+`to_tap_leaf_hash` converts a tapleaf hash, such as the
+`leaf_hash` of a `psbt::TapScriptSig`, to a
+`TapLeafHash`.
+*/
+pub
+fn to_tap_leaf_hash(hash: &[u8; 32]) -> TapLeafHash {
+	TapLeafHash::from_byte_array(*hash)
+}
+
+/** `from_tap_leaf_hash` converts a `TapLeafHash`.  */
+pub
+fn from_tap_leaf_hash(hash: &TapLeafHash) -> [u8; 32] {
+	hash.to_byte_array()
+}
+
+/**
+`to_tap_node_hash` converts a Taproot Merkle root, such
+as a `PSBT_IN_TAP_MERKLE_ROOT`, to a `TapNodeHash`.
+*/
+pub
+fn to_tap_node_hash(hash: &[u8; 32]) -> TapNodeHash {
+	TapNodeHash::from_byte_array(*hash)
+}
+
+/** `from_tap_node_hash` converts a `TapNodeHash`.  */
+pub
+fn from_tap_node_hash(hash: &TapNodeHash) -> [u8; 32] {
+	hash.to_byte_array()
+}
+
+/** `to_outpoint` converts an `OutPoint`.  */
+pub
+fn to_outpoint(outpoint: &OutPoint) -> bitcoin::OutPoint {
+	bitcoin::OutPoint{ txid: bitcoin::Txid::from_byte_array(outpoint.txid)
+			 , vout: outpoint.vout
+			 }
+}
+
+/** `from_outpoint` converts a `bitcoin::OutPoint`.  */
+pub
+fn from_outpoint(outpoint: &bitcoin::OutPoint) -> OutPoint {
+	OutPoint{ txid: outpoint.txid.to_byte_array()
+		, vout: outpoint.vout
+		}
+}
+
+/** `to_txout` converts a `TxOut`.  */
+pub
+fn to_txout(txout: &TxOut) -> bitcoin::TxOut {
+	bitcoin::TxOut{ value: bitcoin::Amount::from_sat(txout.value)
+		      , script_pubkey: to_script_buf(&txout.script_pubkey)
+		      }
+}
+
+/** `from_txout` converts a `bitcoin::TxOut`.  */
+pub
+fn from_txout(txout: &bitcoin::TxOut) -> TxOut {
+	TxOut{ value: txout.value.to_sat()
+	     , script_pubkey: from_script(&txout.script_pubkey)
+	     }
+}
+
+/**
+`to_transaction` converts a `Transaction`, through its
+consensus serialization.
+Fails if the transaction is not valid for the `bitcoin`
+crate, e.g. if it has a segwit witness but no inputs.
+*/
+pub
+fn to_transaction(tx: &Transaction) -> Option<bitcoin::Transaction> {
+	consensus::deserialize(&tx.serialize()).ok()
+}
+
+/** `from_transaction` converts a `bitcoin::Transaction`.  */
+pub
+fn from_transaction(tx: &bitcoin::Transaction) -> Option<Transaction> {
+	Transaction::deserialize(&consensus::serialize(tx))
+}
+
+#[cfg(test)]
+mod tests {
+	use secp256k1::PublicKey;
+	use secp256k1::Secp256k1;
+	use super::*;
+	use super::super::address;
+	use super::super::scripts;
+
+	fn point_txt(pk_s: &str) -> PublicKey {
+		PublicKey::from_slice(&hex::decode(pk_s).unwrap()).unwrap()
+	}
+
+	/* swap-in-potentiam.md
+	 * Test Vectors For Control Block Of 2-of-2 Tapleaf Path
+	 * Test Vectors For Control Block Of Timelock Tapleaf Path
+	 *
+	 * Checks the control blocks of the specification, not only
+	 * those computed by this crate, against the Taproot
+	 * commitment check of the `bitcoin` crate.
+	 */
+	#[test]
+	fn test_control_block() {
+		let s_ctx = Secp256k1::new();
+		let vectors = [ ( "02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae"
+				, "03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3"
+				, "8e65ee023bbe8850816b833690b565c5edb5c7570724d65958620ea7f6846da5"
+				, "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c69b15e91cdc464dfdb2f19faa6fcb96d5fb58773452f6afcfe251ed1cf83f1576a"
+				, "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c696a3d6cef744256400f4a7ae5e5f72b04cb8668c117d47d6df325e5c13e8acef3"
+				)
+			      , ( "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+				, "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+				, "a3e57251565298e58c86dddf4919a14645c34bf0cbe98d6fc635f7eb60efd2ba"
+				, "c0f89c20245de19bd2889af0b0b4bad84bfa99e7e181ac8e9549aeebfcbb10fb1b84227987deb98d26d0b8b665a159bc959b2f451ad8451bfa6bf34b25b979d9f4"
+				, "c0f89c20245de19bd2889af0b0b4bad84bfa99e7e181ac8e9549aeebfcbb10fb1bc9bcb65c1015db6b44e5c2977606cf7a5d8f13d226531c6d01133d0810bed17b"
+				)
+			      , ( "038a3ba5c99568d26602f4cf8038371da3c86057a96eb1b6a8de1b4f1be723c236"
+				, "03de2848d46044aec16ea7b73233f2709f15b9bfeb720dd5d5ae595cfa51e01f15"
+				, "fccde9d2266a2e68c99f0aab5adafea5f97d8a33f81b0525de8f7cb40c08ce7d"
+				, "c059774215a479bd01274044024c52dcd5e37e50f5d3596cc374eaf5035ebc884daa7a24068eade1dff691d8d198aeeb5eca481cf937ab461d531eeb2bb1fd394d"
+				, "c059774215a479bd01274044024c52dcd5e37e50f5d3596cc374eaf5035ebc884d3a0941de1e532cb3db21839462a6c10d2ab195ee73da09ff37cda2a01c8df72d"
+				)
+			      , ( "022f8bde4d1a07209355b4a7250a5c5128e88b84bddc619ab7cba8d569b240efe4"
+				, "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556"
+				, "218eb8834e2f3a591c08e9fa8f96f794f164b4686813765d0e4a0dfc0d3f8d30"
+				, "c1b9de2c615be15e40c666c1c8e7a2d47aa6ad93fc2a33b446291ed8883003bfc59d554f7390ca4fce20c4b638d9619c57c2abe23f45ac762a481dcb7028af5496"
+				, "c1b9de2c615be15e40c666c1c8e7a2d47aa6ad93fc2a33b446291ed8883003bfc5c46a5731331877af9659651233d202bcbc9d41a8ae842d704d3adbde76e4cd82"
+				)
+			      ];
+		for (a_s, b_s, s_s, cooperative, alice_recovery) in vectors {
+			let a = point_txt(a_s);
+			let b = point_txt(b_s);
+			let output_key = XOnlyPublicKey::from_slice(&hex::decode(s_s).unwrap())
+			.unwrap();
+			assert_eq!( address::derive_taproot_xonly_pubkey(&s_ctx, &a, &b)
+				  , Some(from_xonly_pubkey(&output_key))
+				  );
+			let scripts = [ (cooperative, scripts::tapleaf_cooperative(&a, &b))
+				      , (alice_recovery, scripts::tapleaf_alice_recovery(&a))
+				      ];
+			for (control_block, script) in scripts {
+				let control_block = hex::decode(control_block).unwrap();
+				let cb = to_control_block(&control_block).unwrap();
+				assert_eq!(from_control_block(&cb), control_block);
+				assert!(cb.verify_taproot_commitment(&s_ctx, output_key, &to_script_buf(&script)));
+			}
+		}
+		assert_eq!(to_control_block(&[0xC0; 10]), None);
+	}
+
+	#[test]
+	fn test_conversions() {
+		assert_eq!(to_network(&Network::Mainnet), Some(bitcoin::Network::Bitcoin));
+		assert_eq!(to_network(&Network::Custom("sip".to_string())), None);
+		for network in [Network::Mainnet, Network::Testnet, Network::Signet, Network::Regtest] {
+			assert_eq!(from_network(to_network(&network).unwrap()), network);
+		}
+
+		let hash = [0x5A; 32];
+		assert_eq!(from_tap_leaf_hash(&to_tap_leaf_hash(&hash)), hash);
+		assert_eq!(from_tap_node_hash(&to_tap_node_hash(&hash)), hash);
+		assert_eq!(to_xonly_pubkey(&[0; 32]), None);
+
+		let buf = hex::decode("02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000").unwrap();
+		let tx = Transaction::deserialize(&buf).unwrap();
+		let btx = to_transaction(&tx).unwrap();
+		assert_eq!(btx.txid().to_byte_array(), tx.txid());
+		assert_eq!(btx.wtxid().to_byte_array(), tx.wtxid());
+		assert_eq!(btx.weight().to_wu(), tx.weight());
+		assert_eq!(from_transaction(&btx), Some(tx.clone()));
+		assert_eq!(from_outpoint(&to_outpoint(&tx.input[0].prevout)), tx.input[0].prevout);
+		assert_eq!(to_outpoint(&tx.input[0].prevout), btx.input[0].previous_output);
+		assert_eq!(from_txout(&to_txout(&tx.output[0])), tx.output[0]);
+		assert_eq!(to_txout(&tx.output[0]), btx.output[0]);
+	}
+}
@@ -0,0 +1,490 @@
+/*!
+The `chain` module contains `ChainProcessor`, which
+does the state transitions of Bob's mapping that depend
+on the chain: an `unconfirmed_alice_change` output
+becomes `confirmed_alice_change` once the transaction
+it is an output of is confirmed to the `minimum_depth`
+Bob would use in `accept_channel`, and, if a chain
+reorganization then leaves the transaction less deeply
+confirmed, becomes `unconfirmed_alice_change` again.
+*/
+use hashes::sha2::sha256;
+use std::collections::HashMap;
+use super::prune::FORGET_DEPTH;
+use super::state::State;
+use super::state::StateStore;
+use super::state::Transition;
+use super::tx::OutPoint;
+
+/**
+`Block` is a block connected to, or disconnected from,
+the best chain: its 80-byte header and the txids of its
+transactions.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct Block {
+	pub header: [u8; 80],
+	pub txids: Vec<[u8; 32]>
+}
+
+impl Block {
+	/// `block_hash` returns the hash of the block, in
+	/// internal byte order.
+	pub
+	fn block_hash(&self) -> [u8; 32] {
+		let h = sha256::hash(&self.header).into_bytes();
+		sha256::hash(&h).into_bytes()
+	}
+
+	/// `prev_block_hash` returns the hash of the previous
+	/// block, as committed to in the header.
+	pub
+	fn prev_block_hash(&self) -> [u8; 32] {
+		let mut rv = [0u8; 32];
+		rv.copy_from_slice(&self.header[4..36]);
+		rv
+	}
+}
+
+/**
+`ChainError` is the reason `ChainProcessor` did not
+process a block.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum ChainError {
+	/// The connected block does not build on the
+	/// current tip.
+	NotOnTip,
+	/// The disconnected block is not the current
+	/// tip.
+	NotTip,
+	/// The confirmation height given to
+	/// `watch_confirmed` is above the current tip.
+	AboveTip,
+	/// The store failed to persist the new states.
+	/// The block is not processed, and may be given
+	/// again.
+	Store
+}
+
+/**
+`ChainProcessor` follows the best chain, as given by
+`block_connected` and `block_disconnected`, and moves
+the watched outputs between `unconfirmed_alice_change`
+and `confirmed_alice_change` according to the depth of
+their transactions.
+
+An output stops being watched once it is neither of
+those states (e.g. Alice moved it, or it was deleted
+by an abort), or once its transaction has
+`FORGET_DEPTH` confirmations.
+
+The watched outputs are only kept in memory: after a
+restart, each output in one of those states has to be
+watched again, with `watch` if its transaction is not
+confirmed, or with `watch_confirmed` if it is.
+*/
+#[derive(Clone, Debug)]
+pub
+struct ChainProcessor {
+	minimum_depth: u32,
+	tip_hash: [u8; 32],
+	tip_height: u32,
+	/* The watched outputs, by txid.  */
+	watched: HashMap<[u8; 32], Vec<u32>>,
+	/* The height of the block each watched
+	transaction is in, if it is confirmed.
+	*/
+	confirmed: HashMap<[u8; 32], u32>
+}
+
+impl ChainProcessor {
+	/// `new` creates a `ChainProcessor` with the given
+	/// current chain tip, promoting outputs once their
+	/// transaction has `minimum_depth` confirmations.
+	pub
+	fn new( minimum_depth: u32
+	      , tip_hash: [u8; 32]
+	      , tip_height: u32
+	      ) -> ChainProcessor
+	{
+		ChainProcessor{ minimum_depth
+			      , tip_hash
+			      , tip_height
+			      , watched: HashMap::new()
+			      , confirmed: HashMap::new()
+			      }
+	}
+
+	/// `watch` starts watching the `unconfirmed_alice_change`
+	/// output `outpoint`, whose transaction must not yet be
+	/// confirmed.
+	pub
+	fn watch(&mut self, outpoint: &OutPoint) {
+		let vouts = self.watched.entry(outpoint.txid).or_default();
+		if !vouts.contains(&outpoint.vout) {
+			vouts.push(outpoint.vout);
+		}
+	}
+
+	/// `watch_confirmed` starts watching the
+	/// `unconfirmed_alice_change` or
+	/// `confirmed_alice_change` output `outpoint`, whose
+	/// transaction is confirmed in the block at
+	/// `conf_height` of the current chain, for example
+	/// while Bob was not running.
+	///
+	/// The output is moved to the state for its depth
+	/// right away, rather than at the next block.
+	pub
+	fn watch_confirmed<S>( &mut self
+			     , store: &mut S
+			     , outpoint: &OutPoint
+			     , conf_height: u32
+			     ) -> Result<(), ChainError>
+		where S: StateStore
+	{
+		if conf_height > self.tip_height {
+			return Err(ChainError::AboveTip);
+		}
+		let watched = self.watched.clone();
+		self.watch(outpoint);
+		let mut confirmed = self.confirmed.clone();
+		confirmed.insert(outpoint.txid, conf_height);
+		let rv = self.update(store, confirmed, self.tip_hash, self.tip_height);
+		if rv.is_err() {
+			self.watched = watched;
+		}
+		rv
+	}
+
+	/// `is_watched` returns true if `outpoint` is being
+	/// watched.
+	pub
+	fn is_watched(&self, outpoint: &OutPoint) -> bool {
+		match self.watched.get(&outpoint.txid) {
+			Some(vouts) => vouts.contains(&outpoint.vout),
+			None => false
+		}
+	}
+
+	/// `tip` returns the hash and height of the current
+	/// chain tip.
+	pub
+	fn tip(&self) -> ([u8; 32], u32) {
+		(self.tip_hash, self.tip_height)
+	}
+
+	/// `block_connected` processes the `block`, which must
+	/// build on the current tip, and becomes the new tip.
+	pub
+	fn block_connected<S>( &mut self
+			     , store: &mut S
+			     , block: &Block
+			     ) -> Result<(), ChainError>
+		where S: StateStore
+	{
+		if block.prev_block_hash() != self.tip_hash {
+			return Err(ChainError::NotOnTip);
+		}
+		let height = self.tip_height + 1;
+		let mut confirmed = self.confirmed.clone();
+		for txid in block.txids.iter() {
+			if self.watched.contains_key(txid) {
+				confirmed.insert(*txid, height);
+			}
+		}
+		self.update(store, confirmed, block.block_hash(), height)
+	}
+
+	/// `block_disconnected` processes the `block`, which
+	/// must be the current tip, and is removed from the
+	/// chain by a reorganization.
+	pub
+	fn block_disconnected<S>( &mut self
+				, store: &mut S
+				, block: &Block
+				) -> Result<(), ChainError>
+		where S: StateStore
+	{
+		if block.block_hash() != self.tip_hash {
+			return Err(ChainError::NotTip);
+		}
+		let height = self.tip_height.checked_sub(1).ok_or(ChainError::NotTip)?;
+		let mut confirmed = self.confirmed.clone();
+		confirmed.retain(|_, h| *h <= height);
+		self.update(store, confirmed, block.prev_block_hash(), height)
+	}
+
+	/* Moves the watched outputs to the states for the
+	new tip, then commits to the new tip.
+	*/
+	fn update<S>( &mut self
+		    , store: &mut S
+		    , confirmed: HashMap<[u8; 32], u32>
+		    , tip_hash: [u8; 32]
+		    , tip_height: u32
+		    ) -> Result<(), ChainError>
+		where S: StateStore
+	{
+		let mut batch = Vec::new();
+		let mut watched = HashMap::new();
+		for (txid, vouts) in self.watched.iter() {
+			let depth = match confirmed.get(txid) {
+				Some(h) => tip_height - h + 1,
+				None => 0
+			};
+			let mut still_watched = Vec::new();
+			for vout in vouts.iter() {
+				let outpoint = OutPoint{txid: *txid, vout: *vout};
+				let state = store.get(&outpoint);
+				match state {
+					Some(State::UnconfirmedAliceChange) => {
+						if depth >= self.minimum_depth {
+							batch.push((outpoint, Transition::AliceChangeConfirmed));
+						}
+					},
+					Some(State::ConfirmedAliceChange) => {
+						if depth < self.minimum_depth {
+							batch.push((outpoint, Transition::AliceChangeUnconfirmed));
+						}
+					},
+					_ => { continue; }
+				}
+				if depth < FORGET_DEPTH {
+					still_watched.push(*vout);
+				}
+			}
+			if !still_watched.is_empty() {
+				watched.insert(*txid, still_watched);
+			}
+		}
+
+		store.transition_all(&batch).map_err(|_| ChainError::Store)?;
+
+		self.confirmed = confirmed;
+		self.confirmed.retain(|txid, _| watched.contains_key(txid));
+		self.watched = watched;
+		self.tip_hash = tip_hash;
+		self.tip_height = tip_height;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/* An in-memory chain of blocks, with forks.  */
+	struct FakeChain {
+		blocks: Vec<Block>
+	}
+
+	impl FakeChain {
+		/* A chain with only a genesis block.  */
+		fn new() -> FakeChain {
+			FakeChain{blocks: vec!(Block{header: [0u8; 80], txids: Vec::new()})}
+		}
+
+		fn tip(&self) -> &Block {
+			self.blocks.last().unwrap()
+		}
+
+		/* Builds a block on the tip, with `nonce` to
+		 * distinguish forks.
+		 */
+		fn mine(&mut self, txids: &[[u8; 32]], nonce: u8) -> Block {
+			let mut header = [0u8; 80];
+			header[0] = 2;
+			header[4..36].copy_from_slice(&self.tip().block_hash());
+			header[76] = nonce;
+			let block = Block{header, txids: txids.to_vec()};
+			self.blocks.push(block.clone());
+			block
+		}
+
+		fn unmine(&mut self) -> Block {
+			self.blocks.pop().unwrap()
+		}
+	}
+
+	fn outpoint(n: u8, vout: u32) -> OutPoint {
+		OutPoint{txid: [n; 32], vout}
+	}
+
+	#[test]
+	fn test_promotion() {
+		let mut chain = FakeChain::new();
+		let mut processor = ChainProcessor::new(3, chain.tip().block_hash(), 0);
+		let mut store = HashMap::new();
+		store.transition_all(&[ (outpoint(1, 0), Transition::AliceChangeCreated)
+				      , (outpoint(1, 2), Transition::AliceChangeCreated)
+				      , (outpoint(2, 1), Transition::AliceChangeCreated)
+				      ]).unwrap();
+		processor.watch(&outpoint(1, 0));
+		processor.watch(&outpoint(1, 2));
+		processor.watch(&outpoint(2, 1));
+
+		let b = chain.mine(&[[9; 32], [1; 32]], 0);
+		processor.block_connected(&mut store, &b).unwrap();
+		let b = chain.mine(&[[2; 32]], 0);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::UnconfirmedAliceChange));
+
+		/* Alice moved outpoint(1, 2) once confirmed.  */
+		store.insert(outpoint(1, 2), State::AliceMoved);
+
+		let b = chain.mine(&[], 0);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert_eq!(processor.tip(), (b.block_hash(), 3));
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(1, 2)), Some(State::AliceMoved));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 1)), Some(State::UnconfirmedAliceChange));
+		assert!(!processor.is_watched(&outpoint(1, 2)));
+
+		let b = chain.mine(&[], 0);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(2, 1)), Some(State::ConfirmedAliceChange));
+
+		/* Watched until FORGET_DEPTH.  */
+		for _ in 4..100 {
+			let b = chain.mine(&[], 0);
+			processor.block_connected(&mut store, &b).unwrap();
+		}
+		assert!(processor.is_watched(&outpoint(2, 1)));
+		assert!(!processor.is_watched(&outpoint(1, 0)));
+		let b = chain.mine(&[], 0);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert!(!processor.is_watched(&outpoint(2, 1)));
+		assert_eq!(store.len(), 3);
+	}
+
+	#[test]
+	fn test_reorg() {
+		let mut chain = FakeChain::new();
+		let mut processor = ChainProcessor::new(2, chain.tip().block_hash(), 0);
+		let mut store = HashMap::new();
+		store.transition_all(&[ (outpoint(1, 0), Transition::AliceChangeCreated)
+				      , (outpoint(2, 0), Transition::AliceChangeCreated)
+				      ]).unwrap();
+		processor.watch(&outpoint(1, 0));
+		processor.watch(&outpoint(2, 0));
+
+		for txids in [vec!([1; 32]), vec!([2; 32]), Vec::new()] {
+			let b = chain.mine(&txids, 0);
+			processor.block_connected(&mut store, &b).unwrap();
+		}
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::ConfirmedAliceChange));
+
+		/* Reorganize out the blocks from height 2;
+		 * only outpoint(2, 0) is unconfirmed, and
+		 * outpoint(1, 0) is too shallow once the tip is
+		 * at 1.
+		 */
+		let b = chain.unmine();
+		processor.block_disconnected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::UnconfirmedAliceChange));
+		let b = chain.unmine();
+		processor.block_disconnected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::UnconfirmedAliceChange));
+
+		/* The new branch confirms outpoint(2, 0) at
+		 * height 3.
+		 */
+		let b = chain.mine(&[], 1);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		let b = chain.mine(&[[2; 32]], 1);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::UnconfirmedAliceChange));
+		let b = chain.mine(&[], 1);
+		processor.block_connected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::ConfirmedAliceChange));
+	}
+
+	#[test]
+	fn test_restart() {
+		let mut chain = FakeChain::new();
+		let mut processor = ChainProcessor::new(2, chain.tip().block_hash(), 0);
+		let mut store = HashMap::new();
+		store.transition_all(&[ (outpoint(1, 0), Transition::AliceChangeCreated)
+				      , (outpoint(2, 0), Transition::AliceChangeCreated)
+				      , (outpoint(3, 0), Transition::AliceChangeCreated)
+				      ]).unwrap();
+		processor.watch(&outpoint(1, 0));
+		processor.watch(&outpoint(2, 0));
+		processor.watch(&outpoint(3, 0));
+		for txids in [vec!([1; 32]), Vec::new()] {
+			let b = chain.mine(&txids, 0);
+			processor.block_connected(&mut store, &b).unwrap();
+		}
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+
+		/* Bob stops, and outpoint(2, 0) confirms at
+		 * height 3 while he is down.
+		 */
+		drop(processor);
+		chain.mine(&[[2; 32]], 0);
+		chain.mine(&[], 0);
+
+		/* On restart, the watched outputs are rebuilt from
+		 * the store and the chain.
+		 */
+		let mut processor = ChainProcessor::new(2, chain.tip().block_hash(), 4);
+		assert_eq!(processor.watch_confirmed(&mut store, &outpoint(1, 0), 5), Err(ChainError::AboveTip));
+		processor.watch_confirmed(&mut store, &outpoint(1, 0), 1).unwrap();
+		processor.watch_confirmed(&mut store, &outpoint(2, 0), 3).unwrap();
+		processor.watch(&outpoint(3, 0));
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(3, 0)), Some(State::UnconfirmedAliceChange));
+
+		/* Reorganizing out height 4 leaves outpoint(2, 0)
+		 * too shallow, which is only known because its
+		 * height was given.
+		 */
+		let b = chain.unmine();
+		processor.block_disconnected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::UnconfirmedAliceChange));
+		let b = chain.unmine();
+		processor.block_disconnected(&mut store, &b).unwrap();
+		assert_eq!(StateStore::get(&store, &outpoint(1, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::UnconfirmedAliceChange));
+		for txids in [vec!([3; 32]), Vec::new()] {
+			let b = chain.mine(&txids, 1);
+			processor.block_connected(&mut store, &b).unwrap();
+		}
+		assert_eq!(StateStore::get(&store, &outpoint(3, 0)), Some(State::ConfirmedAliceChange));
+		assert_eq!(StateStore::get(&store, &outpoint(2, 0)), Some(State::UnconfirmedAliceChange));
+	}
+
+	#[test]
+	fn test_errors() {
+		let mut chain = FakeChain::new();
+		let mut processor = ChainProcessor::new(1, chain.tip().block_hash(), 0);
+		let mut store = HashMap::new();
+		store.transition_all(&[(outpoint(1, 0), Transition::AliceChangeCreated)]).unwrap();
+		processor.watch(&outpoint(1, 0));
+
+		let b1 = chain.mine(&[], 0);
+		let b2 = chain.mine(&[[1; 32]], 0);
+		assert_eq!(processor.block_connected(&mut store, &b2), Err(ChainError::NotOnTip));
+		assert_eq!(processor.block_disconnected(&mut store, &b1), Err(ChainError::NotTip));
+		processor.block_connected(&mut store, &b1).unwrap();
+		assert_eq!(processor.block_disconnected(&mut store, &b2), Err(ChainError::NotTip));
+
+		/* An abort deletes outpoint(1, 0) before it
+		 * confirms.
+		 */
+		store.transition_all(&[(outpoint(1, 0), Transition::ZeroconfAbortedChange)]).unwrap();
+		processor.block_connected(&mut store, &b2).unwrap();
+		assert!(store.is_empty());
+		assert!(!processor.is_watched(&outpoint(1, 0)));
+		assert_eq!(processor.tip(), (b2.block_hash(), 2));
+	}
+}
@@ -0,0 +1,372 @@
+/*!
+The `cpfp` module builds the child transaction with
+which Bob bumps the fee of a funding transaction by
+spending its anchor output (child-pays-for-parent), so
+that the funding transaction confirms before the
+swap-in-potentiam outputs it spends can be recovered by
+Alice.
+
+The child spends the anchor output, which Bob signs
+here, and as many of the given outputs of the wallet of
+Bob as it needs, which the wallet signs afterwards.
+All feerates are in sat/kWU, like the `min_feerate` of
+the `onchain_fee_schedule`.
+*/
+use secp256k1::Message;
+use secp256k1::PublicKey;
+use secp256k1::Secp256k1;
+use secp256k1::SecretKey;
+use secp256k1::Signing;
+use super::address;
+use super::funding::ANCHOR_AMOUNT_SAT;
+use super::sighash;
+use super::tx::OutPoint;
+use super::tx::Transaction;
+use super::tx::TxIn;
+use super::tx::TxOut;
+
+/**
+`CHANGE_DUST_LIMIT_SAT` is the smallest amount of the
+change output of the child, which is the dust limit of
+the largest standard output types.
+*/
+pub
+const CHANGE_DUST_LIMIT_SAT: u64 = 546;
+
+/* The weight of the witness of the anchor input: the
+item count, then a signature of at most 72 bytes plus
+the SIGHASH byte, then the 40-byte witness script, each
+with its length.
+*/
+const ANCHOR_WITNESS_WEIGHT: u64 = 1 + 1 + 73 + 1 + 40;
+
+/* The segwit marker and flag bytes.  */
+const SEGWIT_MARKER_WEIGHT: u64 = 2;
+
+/**
+`WalletUtxo` is an output of the wallet of Bob that the
+child may spend.
+
+`satisfaction_weight` is the weight that the `scriptSig`
+and witness add to the input once the wallet signs it,
+including the witness item count, e.g. 108 for a P2WPKH
+output, or 66 for a P2TR keypath spend.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct WalletUtxo {
+	pub outpoint: OutPoint,
+	pub txout: TxOut,
+	pub satisfaction_weight: u64
+}
+
+/**
+`CpfpParent` is the funding transaction whose fee
+`build_anchor_cpfp` bumps.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct CpfpParent {
+	/// The funding transaction, with its witnesses, so
+	/// that its weight is correct.
+	pub tx: Transaction,
+	/// The fee `tx` pays, in satoshis.
+	pub fee: u64,
+	/// The anchor output of Bob in `tx`.
+	pub anchor: OutPoint
+}
+
+/**
+`AnchorCpfp` is the child transaction built by
+`build_anchor_cpfp`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct AnchorCpfp {
+	/// The child, whose input 0 is the anchor output,
+	/// with the witness of Bob, and whose input `i + 1`
+	/// spends the wallet output `i`, without its
+	/// `scriptSig` and witness.
+	/// Its only output is the change.
+	pub tx: Transaction,
+	/// The number of wallet outputs spent, which are
+	/// the first ones given.
+	pub wallet_inputs: usize,
+	/// The fee of the child, in satoshis.
+	pub fee: u64,
+	/// The weight of the child once the wallet inputs
+	/// are signed, assuming their `satisfaction_weight`s.
+	pub weight: u64
+}
+
+/**
+`CpfpError` is the reason `build_anchor_cpfp` failed.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum CpfpError {
+	/// The anchor outpoint is not an output of the
+	/// parent, or is not the anchor output of Bob.
+	InvalidAnchor,
+	/// The wallet outputs are not enough to pay for
+	/// the fee and a change output above the dust limit,
+	/// including when the fee overflows.
+	InsufficientFunds,
+	/// The sum of the amounts, or of the
+	/// `satisfaction_weight`s, of the wallet outputs
+	/// overflows.
+	Overflow
+}
+
+/* Returns None if the fee overflows, so that the fee
+returned is far from overflowing itself.
+*/
+fn fee_for(feerate: u64, weight: u64) -> Option<u64> {
+	Some(feerate.checked_mul(weight)?.div_ceil(1000))
+}
+
+/**
+`build_anchor_cpfp` builds and signs the anchor input
+of a child of the funding transaction `parent`, such
+that the parent and child together pay at least
+`package_feerate`, and the child alone pays at least
+that feerate too.
+
+The anchor output of `parent` must be that of `bob`.
+The child spends the `wallet` outputs in the order
+given, as few as it needs, and sends the remainder to
+`change_script_pubkey`.
+*/
+pub
+fn build_anchor_cpfp<C>( secp256k1: &Secp256k1<C>
+		       , bob: &SecretKey
+		       , parent: &CpfpParent
+		       , wallet: &[WalletUtxo]
+		       , change_script_pubkey: &[u8]
+		       , package_feerate: u64
+		       ) -> Result<AnchorCpfp, CpfpError>
+	where C: Signing
+{
+	let bob_pubkey = PublicKey::from_secret_key(secp256k1, bob);
+	let anchor = &parent.anchor;
+	if anchor.txid != parent.tx.txid() {
+		return Err(CpfpError::InvalidAnchor);
+	}
+	let anchor_txout = parent.tx.output.get(anchor.vout as usize)
+	.ok_or(CpfpError::InvalidAnchor)?;
+	if anchor_txout.value != ANCHOR_AMOUNT_SAT
+	|| anchor_txout.script_pubkey != address::derive_anchor_scriptpubkey(&bob_pubkey) {
+		return Err(CpfpError::InvalidAnchor);
+	}
+	let parent_weight = parent.tx.weight();
+
+	let mut tx = Transaction{ version: 2
+				, lock_time: 0
+				, input: vec!(TxIn{ prevout: anchor.clone()
+						  , script_sig: Vec::new()
+						  , sequence: 0xFFFFFFFD
+						  , witness: Vec::new()
+						  })
+				, output: vec!(TxOut{ value: 0
+						    , script_pubkey: change_script_pubkey.to_vec()
+						    })
+				};
+	let mut total = ANCHOR_AMOUNT_SAT;
+	let mut satisfaction_weight = 0;
+	let mut wallet_inputs = 0;
+	let (fee, weight) = loop {
+		let weight = (tx.weight() + SEGWIT_MARKER_WEIGHT + ANCHOR_WITNESS_WEIGHT)
+		.checked_add(satisfaction_weight)
+		.ok_or(CpfpError::Overflow)?;
+		let package_fee = parent_weight.checked_add(weight)
+		.and_then(|weight| fee_for(package_feerate, weight))
+		.ok_or(CpfpError::InsufficientFunds)?;
+		let child_fee = fee_for(package_feerate, weight)
+		.ok_or(CpfpError::InsufficientFunds)?;
+		let fee = std::cmp::max(package_fee.saturating_sub(parent.fee), child_fee);
+		if total >= fee + CHANGE_DUST_LIMIT_SAT {
+			break (fee, weight);
+		}
+
+		let utxo = wallet.get(wallet_inputs)
+		.ok_or(CpfpError::InsufficientFunds)?;
+		tx.input.push(TxIn{ prevout: utxo.outpoint.clone()
+				  , script_sig: Vec::new()
+				  , sequence: 0xFFFFFFFD
+				  , witness: Vec::new()
+				  });
+		total = total.checked_add(utxo.txout.value)
+		.ok_or(CpfpError::Overflow)?;
+		satisfaction_weight = satisfaction_weight.checked_add(utxo.satisfaction_weight)
+		.ok_or(CpfpError::Overflow)?;
+		wallet_inputs += 1;
+	};
+	tx.output[0].value = total - fee;
+
+	let sighash = sighash::anchor_sighash(&tx, 0, &bob_pubkey, ANCHOR_AMOUNT_SAT)
+	.expect("input 0 exists");
+	let sig = secp256k1.sign_ecdsa(&Message::from_digest(sighash), bob);
+	let mut sig = sig.serialize_der().to_vec();
+	sig.push(sighash::SIGHASH_ALL);
+	tx.input[0].witness = vec!(sig, address::anchor_witness_script(&bob_pubkey));
+
+	Ok(AnchorCpfp{ tx
+		     , wallet_inputs
+		     , fee
+		     , weight
+		     })
+}
+
+#[cfg(test)]
+mod tests {
+	use secp256k1::ecdsa::Signature;
+	use super::*;
+	use super::super::api::FundingInput;
+	use super::super::api::FundingOutput;
+	use super::super::api::Order;
+	use super::super::api::SignFundingBobRequest;
+	use super::super::funding;
+
+	fn secret(n: u8) -> SecretKey {
+		SecretKey::from_slice(&[n; 32]).unwrap()
+	}
+
+	/* A signed funding transaction of one input, with
+	 * a 2-of-2 tapleaf witness, paying `fee`.
+	 */
+	fn parent(bob: &PublicKey, fee: u64) -> CpfpParent {
+		let secp256k1 = Secp256k1::new();
+		let alice = PublicKey::from_secret_key(&secp256k1, &secret(1));
+		let request = SignFundingBobRequest{ temporary_channel_id: [0x42; 32]
+						   , current_blockheight: 800000
+						   , inputs: vec!(FundingInput{ prev_out: OutPoint{txid: [0xA1; 32], vout: 0}
+									      , alice_pubkey: alice.serialize()
+									      , amount_sat: 100000
+									      })
+						   , change: None
+						   , funding: FundingOutput{ amount_sat: 99000
+									   , output_script: [vec!(0x00, 0x20), vec!(0x55; 32)].concat()
+									   }
+						   , order: Order::parse("fa").unwrap()
+						   , n_lock_time: 800001
+						   };
+		let template = funding::build_funding_tx(&secp256k1, bob, &request).unwrap();
+		let mut tx = template.tx.clone();
+		tx.input[0].witness = vec!(vec!(0; 64), vec!(0; 64), vec!(0; 68), vec!(0; 65));
+		CpfpParent{tx, fee, anchor: template.anchor_outpoint()}
+	}
+
+	fn wallet() -> Vec<WalletUtxo> {
+		(0..3).map(|i| WalletUtxo{ outpoint: OutPoint{txid: [0xB0 + i; 32], vout: i as u32}
+					 , txout: TxOut{ value: 3000
+						       , script_pubkey: [vec!(0x00, 0x14), vec!(i; 20)].concat()
+						       }
+					 , satisfaction_weight: 108
+					 })
+		.collect()
+	}
+
+	#[test]
+	fn test_build_anchor_cpfp() {
+		let secp256k1 = Secp256k1::new();
+		/* Bob public key with odd Y.  */
+		let bob = secret(6);
+		let bob_pubkey = PublicKey::from_secret_key(&secp256k1, &bob);
+		assert_eq!(bob_pubkey.serialize()[0], 0x03);
+		let parent = parent(&bob_pubkey, 1000);
+		let anchor = parent.anchor.clone();
+		let parent_weight = parent.tx.weight();
+		let change = [vec!(0x51, 0x20), vec!(0x77; 32)].concat();
+
+		/* The parent pays 1000 sat, far below 2500
+		 * sat/kWU, so the child needs two wallet outputs.
+		 */
+		let cpfp = build_anchor_cpfp(&secp256k1, &bob, &parent, &wallet(), &change, 2500)
+		.unwrap();
+		assert_eq!(cpfp.wallet_inputs, 2);
+		assert_eq!(cpfp.tx.input.len(), 3);
+		assert_eq!(cpfp.tx.input[0].prevout, anchor);
+		assert_eq!(cpfp.tx.input[1].prevout, wallet()[0].outpoint);
+		assert_eq!(cpfp.tx.output.len(), 1);
+		assert_eq!(cpfp.tx.output[0].script_pubkey, change);
+		assert_eq!(cpfp.tx.output[0].value, 330 + 6000 - cpfp.fee);
+		assert!(cpfp.tx.output[0].value >= CHANGE_DUST_LIMIT_SAT);
+		assert!((1000 + cpfp.fee) * 1000 >= 2500 * (parent_weight + cpfp.weight));
+		assert!((1000 + cpfp.fee - 1) * 1000 < 2500 * (parent_weight + cpfp.weight));
+
+		/* The anchor witness is valid, and the estimate
+		 * is an upper bound of the signed weight.
+		 */
+		let witness = &cpfp.tx.input[0].witness;
+		assert_eq!(witness.len(), 2);
+		assert_eq!(witness[1], address::anchor_witness_script(&bob_pubkey));
+		assert_eq!(witness[0].last(), Some(&0x01));
+		let sig = Signature::from_der(&witness[0][..witness[0].len() - 1]).unwrap();
+		let sighash = sighash::anchor_sighash(&cpfp.tx, 0, &bob_pubkey, 330).unwrap();
+		assert!(secp256k1.verify_ecdsa(&Message::from_digest(sighash), &sig, &bob_pubkey).is_ok());
+		let mut signed = cpfp.tx.clone();
+		for txin in signed.input[1..].iter_mut() {
+			txin.witness = vec!(vec!(0; 72), vec!(0; 33));
+		}
+		assert!(signed.weight() <= cpfp.weight);
+		assert!(signed.weight() + 2 >= cpfp.weight);
+
+		/* A parent that already pays enough: the child
+		 * only pays for itself.
+		 */
+		let parent = CpfpParent{fee: 100000, ..parent};
+		let cpfp = build_anchor_cpfp(&secp256k1, &bob, &parent, &wallet(), &change, 2500)
+		.unwrap();
+		assert_eq!(cpfp.wallet_inputs, 1);
+		assert_eq!(Some(cpfp.fee), fee_for(2500, cpfp.weight));
+	}
+
+	#[test]
+	fn test_build_anchor_cpfp_fails() {
+		let secp256k1 = Secp256k1::new();
+		let bob = secret(6);
+		let bob_pubkey = PublicKey::from_secret_key(&secp256k1, &bob);
+		let parent = parent(&bob_pubkey, 1000);
+		let anchor = parent.anchor.clone();
+		let change = [vec!(0x51, 0x20), vec!(0x77; 32)].concat();
+
+		assert_eq!( build_anchor_cpfp(&secp256k1, &bob, &parent, &wallet(), &change, 25000)
+			  , Err(CpfpError::InsufficientFunds)
+			  );
+		assert_eq!( build_anchor_cpfp(&secp256k1, &bob, &parent, &[], &change, 253)
+			  , Err(CpfpError::InsufficientFunds)
+			  );
+		/* A fee that overflows.  */
+		assert_eq!( build_anchor_cpfp(&secp256k1, &bob, &parent, &wallet(), &change, u64::MAX)
+			  , Err(CpfpError::InsufficientFunds)
+			  );
+		/* Amounts that overflow.  */
+		let mut huge = wallet();
+		huge[0].txout.value = u64::MAX;
+		assert_eq!( build_anchor_cpfp(&secp256k1, &bob, &parent, &huge, &change, 25000)
+			  , Err(CpfpError::Overflow)
+			  );
+		let mut huge = wallet();
+		huge[0].satisfaction_weight = u64::MAX;
+		assert_eq!( build_anchor_cpfp(&secp256k1, &bob, &parent, &huge, &change, 25000)
+			  , Err(CpfpError::Overflow)
+			  );
+
+		/* The funding output, an output that does not
+		 * exist, another transaction, and another Bob.
+		 */
+		let anchors = [ OutPoint{txid: anchor.txid, vout: 0}
+			      , OutPoint{txid: anchor.txid, vout: 2}
+			      , OutPoint{txid: [0; 32], vout: 1}
+			      ];
+		for other in anchors.iter() {
+			let parent = CpfpParent{anchor: other.clone(), ..parent.clone()};
+			assert_eq!( build_anchor_cpfp(&secp256k1, &bob, &parent, &wallet(), &change, 2500)
+				  , Err(CpfpError::InvalidAnchor)
+				  );
+		}
+		assert_eq!( build_anchor_cpfp(&secp256k1, &secret(7), &parent, &wallet(), &change, 2500)
+			  , Err(CpfpError::InvalidAnchor)
+			  );
+	}
+}
@@ -0,0 +1,670 @@
+/*!
+The `file_store` module contains `FileStore`, a
+`StateStore` that persists Bob's mapping to a single
+file, as a write-ahead log.
+
+The file is the 8-byte magic `SIPSTATE`, followed by
+records, each being a batch of changes given to
+`StateStore::set_all`:
+
+* The length of the payload, 4 bytes little-endian.
+* The first 4 bytes of the SHA256 of the length.
+* The first 4 bytes of the SHA256 of the payload.
+* The payload: the CompactSize number of changes,
+  followed by each change, as the 32-byte `txid`, the
+  4-byte little-endian `vout`, and a 1-byte state, with
+  0 deleting the output from the mapping.
+
+A record is appended in two steps, each followed by an
+`fsync`: first its payload, then its header.
+So a batch is either entirely in the file, or is at most
+a torn record at its end, whose header is not valid,
+and which is discarded when the file is next opened.
+A record with a valid header, on the other hand, is
+always complete: if its payload is missing or does not
+match, the file is corrupted.
+
+While a `FileStore` has the file open, it holds an
+advisory lock (`File::try_lock`) on a lock file next to
+it, named as the file with `.lock` appended.
+The operating system releases the lock when the
+`FileStore` is dropped, or its process dies, so a crash
+does not leave the store locked.
+The lock file itself is never deleted: deleting it would
+let two processes lock two different lock files.
+*/
+use hashes::sha2::sha256;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::TryLockError;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use super::bip341;
+use super::state::State;
+use super::state::StateStore;
+use super::tx::OutPoint;
+
+const MAGIC: &[u8; 8] = b"SIPSTATE";
+
+/* Size of a record header: payload length, its
+checksum, and the payload checksum.
+*/
+const HEADER_SIZE: usize = 12;
+
+fn encode_state(state: Option<State>) -> u8 {
+	match state {
+		None => 0,
+		Some(State::UnconfirmedAliceChange) => 1,
+		Some(State::ConfirmedAliceChange) => 2,
+		Some(State::AliceMoved) => 3,
+		Some(State::BobProvisionallySecured) => 4,
+		Some(State::BobSecured) => 5,
+		Some(State::BobRetriable) => 6
+	}
+}
+
+fn decode_state(b: u8) -> Option<Option<State>> {
+	match b {
+		0 => Some(None),
+		1 => Some(Some(State::UnconfirmedAliceChange)),
+		2 => Some(Some(State::ConfirmedAliceChange)),
+		3 => Some(Some(State::AliceMoved)),
+		4 => Some(Some(State::BobProvisionallySecured)),
+		5 => Some(Some(State::BobSecured)),
+		6 => Some(Some(State::BobRetriable)),
+		_ => None
+	}
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+	let h = sha256::hash(payload).into_bytes();
+	[h[0], h[1], h[2], h[3]]
+}
+
+fn encode_record(changes: &[(OutPoint, Option<State>)]) -> Vec<u8> {
+	let mut payload = Vec::new();
+	bip341::load_compactsize(&mut payload, changes.len());
+	for (outpoint, state) in changes.iter() {
+		payload.extend_from_slice(&outpoint.txid);
+		payload.extend_from_slice(&outpoint.vout.to_le_bytes());
+		payload.push(encode_state(*state));
+	}
+	let len = (payload.len() as u32).to_le_bytes();
+	let mut rv = Vec::with_capacity(HEADER_SIZE + payload.len());
+	rv.extend_from_slice(&len);
+	rv.extend_from_slice(&checksum(&len));
+	rv.extend_from_slice(&checksum(&payload));
+	rv.extend_from_slice(&payload);
+	rv
+}
+
+fn decode_payload(payload: &[u8]) -> Option<Vec<(OutPoint, Option<State>)>> {
+	let mut pos = 0;
+	let count = bip341::read_compactsize(payload, &mut pos)?;
+	let mut rv = Vec::new();
+	for _ in 0..count {
+		let change = payload.get(pos..pos + 37)?;
+		let mut txid = [0u8; 32];
+		txid.copy_from_slice(&change[0..32]);
+		let vout = u32::from_le_bytes(change[32..36].try_into().unwrap());
+		let state = decode_state(change[36])?;
+		rv.push((OutPoint{txid, vout}, state));
+		pos += 37;
+	}
+	if pos != payload.len() {
+		return None;
+	}
+	Some(rv)
+}
+
+/* Returns the header of the record at `pos`, if it is
+there and valid.
+*/
+fn read_header(buf: &[u8], pos: usize) -> Option<&[u8]> {
+	let header = buf.get(pos..pos + HEADER_SIZE)?;
+	if checksum(&header[0..4]) != header[4..8] {
+		return None;
+	}
+	Some(header)
+}
+
+fn header_len(header: &[u8]) -> usize {
+	u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize
+}
+
+/* Returns true if there is a complete and valid record
+anywhere from `pos` on.
+*/
+fn any_record(buf: &[u8], pos: usize) -> bool {
+	(pos..buf.len()).any(|p| {
+		let Some(header) = read_header(buf, p) else {
+			return false;
+		};
+		let start = p + HEADER_SIZE;
+		match buf.get(start..start + header_len(header)) {
+			Some(payload) => checksum(payload) == header[8..12],
+			None => false
+		}
+	})
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/* Makes a rename or creation of a file in the directory
+of `path` durable.
+*/
+fn sync_dir(path: &Path) -> io::Result<()> {
+	if cfg!(unix) {
+		let dir = match path.parent() {
+			Some(p) if !p.as_os_str().is_empty() => p,
+			_ => Path::new(".")
+		};
+		File::open(dir)?.sync_all()?;
+	}
+	Ok(())
+}
+
+/* Writes `contents` to a temporary file next to `path`,
+and returns its path, for renaming over `path`.
+*/
+fn write_tmp(path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+	let tmp_path = PathBuf::from(tmp_path);
+	let mut tmp = File::create(&tmp_path)?;
+	tmp.write_all(contents)?;
+	tmp.sync_all()?;
+	Ok(tmp_path)
+}
+
+/* Atomically replaces the file at `path` with one
+containing `contents`.
+*/
+fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+	let tmp_path = write_tmp(path, contents)?;
+	std::fs::rename(&tmp_path, path)?;
+	sync_dir(path)
+}
+
+fn open_rw(path: &Path) -> io::Result<File> {
+	OpenOptions::new().read(true).write(true).open(path)
+}
+
+/* Returns the path of the lock file of the store at
+`path`.
+*/
+fn lock_path(path: &Path) -> PathBuf {
+	let mut lock_path = path.as_os_str().to_owned();
+	lock_path.push(".lock");
+	PathBuf::from(lock_path)
+}
+
+/**
+`FileStore` is a `StateStore` that persists the mapping
+in a write-ahead log file, keeping a copy of the mapping
+in memory.
+
+Only one `FileStore` may have a file open at a time,
+which is enforced with a lock on a lock file.
+*/
+pub
+struct FileStore {
+	path: PathBuf,
+	/* The locked lock file, unlocked when closed.  */
+	_lock: File,
+	file: File,
+	/* Length of the valid part of the file.  */
+	len: u64,
+	map: HashMap<OutPoint, State>,
+	/* Set if a failed write could not be undone, or if
+	`file` may no longer be the file at `path`, after
+	which nothing more is written.
+	*/
+	poisoned: bool
+}
+
+impl FileStore {
+	/// `open` opens the store at `path`, creating an empty
+	/// one if it does not exist.
+	///
+	/// A torn record at the end of the file, left by a
+	/// crash during `set_all`, is discarded.
+	/// Fails with `io::ErrorKind::AlreadyExists` if the
+	/// store is already open, in this process or another.
+	/// Fails with `io::ErrorKind::InvalidData` if the file
+	/// is not a store, or is otherwise corrupted.
+	pub
+	fn open<P: AsRef<Path>>(path: P) -> io::Result<FileStore> {
+		let path = path.as_ref().to_path_buf();
+		let lock = OpenOptions::new().write(true).create(true).truncate(false)
+		.open(lock_path(&path))?;
+		match lock.try_lock() {
+			Ok(()) => {},
+			Err(TryLockError::WouldBlock) => {
+				return Err(io::Error::new( io::ErrorKind::AlreadyExists
+							 , "state file is already open"
+							 ));
+			},
+			Err(TryLockError::Error(e)) => { return Err(e); }
+		}
+		FileStore::open_locked(path, lock)
+	}
+
+	fn open_locked(path: PathBuf, lock: File) -> io::Result<FileStore> {
+		if !path.exists() {
+			write_file(&path, MAGIC)?;
+		}
+		let mut file = open_rw(&path)?;
+		let mut buf = Vec::new();
+		file.read_to_end(&mut buf)?;
+		if buf.len() < MAGIC.len() || buf[0..MAGIC.len()] != MAGIC[..] {
+			return Err(invalid_data("not a swap-in-potentiam state file"));
+		}
+
+		let mut map = HashMap::new();
+		let mut pos = MAGIC.len();
+		while pos < buf.len() {
+			let Some(header) = read_header(&buf, pos) else {
+				/* The header was not written, or only
+				partly, which can only be the case for
+				the last record; a valid record after it
+				means the header was corrupted instead.
+				*/
+				if any_record(&buf, pos + 1) {
+					return Err(invalid_data("corrupted record header"));
+				}
+				break;
+			};
+			let end = pos + HEADER_SIZE + header_len(header);
+			let payload = buf.get(pos + HEADER_SIZE..end)
+			.ok_or_else(|| invalid_data("record past the end of the file"))?;
+			if checksum(payload) != header[8..12] {
+				return Err(invalid_data("corrupted record"));
+			}
+			let changes = decode_payload(payload)
+			.ok_or_else(|| invalid_data("invalid record"))?;
+			for (outpoint, state) in changes {
+				match state {
+					Some(state) => { map.insert(outpoint, state); },
+					None => { map.remove(&outpoint); }
+				}
+			}
+			pos = end;
+		}
+
+		let len = pos as u64;
+		if len != buf.len() as u64 {
+			file.set_len(len)?;
+			file.sync_all()?;
+		}
+		Ok(FileStore{ path
+			    , _lock: lock
+			    , file
+			    , len
+			    , map
+			    , poisoned: false
+			    })
+	}
+
+	/// `compact` rewrites the file with a single record
+	/// of the current mapping, discarding the history of
+	/// changes and the outputs that were deleted.
+	///
+	/// If it fails after the rewritten file replaced the
+	/// old one, the store is poisoned: the rewritten file
+	/// is complete, but the store can no longer write to
+	/// it, and has to be opened again.
+	pub
+	fn compact(&mut self) -> io::Result<()> {
+		self.compact_with(open_rw)
+	}
+
+	/* `compact`, with `reopen` opening the rewritten
+	file, so that tests can make it fail.
+	*/
+	fn compact_with<F>(&mut self, reopen: F) -> io::Result<()>
+		where F: FnOnce(&Path) -> io::Result<File>
+	{
+		if self.poisoned {
+			return Err(io::Error::other("store is poisoned"));
+		}
+		let mut contents = MAGIC.to_vec();
+		if !self.map.is_empty() {
+			let changes: Vec<(OutPoint, Option<State>)> = self.map.iter()
+			.map(|(o, s)| (o.clone(), Some(*s)))
+			.collect();
+			contents.extend_from_slice(&encode_record(&changes));
+		}
+		let tmp_path = write_tmp(&self.path, &contents)?;
+		std::fs::rename(&tmp_path, &self.path)?;
+		/* `self.file` is now the old file, which is no
+		longer at `path`.
+		*/
+		let file = sync_dir(&self.path).and_then(|_| reopen(&self.path));
+		match file {
+			Ok(file) => {
+				self.file = file;
+				self.len = contents.len() as u64;
+				Ok(())
+			},
+			Err(e) => {
+				self.poisoned = true;
+				Err(e)
+			}
+		}
+	}
+
+	/// `len` returns the number of outputs in the
+	/// mapping.
+	pub
+	fn len(&self) -> usize {
+		self.map.len()
+	}
+
+	/// `is_empty` returns true if there are no outputs in
+	/// the mapping.
+	pub
+	fn is_empty(&self) -> bool {
+		self.map.is_empty()
+	}
+
+	fn append(&mut self, record: &[u8]) -> io::Result<()> {
+		/* The payload must be durable before the
+		header is written, so that a valid header is
+		never followed by a torn payload.
+		*/
+		self.file.seek(SeekFrom::Start(self.len + HEADER_SIZE as u64))?;
+		self.file.write_all(&record[HEADER_SIZE..])?;
+		self.file.sync_data()?;
+		self.file.seek(SeekFrom::Start(self.len))?;
+		self.file.write_all(&record[0..HEADER_SIZE])?;
+		self.file.sync_data()
+	}
+}
+
+impl StateStore for FileStore {
+	fn get(&self, outpoint: &OutPoint) -> Option<State> {
+		self.map.get(outpoint).copied()
+	}
+
+	fn set_all(&mut self, changes: &[(OutPoint, Option<State>)]) -> Option<()> {
+		if self.poisoned {
+			return None;
+		}
+		let record = encode_record(changes);
+		if self.append(&record).is_err() {
+			/* Remove whatever part of the record was
+			written, so that later records are not
+			after a torn one.
+			*/
+			let undone = self.file.set_len(self.len)
+			.and_then(|_| self.file.sync_data());
+			if undone.is_err() {
+				self.poisoned = true;
+			}
+			return None;
+		}
+		self.len += record.len() as u64;
+		for (outpoint, state) in changes.iter() {
+			match state {
+				Some(state) => { self.map.insert(outpoint.clone(), *state); },
+				None => { self.map.remove(outpoint); }
+			}
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::state::BatchError;
+	use super::super::state::Transition;
+
+	/* A path in the temporary directory, not existing.  */
+	fn temp_path(name: &str) -> PathBuf {
+		let mut path = std::env::temp_dir();
+		path.push(format!("sip-file-store-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_file(&path);
+		let _ = std::fs::remove_file(lock_path(&path));
+		path
+	}
+
+	fn remove_store(path: &Path) {
+		std::fs::remove_file(path).unwrap();
+		std::fs::remove_file(lock_path(path)).unwrap();
+	}
+
+	fn outpoint(n: u8) -> OutPoint {
+		OutPoint{txid: [n; 32], vout: n as u32}
+	}
+
+	#[test]
+	fn test_persistence() {
+		let path = temp_path("persistence");
+		{
+			let mut store = FileStore::open(&path).unwrap();
+			assert!(store.is_empty());
+			assert_eq!( store.transition_all(&[ (outpoint(1), Transition::ZeroconfRequest)
+							  , (outpoint(2), Transition::AliceChangeCreated)
+							  , (outpoint(3), Transition::OnchainRequest)
+							  ])
+				  , Ok(())
+				  );
+			assert_eq!( store.transition_all(&[ (outpoint(1), Transition::ZeroconfAbortedInput)
+							  , (outpoint(2), Transition::ZeroconfAbortedChange)
+							  ])
+				  , Ok(())
+				  );
+			/* Rejected, so not written.  */
+			assert!(store.transition_all(&[(outpoint(3), Transition::ZeroconfRequest)]).is_err());
+		}
+		let check = |store: &FileStore| {
+			assert_eq!(store.len(), 2);
+			assert_eq!(store.get(&outpoint(1)), Some(State::BobRetriable));
+			assert_eq!(store.get(&outpoint(2)), None);
+			assert_eq!(store.get(&outpoint(3)), Some(State::AliceMoved));
+		};
+		let mut store = FileStore::open(&path).unwrap();
+		check(&store);
+
+		let before = std::fs::metadata(&path).unwrap().len();
+		store.compact().unwrap();
+		assert!(std::fs::metadata(&path).unwrap().len() < before);
+		check(&store);
+		assert_eq!( store.transition_all(&[(outpoint(4), Transition::OnchainRequest)])
+			  , Ok(())
+			  );
+		drop(store);
+		let store = FileStore::open(&path).unwrap();
+		assert_eq!(store.len(), 3);
+		assert_eq!(store.get(&outpoint(1)), Some(State::BobRetriable));
+		assert_eq!(store.get(&outpoint(4)), Some(State::AliceMoved));
+
+		remove_store(&path);
+	}
+
+	#[test]
+	fn test_compact_reopen_fails() {
+		let path = temp_path("reopen");
+		let mut store = FileStore::open(&path).unwrap();
+		store.transition_all(&[(outpoint(1), Transition::OnchainRequest)]).unwrap();
+		store.transition_all(&[(outpoint(2), Transition::AliceChangeCreated)]).unwrap();
+		store.transition_all(&[(outpoint(2), Transition::ZeroconfAbortedChange)]).unwrap();
+
+		let e = store.compact_with(|_| Err(io::Error::other("cannot reopen")))
+		.err().unwrap();
+		assert_eq!(e.to_string(), "cannot reopen");
+		/* Writes would go to the replaced file.  */
+		assert_eq!( store.transition_all(&[(outpoint(3), Transition::OnchainRequest)])
+			  , Err(BatchError::Store)
+			  );
+		assert!(store.compact().is_err());
+		drop(store);
+
+		/* The rewritten file is complete.  */
+		let store = FileStore::open(&path).unwrap();
+		assert_eq!(store.len(), 1);
+		assert_eq!(store.get(&outpoint(1)), Some(State::AliceMoved));
+		assert_eq!(store.get(&outpoint(3)), None);
+		assert_eq!( std::fs::metadata(&path).unwrap().len()
+			  , (MAGIC.len() + HEADER_SIZE + 1 + 37) as u64
+			  );
+		drop(store);
+
+		remove_store(&path);
+	}
+
+	#[test]
+	fn test_torn_record() {
+		let path = temp_path("torn");
+		let mut store = FileStore::open(&path).unwrap();
+		store.transition_all(&[(outpoint(1), Transition::OnchainRequest)]).unwrap();
+		drop(store);
+		let good = std::fs::read(&path).unwrap();
+
+		let record = encode_record(&[ (outpoint(2), Some(State::AliceMoved))
+					    , (outpoint(3), Some(State::AliceMoved))
+					    ]);
+		/* A crash part way through writing the record:
+		 * the payload only partly written, or entirely
+		 * but the header not, or only partly.
+		 */
+		let mut no_header = vec!(0; HEADER_SIZE);
+		no_header.extend_from_slice(&record[HEADER_SIZE..]);
+		let mut torn_header = no_header.clone();
+		torn_header[0..6].copy_from_slice(&record[0..6]);
+		for torn in [&record[0..5], &no_header[0..HEADER_SIZE + 10], &no_header[..], &torn_header[..]] {
+			let mut contents = good.clone();
+			contents.extend_from_slice(torn);
+			std::fs::write(&path, &contents).unwrap();
+
+			let mut store = FileStore::open(&path).unwrap();
+			assert_eq!(store.len(), 1);
+			assert_eq!(store.get(&outpoint(2)), None);
+			assert_eq!(std::fs::read(&path).unwrap(), good);
+
+			store.transition_all(&[(outpoint(4), Transition::OnchainRequest)]).unwrap();
+			drop(store);
+			let store = FileStore::open(&path).unwrap();
+			assert_eq!(store.len(), 2);
+		}
+
+		/* A valid header is only written after its
+		 * payload, so a missing or mismatched payload is
+		 * not a torn record.
+		 */
+		let mut bad_checksum = record.clone();
+		bad_checksum[HEADER_SIZE + 40] ^= 1;
+		let mut bad_payloads = vec!(&record[0..HEADER_SIZE + 10], &bad_checksum[..]);
+		/* Nor is a corrupted record that is not the
+		 * last.
+		 */
+		let mut not_last = bad_checksum.clone();
+		not_last.extend_from_slice(&record);
+		bad_payloads.push(&not_last[..]);
+		for bad in bad_payloads {
+			let mut contents = good.clone();
+			contents.extend_from_slice(bad);
+			std::fs::write(&path, &contents).unwrap();
+			assert_eq!( FileStore::open(&path).err().unwrap().kind()
+				  , io::ErrorKind::InvalidData
+				  );
+			assert_eq!(std::fs::read(&path).unwrap(), contents);
+		}
+
+		/* Not a state file.  */
+		std::fs::write(&path, b"SIPSTAT").unwrap();
+		assert_eq!( FileStore::open(&path).err().unwrap().kind()
+			  , io::ErrorKind::InvalidData
+			  );
+
+		remove_store(&path);
+	}
+
+	#[test]
+	fn test_corrupted_length() {
+		let path = temp_path("length");
+		let mut store = FileStore::open(&path).unwrap();
+		store.transition_all(&[(outpoint(1), Transition::OnchainRequest)]).unwrap();
+		store.transition_all(&[(outpoint(2), Transition::OnchainRequest)]).unwrap();
+		drop(store);
+		let good = std::fs::read(&path).unwrap();
+
+		/* The length of the first record corrupted to
+		 * point within the second record, or past the end
+		 * of the file, would otherwise make the second
+		 * record look like a torn one.
+		 */
+		for len in [1u32, 37, 1000] {
+			let mut contents = good.clone();
+			contents[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&len.to_le_bytes());
+			std::fs::write(&path, &contents).unwrap();
+			assert_eq!( FileStore::open(&path).err().unwrap().kind()
+				  , io::ErrorKind::InvalidData
+				  );
+			assert_eq!(std::fs::read(&path).unwrap(), contents);
+		}
+
+		std::fs::write(&path, &good).unwrap();
+		assert_eq!(FileStore::open(&path).unwrap().len(), 2);
+		remove_store(&path);
+	}
+
+	#[test]
+	fn test_lock() {
+		let path = temp_path("lock");
+		let store = FileStore::open(&path).unwrap();
+		assert_eq!( FileStore::open(&path).err().unwrap().kind()
+			  , io::ErrorKind::AlreadyExists
+			  );
+		/* The failed open left the lock alone.  */
+		assert_eq!( FileStore::open(&path).err().unwrap().kind()
+			  , io::ErrorKind::AlreadyExists
+			  );
+		drop(store);
+		let store = FileStore::open(&path).unwrap();
+		drop(store);
+
+		/* A lock file left by a crash is not locked.  */
+		assert!(lock_path(&path).exists());
+		FileStore::open(&path).unwrap();
+
+		/* A failed open releases the lock.  */
+		std::fs::write(&path, b"SIPSTAT").unwrap();
+		assert_eq!( FileStore::open(&path).err().unwrap().kind()
+			  , io::ErrorKind::InvalidData
+			  );
+		std::fs::write(&path, MAGIC).unwrap();
+		FileStore::open(&path).unwrap();
+
+		remove_store(&path);
+	}
+
+	#[test]
+	fn test_record_encoding() {
+		let changes = [ (outpoint(1), None)
+			      , (outpoint(2), Some(State::UnconfirmedAliceChange))
+			      , (outpoint(3), Some(State::ConfirmedAliceChange))
+			      , (outpoint(4), Some(State::AliceMoved))
+			      , (outpoint(5), Some(State::BobProvisionallySecured))
+			      , (outpoint(6), Some(State::BobSecured))
+			      , (outpoint(7), Some(State::BobRetriable))
+			      ];
+		let record = encode_record(&changes);
+		assert_eq!(record.len(), HEADER_SIZE + 1 + 7 * 37);
+		assert_eq!(decode_payload(&record[HEADER_SIZE..]), Some(changes.to_vec()));
+		assert_eq!(decode_payload(&record[HEADER_SIZE..record.len() - 1]), None);
+		let mut bad_state = record.clone();
+		*bad_state.last_mut().unwrap() = 7;
+		assert_eq!(decode_payload(&bad_state[HEADER_SIZE..]), None);
+	}
+}
@@ -0,0 +1,310 @@
+/*!
+The `funding` module builds the unsigned channel funding
+transaction of `c=.sip.sign_funding_bob` from the
+parameters of the call.
+
+The specification defines the transaction exactly, so
+that the client and the LSP each build it from the same
+parameters, and sign the same transaction:
+
+* `nVersion` is 2, and `nLockTime` is the `nLockTime`
+  parameter.
+* The inputs are the `prev_out`s of the `inputs`, in
+  order, each with an empty `scriptSig` and an
+  `nSequence` of `0xFFFFFFFD`.
+* The outputs are the funding output, the anchor output
+  of Bob, and the optional change output of Alice, in
+  the given `order`.
+*/
+use secp256k1::PublicKey;
+use secp256k1::Secp256k1;
+use secp256k1::Verification;
+use super::address;
+use super::api::OutputKind;
+use super::api::SignFundingBobRequest;
+use super::tx::OutPoint;
+use super::tx::Transaction;
+use super::tx::TxIn;
+use super::tx::TxOut;
+
+/**
+`ANCHOR_AMOUNT_SAT` is the amount of the anchor output
+of Bob, in satoshis.
+*/
+pub
+const ANCHOR_AMOUNT_SAT: u64 = 330;
+
+/**
+`FUNDING_SEQUENCE` is the `nSequence` of every input of
+the funding transaction, which opts in to RBF.
+*/
+pub
+const FUNDING_SEQUENCE: u32 = 0xFFFFFFFD;
+
+/**
+`FUNDING_VERSION` is the `nVersion` of the funding
+transaction.
+*/
+pub
+const FUNDING_VERSION: u32 = 2;
+
+/**
+`FundingTemplate` is the unsigned funding transaction,
+with the positions of its outputs.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct FundingTemplate {
+	/// The transaction, without witnesses.
+	pub tx: Transaction,
+	/// The txid of `tx`.
+	pub txid: [u8; 32],
+	/// The outputs spent by the inputs of `tx`, in
+	/// input order, as needed to compute the signature
+	/// hashes of the inputs.
+	pub prevouts: Vec<TxOut>,
+	pub funding_index: u32,
+	pub anchor_index: u32,
+	pub change_index: Option<u32>
+}
+
+impl FundingTemplate {
+	/// `funding_outpoint` returns the channel funding
+	/// outpoint.
+	pub
+	fn funding_outpoint(&self) -> OutPoint {
+		OutPoint{txid: self.txid, vout: self.funding_index}
+	}
+
+	/// `anchor_outpoint` returns the outpoint of the
+	/// anchor output.
+	pub
+	fn anchor_outpoint(&self) -> OutPoint {
+		OutPoint{txid: self.txid, vout: self.anchor_index}
+	}
+
+	/// `change_outpoint` returns the outpoint of the change
+	/// output, if any.
+	pub
+	fn change_outpoint(&self) -> Option<OutPoint> {
+		self.change_index.map(|vout| OutPoint{txid: self.txid, vout})
+	}
+}
+
+/**
+`build_funding_tx` builds the unsigned funding
+transaction of `request`, where `bob` is the LSP node
+ID.
+
+It does not validate the request against the state of
+the LSP or the blockchain, only that the transaction can
+be built: it fails (returns None) if an `alice_pubkey`
+is not a valid public key, or if `order` has a change
+output but `request` has no `change`, or vice versa.
+*/
+pub
+fn build_funding_tx<C>( secp256k1: &Secp256k1<C>
+		      , bob: &PublicKey
+		      , request: &SignFundingBobRequest
+		      ) -> Option<FundingTemplate>
+	where C: Verification
+{
+	if request.order.has_change() != request.change.is_some() {
+		return None;
+	}
+
+	let mut input = Vec::with_capacity(request.inputs.len());
+	let mut prevouts = Vec::with_capacity(request.inputs.len());
+	for funding_input in request.inputs.iter() {
+		let alice = PublicKey::from_slice(&funding_input.alice_pubkey).ok()?;
+		let script_pubkey = address::derive_taproot_scriptpubkey(
+			secp256k1, &alice, bob
+		)?;
+		input.push(TxIn{ prevout: funding_input.prev_out.clone()
+			       , script_sig: Vec::new()
+			       , sequence: FUNDING_SEQUENCE
+			       , witness: Vec::new()
+			       });
+		prevouts.push(TxOut{ value: funding_input.amount_sat
+				   , script_pubkey
+				   });
+	}
+
+	let mut output = Vec::with_capacity(request.order.outputs().len());
+	for kind in request.order.outputs().iter() {
+		let txout = match kind {
+			OutputKind::Funding => TxOut{ value: request.funding.amount_sat
+						    , script_pubkey: request.funding.output_script.clone()
+						    },
+			OutputKind::Anchor => TxOut{ value: ANCHOR_AMOUNT_SAT
+						   , script_pubkey: address::derive_anchor_scriptpubkey(bob)
+						   },
+			OutputKind::Change => {
+				let change = request.change.as_ref()?;
+				let alice = PublicKey::from_slice(&change.alice_pubkey).ok()?;
+				TxOut{ value: change.amount_sat
+				     , script_pubkey: address::derive_taproot_scriptpubkey(
+					secp256k1, &alice, bob
+				       )?
+				     }
+			}
+		};
+		output.push(txout);
+	}
+
+	let tx = Transaction{ version: FUNDING_VERSION
+			    , lock_time: request.n_lock_time
+			    , input
+			    , output
+			    };
+	let txid = tx.txid();
+	let index = |kind| request.order.index(kind).map(|i| i as u32);
+	Some(FundingTemplate{ tx
+			    , txid
+			    , prevouts
+			    , funding_index: index(OutputKind::Funding)?
+			    , anchor_index: index(OutputKind::Anchor)?
+			    , change_index: index(OutputKind::Change)
+			    })
+}
+
+#[cfg(test)]
+mod tests {
+	use secp256k1::SecretKey;
+	use super::*;
+	use super::super::api::ChangeOutput;
+	use super::super::api::FundingInput;
+	use super::super::api::FundingOutput;
+	use super::super::api::Order;
+
+	fn pubkey(n: u8) -> PublicKey {
+		let secp256k1 = Secp256k1::new();
+		let sk = SecretKey::from_slice(&[n; 32]).unwrap();
+		PublicKey::from_secret_key(&secp256k1, &sk)
+	}
+
+	fn request(order: &str) -> SignFundingBobRequest {
+		let change = if order.contains('c') {
+			Some(ChangeOutput{ amount_sat: 99999
+					 , alice_pubkey: pubkey(3).serialize()
+					 })
+		} else {
+			None
+		};
+		SignFundingBobRequest{ temporary_channel_id: [0x42; 32]
+				     , current_blockheight: 424691
+				     , inputs: vec!(
+					FundingInput{ prev_out: OutPoint{txid: [0xA1; 32], vout: 2}
+						    , alice_pubkey: pubkey(1).serialize()
+						    , amount_sat: 100000
+						    },
+					FundingInput{ prev_out: OutPoint{txid: [0xA2; 32], vout: 0}
+						    , alice_pubkey: pubkey(2).serialize()
+						    , amount_sat: 19920
+						    }
+				       )
+				     , change
+				     , funding: FundingOutput{ amount_sat: 19000
+							     , output_script: [vec!(0x00, 0x20), vec!(0x55; 32)].concat()
+							     }
+				     , order: Order::parse(order).unwrap()
+				     , n_lock_time: 424692
+				     }
+	}
+
+	#[test]
+	fn test_build_funding_tx() {
+		let secp256k1 = Secp256k1::new();
+		let bob = pubkey(9);
+		let anchor_spk = address::derive_anchor_scriptpubkey(&bob);
+
+		for order in ["fa", "af", "fac", "fca", "afc", "acf", "cfa", "caf"] {
+			let req = request(order);
+			let t = build_funding_tx(&secp256k1, &bob, &req).unwrap();
+			assert_eq!(t.tx.version, 2);
+			assert_eq!(t.tx.lock_time, 424692);
+			assert_eq!(t.tx.input.len(), 2);
+			for (i, txin) in t.tx.input.iter().enumerate() {
+				assert_eq!(txin.prevout, req.inputs[i].prev_out);
+				assert_eq!(txin.sequence, 0xFFFFFFFD);
+				assert!(txin.script_sig.is_empty());
+				assert!(txin.witness.is_empty());
+				assert_eq!(t.prevouts[i].value, req.inputs[i].amount_sat);
+				assert_eq!( Some(t.prevouts[i].script_pubkey.clone())
+					  , address::derive_taproot_scriptpubkey(&secp256k1, &pubkey(i as u8 + 1), &bob)
+					  );
+			}
+			assert_eq!(t.txid, t.tx.txid());
+
+			assert_eq!(t.tx.output.len(), order.len());
+			assert_eq!(t.funding_index as usize, order.find('f').unwrap());
+			assert_eq!(t.anchor_index as usize, order.find('a').unwrap());
+			assert_eq!(t.change_index.map(|i| i as usize), order.find('c'));
+			let funding = &t.tx.output[t.funding_index as usize];
+			assert_eq!(funding.value, 19000);
+			assert_eq!(funding.script_pubkey, req.funding.output_script);
+			let anchor = &t.tx.output[t.anchor_index as usize];
+			assert_eq!(anchor.value, 330);
+			assert_eq!(anchor.script_pubkey, anchor_spk);
+			if let Some(change_index) = t.change_index {
+				let change = &t.tx.output[change_index as usize];
+				assert_eq!(change.value, 99999);
+				assert_eq!( Some(change.script_pubkey.clone())
+					  , address::derive_taproot_scriptpubkey(&secp256k1, &pubkey(3), &bob)
+					  );
+			}
+			assert_eq!(t.funding_outpoint(), OutPoint{txid: t.txid, vout: t.funding_index});
+			assert_eq!(t.change_outpoint().is_some(), order.contains('c'));
+
+			/* Both sides build the same transaction.  */
+			let json = serde_json::to_string(&req).unwrap();
+			let echoed: SignFundingBobRequest = serde_json::from_str(&json).unwrap();
+			assert_eq!(build_funding_tx(&secp256k1, &bob, &echoed), Some(t));
+		}
+	}
+
+	#[test]
+	fn test_anchor_output() {
+		/* The anchor output is the anchor of Bob, which
+		 * the address tests check against the BOLT 3
+		 * vectors.
+		 */
+		let secp256k1 = Secp256k1::new();
+		for bob in [pubkey(8), pubkey(9)] {
+			let t = build_funding_tx(&secp256k1, &bob, &request("fac")).unwrap();
+			let anchor = &t.tx.output[t.anchor_index as usize];
+			assert_eq!(anchor.value, ANCHOR_AMOUNT_SAT);
+			assert_eq!(anchor.script_pubkey, address::derive_anchor_scriptpubkey(&bob));
+		}
+	}
+
+	#[test]
+	fn test_build_funding_tx_fails() {
+		let secp256k1 = Secp256k1::new();
+		let bob = pubkey(9);
+
+		/* There is no point with X coordinate 0.  */
+		let mut req = request("fa");
+		req.inputs[1].alice_pubkey = [0; 33];
+		req.inputs[1].alice_pubkey[0] = 0x02;
+		assert_eq!(build_funding_tx(&secp256k1, &bob, &req), None);
+		let mut req = request("fca");
+		req.change.as_mut().unwrap().alice_pubkey = req.inputs[1].alice_pubkey;
+		req.change.as_mut().unwrap().alice_pubkey[0] = 0x04;
+		assert_eq!(build_funding_tx(&secp256k1, &bob, &req), None);
+
+		let mut req = request("fa");
+		req.change = request("fac").change;
+		assert_eq!(build_funding_tx(&secp256k1, &bob, &req), None);
+
+		let mut req = request("fac");
+		req.change = None;
+		assert_eq!(build_funding_tx(&secp256k1, &bob, &req), None);
+
+		/* Another Bob gives another transaction.  */
+		let req = request("fa");
+		assert_ne!( build_funding_tx(&secp256k1, &bob, &req).unwrap().txid
+			  , build_funding_tx(&secp256k1, &pubkey(8), &req).unwrap().txid
+			  );
+	}
+}
@@ -1,15 +1,40 @@
 pub mod address;
-mod bip327;
+pub mod api;
+mod base64;
+pub mod bip327;
 mod bip340;
 mod bip341;
 mod bip350;
+#[cfg(feature = "bitcoin")]
+pub mod bitcoin_interop;
+pub mod chain;
+pub mod cpfp;
+pub mod file_store;
+pub mod funding;
+pub mod lsps0;
+pub mod promise;
+pub mod prune;
+pub mod psbt;
+pub mod recovery;
 mod scalars;
 mod scripts;
+pub mod sighash;
+pub mod sign_psbt_bob;
+pub mod sip_info;
+pub mod state;
+pub mod tx;
+pub mod witness;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub
 enum Network {
 	Mainnet,
 	Testnet,
-	Regtest
+	Regtest,
+	/// Signet uses the same address HRP as testnet.
+	Signet,
+	/// Any other network, identified by the BIP-173
+	/// human-readable part of its SegWit addresses,
+	/// e.g. a regtest with a non-default HRP.
+	Custom(String)
 }
@@ -0,0 +1,340 @@
+/*!
+The `lsps0` module contains the common JSON types of
+[LSPS0][] used by the swap-in-potentiam API: the
+`<LSPS0 sat>` amount, which is a JSON string of a
+decimal number of satoshis, the `<LSPS0 outpoint>`,
+which is a JSON string of the displayed txid, a colon,
+and the decimal output index, and the
+`<LSPS0 datetime>`, which is an ISO 8601 UTC date and
+time, e.g. `2024-01-18T14:42:24.000Z`.
+
+[LSPS0]: https://github.com/BitcoinAndLightningLayerSpecs/lsp/blob/main/LSPS0/README.md
+*/
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::fmt;
+use super::tx::OutPoint;
+
+/**
+`parse_sat` parses an `<LSPS0 sat>` string, which must
+be only decimal digits.
+*/
+pub
+fn parse_sat(s: &str) -> Option<u64> {
+	if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+		return None;
+	}
+	s.parse().ok()
+}
+
+/**
+`sat` is a `serde` `with` module for `u64` amounts that
+are `<LSPS0 sat>` strings in JSON.
+*/
+pub
+mod sat {
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+
+	pub
+	fn serialize<S>(amount: &u64, serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		serializer.serialize_str(&amount.to_string())
+	}
+
+	pub
+	fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+		where D: Deserializer<'de>
+	{
+		let s = String::deserialize(deserializer)?;
+		super::parse_sat(&s)
+		.ok_or_else(|| D::Error::custom("invalid <LSPS0 sat>"))
+	}
+}
+
+/**
+`parse_outpoint` parses an `<LSPS0 outpoint>` string,
+`<txid>:<vout>`, where `txid` is 64 hexadecimal digits
+in the usual displayed byte order.
+*/
+pub
+fn parse_outpoint(s: &str) -> Option<OutPoint> {
+	let (txid_s, vout_s) = s.split_once(':')?;
+	if txid_s.len() != 64 {
+		return None;
+	}
+	let mut txid = [0u8; 32];
+	hex::decode_to_slice(txid_s, &mut txid).ok()?;
+	txid.reverse();
+	if vout_s.is_empty() || !vout_s.bytes().all(|c| c.is_ascii_digit()) {
+		return None;
+	}
+	let vout = vout_s.parse().ok()?;
+	Some(OutPoint{txid, vout})
+}
+
+/**
+`format_outpoint` returns the `<LSPS0 outpoint>` string
+of `outpoint`.
+*/
+pub
+fn format_outpoint(outpoint: &OutPoint) -> String {
+	let mut txid = outpoint.txid;
+	txid.reverse();
+	format!("{}:{}", hex::encode(txid), outpoint.vout)
+}
+
+/**
+`outpoint` is a `serde` `with` module for `OutPoint`s
+that are `<LSPS0 outpoint>` strings in JSON.
+*/
+pub
+mod outpoint {
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+	use super::super::tx::OutPoint;
+
+	pub
+	fn serialize<S>(outpoint: &OutPoint, serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		serializer.serialize_str(&super::format_outpoint(outpoint))
+	}
+
+	pub
+	fn deserialize<'de, D>(deserializer: D) -> Result<OutPoint, D::Error>
+		where D: Deserializer<'de>
+	{
+		let s = String::deserialize(deserializer)?;
+		super::parse_outpoint(&s)
+		.ok_or_else(|| D::Error::custom("invalid <LSPS0 outpoint>"))
+	}
+}
+
+/* Days since 1970-01-01 of the given date in the
+proleptic Gregorian calendar, from Howard Hinnant's
+`days_from_civil`.
+*/
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = y.div_euclid(400);
+	let yoe = y - era * 400;
+	let mp = (m as i64 + 9) % 12;
+	let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+/* The inverse of `days_from_civil`.  */
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+	let z = z + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+	let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+	(y, m, d)
+}
+
+fn is_leap_year(y: i64) -> bool {
+	(y % 4 == 0 && y % 100 != 0) || y % 400 == 0
+}
+
+fn days_in_month(y: i64, m: u32) -> u32 {
+	match m {
+		2 => if is_leap_year(y) { 29 } else { 28 },
+		4 | 6 | 9 | 11 => 30,
+		_ => 31
+	}
+}
+
+/**
+`Datetime` is an `<LSPS0 datetime>`, with millisecond
+precision, as milliseconds since the Unix epoch.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub
+struct Datetime {
+	pub unix_millis: u64
+}
+
+impl Datetime {
+	/// `from_unix_millis` returns the `Datetime` the given
+	/// number of milliseconds after the Unix epoch.
+	pub
+	fn from_unix_millis(unix_millis: u64) -> Datetime {
+		Datetime{unix_millis}
+	}
+
+	/// `now` returns the current time.
+	pub
+	fn now() -> Datetime {
+		let d = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.expect("system time after the Unix epoch");
+		Datetime{unix_millis: d.as_millis() as u64}
+	}
+
+	/// `parse` parses an `<LSPS0 datetime>` of the form
+	/// `YYYY-MM-DDThh:mm:ss.sssZ`, where the fraction of a
+	/// second is optional and may have any number of
+	/// digits, of which only the first three are kept.
+	/// Fails on any other form, including other time
+	/// zones, and on dates before 1970.
+	pub
+	fn parse(s: &str) -> Option<Datetime> {
+		let b = s.as_bytes();
+		if !s.is_ascii() || b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[10] != b'T'
+		|| b[13] != b':' || b[16] != b':' || b[b.len() - 1] != b'Z' {
+			return None;
+		}
+		let num = |r: std::ops::Range<usize>| -> Option<u32> {
+			let d = &s[r];
+			if !d.bytes().all(|c| c.is_ascii_digit()) {
+				return None;
+			}
+			d.parse().ok()
+		};
+		let y = num(0..4)? as i64;
+		let mo = num(5..7)?;
+		let d = num(8..10)?;
+		let h = num(11..13)?;
+		let mi = num(14..16)?;
+		let sec = num(17..19)?;
+		if y < 1970 || !(1..=12).contains(&mo) || d < 1 || d > days_in_month(y, mo)
+		|| h > 23 || mi > 59 || sec > 59 {
+			return None;
+		}
+		let frac = &s[19..s.len() - 1];
+		let millis = if frac.is_empty() {
+			0
+		} else {
+			let digits = frac.strip_prefix('.')?;
+			if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+				return None;
+			}
+			let mut ms = 0u64;
+			for c in format!("{:0<3}", digits).bytes().take(3) {
+				ms = ms * 10 + (c - b'0') as u64;
+			}
+			ms
+		};
+		let days = days_from_civil(y, mo, d) as u64;
+		let secs = days * 86400 + (h as u64) * 3600 + (mi as u64) * 60 + sec as u64;
+		Some(Datetime{unix_millis: secs * 1000 + millis})
+	}
+}
+
+/* Formats as `YYYY-MM-DDThh:mm:ss.sssZ`.  */
+impl fmt::Display for Datetime {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let secs = self.unix_millis / 1000;
+		let (y, mo, d) = civil_from_days((secs / 86400) as i64);
+		let s = secs % 86400;
+		write!( f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z"
+		      , y, mo, d, s / 3600, (s / 60) % 60, s % 60
+		      , self.unix_millis % 1000
+		      )
+	}
+}
+
+impl Serialize for Datetime {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+		where S: Serializer
+	{
+		serializer.serialize_str(&self.to_string())
+	}
+}
+
+impl<'de> Deserialize<'de> for Datetime {
+	fn deserialize<D>(deserializer: D) -> Result<Datetime, D::Error>
+		where D: Deserializer<'de>
+	{
+		let s = String::deserialize(deserializer)?;
+		Datetime::parse(&s)
+		.ok_or_else(|| serde::de::Error::custom("invalid <LSPS0 datetime>"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_datetime() {
+		let vectors = [ ("1970-01-01T00:00:00.000Z", 0)
+			      , ("2024-01-18T14:42:24.000Z", 1705588944000)
+			      , ("2023-02-23T08:47:30.511Z", 1677142050511)
+			      , ("2000-02-29T23:59:59.999Z", 951868799999)
+			      , ("2100-03-01T00:00:00.000Z", 4107542400000)
+			      ];
+		for (s, ms) in vectors {
+			assert_eq!(Datetime::parse(s), Some(Datetime::from_unix_millis(ms)), "{}", s);
+			assert_eq!(Datetime::from_unix_millis(ms).to_string(), s);
+		}
+		assert_eq!( Datetime::parse("2024-01-18T14:42:24Z")
+			  , Datetime::parse("2024-01-18T14:42:24.000Z")
+			  );
+		assert_eq!( Datetime::parse("2024-01-18T14:42:24.5Z")
+			  , Datetime::parse("2024-01-18T14:42:24.500Z")
+			  );
+		assert_eq!( Datetime::parse("2024-01-18T14:42:24.123456789Z")
+			  , Datetime::parse("2024-01-18T14:42:24.123Z")
+			  );
+		for s in [ "2024-01-18T14:42:24.000", "2024-01-18T14:42:24.000+00:00"
+			 , "2024-01-18 14:42:24.000Z", "2024-1-18T14:42:24.000Z"
+			 , "2023-02-29T00:00:00.000Z", "2024-13-01T00:00:00.000Z"
+			 , "2024-01-18T24:00:00.000Z", "2024-01-18T14:42:60.000Z"
+			 , "2024-01-18T14:42:24.Z", "2024-01-18T14:42:24.+12Z"
+			 , "1969-12-31T23:59:59.999Z", "+024-01-18T14:42:24.000Z"
+			 , "2024-01-18T14:42:24.\u{e9}Z"
+			 ] {
+			assert_eq!(Datetime::parse(s), None, "{}", s);
+		}
+		assert!(Datetime::parse("2024-01-18T14:42:24.000Z").unwrap() < Datetime::now());
+	}
+
+	#[test]
+	fn test_sat() {
+		assert_eq!(parse_sat("0"), Some(0));
+		assert_eq!(parse_sat("10000000000"), Some(10000000000));
+		assert_eq!(parse_sat("18446744073709551615"), Some(u64::MAX));
+		for s in ["", "-1", "+1", "1.0", " 1", "1e3", "18446744073709551616"] {
+			assert_eq!(parse_sat(s), None, "{}", s);
+		}
+	}
+
+	#[test]
+	fn test_outpoint() {
+		let s = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211:2";
+		let outpoint = parse_outpoint(s).unwrap();
+		assert_eq!(outpoint.txid[0], 0x11);
+		assert_eq!(outpoint.txid[31], 0xfe);
+		assert_eq!(outpoint.vout, 2);
+		assert_eq!(format_outpoint(&outpoint), s);
+		assert_eq!( parse_outpoint("FEDCBA9876543210FEDCBA9876543210FEDCBA9876543210FEDCBA9876543211:2")
+			  , Some(outpoint)
+			  );
+		for s in [ "fedcba9876543210fedcba9876543210fedcba9876543210fedcba98765432:2"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211:"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211:-1"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211:+1"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211:4294967296"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba987654321g:2"
+			 , "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543211:2:3"
+			 ] {
+			assert_eq!(parse_outpoint(s), None, "{}", s);
+		}
+	}
+}
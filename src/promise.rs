@@ -0,0 +1,214 @@
+/*!
+The `promise` module lets the LSP mint the `promise` of
+a `sip_offchain_info` object as a MAC of its other
+fields, and later verify a `sip_offchain_info` given
+back by the client, without storing the objects it has
+returned.
+
+The `promise` is the hexadecimal HMAC-SHA256, keyed by
+a secret of the LSP, of a domain tag followed by
+`SipOffchainInfo::canonical_serialization`.
+*/
+use hashes::sha2::sha256;
+use super::lsps0::Datetime;
+use super::sip_info::SipOffchainInfo;
+
+/* Prefixed to the MACed data, so that MACs with the
+same key for other purposes are not valid promises.
+*/
+const TAG: &[u8] = b"swap-in-potentiam/promise/v1";
+
+const BLOCK_SIZE: usize = 64;
+
+/* HMAC-SHA256, as specified in RFC 2104.  */
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+	let mut k = [0u8; BLOCK_SIZE];
+	if key.len() > BLOCK_SIZE {
+		k[0..32].copy_from_slice(&sha256::hash(key).into_bytes());
+	} else {
+		k[0..key.len()].copy_from_slice(key);
+	}
+
+	let mut inner = Vec::with_capacity(BLOCK_SIZE + data.len());
+	inner.extend(k.iter().map(|b| b ^ 0x36));
+	inner.extend_from_slice(data);
+	let inner = sha256::hash(&inner).into_bytes();
+
+	let mut outer = Vec::with_capacity(BLOCK_SIZE + 32);
+	outer.extend(k.iter().map(|b| b ^ 0x5C));
+	outer.extend_from_slice(&inner);
+	sha256::hash(&outer).into_bytes()
+}
+
+/* Compares without exiting early, so that the time
+taken does not reveal how much of a forged promise is
+correct.
+*/
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/**
+`PromiseError` is the reason a `sip_offchain_info` is
+not accepted.
+Both are reported to the client as
+`invalid_or_unknown_promise`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum PromiseError {
+	/// The `promise` was not minted by the LSP for
+	/// the other fields of the object.
+	InvalidPromise,
+	/// The `valid_until` of the object is in the
+	/// past.
+	Expired
+}
+
+impl PromiseError {
+	/// `code` returns the JSON-RPC error `code`,
+	/// `invalid_or_unknown_promise`.
+	pub
+	fn code(&self) -> i64 {
+		2
+	}
+}
+
+/**
+`PromiseKey` is the secret key of the LSP that its
+promises are minted with.
+*/
+#[derive(Clone)]
+pub
+struct PromiseKey {
+	secret: [u8; 32]
+}
+
+impl PromiseKey {
+	/// `new` creates a `PromiseKey` from a secret, which
+	/// should be random, and kept for as long as any
+	/// promise minted with it is valid.
+	pub
+	fn new(secret: [u8; 32]) -> PromiseKey {
+		PromiseKey{secret}
+	}
+
+	fn mac(&self, info: &SipOffchainInfo) -> [u8; 32] {
+		let mut data = TAG.to_vec();
+		data.extend_from_slice(&info.canonical_serialization());
+		hmac_sha256(&self.secret, &data)
+	}
+
+	/// `mint` returns the `promise` for the fields of
+	/// `info` other than its `promise`.
+	pub
+	fn mint(&self, info: &SipOffchainInfo) -> String {
+		hex::encode(self.mac(info))
+	}
+
+	/// `verify` checks that the `promise` of `info` was
+	/// minted by `mint` with this key for the other fields
+	/// of `info`, and that its `valid_until` is not before
+	/// `now`.
+	pub
+	fn verify( &self
+		 , info: &SipOffchainInfo
+		 , now: Datetime
+		 ) -> Result<(), PromiseError>
+	{
+		let expected = hex::encode(self.mac(info));
+		if !constant_time_eq(expected.as_bytes(), info.promise.as_bytes()) {
+			return Err(PromiseError::InvalidPromise);
+		}
+		if info.valid_until < now {
+			return Err(PromiseError::Expired);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex;
+	use super::*;
+	use super::super::sip_info::FeeScheduleEntry;
+
+	/* RFC 4231 section 4.  */
+	#[test]
+	fn test_hmac_sha256() {
+		let vectors: [(Vec<u8>, &[u8], &str); 3] = [
+			( vec!(0x0b; 20), b"Hi There"
+			, "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+			),
+			( b"Jefe".to_vec(), b"what do ya want for nothing?"
+			, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+			),
+			( vec!(0xaa; 131), b"Test Using Larger Than Block-Size Key - Hash Key First"
+			, "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+			)
+		];
+		for (key, data, mac) in vectors {
+			assert_eq!(hex::encode(hmac_sha256(&key, data)), mac);
+		}
+	}
+
+	fn info() -> SipOffchainInfo {
+		SipOffchainInfo{ min_confirmations: 3
+			       , onchain_fee_schedule: vec!(
+				FeeScheduleEntry{max_deadline: 288, min_feerate: 50000},
+				FeeScheduleEntry{max_deadline: 576, min_feerate: 25000}
+			       )
+			       , min_offchain_size_sat: 100000000
+			       , max_offchain_size_sat: 10000000000
+			       , valid_until: Datetime::parse("2024-01-18T14:42:24.000Z").unwrap()
+			       , promise: String::new()
+			       }
+	}
+
+	#[test]
+	fn test_promise() {
+		let key = PromiseKey::new([0x42; 32]);
+		let mut info = info();
+		info.promise = key.mint(&info);
+		assert_eq!(info.promise.len(), 64);
+		assert_eq!(info.validate(), Ok(()));
+
+		let now = Datetime::parse("2024-01-18T13:42:24.000Z").unwrap();
+		assert_eq!(key.verify(&info, now), Ok(()));
+		assert_eq!(key.verify(&info, info.valid_until), Ok(()));
+
+		/* Round-trips through JSON with extra fields.  */
+		let mut v: serde_json::Value = serde_json::from_str(&info.to_json()).unwrap();
+		v["extra"] = serde_json::json!("field");
+		let echoed = SipOffchainInfo::from_json(&v.to_string()).unwrap();
+		assert_eq!(key.verify(&echoed, now), Ok(()));
+
+		let later = Datetime::from_unix_millis(info.valid_until.unix_millis + 1);
+		assert_eq!(key.verify(&info, later), Err(PromiseError::Expired));
+
+		/* Changing any field invalidates the promise.  */
+		let changes: [fn(&mut SipOffchainInfo); 7] = [
+			|i| i.min_confirmations = 2,
+			|i| i.onchain_fee_schedule[0].max_deadline = 289,
+			|i| i.onchain_fee_schedule[1].min_feerate = 24999,
+			|i| { i.onchain_fee_schedule.pop(); },
+			|i| i.max_offchain_size_sat += 1,
+			|i| i.valid_until.unix_millis += 3600000,
+			|i| i.promise = i.promise.to_uppercase()
+		];
+		for change in changes {
+			let mut changed = info.clone();
+			change(&mut changed);
+			assert_eq!(key.verify(&changed, now), Err(PromiseError::InvalidPromise));
+			assert_eq!(key.verify(&changed, now).unwrap_err().code(), 2);
+		}
+
+		/* Another key.  */
+		let other = PromiseKey::new([0x43; 32]);
+		assert_eq!(other.verify(&info, now), Err(PromiseError::InvalidPromise));
+		assert_ne!(other.mint(&info), info.promise);
+	}
+}
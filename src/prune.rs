@@ -0,0 +1,262 @@
+/*!
+The `prune` module contains `Pruner`, which deletes
+outputs from Bob's mapping once they can be forgotten.
+
+The specification lets Bob forget the state of a
+transaction output once the transaction spending it
+has confirmed for 100 blocks, the same as coinbase
+maturity, on the assumption that no chain
+reorganization is that deep.
+Until then, the output must be remembered, even if it
+is spent, as a reorganization could unspend it.
+
+The `Pruner` only keeps track of spends in memory.
+After a restart, replaying `Pruner::block_connected`
+for the last `FORGET_DEPTH` blocks recovers them; if
+that is not done, outputs spent before the restart are
+never forgotten, which wastes space but is safe.
+*/
+use std::collections::HashMap;
+use super::state::State;
+use super::state::StateStore;
+use super::tx::OutPoint;
+
+/**
+`FORGET_DEPTH` is the number of confirmations that the
+spending transaction of an output must have before
+Bob can forget the output.
+*/
+pub
+const FORGET_DEPTH: u32 = 100;
+
+/**
+`Spend` is the spending of a transaction output by a
+transaction in a block.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct Spend {
+	pub outpoint: OutPoint,
+	pub spending_txid: [u8; 32]
+}
+
+/**
+`Pruner` tracks the spends of the outputs in Bob's
+mapping, and deletes them from the mapping once the
+spend has `FORGET_DEPTH` confirmations.
+
+It must be given every block connected to and
+disconnected from Bob's view of the best chain, in
+order.
+*/
+#[derive(Clone, Debug, Default)]
+pub
+struct Pruner {
+	/* The spending txid and the height of the block
+	it is in, of each spent output in the mapping.
+	*/
+	spends: HashMap<OutPoint, ([u8; 32], u32)>
+}
+
+impl Pruner {
+	/// `new` creates a `Pruner` tracking no spends.
+	pub
+	fn new() -> Pruner {
+		Pruner{spends: HashMap::new()}
+	}
+
+	/// `block_connected` records the `spends` in the
+	/// block at `height`, which is the new chain tip, then
+	/// deletes from `store` all outputs whose spend now
+	/// has at least `FORGET_DEPTH` confirmations.
+	///
+	/// Returns the deleted outputs, or None if `store`
+	/// failed to delete them, in which case they are
+	/// deleted on a later block.
+	pub
+	fn block_connected<S>( &mut self
+			     , store: &mut S
+			     , height: u32
+			     , spends: &[Spend]
+			     ) -> Option<Vec<OutPoint>>
+		where S: StateStore
+	{
+		for spend in spends.iter() {
+			/* Spends of outputs that are not in
+			the mapping do not need to be tracked.
+			*/
+			if store.get(&spend.outpoint).is_none() {
+				continue;
+			}
+			self.spends.insert( spend.outpoint.clone()
+					  , (spend.spending_txid, height)
+					  );
+		}
+
+		let changes: Vec<(OutPoint, Option<State>)> = self.spends.iter()
+		.filter(|(_, (_, h))| {
+			*h <= height && height - *h + 1 >= FORGET_DEPTH
+		})
+		.map(|(outpoint, _)| (outpoint.clone(), None))
+		.collect();
+		if changes.is_empty() {
+			return Some(Vec::new());
+		}
+		store.set_all(&changes)?;
+		let mut rv = Vec::new();
+		for (outpoint, _) in changes {
+			self.spends.remove(&outpoint);
+			rv.push(outpoint);
+		}
+		Some(rv)
+	}
+
+	/// `block_disconnected` undoes the `spends` in the
+	/// block at `height`, which is removed from the chain
+	/// by a reorganization, so that the outputs they spent
+	/// are remembered until they are spent again.
+	pub
+	fn block_disconnected( &mut self
+			     , height: u32
+			     , spends: &[Spend]
+			     )
+	{
+		for spend in spends.iter() {
+			let tracked = self.spends.get(&spend.outpoint);
+			if tracked == Some(&(spend.spending_txid, height)) {
+				self.spends.remove(&spend.outpoint);
+			}
+		}
+	}
+
+	/// `spend_height` returns the height of the block
+	/// with the tracked spend of `outpoint`, if any.
+	pub
+	fn spend_height(&self, outpoint: &OutPoint) -> Option<u32> {
+		self.spends.get(outpoint).map(|(_, h)| *h)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn outpoint(n: u8) -> OutPoint {
+		OutPoint{txid: [n; 32], vout: n as u32}
+	}
+
+	fn spend(n: u8, txid: u8) -> Spend {
+		Spend{outpoint: outpoint(n), spending_txid: [txid; 32]}
+	}
+
+	struct FailingStore(HashMap<OutPoint, State>);
+	impl StateStore for FailingStore {
+		fn get(&self, outpoint: &OutPoint) -> Option<State> {
+			self.0.get(outpoint).copied()
+		}
+		fn set_all(&mut self, _: &[(OutPoint, Option<State>)]) -> Option<()> {
+			None
+		}
+	}
+
+	#[test]
+	fn test_prune() {
+		let mut store = HashMap::new();
+		store.insert(outpoint(1), State::AliceMoved);
+		store.insert(outpoint(2), State::BobSecured);
+		let mut pruner = Pruner::new();
+
+		/* outpoint(3) is not in the mapping.  */
+		assert_eq!( pruner.block_connected(&mut store, 1000, &[spend(1, 0xA1), spend(3, 0xA3)])
+			  , Some(Vec::new())
+			  );
+		assert_eq!(pruner.spend_height(&outpoint(3)), None);
+		assert_eq!( pruner.block_connected(&mut store, 1001, &[spend(2, 0xA2)])
+			  , Some(Vec::new())
+			  );
+		for height in 1002..1099 {
+			assert_eq!( pruner.block_connected(&mut store, height, &[])
+				  , Some(Vec::new())
+				  );
+		}
+		assert_eq!(store.len(), 2);
+		/* The spend of outpoint(1) now has 100
+		 * confirmations.
+		 */
+		assert_eq!( pruner.block_connected(&mut store, 1099, &[])
+			  , Some(vec!(outpoint(1)))
+			  );
+		assert_eq!(store.len(), 1);
+		assert_eq!(pruner.spend_height(&outpoint(1)), None);
+		assert_eq!( pruner.block_connected(&mut store, 1100, &[])
+			  , Some(vec!(outpoint(2)))
+			  );
+		assert!(store.is_empty());
+	}
+
+	#[test]
+	fn test_reorg() {
+		let mut store = HashMap::new();
+		store.insert(outpoint(1), State::AliceMoved);
+		store.insert(outpoint(2), State::AliceMoved);
+		let mut pruner = Pruner::new();
+
+		pruner.block_connected(&mut store, 1000, &[spend(1, 0xA1)]).unwrap();
+		pruner.block_connected(&mut store, 1001, &[spend(2, 0xA2)]).unwrap();
+		for height in 1002..1050 {
+			pruner.block_connected(&mut store, height, &[]).unwrap();
+		}
+
+		/* Reorganization of the blocks from 1001: the
+		 * spend of outpoint(2) is unconfirmed, then
+		 * confirmed again later by another transaction.
+		 */
+		for height in (1001..1050).rev() {
+			let spends = if height == 1001 { vec!(spend(2, 0xA2)) } else { Vec::new() };
+			pruner.block_disconnected(height, &spends);
+		}
+		assert_eq!(pruner.spend_height(&outpoint(1)), Some(1000));
+		assert_eq!(pruner.spend_height(&outpoint(2)), None);
+		for height in 1001..1099 {
+			let spends = if height == 1010 { vec!(spend(2, 0xB2)) } else { Vec::new() };
+			assert_eq!( pruner.block_connected(&mut store, height, &spends)
+				  , Some(Vec::new())
+				  );
+		}
+		assert_eq!( pruner.block_connected(&mut store, 1099, &[])
+			  , Some(vec!(outpoint(1)))
+			  );
+		for height in 1100..1109 {
+			assert_eq!( pruner.block_connected(&mut store, height, &[])
+				  , Some(Vec::new())
+				  );
+		}
+		/* Disconnecting a block with a spend that is
+		 * not the tracked one does not affect it.
+		 */
+		pruner.block_disconnected(1001, &[spend(2, 0xA2)]);
+		assert_eq!(pruner.spend_height(&outpoint(2)), Some(1010));
+		assert_eq!( pruner.block_connected(&mut store, 1109, &[])
+			  , Some(vec!(outpoint(2)))
+			  );
+		assert!(store.is_empty());
+	}
+
+	#[test]
+	fn test_store_failure() {
+		let mut store = HashMap::new();
+		store.insert(outpoint(1), State::AliceMoved);
+		let mut store = FailingStore(store);
+		let mut pruner = Pruner::new();
+
+		pruner.block_connected(&mut store, 1000, &[spend(1, 0xA1)]).unwrap();
+		assert_eq!(pruner.block_connected(&mut store, 1099, &[]), None);
+		assert_eq!(pruner.spend_height(&outpoint(1)), Some(1000));
+
+		let mut store = store.0;
+		assert_eq!( pruner.block_connected(&mut store, 1100, &[])
+			  , Some(vec!(outpoint(1)))
+			  );
+		assert!(store.is_empty());
+	}
+}
@@ -0,0 +1,1605 @@
+/*!
+The `psbt` module parses and serializes [BIP-174][]
+Partially Signed Bitcoin Transactions, version 2
+([BIP-370][]), as exchanged by `c=.sip.sign_psbt_bob`.
+
+A `Psbt` is kept as its raw key-value maps: one global
+map, one map per input, and one map per output, each in
+the order the pairs appear in the serialization.
+Pairs that this module does not know about are kept
+as-is, so a parse-then-serialize round trip returns the
+exact same bytes.
+Typed accessors are provided for the fields needed to
+reconstruct the unsigned transaction, and for the
+[BIP-371][] Taproot fields.
+
+Version 0 PSBTs (which carry the unsigned transaction
+in `PSBT_GLOBAL_UNSIGNED_TX`), as well as any other
+version besides 2, are rejected with
+`PsbtError::UnsupportedPsbtVersion`, which corresponds
+to the `unsupported_psbt_version` error of
+`c=.sip.sign_psbt_bob`.
+All other failures are `PsbtError::InvalidPsbt`, which
+corresponds to `invalid_psbt`.
+
+[BIP-174]: https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
+[BIP-370]: https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+use super::base64;
+use super::bip340;
+use super::bip341;
+use super::tx::OutPoint;
+use super::tx::Transaction;
+use super::tx::TxIn;
+use super::tx::TxOut;
+
+pub const PSBT_GLOBAL_UNSIGNED_TX: u64 = 0x00;
+pub const PSBT_GLOBAL_XPUB: u64 = 0x01;
+pub const PSBT_GLOBAL_TX_VERSION: u64 = 0x02;
+pub const PSBT_GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+pub const PSBT_GLOBAL_INPUT_COUNT: u64 = 0x04;
+pub const PSBT_GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+pub const PSBT_GLOBAL_TX_MODIFIABLE: u64 = 0x06;
+pub const PSBT_GLOBAL_VERSION: u64 = 0xFB;
+pub const PSBT_GLOBAL_PROPRIETARY: u64 = 0xFC;
+
+pub const PSBT_IN_NON_WITNESS_UTXO: u64 = 0x00;
+pub const PSBT_IN_WITNESS_UTXO: u64 = 0x01;
+pub const PSBT_IN_PARTIAL_SIG: u64 = 0x02;
+pub const PSBT_IN_SIGHASH_TYPE: u64 = 0x03;
+pub const PSBT_IN_REDEEM_SCRIPT: u64 = 0x04;
+pub const PSBT_IN_WITNESS_SCRIPT: u64 = 0x05;
+pub const PSBT_IN_BIP32_DERIVATION: u64 = 0x06;
+pub const PSBT_IN_FINAL_SCRIPTSIG: u64 = 0x07;
+pub const PSBT_IN_FINAL_SCRIPTWITNESS: u64 = 0x08;
+pub const PSBT_IN_PREVIOUS_TXID: u64 = 0x0e;
+pub const PSBT_IN_OUTPUT_INDEX: u64 = 0x0f;
+pub const PSBT_IN_SEQUENCE: u64 = 0x10;
+pub const PSBT_IN_REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+pub const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
+pub const PSBT_IN_TAP_KEY_SIG: u64 = 0x13;
+pub const PSBT_IN_TAP_SCRIPT_SIG: u64 = 0x14;
+pub const PSBT_IN_TAP_LEAF_SCRIPT: u64 = 0x15;
+pub const PSBT_IN_TAP_BIP32_DERIVATION: u64 = 0x16;
+pub const PSBT_IN_TAP_INTERNAL_KEY: u64 = 0x17;
+pub const PSBT_IN_TAP_MERKLE_ROOT: u64 = 0x18;
+pub const PSBT_IN_PROPRIETARY: u64 = 0xFC;
+
+pub const PSBT_OUT_REDEEM_SCRIPT: u64 = 0x00;
+pub const PSBT_OUT_WITNESS_SCRIPT: u64 = 0x01;
+pub const PSBT_OUT_BIP32_DERIVATION: u64 = 0x02;
+pub const PSBT_OUT_AMOUNT: u64 = 0x03;
+pub const PSBT_OUT_SCRIPT: u64 = 0x04;
+pub const PSBT_OUT_TAP_INTERNAL_KEY: u64 = 0x05;
+pub const PSBT_OUT_TAP_TREE: u64 = 0x06;
+pub const PSBT_OUT_TAP_BIP32_DERIVATION: u64 = 0x07;
+pub const PSBT_OUT_PROPRIETARY: u64 = 0xFC;
+
+const MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xFF];
+
+/* Locktimes below this are block heights, at or above it
+are UNIX timestamps.
+*/
+const LOCKTIME_THRESHOLD: u32 = 500000000;
+
+/**
+`PsbtError` is the reason a PSBT failed to parse.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum PsbtError {
+	/// Not parseable as a PSBT, or not a valid PSBT.
+	InvalidPsbt,
+	/// A PSBT of the given `PSBT_GLOBAL_VERSION`, which
+	/// is not supported.
+	/// A PSBT without `PSBT_GLOBAL_VERSION` is version 0.
+	UnsupportedPsbtVersion(u32)
+}
+
+/**
+`KeyValue` is a single key-value pair in a PSBT map.
+
+The key is split into its `key_type` and `key_data`
+(which is empty for most key types).
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct KeyValue {
+	pub key_type: u64,
+	pub key_data: Vec<u8>,
+	pub value: Vec<u8>
+}
+
+/**
+`Map` is a PSBT map, i.e. a sequence of key-value pairs
+with unique keys.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub
+struct Map {
+	pub pairs: Vec<KeyValue>
+}
+
+impl Map {
+	pub
+	fn new() -> Map {
+		Map{pairs: Vec::new()}
+	}
+
+	/// `get` returns the value of the key with the given
+	/// `key_type` and `key_data`, if present.
+	pub
+	fn get(&self, key_type: u64, key_data: &[u8]) -> Option<&[u8]> {
+		self.pairs.iter()
+		.find(|kv| kv.key_type == key_type && kv.key_data == key_data)
+		.map(|kv| &kv.value[..])
+	}
+
+	/// `get_all` returns all the pairs with the given
+	/// `key_type`, whatever their `key_data`.
+	pub
+	fn get_all(&self, key_type: u64) -> impl Iterator<Item = &KeyValue> {
+		self.pairs.iter().filter(move |kv| kv.key_type == key_type)
+	}
+
+	/// `contains_type` returns true if there is any pair
+	/// with the given `key_type`.
+	pub
+	fn contains_type(&self, key_type: u64) -> bool {
+		self.get_all(key_type).next().is_some()
+	}
+
+	/// `insert` sets the value of the key with the given
+	/// `key_type` and `key_data`, replacing the existing
+	/// value in-place if the key is already present, or
+	/// appending a new pair otherwise.
+	pub
+	fn insert( &mut self
+		 , key_type: u64
+		 , key_data: Vec<u8>
+		 , value: Vec<u8>
+		 )
+	{
+		match self.pairs.iter_mut()
+		.find(|kv| kv.key_type == key_type && kv.key_data == key_data) {
+			Some(kv) => { kv.value = value; },
+			None => {
+				self.pairs.push(KeyValue{key_type, key_data, value});
+			}
+		}
+	}
+
+	/// `remove` removes the key with the given `key_type`
+	/// and `key_data`, returning its value if it was
+	/// present.
+	pub
+	fn remove(&mut self, key_type: u64, key_data: &[u8]) -> Option<Vec<u8>> {
+		let i = self.pairs.iter()
+		.position(|kv| kv.key_type == key_type && kv.key_data == key_data)?;
+		Some(self.pairs.remove(i).value)
+	}
+
+	/* The value of a key type that has no key data.  */
+	fn get_fixed(&self, key_type: u64) -> Option<&[u8]> {
+		self.get(key_type, &[])
+	}
+
+	fn get_u32(&self, key_type: u64) -> Option<u32> {
+		let v = self.get_fixed(key_type)?;
+		Some(u32::from_le_bytes(v.try_into().ok()?))
+	}
+
+	fn get_u64(&self, key_type: u64) -> Option<u64> {
+		let v = self.get_fixed(key_type)?;
+		Some(u64::from_le_bytes(v.try_into().ok()?))
+	}
+
+	fn get_compactsize(&self, key_type: u64) -> Option<u64> {
+		let v = self.get_fixed(key_type)?;
+		let mut pos = 0;
+		let n = bip341::read_compactsize(v, &mut pos)?;
+		if pos != v.len() {
+			return None;
+		}
+		Some(n)
+	}
+}
+
+/**
+`TapScriptSig` is a [BIP-371][] `PSBT_IN_TAP_SCRIPT_SIG`,
+the signature of `xonly_pubkey` for the tapleaf with the
+hash `leaf_hash`.
+
+`sig` is 64 bytes, or 65 bytes with the sighash type
+appended.
+
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct TapScriptSig {
+	pub xonly_pubkey: [u8; 32],
+	pub leaf_hash: [u8; 32],
+	pub sig: Vec<u8>
+}
+
+/**
+`TapLeafScript` is a [BIP-371][]
+`PSBT_IN_TAP_LEAF_SCRIPT`, a tapleaf `script` of the
+given `leaf_version`, together with the
+`control_block` needed to spend it.
+
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct TapLeafScript {
+	pub control_block: Vec<u8>,
+	pub script: Vec<u8>,
+	pub leaf_version: u8
+}
+
+/**
+`TapBip32Derivation` is a [BIP-371][]
+`PSBT_IN_TAP_BIP32_DERIVATION` or
+`PSBT_OUT_TAP_BIP32_DERIVATION`: the hashes of the
+tapleaves `xonly_pubkey` is used in, and the BIP-32
+master key `fingerprint` and derivation `path` of the
+key.
+
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct TapBip32Derivation {
+	pub xonly_pubkey: [u8; 32],
+	pub leaf_hashes: Vec<[u8; 32]>,
+	pub fingerprint: [u8; 4],
+	pub path: Vec<u32>
+}
+
+/**
+`TapTreeLeaf` is a single leaf of a [BIP-371][]
+`PSBT_OUT_TAP_TREE`, at the given `depth` in the tree.
+
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct TapTreeLeaf {
+	pub depth: u8,
+	pub leaf_version: u8,
+	pub script: Vec<u8>
+}
+
+/* BIP-341 limits the depth of the tree, and thus the
+number of hashes in a control block, to 128.
+*/
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+fn parse_xonly(v: &[u8]) -> Option<[u8; 32]> {
+	let xonly: [u8; 32] = v.try_into().ok()?;
+	bip340::lift_x(&xonly)?;
+	Some(xonly)
+}
+
+/* A Schnorr signature, with an optional sighash type
+byte which may not be an explicit SIGHASH_DEFAULT.
+*/
+fn parse_tap_sig(v: &[u8]) -> Option<Vec<u8>> {
+	match v.len() {
+		64 => { },
+		65 => {
+			let h = v[64];
+			if h == bip341::SIGHASH_DEFAULT || !bip341::is_valid_hash_type(h) {
+				return None;
+			}
+		},
+		_ => { return None; }
+	}
+	Some(v.to_vec())
+}
+
+fn parse_tap_script_sig(kv: &KeyValue) -> Option<TapScriptSig> {
+	if kv.key_data.len() != 64 {
+		return None;
+	}
+	Some(TapScriptSig{
+		xonly_pubkey: parse_xonly(&kv.key_data[0..32])?,
+		leaf_hash: kv.key_data[32..64].try_into().ok()?,
+		sig: parse_tap_sig(&kv.value)?
+	})
+}
+
+fn parse_tap_leaf_script(kv: &KeyValue) -> Option<TapLeafScript> {
+	let cb = &kv.key_data;
+	if cb.len() < 33
+	|| !(cb.len() - 33).is_multiple_of(32)
+	|| (cb.len() - 33) / 32 > TAPROOT_CONTROL_MAX_NODE_COUNT {
+		return None;
+	}
+	parse_xonly(&cb[1..33])?;
+	let (leaf_version, script) = kv.value.split_last()?;
+	Some(TapLeafScript{
+		control_block: cb.clone(),
+		script: script.to_vec(),
+		leaf_version: *leaf_version
+	})
+}
+
+fn parse_tap_bip32_derivation(kv: &KeyValue) -> Option<TapBip32Derivation> {
+	let xonly_pubkey = parse_xonly(&kv.key_data)?;
+	let v = &kv.value;
+	let mut pos = 0;
+	let num_hashes = bip341::read_compactsize(v, &mut pos)? as usize;
+	let mut leaf_hashes = Vec::new();
+	for _ in 0..num_hashes {
+		leaf_hashes.push(v.get(pos..pos + 32)?.try_into().ok()?);
+		pos += 32;
+	}
+	let fingerprint = v.get(pos..pos + 4)?.try_into().ok()?;
+	pos += 4;
+	let rest = &v[pos..];
+	if !rest.len().is_multiple_of(4) {
+		return None;
+	}
+	let path = rest.chunks(4)
+		   .map(|c| u32::from_le_bytes(c.try_into().expect("chunk of 4")))
+		   .collect();
+	Some(TapBip32Derivation{xonly_pubkey, leaf_hashes, fingerprint, path})
+}
+
+fn tap_bip32_derivation_pair( key_type: u64
+			    , d: &TapBip32Derivation
+			    ) -> KeyValue
+{
+	let mut value = Vec::new();
+	bip341::load_compactsize(&mut value, d.leaf_hashes.len());
+	for h in d.leaf_hashes.iter() {
+		value.extend_from_slice(h);
+	}
+	value.extend_from_slice(&d.fingerprint);
+	for i in d.path.iter() {
+		value.extend_from_slice(&i.to_le_bytes());
+	}
+	KeyValue{key_type, key_data: d.xonly_pubkey.to_vec(), value}
+}
+
+/* The leaves must be in depth-first order and form a
+complete binary tree.
+*/
+fn parse_tap_tree(v: &[u8]) -> Option<Vec<TapTreeLeaf>> {
+	let mut leaves = Vec::new();
+	/* Depths of the subtrees not yet merged with their
+	sibling.
+	*/
+	let mut stack: Vec<u8> = Vec::new();
+	let mut pos = 0;
+	while pos < v.len() {
+		let depth = v[pos];
+		let leaf_version = *v.get(pos + 1)?;
+		pos += 2;
+		let len = bip341::read_compactsize(v, &mut pos)? as usize;
+		let script = v.get(pos..pos.checked_add(len)?)?.to_vec();
+		pos += len;
+		if depth as usize > TAPROOT_CONTROL_MAX_NODE_COUNT
+		|| leaf_version & 1 != 0
+		|| stack.last() == Some(&0) {
+			return None;
+		}
+		leaves.push(TapTreeLeaf{depth, leaf_version, script});
+
+		let mut d = depth;
+		while stack.last() == Some(&d) && d > 0 {
+			stack.pop();
+			d -= 1;
+		}
+		stack.push(d);
+	}
+	if stack != [0] {
+		return None;
+	}
+	Some(leaves)
+}
+
+fn check_taproot_input(input: &Map) -> Option<()> {
+	for kv in input.pairs.iter() {
+		match kv.key_type {
+			PSBT_IN_TAP_KEY_SIG => { parse_tap_sig(&kv.value)?; },
+			PSBT_IN_TAP_SCRIPT_SIG => { parse_tap_script_sig(kv)?; },
+			PSBT_IN_TAP_LEAF_SCRIPT => { parse_tap_leaf_script(kv)?; },
+			PSBT_IN_TAP_BIP32_DERIVATION => { parse_tap_bip32_derivation(kv)?; },
+			PSBT_IN_TAP_INTERNAL_KEY => { parse_xonly(&kv.value)?; },
+			_ => { }
+		}
+	}
+	Some(())
+}
+
+fn check_taproot_output(output: &Map) -> Option<()> {
+	for kv in output.pairs.iter() {
+		match kv.key_type {
+			PSBT_OUT_TAP_INTERNAL_KEY => { parse_xonly(&kv.value)?; },
+			PSBT_OUT_TAP_TREE => { parse_tap_tree(&kv.value)?; },
+			PSBT_OUT_TAP_BIP32_DERIVATION => { parse_tap_bip32_derivation(kv)?; },
+			_ => { }
+		}
+	}
+	Some(())
+}
+
+/**
+`Psbt` is a version 2 PSBT.
+
+The maps can be modified freely (for example, to add a
+signature), but adding or removing inputs and outputs
+also requires updating `PSBT_GLOBAL_INPUT_COUNT` and
+`PSBT_GLOBAL_OUTPUT_COUNT`, as `serialize` writes the
+maps as-is.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct Psbt {
+	pub global: Map,
+	pub inputs: Vec<Map>,
+	pub outputs: Vec<Map>
+}
+
+/* Reads a single map, failing on truncated input or
+duplicate keys.
+*/
+fn read_map(buf: &[u8], pos: &mut usize) -> Option<Map> {
+	let mut map = Map::new();
+	loop {
+		let key_len = bip341::read_compactsize(buf, pos)? as usize;
+		if key_len == 0 {
+			return Some(map);
+		}
+		let key = buf.get(*pos..pos.checked_add(key_len)?)?;
+		*pos += key_len;
+		let mut key_pos = 0;
+		let key_type = bip341::read_compactsize(key, &mut key_pos)?;
+		let key_data = &key[key_pos..];
+
+		let value_len = bip341::read_compactsize(buf, pos)? as usize;
+		let value = buf.get(*pos..pos.checked_add(value_len)?)?;
+		*pos += value_len;
+
+		if map.get(key_type, key_data).is_some() {
+			return None;
+		}
+		map.pairs.push(KeyValue{ key_type
+				       , key_data: key_data.to_vec()
+				       , value: value.to_vec()
+				       });
+	}
+}
+
+fn write_map(buf: &mut Vec<u8>, map: &Map) {
+	for kv in map.pairs.iter() {
+		let mut key = Vec::new();
+		bip341::load_compactsize(&mut key, kv.key_type as usize);
+		key.extend_from_slice(&kv.key_data);
+		bip341::load_compactsize(buf, key.len());
+		buf.extend_from_slice(&key);
+		bip341::load_compactsize(buf, kv.value.len());
+		buf.extend_from_slice(&kv.value);
+	}
+	buf.push(0x00);
+}
+
+/* Parses an entire serialized transaction output, as in
+PSBT_IN_WITNESS_UTXO.
+*/
+fn parse_txout(v: &[u8]) -> Option<TxOut> {
+	let value = u64::from_le_bytes(v.get(0..8)?.try_into().ok()?);
+	let mut pos = 8;
+	let len = bip341::read_compactsize(v, &mut pos)? as usize;
+	let script_pubkey = v.get(pos..)?;
+	if script_pubkey.len() != len {
+		return None;
+	}
+	Some(TxOut{value, script_pubkey: script_pubkey.to_vec()})
+}
+
+/* Checks that the key types in `fixed` have no key data
+and, if present, have a value of the length in `fixed`
+(None meaning any length).
+*/
+fn check_fixed(map: &Map, fixed: &[(u64, Option<usize>)]) -> Option<()> {
+	for kv in map.pairs.iter() {
+		let Some((_, len)) = fixed.iter().find(|(t, _)| *t == kv.key_type) else {
+			continue;
+		};
+		if !kv.key_data.is_empty() {
+			return None;
+		}
+		if let Some(len) = len {
+			if kv.value.len() != *len {
+				return None;
+			}
+		}
+	}
+	Some(())
+}
+
+fn check_global(global: &Map) -> Option<()> {
+	check_fixed(global, &[ (PSBT_GLOBAL_UNSIGNED_TX, None)
+			     , (PSBT_GLOBAL_TX_VERSION, Some(4))
+			     , (PSBT_GLOBAL_FALLBACK_LOCKTIME, Some(4))
+			     , (PSBT_GLOBAL_INPUT_COUNT, None)
+			     , (PSBT_GLOBAL_OUTPUT_COUNT, None)
+			     , (PSBT_GLOBAL_TX_MODIFIABLE, Some(1))
+			     , (PSBT_GLOBAL_VERSION, Some(4))
+			     ])?;
+	/* BIP-370: must be excluded in version 2.  */
+	if global.contains_type(PSBT_GLOBAL_UNSIGNED_TX) {
+		return None;
+	}
+	/* BIP-370: required, and must be at least 2.  */
+	if global.get_u32(PSBT_GLOBAL_TX_VERSION)? < 2 {
+		return None;
+	}
+	global.get_compactsize(PSBT_GLOBAL_INPUT_COUNT)?;
+	global.get_compactsize(PSBT_GLOBAL_OUTPUT_COUNT)?;
+	Some(())
+}
+
+fn check_input(input: &Map) -> Option<()> {
+	check_fixed(input, &[ (PSBT_IN_NON_WITNESS_UTXO, None)
+			    , (PSBT_IN_WITNESS_UTXO, None)
+			    , (PSBT_IN_SIGHASH_TYPE, Some(4))
+			    , (PSBT_IN_REDEEM_SCRIPT, None)
+			    , (PSBT_IN_WITNESS_SCRIPT, None)
+			    , (PSBT_IN_FINAL_SCRIPTSIG, None)
+			    , (PSBT_IN_FINAL_SCRIPTWITNESS, None)
+			    , (PSBT_IN_PREVIOUS_TXID, Some(32))
+			    , (PSBT_IN_OUTPUT_INDEX, Some(4))
+			    , (PSBT_IN_SEQUENCE, Some(4))
+			    , (PSBT_IN_REQUIRED_TIME_LOCKTIME, Some(4))
+			    , (PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Some(4))
+			    , (PSBT_IN_TAP_KEY_SIG, None)
+			    , (PSBT_IN_TAP_INTERNAL_KEY, Some(32))
+			    , (PSBT_IN_TAP_MERKLE_ROOT, Some(32))
+			    ])?;
+	check_taproot_input(input)?;
+	/* BIP-370: required.  */
+	input.get_fixed(PSBT_IN_PREVIOUS_TXID)?;
+	input.get_fixed(PSBT_IN_OUTPUT_INDEX)?;
+	if let Some(v) = input.get_fixed(PSBT_IN_WITNESS_UTXO) {
+		parse_txout(v)?;
+	}
+	if let Some(t) = input.get_u32(PSBT_IN_REQUIRED_TIME_LOCKTIME) {
+		if t < LOCKTIME_THRESHOLD {
+			return None;
+		}
+	}
+	if let Some(h) = input.get_u32(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME) {
+		if h == 0 || h >= LOCKTIME_THRESHOLD {
+			return None;
+		}
+	}
+	Some(())
+}
+
+fn check_output(output: &Map) -> Option<()> {
+	check_fixed(output, &[ (PSBT_OUT_REDEEM_SCRIPT, None)
+			     , (PSBT_OUT_WITNESS_SCRIPT, None)
+			     , (PSBT_OUT_AMOUNT, Some(8))
+			     , (PSBT_OUT_SCRIPT, None)
+			     , (PSBT_OUT_TAP_INTERNAL_KEY, Some(32))
+			     , (PSBT_OUT_TAP_TREE, None)
+			     ])?;
+	check_taproot_output(output)?;
+	/* BIP-370: required.  */
+	output.get_fixed(PSBT_OUT_AMOUNT)?;
+	output.get_fixed(PSBT_OUT_SCRIPT)?;
+	Some(())
+}
+
+impl Psbt {
+	/// `deserialize` parses a binary PSBT, which must be
+	/// a valid version 2 PSBT.
+	pub
+	fn deserialize(buf: &[u8]) -> Result<Psbt, PsbtError> {
+		use PsbtError::*;
+
+		if buf.get(0..5) != Some(&MAGIC[..]) {
+			return Err(InvalidPsbt);
+		}
+		let mut pos = 5;
+		let global = read_map(buf, &mut pos).ok_or(InvalidPsbt)?;
+
+		/* The version determines how the rest is
+		parsed, so check it before anything else.
+		*/
+		let version = match global.get_fixed(PSBT_GLOBAL_VERSION) {
+			None => 0,
+			Some(_) => global.get_u32(PSBT_GLOBAL_VERSION)
+				   .ok_or(InvalidPsbt)?
+		};
+		if version != 2 {
+			return Err(UnsupportedPsbtVersion(version));
+		}
+		check_global(&global).ok_or(InvalidPsbt)?;
+
+		let input_count = global.get_compactsize(PSBT_GLOBAL_INPUT_COUNT)
+				  .ok_or(InvalidPsbt)?;
+		let output_count = global.get_compactsize(PSBT_GLOBAL_OUTPUT_COUNT)
+				   .ok_or(InvalidPsbt)?;
+		/* Do not preallocate from the counts, they
+		are untrusted; a too-large count simply runs
+		out of input.
+		*/
+		let mut inputs = Vec::new();
+		for _ in 0..input_count {
+			let input = read_map(buf, &mut pos).ok_or(InvalidPsbt)?;
+			check_input(&input).ok_or(InvalidPsbt)?;
+			inputs.push(input);
+		}
+		let mut outputs = Vec::new();
+		for _ in 0..output_count {
+			let output = read_map(buf, &mut pos).ok_or(InvalidPsbt)?;
+			check_output(&output).ok_or(InvalidPsbt)?;
+			outputs.push(output);
+		}
+		if pos != buf.len() {
+			return Err(InvalidPsbt);
+		}
+
+		let psbt = Psbt{global, inputs, outputs};
+		/* BIP-370: the locktime must be determinable.  */
+		psbt.lock_time().ok_or(InvalidPsbt)?;
+		Ok(psbt)
+	}
+
+	/// `serialize` generates the binary PSBT.
+	pub
+	fn serialize(&self) -> Vec<u8> {
+		let mut buf = MAGIC.to_vec();
+		write_map(&mut buf, &self.global);
+		for input in self.inputs.iter() {
+			write_map(&mut buf, input);
+		}
+		for output in self.outputs.iter() {
+			write_map(&mut buf, output);
+		}
+		buf
+	}
+
+	/// `from_base64` parses a Base64 PSBT, as used in
+	/// `c=.sip.sign_psbt_bob`.
+	pub
+	fn from_base64(s: &str) -> Result<Psbt, PsbtError> {
+		let buf = base64::decode(s).ok_or(PsbtError::InvalidPsbt)?;
+		Psbt::deserialize(&buf)
+	}
+
+	/// `to_base64` generates the Base64 PSBT.
+	pub
+	fn to_base64(&self) -> String {
+		base64::encode(&self.serialize())
+	}
+
+	/// `tx_version` returns the `PSBT_GLOBAL_TX_VERSION`.
+	pub
+	fn tx_version(&self) -> Option<u32> {
+		self.global.get_u32(PSBT_GLOBAL_TX_VERSION)
+	}
+
+	/// `lock_time` determines the `nLockTime` of the
+	/// transaction, using the algorithm in BIP-370, from
+	/// the `PSBT_IN_REQUIRED_TIME_LOCKTIME` and
+	/// `PSBT_IN_REQUIRED_HEIGHT_LOCKTIME` of the inputs,
+	/// or `PSBT_GLOBAL_FALLBACK_LOCKTIME` (or 0) if no
+	/// input has either.
+	///
+	/// Returns None if some input requires a height and
+	/// another input requires a time.
+	pub
+	fn lock_time(&self) -> Option<u32> {
+		let mut any = false;
+		let mut all_height = true;
+		let mut all_time = true;
+		let mut max_height = 0;
+		let mut max_time = 0;
+		for input in self.inputs.iter() {
+			let time = input.get_u32(PSBT_IN_REQUIRED_TIME_LOCKTIME);
+			let height = input.get_u32(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME);
+			if time.is_none() && height.is_none() {
+				continue;
+			}
+			any = true;
+			match time {
+				Some(t) => { max_time = max_time.max(t); },
+				None => { all_time = false; }
+			}
+			match height {
+				Some(h) => { max_height = max_height.max(h); },
+				None => { all_height = false; }
+			}
+		}
+		if !any {
+			return match self.global.get_fixed(PSBT_GLOBAL_FALLBACK_LOCKTIME) {
+				None => Some(0),
+				Some(_) => self.global.get_u32(PSBT_GLOBAL_FALLBACK_LOCKTIME)
+			};
+		}
+		/* BIP-370: height is preferred if every input
+		that has a locktime requirement supports it.
+		*/
+		if all_height {
+			Some(max_height)
+		} else if all_time {
+			Some(max_time)
+		} else {
+			None
+		}
+	}
+
+	/// `previous_outpoint` returns the output spent by the
+	/// input at `input_index`, from its
+	/// `PSBT_IN_PREVIOUS_TXID` and `PSBT_IN_OUTPUT_INDEX`.
+	pub
+	fn previous_outpoint(&self, input_index: usize) -> Option<OutPoint> {
+		let input = self.inputs.get(input_index)?;
+		let txid = input.get_fixed(PSBT_IN_PREVIOUS_TXID)?.try_into().ok()?;
+		let vout = input.get_u32(PSBT_IN_OUTPUT_INDEX)?;
+		Some(OutPoint{txid, vout})
+	}
+
+	/// `sequence` returns the `nSequence` of the input at
+	/// `input_index`, from its `PSBT_IN_SEQUENCE`, or
+	/// 0xFFFFFFFF if it has none.
+	pub
+	fn sequence(&self, input_index: usize) -> Option<u32> {
+		let input = self.inputs.get(input_index)?;
+		match input.get_fixed(PSBT_IN_SEQUENCE) {
+			None => Some(0xFFFFFFFF),
+			Some(_) => input.get_u32(PSBT_IN_SEQUENCE)
+		}
+	}
+
+	/// `witness_utxo` returns the `PSBT_IN_WITNESS_UTXO`
+	/// of the input at `input_index`, if it has one.
+	pub
+	fn witness_utxo(&self, input_index: usize) -> Option<TxOut> {
+		let input = self.inputs.get(input_index)?;
+		parse_txout(input.get_fixed(PSBT_IN_WITNESS_UTXO)?)
+	}
+
+	/// `sighash_type` returns the `PSBT_IN_SIGHASH_TYPE`
+	/// of the input at `input_index`, if it has one.
+	pub
+	fn sighash_type(&self, input_index: usize) -> Option<u32> {
+		self.inputs.get(input_index)?.get_u32(PSBT_IN_SIGHASH_TYPE)
+	}
+
+	/// `is_finalized` returns true if the input at
+	/// `input_index` has a `PSBT_IN_FINAL_SCRIPTSIG` or a
+	/// `PSBT_IN_FINAL_SCRIPTWITNESS`.
+	pub
+	fn is_finalized(&self, input_index: usize) -> bool {
+		match self.inputs.get(input_index) {
+			None => false,
+			Some(input) => {
+				input.contains_type(PSBT_IN_FINAL_SCRIPTSIG)
+				|| input.contains_type(PSBT_IN_FINAL_SCRIPTWITNESS)
+			}
+		}
+	}
+
+	/// `output` returns the output at `output_index`, from
+	/// its `PSBT_OUT_AMOUNT` and `PSBT_OUT_SCRIPT`.
+	pub
+	fn output(&self, output_index: usize) -> Option<TxOut> {
+		let output = self.outputs.get(output_index)?;
+		let value = output.get_u64(PSBT_OUT_AMOUNT)?;
+		let script_pubkey = output.get_fixed(PSBT_OUT_SCRIPT)?.to_vec();
+		Some(TxOut{value, script_pubkey})
+	}
+
+	/// `unsigned_tx` reconstructs the unsigned transaction,
+	/// with empty `scriptSig`s and witnesses.
+	pub
+	fn unsigned_tx(&self) -> Option<Transaction> {
+		let mut input = Vec::new();
+		for i in 0..self.inputs.len() {
+			input.push(TxIn{
+				prevout: self.previous_outpoint(i)?,
+				script_sig: Vec::new(),
+				sequence: self.sequence(i)?,
+				witness: Vec::new()
+			});
+		}
+		let mut output = Vec::new();
+		for i in 0..self.outputs.len() {
+			output.push(self.output(i)?);
+		}
+		Some(Transaction{
+			version: self.tx_version()?,
+			lock_time: self.lock_time()?,
+			input,
+			output
+		})
+	}
+}
+
+/* BIP-371 accessors.
+
+The getters return None if the input or output does not
+exist, or (for single-valued fields) if it does not have
+the field.
+The setters replace any existing value for the same key,
+and return None if the input or output does not exist,
+or if the value is invalid, leaving the PSBT unchanged.
+*/
+impl Psbt {
+	/// `tap_key_sig` returns the `PSBT_IN_TAP_KEY_SIG` of
+	/// the input at `input_index`.
+	pub
+	fn tap_key_sig(&self, input_index: usize) -> Option<Vec<u8>> {
+		let input = self.inputs.get(input_index)?;
+		parse_tap_sig(input.get_fixed(PSBT_IN_TAP_KEY_SIG)?)
+	}
+
+	pub
+	fn set_tap_key_sig(&mut self, input_index: usize, sig: &[u8]) -> Option<()> {
+		let input = self.inputs.get_mut(input_index)?;
+		let sig = parse_tap_sig(sig)?;
+		input.insert(PSBT_IN_TAP_KEY_SIG, Vec::new(), sig);
+		Some(())
+	}
+
+	/// `tap_script_sigs` returns all the
+	/// `PSBT_IN_TAP_SCRIPT_SIG`s of the input at
+	/// `input_index`.
+	pub
+	fn tap_script_sigs(&self, input_index: usize) -> Option<Vec<TapScriptSig>> {
+		let input = self.inputs.get(input_index)?;
+		Some(input.get_all(PSBT_IN_TAP_SCRIPT_SIG)
+		     .filter_map(parse_tap_script_sig)
+		     .collect())
+	}
+
+	pub
+	fn set_tap_script_sig( &mut self
+			     , input_index: usize
+			     , s: &TapScriptSig
+			     ) -> Option<()>
+	{
+		let input = self.inputs.get_mut(input_index)?;
+		parse_xonly(&s.xonly_pubkey)?;
+		let sig = parse_tap_sig(&s.sig)?;
+		let mut key_data = s.xonly_pubkey.to_vec();
+		key_data.extend_from_slice(&s.leaf_hash);
+		input.insert(PSBT_IN_TAP_SCRIPT_SIG, key_data, sig);
+		Some(())
+	}
+
+	/// `tap_leaf_scripts` returns all the
+	/// `PSBT_IN_TAP_LEAF_SCRIPT`s of the input at
+	/// `input_index`.
+	pub
+	fn tap_leaf_scripts(&self, input_index: usize) -> Option<Vec<TapLeafScript>> {
+		let input = self.inputs.get(input_index)?;
+		Some(input.get_all(PSBT_IN_TAP_LEAF_SCRIPT)
+		     .filter_map(parse_tap_leaf_script)
+		     .collect())
+	}
+
+	pub
+	fn set_tap_leaf_script( &mut self
+			      , input_index: usize
+			      , l: &TapLeafScript
+			      ) -> Option<()>
+	{
+		let input = self.inputs.get_mut(input_index)?;
+		let mut value = l.script.clone();
+		value.push(l.leaf_version);
+		let kv = KeyValue{ key_type: PSBT_IN_TAP_LEAF_SCRIPT
+				 , key_data: l.control_block.clone()
+				 , value
+				 };
+		parse_tap_leaf_script(&kv)?;
+		input.insert(kv.key_type, kv.key_data, kv.value);
+		Some(())
+	}
+
+	/// `tap_bip32_derivations` returns all the
+	/// `PSBT_IN_TAP_BIP32_DERIVATION`s of the input at
+	/// `input_index`.
+	pub
+	fn tap_bip32_derivations(&self, input_index: usize) -> Option<Vec<TapBip32Derivation>> {
+		let input = self.inputs.get(input_index)?;
+		Some(input.get_all(PSBT_IN_TAP_BIP32_DERIVATION)
+		     .filter_map(parse_tap_bip32_derivation)
+		     .collect())
+	}
+
+	pub
+	fn set_tap_bip32_derivation( &mut self
+				   , input_index: usize
+				   , d: &TapBip32Derivation
+				   ) -> Option<()>
+	{
+		let input = self.inputs.get_mut(input_index)?;
+		parse_xonly(&d.xonly_pubkey)?;
+		let kv = tap_bip32_derivation_pair(PSBT_IN_TAP_BIP32_DERIVATION, d);
+		input.insert(kv.key_type, kv.key_data, kv.value);
+		Some(())
+	}
+
+	/// `tap_internal_key` returns the
+	/// `PSBT_IN_TAP_INTERNAL_KEY` of the input at
+	/// `input_index`.
+	pub
+	fn tap_internal_key(&self, input_index: usize) -> Option<[u8; 32]> {
+		let input = self.inputs.get(input_index)?;
+		parse_xonly(input.get_fixed(PSBT_IN_TAP_INTERNAL_KEY)?)
+	}
+
+	pub
+	fn set_tap_internal_key( &mut self
+			       , input_index: usize
+			       , xonly_pubkey: &[u8; 32]
+			       ) -> Option<()>
+	{
+		let input = self.inputs.get_mut(input_index)?;
+		parse_xonly(xonly_pubkey)?;
+		input.insert(PSBT_IN_TAP_INTERNAL_KEY, Vec::new(), xonly_pubkey.to_vec());
+		Some(())
+	}
+
+	/// `tap_merkle_root` returns the
+	/// `PSBT_IN_TAP_MERKLE_ROOT` of the input at
+	/// `input_index`.
+	pub
+	fn tap_merkle_root(&self, input_index: usize) -> Option<[u8; 32]> {
+		let input = self.inputs.get(input_index)?;
+		input.get_fixed(PSBT_IN_TAP_MERKLE_ROOT)?.try_into().ok()
+	}
+
+	pub
+	fn set_tap_merkle_root( &mut self
+			      , input_index: usize
+			      , root_hash: &[u8; 32]
+			      ) -> Option<()>
+	{
+		let input = self.inputs.get_mut(input_index)?;
+		input.insert(PSBT_IN_TAP_MERKLE_ROOT, Vec::new(), root_hash.to_vec());
+		Some(())
+	}
+
+	/// `output_tap_internal_key` returns the
+	/// `PSBT_OUT_TAP_INTERNAL_KEY` of the output at
+	/// `output_index`.
+	pub
+	fn output_tap_internal_key(&self, output_index: usize) -> Option<[u8; 32]> {
+		let output = self.outputs.get(output_index)?;
+		parse_xonly(output.get_fixed(PSBT_OUT_TAP_INTERNAL_KEY)?)
+	}
+
+	pub
+	fn set_output_tap_internal_key( &mut self
+				      , output_index: usize
+				      , xonly_pubkey: &[u8; 32]
+				      ) -> Option<()>
+	{
+		let output = self.outputs.get_mut(output_index)?;
+		parse_xonly(xonly_pubkey)?;
+		output.insert(PSBT_OUT_TAP_INTERNAL_KEY, Vec::new(), xonly_pubkey.to_vec());
+		Some(())
+	}
+
+	/// `output_tap_tree` returns the leaves of the
+	/// `PSBT_OUT_TAP_TREE` of the output at `output_index`,
+	/// in depth-first order.
+	pub
+	fn output_tap_tree(&self, output_index: usize) -> Option<Vec<TapTreeLeaf>> {
+		let output = self.outputs.get(output_index)?;
+		parse_tap_tree(output.get_fixed(PSBT_OUT_TAP_TREE)?)
+	}
+
+	pub
+	fn set_output_tap_tree( &mut self
+			      , output_index: usize
+			      , leaves: &[TapTreeLeaf]
+			      ) -> Option<()>
+	{
+		let output = self.outputs.get_mut(output_index)?;
+		let mut value = Vec::new();
+		for l in leaves.iter() {
+			value.push(l.depth);
+			value.push(l.leaf_version);
+			bip341::load_compactsize(&mut value, l.script.len());
+			value.extend_from_slice(&l.script);
+		}
+		parse_tap_tree(&value)?;
+		output.insert(PSBT_OUT_TAP_TREE, Vec::new(), value);
+		Some(())
+	}
+
+	/// `output_tap_bip32_derivations` returns all the
+	/// `PSBT_OUT_TAP_BIP32_DERIVATION`s of the output at
+	/// `output_index`.
+	pub
+	fn output_tap_bip32_derivations(&self, output_index: usize) -> Option<Vec<TapBip32Derivation>> {
+		let output = self.outputs.get(output_index)?;
+		Some(output.get_all(PSBT_OUT_TAP_BIP32_DERIVATION)
+		     .filter_map(parse_tap_bip32_derivation)
+		     .collect())
+	}
+
+	pub
+	fn set_output_tap_bip32_derivation( &mut self
+					  , output_index: usize
+					  , d: &TapBip32Derivation
+					  ) -> Option<()>
+	{
+		let output = self.outputs.get_mut(output_index)?;
+		parse_xonly(&d.xonly_pubkey)?;
+		let kv = tap_bip32_derivation_pair(PSBT_OUT_TAP_BIP32_DERIVATION, d);
+		output.insert(kv.key_type, kv.key_data, kv.value);
+		Some(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex;
+	use super::*;
+
+	fn psbt_hex(s: &str) -> Result<Psbt, PsbtError> {
+		Psbt::deserialize(&hex::decode(s).expect("Test input must be hex"))
+	}
+
+	/* BIP-174 test vectors, which are all version 0, so
+	even the invalid ones are rejected as version 0,
+	before the check they are about.
+	https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki#test-vectors
+	*/
+	#[test]
+	fn test_bip174_vectors() {
+		use PsbtError::*;
+
+		/* "PSBT with one P2PKH input and one P2SH-P2WPKH
+		 * input both with non-final scriptSigs.
+		 * P2PKH input's utxo is finalized" (valid).
+		 */
+		assert_eq!( psbt_hex("70736274ff0100a00200000002ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40000000000feffffffab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40100000000feffffff02603bea0b000000001976a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac8e240000000000001976a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac000000000001076a47304402204759661797c01b036b25928948686218347d89864b719e1f7fcf57d1e511658702205309eabf56aa4d8891ffd111fdf1336f3a29da866d7f8486d75546ceedaf93190121035cdc61fc7ba971c0b501a646a2a83b102cb43881217ca682dc86e2d73fa882920001012000e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787010416001485d13537f2e265405a34dbafa9e3dda01fb82308000000")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+		assert_eq!( Psbt::from_base64("cHNidP8BAKACAAAAAqsJSaCMWvfEm4IS9Bfi8Vqz9cM9zxU4IagTn4d6W3vkAAAAAAD+////qwlJoIxa98SbghL0F+LxWrP1wz3PFTghqBOfh3pbe+QBAAAAAP7///8CYDvqCwAAAAAZdqkUdopAu9dAy+gdmI5x3ipNXHE5ax2IrI4kAAAAAAAAGXapFG9GILVT+glechue4O/p+gOcykWXiKwAAAAAAAEHakcwRAIgR1lmF5fAGwNrJZKJSGhiGDR9iYZLcZ4ff89X0eURZYcCIFMJ6r9Wqk2Ikf/REf3xM286KdqGbX+EhtdVRs7tr5MZASEDXNxh/HupccC1AaZGoqg7ECy0OIEhfKaC3Ibi1z+ogpIAAQEgAOH1BQAAAAAXqRQ1RebjO4MsRwUPJNPuuTycA5SLx4cBBBYAFIXRNTfy4mVAWjTbr6nj3aAfuCMIAAAA")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+		/* "PSBT with unknown types in the inputs" (valid).  */
+		assert_eq!( psbt_hex("70736274ff01003f0200000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff0000000000ffffffff010000000000000000036a010000000000000a0f0102030405060708090f0102030405060708090a0b0c0d0e0f0000")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+
+		/* "Network transaction, not PSBT format" (invalid).  */
+		assert_eq!( psbt_hex("0200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf6000000006a473044022070b2245123e6bf474d60c5b50c043d4c691a5d2435f09a34a7662a9dc251790a022001329ca9dacf280bdf30740ec0390422422c81cb45839457aeb76fc12edd95b3012102657d118d3357b8e0f4c2cd46db7b39f6d9c38d9a70abcb9b2de5dc8dbfe4ce31feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300")
+			  , Err(InvalidPsbt)
+			  );
+		/* "PSBT missing outputs" (invalid).  */
+		assert_eq!( psbt_hex("70736274ff0100750200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf60000000000feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000000")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+		/* "PSBT where one input has a filled scriptSig in
+		 * the unsigned tx" (invalid).
+		 * Version 2 has no unsigned tx, so there is no
+		 * equivalent.
+		 */
+		assert_eq!( psbt_hex("70736274ff0100fd0a010200000002ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be4000000006a47304402204759661797c01b036b25928948686218347d89864b719e1f7fcf57d1e511658702205309eabf56aa4d8891ffd111fdf1336f3a29da866d7f8486d75546ceedaf93190121035cdc61fc7ba971c0b501a646a2a83b102cb43881217ca682dc86e2d73fa88292feffffffab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40100000000feffffff02603bea0b000000001976a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac8e240000000000001976a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac00000000000001012000e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787010416001485d13537f2e265405a34dbafa9e3dda01fb82308000000")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+		/* "PSBT where inputs and outputs are provided but
+		 * without an unsigned tx" (invalid).
+		 * Version 2 has no unsigned tx, so there is no
+		 * equivalent.
+		 */
+		assert_eq!( psbt_hex("70736274ff000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000000")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+		/* "PSBT with duplicate keys in an input" (invalid).  */
+		assert_eq!( psbt_hex("70736274ff0100750200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf60000000000feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000001003f0200000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff0000000000ffffffff010000000000000000036a010000000000000000")
+			  , Err(UnsupportedPsbtVersion(0))
+			  );
+	}
+
+	/* The invalid BIP-174 test vectors that have a version
+	2 equivalent, converted the same way as V2_TWO_INPUTS
+	below, so that they reach the check they are about.
+	*/
+	#[test]
+	fn test_bip174_vectors_v2() {
+		use PsbtError::*;
+
+		/* "PSBT missing outputs": PSBT_GLOBAL_OUTPUT_COUNT
+		 * is 2, but the output maps are missing.
+		 */
+		let missing_outputs = "cHNidP8BAgQCAAAAAQMEsy4TAAEEAQEBBQECAfsEAgAAAAABAP2lAQEAAAAAAQKJo8ceq00g4Dcbu6TMaY+ilclGOvouOX+FM8y2L5Vn5QEAAAAXFgAUvhjRUqmwEgOdrz2n3k9TNJ7suYX/////hviqQ6cd/xRIiTpTCnI372tGCLuy3S0BceY67GpIkLQBAAAAFxYAFP4+nvGnRel02QLENVlDq8s0vVNT/////wIAwusLAAAAABl2qRSFz/EJf9ngCLs0r3CcYhl7OJeKSIiscv74TiwAAAAXqRQzlyW6Ie/WKsdTqbzQZ9bHpqOdBYcCRzBEAiAnEr4i4CcPOU9WgxHcfKmmiXC4Al/dOyQCKfB/il86JAIgAYs419zTFOc0ySdr1vtA9nMyW8S6oUTIANLy8C2ydlwBIQPS4VZ0lButSpljcsuH4YVtNlJgbZhWL+OcXp5+QT8hBQJIMEUCIQDRK4UthdzZYdL19KtmBlTfbu3MeUwMM85cwwn/tfzljQIgZzOKjg4XJcGX+xqIr1n1HkTkJVsgFnyGhAMcBdHyWSoBIQIjtyvu8JZdEL4HeO/s1h/KxveaTqFpOTOAc0Rk+E8qswAAAAABDiAmgXE3Ht/yhek3re6ks3t4AAwFZsuzrWRkFxPKQhcb9gEPBAAAAAABEAT+////AA==";
+		assert_eq!(Psbt::from_base64(missing_outputs), Err(InvalidPsbt));
+		/* With the output maps of V2_FALLBACK_LOCKTIME,
+		 * which was converted from the same transaction, it
+		 * is valid.
+		 */
+		let fallback = base64::decode(V2_FALLBACK_LOCKTIME).unwrap();
+		let outputs = Psbt::deserialize(&fallback).unwrap().outputs;
+		let mut buf = base64::decode(missing_outputs).unwrap();
+		for output in outputs.iter() {
+			write_map(&mut buf, output);
+		}
+		assert!(Psbt::deserialize(&buf).is_ok());
+
+		/* "PSBT with duplicate keys in an input": two
+		 * PSBT_IN_NON_WITNESS_UTXO.
+		 */
+		let duplicate = base64::decode("cHNidP8BAgQCAAAAAQMEsy4TAAEEAQEBBQECAfsEAgAAAAABAP2lAQEAAAAAAQKJo8ceq00g4Dcbu6TMaY+ilclGOvouOX+FM8y2L5Vn5QEAAAAXFgAUvhjRUqmwEgOdrz2n3k9TNJ7suYX/////hviqQ6cd/xRIiTpTCnI372tGCLuy3S0BceY67GpIkLQBAAAAFxYAFP4+nvGnRel02QLENVlDq8s0vVNT/////wIAwusLAAAAABl2qRSFz/EJf9ngCLs0r3CcYhl7OJeKSIiscv74TiwAAAAXqRQzlyW6Ie/WKsdTqbzQZ9bHpqOdBYcCRzBEAiAnEr4i4CcPOU9WgxHcfKmmiXC4Al/dOyQCKfB/il86JAIgAYs419zTFOc0ySdr1vtA9nMyW8S6oUTIANLy8C2ydlwBIQPS4VZ0lButSpljcsuH4YVtNlJgbZhWL+OcXp5+QT8hBQJIMEUCIQDRK4UthdzZYdL19KtmBlTfbu3MeUwMM85cwwn/tfzljQIgZzOKjg4XJcGX+xqIr1n1HkTkJVsgFnyGhAMcBdHyWSoBIQIjtyvu8JZdEL4HeO/s1h/KxveaTqFpOTOAc0Rk+E8qswAAAAABAD8CAAAAAf//////////////////////////////////////////AAAAAAD/////AQAAAAAAAAAAA2oBAAAAAAABDiAmgXE3Ht/yhek3re6ks3t4AAwFZsuzrWRkFxPKQhcb9gEPBAAAAAABEAT+////AAEDCNPf9QUAAAAAAQQZdqkU0MWZA8W6woaHYOkP1SGkZlqnZSCIrAABAwgA4fUFAAAAAAEEF6kUNUXm4zuDLEcFDyTT7rk8nAOUi8eHAA==").unwrap();
+		assert_eq!(Psbt::deserialize(&duplicate), Err(InvalidPsbt));
+		let mut pos = 5;
+		read_map(&duplicate, &mut pos).unwrap();
+		assert_eq!(read_map(&duplicate, &mut pos), None);
+	}
+
+	/* The first BIP-174 valid test vector above, converted
+	to version 2 as per BIP-370: the PSBT_GLOBAL_UNSIGNED_TX
+	is replaced by the version 2 global fields, and its
+	inputs and outputs moved into PSBT_IN_PREVIOUS_TXID,
+	PSBT_IN_OUTPUT_INDEX, PSBT_IN_SEQUENCE, PSBT_OUT_AMOUNT
+	and PSBT_OUT_SCRIPT.
+	All other pairs are unchanged.
+	*/
+	const V2_TWO_INPUTS: &str = "cHNidP8BAgQCAAAAAQQBAgEFAQIB+wQCAAAAAAEHakcwRAIgR1lmF5fAGwNrJZKJSGhiGDR9iYZLcZ4ff89X0eURZYcCIFMJ6r9Wqk2Ikf/REf3xM286KdqGbX+EhtdVRs7tr5MZASEDXNxh/HupccC1AaZGoqg7ECy0OIEhfKaC3Ibi1z+ogpIBDiCrCUmgjFr3xJuCEvQX4vFas/XDPc8VOCGoE5+Helt75AEPBAAAAAABEAT+////AAEBIADh9QUAAAAAF6kUNUXm4zuDLEcFDyTT7rk8nAOUi8eHAQQWABSF0TU38uJlQFo026+p492gH7gjCAEOIKsJSaCMWvfEm4IS9Bfi8Vqz9cM9zxU4IagTn4d6W3vkAQ8EAQAAAAEQBP7///8AAQMIYDvqCwAAAAABBBl2qRR2ikC710DL6B2YjnHeKk1ccTlrHYisAAEDCI4kAAAAAAAAAQQZdqkUb0YgtVP6CV5yG57g7+n6A5zKRZeIrAA=";
+	/* Likewise for "PSBT with one P2PKH input which has
+	a non-final scriptSig and has a sighash type
+	specified", whose transaction has a non-zero
+	nLockTime, which becomes PSBT_GLOBAL_FALLBACK_LOCKTIME.
+	*/
+	const V2_FALLBACK_LOCKTIME: &str = "cHNidP8BAgQCAAAAAQMEsy4TAAEEAQEBBQECAfsEAgAAAAABAP2lAQEAAAAAAQKJo8ceq00g4Dcbu6TMaY+ilclGOvouOX+FM8y2L5Vn5QEAAAAXFgAUvhjRUqmwEgOdrz2n3k9TNJ7suYX/////hviqQ6cd/xRIiTpTCnI372tGCLuy3S0BceY67GpIkLQBAAAAFxYAFP4+nvGnRel02QLENVlDq8s0vVNT/////wIAwusLAAAAABl2qRSFz/EJf9ngCLs0r3CcYhl7OJeKSIiscv74TiwAAAAXqRQzlyW6Ie/WKsdTqbzQZ9bHpqOdBYcCRzBEAiAnEr4i4CcPOU9WgxHcfKmmiXC4Al/dOyQCKfB/il86JAIgAYs419zTFOc0ySdr1vtA9nMyW8S6oUTIANLy8C2ydlwBIQPS4VZ0lButSpljcsuH4YVtNlJgbZhWL+OcXp5+QT8hBQJIMEUCIQDRK4UthdzZYdL19KtmBlTfbu3MeUwMM85cwwn/tfzljQIgZzOKjg4XJcGX+xqIr1n1HkTkJVsgFnyGhAMcBdHyWSoBIQIjtyvu8JZdEL4HeO/s1h/KxveaTqFpOTOAc0Rk+E8qswAAAAABAwQBAAAAAQ4gJoFxNx7f8oXpN63upLN7eAAMBWbLs61kZBcTykIXG/YBDwQAAAAAARAE/v///wABAwjT3/UFAAAAAAEEGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwAAQMIAOH1BQAAAAABBBepFDVF5uM7gyxHBQ8k0+65PJwDlIvHhwA=";
+
+	#[test]
+	fn test_v2() {
+		let psbt = Psbt::from_base64(V2_TWO_INPUTS).expect("Should parse");
+		assert_eq!(psbt.to_base64(), V2_TWO_INPUTS);
+		assert_eq!(psbt.inputs.len(), 2);
+		assert_eq!(psbt.outputs.len(), 2);
+		assert!(psbt.is_finalized(0));
+		assert!(!psbt.is_finalized(1));
+		assert_eq!(psbt.sighash_type(1), None);
+		assert_eq!( psbt.witness_utxo(1)
+			  , Some(TxOut{
+				value: 100000000,
+				script_pubkey: hex::decode("a9143545e6e33b832c47050f24d3eeb93c9c03948bc787").unwrap()
+			  })
+			  );
+
+		let tx = psbt.unsigned_tx().expect("Should succeed");
+		let txid = hex::decode("ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be4").unwrap();
+		assert_eq!(tx.version, 2);
+		assert_eq!(tx.lock_time, 0);
+		assert_eq!(tx.input.len(), 2);
+		for i in 0..2 {
+			assert_eq!(tx.input[i].prevout.txid[..], txid[..]);
+			assert_eq!(tx.input[i].prevout.vout, i as u32);
+			assert_eq!(tx.input[i].sequence, 0xFFFFFFFE);
+		}
+		assert_eq!( tx.output
+			  , vec!( TxOut{ value: 199900000
+				       , script_pubkey: hex::decode("76a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac").unwrap()
+				       }
+				, TxOut{ value: 9358
+				       , script_pubkey: hex::decode("76a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac").unwrap()
+				       }
+				)
+			  );
+
+		let psbt = Psbt::from_base64(V2_FALLBACK_LOCKTIME).expect("Should parse");
+		assert_eq!(psbt.to_base64(), V2_FALLBACK_LOCKTIME);
+		assert_eq!(psbt.lock_time(), Some(1257139));
+		assert_eq!(psbt.sighash_type(0), Some(1));
+		assert_eq!(psbt.sequence(0), Some(0xFFFFFFFE));
+		assert_eq!(psbt.sequence(1), None);
+		assert_eq!(psbt.witness_utxo(0), None);
+		assert!(!psbt.is_finalized(0));
+	}
+
+	/* Parses V2_TWO_INPUTS, modifies it, and parses the
+	re-serialized result.
+	*/
+	fn modified<F>(f: F) -> Result<Psbt, PsbtError>
+		where F: FnOnce(&mut Psbt)
+	{
+		let mut psbt = Psbt::from_base64(V2_TWO_INPUTS).unwrap();
+		f(&mut psbt);
+		Psbt::deserialize(&psbt.serialize())
+	}
+
+	#[test]
+	fn test_v2_unknown_pairs() {
+		let psbt = modified(|p| {
+			p.global.insert(PSBT_GLOBAL_PROPRIETARY, b"\x05hello\x00".to_vec(), vec!(1, 2, 3));
+			p.inputs[1].insert(0xF0, Vec::new(), vec!(4, 5, 6));
+			p.outputs[0].insert(0x1000, vec!(7), Vec::new());
+		}).expect("Should parse");
+		assert_eq!(psbt.global.get(PSBT_GLOBAL_PROPRIETARY, b"\x05hello\x00"), Some(&[1, 2, 3][..]));
+		assert_eq!(psbt.inputs[1].get(0xF0, &[]), Some(&[4, 5, 6][..]));
+		assert_eq!(psbt.outputs[0].get(0x1000, &[7]), Some(&[][..]));
+		assert_eq!(Psbt::deserialize(&psbt.serialize()), Ok(psbt));
+	}
+
+	#[test]
+	fn test_v2_invalid() {
+		use PsbtError::*;
+
+		/* BIP-370 required fields.  */
+		assert_eq!(modified(|p| { p.global.remove(PSBT_GLOBAL_TX_VERSION, &[]); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.global.remove(PSBT_GLOBAL_INPUT_COUNT, &[]); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.global.remove(PSBT_GLOBAL_OUTPUT_COUNT, &[]); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.inputs[1].remove(PSBT_IN_PREVIOUS_TXID, &[]); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.inputs[1].remove(PSBT_IN_OUTPUT_INDEX, &[]); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.outputs[1].remove(PSBT_OUT_AMOUNT, &[]); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.outputs[1].remove(PSBT_OUT_SCRIPT, &[]); }), Err(InvalidPsbt));
+		/* BIP-370 excluded field.  */
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_UNSIGNED_TX, Vec::new(), vec!(0)); }), Err(InvalidPsbt));
+
+		/* Versions.  */
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_VERSION, Vec::new(), vec!(0, 0, 0, 0)); }), Err(UnsupportedPsbtVersion(0)));
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_VERSION, Vec::new(), vec!(3, 0, 0, 0)); }), Err(UnsupportedPsbtVersion(3)));
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_VERSION, Vec::new(), vec!(2, 0, 0)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_TX_VERSION, Vec::new(), vec!(1, 0, 0, 0)); }), Err(InvalidPsbt));
+
+		/* Bad values and keys.  */
+		assert_eq!(modified(|p| { p.inputs[0].insert(PSBT_IN_PREVIOUS_TXID, Vec::new(), vec!(0; 31)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.inputs[0].insert(PSBT_IN_SEQUENCE, Vec::new(), vec!(0; 8)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.inputs[0].insert(PSBT_IN_SEQUENCE, vec!(0), vec!(0; 4)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.inputs[1].insert(PSBT_IN_WITNESS_UTXO, Vec::new(), vec!(0; 10)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.outputs[0].insert(PSBT_OUT_AMOUNT, Vec::new(), vec!(0; 4)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_INPUT_COUNT, Vec::new(), vec!(0xFD, 0x02, 0x00)); }), Err(InvalidPsbt));
+
+		/* Map counts not matching.  */
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_INPUT_COUNT, Vec::new(), vec!(3)); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.global.insert(PSBT_GLOBAL_OUTPUT_COUNT, Vec::new(), vec!(1)); }), Err(InvalidPsbt));
+
+		/* Duplicate keys.  */
+		assert_eq!(modified(|p| {
+			let kv = p.outputs[0].pairs[0].clone();
+			p.outputs[0].pairs.push(kv);
+		}), Err(InvalidPsbt));
+
+		/* Locktime requirements.  */
+		assert_eq!(modified(|p| { p.inputs[0].insert(PSBT_IN_REQUIRED_TIME_LOCKTIME, Vec::new(), 499999999u32.to_le_bytes().to_vec()); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| { p.inputs[0].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Vec::new(), 500000000u32.to_le_bytes().to_vec()); }), Err(InvalidPsbt));
+		assert_eq!(modified(|p| {
+			p.inputs[0].insert(PSBT_IN_REQUIRED_TIME_LOCKTIME, Vec::new(), 500000000u32.to_le_bytes().to_vec());
+			p.inputs[1].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Vec::new(), 800000u32.to_le_bytes().to_vec());
+		}), Err(InvalidPsbt));
+
+		/* Framing.  */
+		let buf = base64::decode(V2_TWO_INPUTS).unwrap();
+		for len in 0..buf.len() {
+			assert_eq!(Psbt::deserialize(&buf[0..len]), Err(InvalidPsbt));
+		}
+		let mut trailing = buf.clone();
+		trailing.push(0x00);
+		assert_eq!(Psbt::deserialize(&trailing), Err(InvalidPsbt));
+		assert_eq!(Psbt::from_base64(&V2_TWO_INPUTS[1..]), Err(InvalidPsbt));
+	}
+
+	/* Like `modified`, but for the version 0 BIP-174
+	vector that V2_TWO_INPUTS was converted from.
+	*/
+	fn modified_v0<F>(f: F) -> Result<Psbt, PsbtError>
+		where F: FnOnce(&mut Psbt)
+	{
+		let buf = base64::decode("cHNidP8BAKACAAAAAqsJSaCMWvfEm4IS9Bfi8Vqz9cM9zxU4IagTn4d6W3vkAAAAAAD+////qwlJoIxa98SbghL0F+LxWrP1wz3PFTghqBOfh3pbe+QBAAAAAP7///8CYDvqCwAAAAAZdqkUdopAu9dAy+gdmI5x3ipNXHE5ax2IrI4kAAAAAAAAGXapFG9GILVT+glechue4O/p+gOcykWXiKwAAAAAAAEHakcwRAIgR1lmF5fAGwNrJZKJSGhiGDR9iYZLcZ4ff89X0eURZYcCIFMJ6r9Wqk2Ikf/REf3xM286KdqGbX+EhtdVRs7tr5MZASEDXNxh/HupccC1AaZGoqg7ECy0OIEhfKaC3Ibi1z+ogpIAAQEgAOH1BQAAAAAXqRQ1RebjO4MsRwUPJNPuuTycA5SLx4cBBBYAFIXRNTfy4mVAWjTbr6nj3aAfuCMIAAAA").unwrap();
+		let mut pos = 5;
+		let mut maps = Vec::new();
+		while pos < buf.len() {
+			maps.push(read_map(&buf, &mut pos).unwrap());
+		}
+		/* One global map, two inputs, two outputs.  */
+		assert_eq!(maps.len(), 5);
+		let outputs = maps.split_off(3);
+		let inputs = maps.split_off(1);
+		let global = maps.pop().unwrap();
+		let mut psbt = Psbt{global, inputs, outputs};
+		f(&mut psbt);
+		Psbt::deserialize(&psbt.serialize())
+	}
+
+	/* The cases of the BIP-370 test vectors, by their
+	descriptions.
+	The BIP-370 serializations themselves are not
+	included; each case is instead built by modifying
+	V2_TWO_INPUTS or, for the version 0 cases, the
+	BIP-174 vector it was converted from.
+	https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki#test-vectors
+	*/
+	#[test]
+	fn test_bip370_cases() {
+		use PsbtError::*;
+
+		let u32_value = |n: u32| n.to_le_bytes().to_vec();
+
+		/* Invalid.  */
+		assert_eq!( modified_v0(|p| { p.global.insert(PSBT_GLOBAL_VERSION, Vec::new(), u32_value(2)); })
+			  , Err(InvalidPsbt)
+			  );
+		let v0_fields = [ (0, PSBT_GLOBAL_TX_VERSION, u32_value(2))
+				, (0, PSBT_GLOBAL_FALLBACK_LOCKTIME, u32_value(0))
+				, (0, PSBT_GLOBAL_INPUT_COUNT, vec!(2))
+				, (0, PSBT_GLOBAL_OUTPUT_COUNT, vec!(2))
+				, (0, PSBT_GLOBAL_TX_MODIFIABLE, vec!(0))
+				, (1, PSBT_IN_PREVIOUS_TXID, vec!(0; 32))
+				, (1, PSBT_IN_OUTPUT_INDEX, u32_value(0))
+				, (1, PSBT_IN_SEQUENCE, u32_value(0xFFFFFFFF))
+				, (1, PSBT_IN_REQUIRED_TIME_LOCKTIME, u32_value(500000000))
+				, (1, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, u32_value(1))
+				, (2, PSBT_OUT_AMOUNT, vec!(0; 8))
+				, (2, PSBT_OUT_SCRIPT, vec!(0x6a))
+				];
+		for (map, key_type, value) in v0_fields {
+			/* "PSBTv0 but with <field>": version 0 is
+			 * rejected as a whole.
+			 */
+			assert_eq!( modified_v0(|p| {
+					let m = match map {
+						0 => &mut p.global,
+						1 => &mut p.inputs[0],
+						_ => &mut p.outputs[0]
+					};
+					m.insert(key_type, Vec::new(), value);
+				  })
+				  , Err(UnsupportedPsbtVersion(0))
+				  );
+		}
+		let v2_missing = [ (0, PSBT_GLOBAL_INPUT_COUNT)
+				 , (0, PSBT_GLOBAL_OUTPUT_COUNT)
+				 , (1, PSBT_IN_PREVIOUS_TXID)
+				 , (1, PSBT_IN_OUTPUT_INDEX)
+				 , (2, PSBT_OUT_AMOUNT)
+				 , (2, PSBT_OUT_SCRIPT)
+				 ];
+		for (map, key_type) in v2_missing {
+			/* "PSBTv2 missing <field>".  */
+			assert_eq!( modified(|p| {
+					let m = match map {
+						0 => &mut p.global,
+						1 => &mut p.inputs[0],
+						_ => &mut p.outputs[0]
+					};
+					m.remove(key_type, &[]);
+				  })
+				  , Err(InvalidPsbt)
+				  );
+		}
+		/* "PSBTv2 with PSBT_IN_REQUIRED_TIME_LOCKTIME less
+		 * than 500000000".
+		 */
+		assert_eq!( modified(|p| { p.inputs[0].insert(PSBT_IN_REQUIRED_TIME_LOCKTIME, Vec::new(), u32_value(499999999)); })
+			  , Err(InvalidPsbt)
+			  );
+		/* "PSBTv2 with PSBT_IN_REQUIRED_HEIGHT_LOCKTIME
+		 * greater than or equal to 500000000".
+		 */
+		assert_eq!( modified(|p| { p.inputs[0].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Vec::new(), u32_value(500000000)); })
+			  , Err(InvalidPsbt)
+			  );
+
+		/* Valid.  */
+		let valid = |r: Result<Psbt, PsbtError>| -> Psbt {
+			let psbt = r.expect("Should parse");
+			assert_eq!(Psbt::deserialize(&psbt.serialize()).as_ref(), Ok(&psbt));
+			psbt
+		};
+		/* "PSBTv2, required fields only".  */
+		let psbt = valid(modified(|p| {
+			p.inputs[0].remove(PSBT_IN_SEQUENCE, &[]);
+			p.inputs[1].remove(PSBT_IN_SEQUENCE, &[]);
+		}));
+		assert_eq!(psbt.lock_time(), Some(0));
+		/* "PSBTv2 updated with PSBT_IN_SEQUENCE".  */
+		let psbt = valid(modified(|_| { }));
+		assert_eq!(psbt.sequence(0), Some(0xFFFFFFFE));
+		/* "PSBTv2 with PSBT_IN_REQUIRED_TIME_LOCKTIME".  */
+		let psbt = valid(modified(|p| {
+			p.inputs[0].insert(PSBT_IN_REQUIRED_TIME_LOCKTIME, Vec::new(), u32_value(500000000));
+		}));
+		assert_eq!(psbt.lock_time(), Some(500000000));
+		/* "PSBTv2 with PSBT_IN_REQUIRED_HEIGHT_LOCKTIME".  */
+		let psbt = valid(modified(|p| {
+			p.inputs[0].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Vec::new(), u32_value(10000));
+		}));
+		assert_eq!(psbt.lock_time(), Some(10000));
+		/* "PSBTv2 with both PSBT_IN_REQUIRED_TIME_LOCKTIME
+		 * and PSBT_IN_REQUIRED_HEIGHT_LOCKTIME".
+		 */
+		let psbt = valid(modified(|p| {
+			p.inputs[0].insert(PSBT_IN_REQUIRED_TIME_LOCKTIME, Vec::new(), u32_value(500000000));
+			p.inputs[0].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Vec::new(), u32_value(10000));
+		}));
+		assert_eq!(psbt.lock_time(), Some(10000));
+		/* "PSBTv2 with PSBT_GLOBAL_TX_MODIFIABLE", for each
+		 * of the flags, including an undefined one.
+		 */
+		for flags in [1u8, 2, 4, 8, 7, 0xFF] {
+			valid(modified(|p| {
+				p.global.insert(PSBT_GLOBAL_TX_MODIFIABLE, Vec::new(), vec!(flags));
+			}));
+		}
+		/* "PSBTv2 with all PSBTv2 fields".  */
+		let psbt = valid(modified(|p| {
+			p.global.insert(PSBT_GLOBAL_FALLBACK_LOCKTIME, Vec::new(), u32_value(0));
+			p.global.insert(PSBT_GLOBAL_TX_MODIFIABLE, Vec::new(), vec!(0));
+			p.inputs[0].insert(PSBT_IN_REQUIRED_TIME_LOCKTIME, Vec::new(), u32_value(500000000));
+			p.inputs[0].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, Vec::new(), u32_value(10000));
+		}));
+		assert_eq!(psbt.lock_time(), Some(10000));
+	}
+
+	/* The BIP-371 test vectors, converted to version 2
+	the same way as V2_TWO_INPUTS.
+	The invalid vector with a truncated signature is
+	omitted, as it cannot be converted.
+	https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki#test-vectors
+	*/
+	#[test]
+	fn test_bip371_vectors() {
+		let invalid = [ "cHNidP8BAgQCAAAAAQQBAQEFAQIB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1cBFyEC/jSQZMmNbiqFP6PJsSvYswShnBlcYO+n7iOTBG0/ojIBDiAndEq6vzAn/g1s8jqW7uLvsYjvUjAZVFhYg+abZiSyQgEPBAAAAAAAAQMIeHwBAAAAAAABBBYAFIOn40vZn/A6SWLvihoQG7KVRh7OAAEDCGBrBCoBAAAAAQQWABR6w2nfGyDgM9YRZiOVewrEnzxS6AA="
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQIB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1cBE0IXO7PTbAdK+3Fv7GMHoGmi5FC5lfPIJ4WUWrjfDiQmDc1wOwy/NN45kYSpSBrCs1httmAfAmp39+SThIG8NHUXAaoBDiAndEq6vzAn/g1s8jqW7uLvsYjvUjAZVFhYg+abZiSyQgEPBAAAAAAAAQMIeHwBAAAAAAABBBYAFIOn40vZn/A6SWLvihoQG7KVRh7OAAEDCGBrBCoBAAAAAQQWABR6w2nfGyDgM9YRZiOVewrEnzxS6AA="
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQIB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1ciFgL+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMhkAdystp1YAAIABAACAAAAAgAEAAAAAAAAAAQ4gJ3RKur8wJ/4NbPI6lu7i77GI71IwGVRYWIPmm2YkskIBDwQAAAAAAAEDCHh8AQAAAAAAAQQWABSDp+NL2Z/wOkli74oaEBuylUYezgABAwhgawQqAQAAAAEEFgAUesNp3xsg4DPWEWYjlXsKxJ88UugA"
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQIB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1cBDiAndEq6vzAn/g1s8jqW7uLvsYjvUjAZVFhYg+abZiSyQgEPBAAAAAAAAQMIiHsBAAAAAAABBBYAFCOChxx+hCGgAJP3VNkSgeZ1h0ufAAEFIQL+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMgEDCGBrBCoBAAAAAQQiUSBaLCz1tSzzH4OtLo2mP/Axg+zY9gnHUQropI4DkQoHVwA="
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQIB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1cBDiAndEq6vzAn/g1s8jqW7uLvsYjvUjAZVFhYg+abZiSyQgEPBAAAAAAAAQMIiHsBAAAAAAABBBYAFCOChxx+hCGgAJP3VNkSgeZ1h0ufACIHAv40kGTJjW4qhT+jybEr2LMEoZwZXGDvp+4jkwRtP6IyGQB3Ky2nVgAAgAEAAIAAAACAAQAAAAAAAAABAwhgawQqAQAAAAEEIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1cA"
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgwiR++/2SrEf29AuNQtFpF1oZ+p+hDkol1/NetN2FtpJCFAIssTrGgkjegGqmo2Wc88A+toIdCcgRSk6Gj+vehlu20s2XDhX1P8DIL5UP1WD/qRm3YXK+AXNoqJkTrwdPQAsJQIl1aqNznMxonsD886NgvjLMC1mxbpOh6LtGBXJrLKej/3BsQXZkljKyzGjh+RK4pXjjcZzncQiFx6lm9JvNQ8sBDiCb1IdlIwv5py5mIAH5clVuVPDG+X/rVry1YA2Bf2mVJgEPBAEAAAAAAQMISOYFKgEAAAABBCJRIAMNpPzk99sowsspUWMeADcThWWX/pY4gstQDmgRLMpjAA=="
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgwiR++/2SrEf29AuNQtFpF1oZ+p+hDkol1/NetN2FtpJBFCyxOsaCSN6AaqajZZzzwD62gh0JyBFKToaP696GW7bSzZcOFfU/wMgvlQ/VYP+pGbdhcr4Bc2iomROvB09ACwlCiXVqo3OczGiewPzzo2C+MswLWbFuk6Hou0YFcmssp6P/cGxBdmSWMrLMaOH5ErileONxnOdxCIXHqWb0m81DywEBAQ4gm9SHZSML+acuZiAB+XJVblTwxvl/61a8tWANgX9plSYBDwQBAAAAAAEDCEjmBSoBAAAAAQQiUSADDaT85PfbKMLLKVFjHgA3E4Vll/6WOILLUA5oESzKYwA="
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgwiR++/2SrEf29AuNQtFpF1oZ+p+hDkol1/NetN2FtpJjFcFQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wG99YgWelJehpKJnVp2YdtpgEBr/OONSm5uTnOf5GulwEV8uSQr3zEXE94UR82BXzlxaXFYyWin7RN/CA/NW4fgAIyAssTrGgkjegGqmo2Wc88A+toIdCcgRSk6Gj+vehlu20qzAAQ4gm9SHZSML+acuZiAB+XJVblTwxvl/61a8tWANgX9plSYBDwQBAAAAAAEDCEjmBSoBAAAAAQQiUSADDaT85PfbKMLLKVFjHgA3E4Vll/6WOILLUA5oESzKYwA="
+			      , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgwiR++/2SrEf29AuNQtFpF1oZ+p+hDkol1/NetN2FtpJhFcFQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wG99YgWelJehpKJnVp2YdtpgEBr/OONSm5uTnOf5GulwEV8uSQr3zEXE94UR82BXzlxaXFYyWin7RN/CA/NW4SMgLLE6xoJI3oBqpqNlnPPAPraCHQnIEUpOho/r3oZbttKswAEOIJvUh2UjC/mnLmYgAflyVW5U8Mb5f+tWvLVgDYF/aZUmAQ8EAQAAAAABAwhI5gUqAQAAAAEEIlEgAw2k/OT32yjCyylRYx4ANxOFZZf+ljiCy1AOaBEsymMA"
+			      ];
+		for v in invalid {
+			assert_eq!(Psbt::from_base64(v), Err(PsbtError::InvalidPsbt));
+		}
+
+		let valid = [ "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1chFv40kGTJjW4qhT+jybEr2LMEoZwZXGDvp+4jkwRtP6IyGQB3Ky2nVgAAgAEAAIAAAACAAQAAAAAAAAABFyD+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMgEOICd0Srq/MCf+DWzyOpbu4u+xiO9SMBlUWFiD5ptmJLJCAQ8EAAAAAAAiAgNrdyptt02HU8mKgnlY3mx4qzMSEJ830+AwRIQkLs5z2Bh3Ky2nVAAAgAEAAIAAAACAAAAAAAAAAAABAwhI5gUqAQAAAAEEFgAUdo4e60z0IIZgM/gKzv8PlyB0SWkA"
+			    , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1cBE0C7U+yRe62dkGrxuocYHEi4as5aritTYFpyXKdGJWMUdvxvW67a9PLuD0d/NvWPOXDVuCc7fkl7l68uPxJcl680IRb+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMhkAdystp1YAAIABAACAAAAAgAEAAAAAAAAAARcg/jSQZMmNbiqFP6PJsSvYswShnBlcYO+n7iOTBG0/ojIBDiAndEq6vzAn/g1s8jqW7uLvsYjvUjAZVFhYg+abZiSyQgEPBAAAAAAAIgIDa3cqbbdNh1PJioJ5WN5seKszEhCfN9PgMESEJC7Oc9gYdystp1QAAIABAACAAAAAgAAAAAAAAAAAAQMISOYFKgEAAAABBBYAFHaOHutM9CCGYDP4Cs7/D5cgdElpAA=="
+			    , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1chFv40kGTJjW4qhT+jybEr2LMEoZwZXGDvp+4jkwRtP6IyGQB3Ky2nVgAAgAEAAIAAAACAAQAAAAAAAAABFyD+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMgEOICd0Srq/MCf+DWzyOpbu4u+xiO9SMBlUWFiD5ptmJLJCAQ8EAAAAAAABBSARJNp67JLM0GyVRWJkf0N7E4uVchqEvivyJ2u92rPmcSEHESTaeuySzNBslUViZH9DexOLlXIahL4r8idrvdqz5nEZAHcrLadWAACAAQAAgAAAAIAAAAAABQAAAAEDCEjmBSoBAAAAAQQiUSCDaY5FjGZk4Vldddoll94eIu6X15jnBsTApLWpgjzXQwA="
+			    , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgwiR++/2SrEf29AuNQtFpF1oZ+p+hDkol1/NetN2FtpJiFcFQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wG99YgWelJehpKJnVp2YdtpgEBr/OONSm5uTnOf5GulwEV8uSQr3zEXE94UR82BXzlxaXFYyWin7RN/CA/NW4fgjICyxOsaCSN6AaqajZZzzwD62gh0JyBFKToaP696GW7bSrMBCFcFQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wJfG5v6l/3FP9XJEmZkIEOQG6YqhD1v35fZ4S8HQqabOIyBDILC/FvARtT6nvmFZJKp/J+XSmtIOoRVdhIZ2w7rRsqzAYhXBUJKbdMGgSVS3i0tgNel6XgeKWg8o7JbVR7/ums6AOsDNlw4V9T/AyC+VD9Vg/6kZt2FyvgFzaKiZE68HT0ALCRFfLkkK98xFxPeFEfNgV85cWlxWMlop+0TfwgPzVuH4IyD6D3o87zsdDAps59JuF62gsuXJLRnvrUi0GFnLikUcqazAIRYssTrGgkjegGqmo2Wc88A+toIdCcgRSk6Gj+vehlu20jkBzZcOFfU/wMgvlQ/VYP+pGbdhcr4Bc2iomROvB09ACwl3Ky2nVgAAgAEAAIACAACAAAAAAAAAAAAhFkMgsL8W8BG1Pqe+YVkkqn8n5dKa0g6hFV2EhnbDutGyOQERXy5JCvfMRcT3hRHzYFfOXFpcVjJaKftE38ID81bh+HcrLadWAACAAQAAgAEAAIAAAAAAAAAAACEWUJKbdMGgSVS3i0tgNel6XgeKWg8o7JbVR7/ums6AOsAFAHxGHl0hFvoPejzvOx0MCmzn0m4XraCy5cktGe+tSLQYWcuKRRypOQFvfWIFnpSXoaSiZ1admHbaYBAa/zjjUpubk5zn+RrpcHcrLadWAACAAQAAgAMAAIAAAAAAAAAAAAEXIFCSm3TBoElUt4tLYDXpel4HiloPKOyW1Ue/7prOgDrAARgg8DYuL3Wm9CClvePrIh2WrmcgzyX4GJDJWx13WstRXmUBDiCb1IdlIwv5py5mIAH5clVuVPDG+X/rVry1YA2Bf2mVJgEPBAEAAAAAAQUgESTaeuySzNBslUViZH9DexOLlXIahL4r8idrvdqz5nEhBxEk2nrskszQbJVFYmR/Q3sTi5VyGoS+K/Ina73as+ZxGQB3Ky2nVgAAgAEAAIAAAACAAAAAAAUAAAABAwhI5gUqAQAAAAEEIlEgg2mORYxmZOFZXXXaJZfeHiLul9eY5wbEwKS1qYI810MA"
+			    , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1chFv40kGTJjW4qhT+jybEr2LMEoZwZXGDvp+4jkwRtP6IyGQB3Ky2nVgAAgAEAAIAAAACAAQAAAAAAAAABFyD+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMgEOICd0Srq/MCf+DWzyOpbu4u+xiO9SMBlUWFiD5ptmJLJCAQ8EAAAAAAABBSBQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wAEGbwLAIiBzblcpAP4SUliaIUPI88efcaBBLSNTr3VelwHHgmlKAqwCwCIgYxxfO1gyuPvev7GXBM7rMjwh9A96JPQ9aO8MwmsSWWmsAcAiIET6pJoDON5IjI3//s37bzKfOAvVZu8gyN9tgT6rHEJzrCEHRPqkmgM43kiMjf/+zftvMp84C9Vm7yDI322BPqscQnM5AfBreYuSoQ7ZqdC7/Trxc6U7FhfaOkFZygCCFs2Fay4Odystp1YAAIABAACAAQAAgAAAAAADAAAAIQdQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wAUAfEYeXSEHYxxfO1gyuPvev7GXBM7rMjwh9A96JPQ9aO8MwmsSWWk5ARis5AmIl4Xg6nDO67jhyokqenjq7eDy4pbPQ1lhqPTKdystp1YAAIABAACAAgAAgAAAAAADAAAAIQdzblcpAP4SUliaIUPI88efcaBBLSNTr3VelwHHgmlKAjkBKaW0kVCQFi11mv0/4Pk/ozJgVtC0CIy5M8rngmy42Cx3Ky2nVgAAgAEAAIADAACAAAAAAAMAAAABAwhI5gUqAQAAAAEEIlEgCoy9yG3hzhwPnK6yLW33ztNoP+Qj4F0eQCqHk0HW9vUA"
+			    , "cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgwiR++/2SrEf29AuNQtFpF1oZ+p+hDkol1/NetN2FtpJBFCyxOsaCSN6AaqajZZzzwD62gh0JyBFKToaP696GW7bSzZcOFfU/wMgvlQ/VYP+pGbdhcr4Bc2iomROvB09ACwlAv4GNl1fW/+tTi6BX+0wfxOD17xhudlvrVkeR4Cr1/T1eJVHU404z2G8na4LJnHmu0/A5Wgge/NLMLGXdfmk9eUEUQyCwvxbwEbU+p75hWSSqfyfl0prSDqEVXYSGdsO60bIRXy5JCvfMRcT3hRHzYFfOXFpcVjJaKftE38ID81bh+EDh8atvq/omsjbyGDNxncHUKKt2jYD5H5mI2KvvR7+4Y7sfKlKfdowV8AzjTsKDzcB+iPhCi+KPbvZAQ8MpEYEaQRT6D3o87zsdDAps59JuF62gsuXJLRnvrUi0GFnLikUcqW99YgWelJehpKJnVp2YdtpgEBr/OONSm5uTnOf5GulwQOwfA3kgZGHIM0IoVCMyZwirAx8NpKJT7kWq+luMkgNNi2BUkPjNE+APmJmJuX4hX6o28S3uNpPS2szzeBwXV/ZiFcFQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wG99YgWelJehpKJnVp2YdtpgEBr/OONSm5uTnOf5GulwEV8uSQr3zEXE94UR82BXzlxaXFYyWin7RN/CA/NW4fgjICyxOsaCSN6AaqajZZzzwD62gh0JyBFKToaP696GW7bSrMBCFcFQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wJfG5v6l/3FP9XJEmZkIEOQG6YqhD1v35fZ4S8HQqabOIyBDILC/FvARtT6nvmFZJKp/J+XSmtIOoRVdhIZ2w7rRsqzAYhXBUJKbdMGgSVS3i0tgNel6XgeKWg8o7JbVR7/ums6AOsDNlw4V9T/AyC+VD9Vg/6kZt2FyvgFzaKiZE68HT0ALCRFfLkkK98xFxPeFEfNgV85cWlxWMlop+0TfwgPzVuH4IyD6D3o87zsdDAps59JuF62gsuXJLRnvrUi0GFnLikUcqazAIRYssTrGgkjegGqmo2Wc88A+toIdCcgRSk6Gj+vehlu20jkBzZcOFfU/wMgvlQ/VYP+pGbdhcr4Bc2iomROvB09ACwl3Ky2nVgAAgAEAAIACAACAAAAAAAAAAAAhFkMgsL8W8BG1Pqe+YVkkqn8n5dKa0g6hFV2EhnbDutGyOQERXy5JCvfMRcT3hRHzYFfOXFpcVjJaKftE38ID81bh+HcrLadWAACAAQAAgAEAAIAAAAAAAAAAACEWUJKbdMGgSVS3i0tgNel6XgeKWg8o7JbVR7/ums6AOsAFAHxGHl0hFvoPejzvOx0MCmzn0m4XraCy5cktGe+tSLQYWcuKRRypOQFvfWIFnpSXoaSiZ1admHbaYBAa/zjjUpubk5zn+RrpcHcrLadWAACAAQAAgAMAAIAAAAAAAAAAAAEXIFCSm3TBoElUt4tLYDXpel4HiloPKOyW1Ue/7prOgDrAARgg8DYuL3Wm9CClvePrIh2WrmcgzyX4GJDJWx13WstRXmUBDiCb1IdlIwv5py5mIAH5clVuVPDG+X/rVry1YA2Bf2mVJgEPBAEAAAAAAQUgESTaeuySzNBslUViZH9DexOLlXIahL4r8idrvdqz5nEhBxEk2nrskszQbJVFYmR/Q3sTi5VyGoS+K/Ina73as+ZxGQB3Ky2nVgAAgAEAAIAAAACAAAAAAAUAAAABAwhI5gUqAQAAAAEEIlEgg2mORYxmZOFZXXXaJZfeHiLul9eY5wbEwKS1qYI810MA"
+			    ];
+		for v in valid {
+			let psbt = Psbt::from_base64(v).expect("Should parse");
+			assert_eq!(psbt.to_base64(), v);
+		}
+	}
+
+	#[test]
+	fn test_bip371_accessors() {
+		/* The BIP-371 valid test vector "P2TR output with
+		 * PSBT_OUT_TAP_TREE" (after conversion), which also has
+		 * an input with PSBT_IN_TAP_INTERNAL_KEY and
+		 * PSBT_IN_TAP_BIP32_DERIVATION.
+		 */
+		let mut psbt = Psbt::from_base64("cHNidP8BAgQCAAAAAQQBAQEFAQEB+wQCAAAAAAEBKwDyBSoBAAAAIlEgWiws9bUs8x+DrS6Npj/wMYPs2PYJx1EK6KSOA5EKB1chFv40kGTJjW4qhT+jybEr2LMEoZwZXGDvp+4jkwRtP6IyGQB3Ky2nVgAAgAEAAIAAAACAAQAAAAAAAAABFyD+NJBkyY1uKoU/o8mxK9izBKGcGVxg76fuI5MEbT+iMgEOICd0Srq/MCf+DWzyOpbu4u+xiO9SMBlUWFiD5ptmJLJCAQ8EAAAAAAABBSBQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wAEGbwLAIiBzblcpAP4SUliaIUPI88efcaBBLSNTr3VelwHHgmlKAqwCwCIgYxxfO1gyuPvev7GXBM7rMjwh9A96JPQ9aO8MwmsSWWmsAcAiIET6pJoDON5IjI3//s37bzKfOAvVZu8gyN9tgT6rHEJzrCEHRPqkmgM43kiMjf/+zftvMp84C9Vm7yDI322BPqscQnM5AfBreYuSoQ7ZqdC7/Trxc6U7FhfaOkFZygCCFs2Fay4Odystp1YAAIABAACAAQAAgAAAAAADAAAAIQdQkpt0waBJVLeLS2A16XpeB4paDyjsltVHv+6azoA6wAUAfEYeXSEHYxxfO1gyuPvev7GXBM7rMjwh9A96JPQ9aO8MwmsSWWk5ARis5AmIl4Xg6nDO67jhyokqenjq7eDy4pbPQ1lhqPTKdystp1YAAIABAACAAgAAgAAAAAADAAAAIQdzblcpAP4SUliaIUPI88efcaBBLSNTr3VelwHHgmlKAjkBKaW0kVCQFi11mv0/4Pk/ozJgVtC0CIy5M8rngmy42Cx3Ky2nVgAAgAEAAIADAACAAAAAAAMAAAABAwhI5gUqAQAAAAEEIlEgCoy9yG3hzhwPnK6yLW33ztNoP+Qj4F0eQCqHk0HW9vUA")
+		.expect("Should parse");
+		let h = |s: &str| -> [u8; 32] {
+			hex::decode(s).unwrap().try_into().unwrap()
+		};
+
+		let internal = h("fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232");
+		assert_eq!(psbt.tap_internal_key(0), Some(internal));
+		assert_eq!(psbt.tap_merkle_root(0), None);
+		assert_eq!(psbt.tap_key_sig(0), None);
+		assert_eq!(psbt.tap_script_sigs(0), Some(Vec::new()));
+		assert_eq!(psbt.tap_leaf_scripts(0), Some(Vec::new()));
+		assert_eq!( psbt.tap_bip32_derivations(0)
+			  , Some(vec!(TapBip32Derivation{
+				xonly_pubkey: internal,
+				leaf_hashes: Vec::new(),
+				fingerprint: [0x77, 0x2b, 0x2d, 0xa7],
+				path: vec!(0x80000056, 0x80000001, 0x80000000, 1, 0)
+			  }))
+			  );
+		assert_eq!(psbt.tap_bip32_derivations(1), None);
+
+		assert_eq!( psbt.output_tap_internal_key(0)
+			  , Some(h("50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0"))
+			  );
+		let tree = psbt.output_tap_tree(0).expect("Should have tree");
+		assert_eq!( tree.iter().map(|l| (l.depth, l.leaf_version)).collect::<Vec<_>>()
+			  , vec!((2, 0xC0), (2, 0xC0), (1, 0xC0))
+			  );
+		assert_eq!( hex::encode(&tree[2].script)
+			  , "2044faa49a0338de488c8dfffecdfb6f329f380bd566ef20c8df6d813eab1c4273ac"
+			  );
+		let derivations = psbt.output_tap_bip32_derivations(0).unwrap();
+		assert_eq!(derivations.len(), 4);
+		assert_eq!( derivations[0].leaf_hashes
+			  , vec!(h("f06b798b92a10ed9a9d0bbfd3af173a53b1617da3a4159ca008216cd856b2e0e"))
+			  );
+		assert_eq!(derivations[0].path, vec!(0x80000056, 0x80000001, 0x80000001, 0, 3));
+		assert_eq!(derivations[1].leaf_hashes, Vec::<[u8; 32]>::new());
+		assert_eq!(derivations[1].fingerprint, [0x7c, 0x46, 0x1e, 0x5d]);
+		assert_eq!(derivations[1].path, Vec::<u32>::new());
+
+		/* Setters.  */
+		let sig = TapScriptSig{ xonly_pubkey: internal
+				      , leaf_hash: [0x11; 32]
+				      , sig: vec!(0x22; 65)
+				      };
+		/* 0x22 is not a sighash type.  */
+		assert_eq!(psbt.set_tap_script_sig(0, &sig), None);
+		let mut sig = sig;
+		sig.sig[64] = 0x83;
+		assert_eq!(psbt.set_tap_script_sig(1, &sig), None);
+		assert_eq!(psbt.set_tap_script_sig(0, &sig), Some(()));
+		assert_eq!(psbt.set_tap_key_sig(0, &[0x33; 64]), Some(()));
+		assert_eq!(psbt.set_tap_merkle_root(0, &[0x44; 32]), Some(()));
+		let leaf = TapLeafScript{ control_block: [&[0xC0][..], &internal[..]].concat()
+					, script: vec!(0x51)
+					, leaf_version: 0xC0
+					};
+		assert_eq!(psbt.set_tap_leaf_script(0, &leaf), Some(()));
+		assert_eq!( psbt.set_tap_leaf_script(0, &TapLeafScript{ control_block: vec!(0xC0; 34)
+								       , ..leaf.clone()
+								       })
+			  , None
+			  );
+		/* Not a complete tree.  */
+		assert_eq!(psbt.set_output_tap_tree(0, &tree[0..2]), None);
+		assert_eq!(psbt.set_output_tap_tree(0, &tree[1..3]), None);
+		assert_eq!(psbt.set_output_tap_tree(0, &[tree[0].clone(), tree[2].clone(), tree[1].clone()]), None);
+		let single = [TapTreeLeaf{depth: 0, leaf_version: 0xC0, script: vec!(0x51)}];
+		assert_eq!(psbt.set_output_tap_tree(0, &single), Some(()));
+
+		let psbt = Psbt::deserialize(&psbt.serialize()).expect("Should parse");
+		assert_eq!(psbt.tap_script_sigs(0), Some(vec!(sig)));
+		assert_eq!(psbt.tap_key_sig(0), Some(vec!(0x33; 64)));
+		assert_eq!(psbt.tap_merkle_root(0), Some([0x44; 32]));
+		assert_eq!(psbt.tap_leaf_scripts(0), Some(vec!(leaf)));
+		assert_eq!(psbt.output_tap_tree(0), Some(single.to_vec()));
+	}
+
+	#[test]
+	fn test_lock_time() {
+		let set = |p: &mut Psbt, i: usize, t: u64, v: u32| {
+			p.inputs[i].insert(t, Vec::new(), v.to_le_bytes().to_vec());
+		};
+
+		let psbt = modified(|p| {
+			p.global.insert(PSBT_GLOBAL_FALLBACK_LOCKTIME, Vec::new(), 700000u32.to_le_bytes().to_vec());
+		}).unwrap();
+		assert_eq!(psbt.lock_time(), Some(700000));
+
+		/* Requirements override the fallback, and the
+		 * maximum is taken.
+		 */
+		let psbt = modified(|p| {
+			p.global.insert(PSBT_GLOBAL_FALLBACK_LOCKTIME, Vec::new(), 700000u32.to_le_bytes().to_vec());
+			set(p, 0, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, 800000);
+			set(p, 1, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, 800001);
+		}).unwrap();
+		assert_eq!(psbt.lock_time(), Some(800001));
+
+		/* Height is preferred if all inputs with
+		 * requirements support it.
+		 */
+		let psbt = modified(|p| {
+			set(p, 0, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, 800000);
+			set(p, 0, PSBT_IN_REQUIRED_TIME_LOCKTIME, 1700000000);
+			set(p, 1, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, 800001);
+		}).unwrap();
+		assert_eq!(psbt.lock_time(), Some(800001));
+
+		/* Otherwise time is used.  */
+		let psbt = modified(|p| {
+			set(p, 0, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, 800000);
+			set(p, 0, PSBT_IN_REQUIRED_TIME_LOCKTIME, 1700000001);
+			set(p, 1, PSBT_IN_REQUIRED_TIME_LOCKTIME, 1700000000);
+		}).unwrap();
+		assert_eq!(psbt.lock_time(), Some(1700000001));
+		assert_eq!(psbt.unsigned_tx().unwrap().lock_time, 1700000001);
+	}
+}
@@ -0,0 +1,308 @@
+/*!
+The `recovery` module builds the transaction with which
+Alice unilaterally recovers her swap-in-potentiam
+outputs via the timelock tapleaf path, without Bob, once
+they have 4032 confirmations, e.g. when the LSP has
+disappeared.
+
+The transaction sweeps all the given outputs into a
+single output, and every input is signed by Alice with
+`SIGHASH_DEFAULT`, so the transaction is finalized and
+can be broadcast as-is.
+Feerates are in sat/kWU, like the `min_feerate` of the
+`onchain_fee_schedule`.
+*/
+use secp256k1::Keypair;
+use secp256k1::Message;
+use secp256k1::PublicKey;
+use secp256k1::Secp256k1;
+use secp256k1::SecretKey;
+use secp256k1::Signing;
+use secp256k1::Verification;
+use super::address;
+use super::scripts;
+use super::sighash;
+use super::tx::OutPoint;
+use super::tx::Transaction;
+use super::tx::TxIn;
+use super::tx::TxOut;
+use super::witness;
+
+/**
+`DUST_LIMIT_SAT` is the smallest amount of the output of
+the recovery transaction, which is the dust limit of the
+largest standard output types.
+*/
+pub
+const DUST_LIMIT_SAT: u64 = 546;
+
+/**
+`RecoveryUtxo` is a swap-in-potentiam output of Alice to
+recover.
+*/
+#[derive(Clone, Debug)]
+pub
+struct RecoveryUtxo {
+	pub outpoint: OutPoint,
+	pub amount_sat: u64,
+	/// The secret key of the Alice public key of the
+	/// swap-in-potentiam address.
+	pub alice: SecretKey,
+	/// The height of the block that confirmed the
+	/// output, or None if it is unconfirmed.
+	pub confirmation_height: Option<u32>
+}
+
+/**
+`RecoveryError` is the reason `build_alice_recovery`
+failed.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+enum RecoveryError {
+	/// No outputs were given.
+	NoInputs,
+	/// The given output is unconfirmed, or its timelock
+	/// has not matured, i.e. the transaction would not be
+	/// valid in the next block.
+	Immature(OutPoint),
+	/// The output would be below `DUST_LIMIT_SAT` after
+	/// paying the fee.
+	FeeTooHigh,
+	/// The sum of the amounts of the outputs
+	/// overflows.
+	Overflow,
+	/// The swap-in-potentiam address of an output is
+	/// the point at infinity, which has negligible
+	/// probability.
+	InvalidAddress
+}
+
+/**
+`build_alice_recovery` builds and signs the transaction
+that sends all of `utxos`, minus the fee at `feerate`,
+to `destination`, a `scriptPubKey`, where `bob` is the
+LSP node ID of the swap-in-potentiam addresses, and
+`current_height` is the height of the chain tip.
+
+Each input has an `nSequence` of 4032, and `nLockTime`
+is `current_height`.
+The transaction is refused unless every input has at
+least 4032 confirmations in the next block.
+
+`aux_rand` is the auxiliary random data for BIP-340
+signing, which should be fresh randomness.
+*/
+pub
+fn build_alice_recovery<C>( secp256k1: &Secp256k1<C>
+			  , bob: &PublicKey
+			  , utxos: &[RecoveryUtxo]
+			  , destination: &[u8]
+			  , feerate: u64
+			  , current_height: u32
+			  , aux_rand: &[u8; 32]
+			  ) -> Result<Transaction, RecoveryError>
+	where C: Signing + Verification
+{
+	if utxos.is_empty() {
+		return Err(RecoveryError::NoInputs);
+	}
+
+	let mut tx = Transaction{ version: 2
+				, lock_time: current_height
+				, input: Vec::with_capacity(utxos.len())
+				, output: vec!(TxOut{ value: 0
+						    , script_pubkey: destination.to_vec()
+						    })
+				};
+	let mut prevouts = Vec::with_capacity(utxos.len());
+	let mut keypairs = Vec::with_capacity(utxos.len());
+	let mut total = 0u64;
+	for utxo in utxos.iter() {
+		let keypair = Keypair::from_secret_key(secp256k1, &utxo.alice);
+		let alice = keypair.public_key();
+
+		/* The nSequence must be the CSV of the
+		timelock tapleaf.
+		*/
+		let (csv, _) = scripts::parse_tapleaf_alice_recovery(
+			&scripts::tapleaf_alice_recovery(&alice)
+		).expect("tapleaf_alice_recovery is parseable");
+		let mature = utxo.confirmation_height.is_some_and(|height| {
+			current_height as u64 + 1 >= height as u64 + csv as u64
+		});
+		if !mature {
+			return Err(RecoveryError::Immature(utxo.outpoint.clone()));
+		}
+
+		/* Placeholder witness of the same size as
+		the signed one, for the weight.
+		*/
+		let witness = witness::witness_alice_recovery(
+			secp256k1, &alice, bob, &[0; 64], None
+		).ok_or(RecoveryError::InvalidAddress)?;
+		tx.input.push(TxIn{ prevout: utxo.outpoint.clone()
+				  , script_sig: Vec::new()
+				  , sequence: csv
+				  , witness
+				  });
+		let script_pubkey = address::derive_taproot_scriptpubkey(
+			secp256k1, &alice, bob
+		).ok_or(RecoveryError::InvalidAddress)?;
+		prevouts.push(TxOut{ value: utxo.amount_sat
+				   , script_pubkey
+				   });
+		keypairs.push(keypair);
+		total = total.checked_add(utxo.amount_sat)
+		.ok_or(RecoveryError::Overflow)?;
+	}
+
+	/* A fee that overflows is certainly too high.  */
+	let fee = feerate.checked_mul(tx.weight())
+	.ok_or(RecoveryError::FeeTooHigh)?
+	.div_ceil(1000);
+	if total < fee + DUST_LIMIT_SAT {
+		return Err(RecoveryError::FeeTooHigh);
+	}
+	tx.output[0].value = total - fee;
+
+	for (i, keypair) in keypairs.iter().enumerate() {
+		let alice = keypair.public_key();
+		let sighash = sighash::alice_recovery_sighash(
+			&tx, i, &prevouts, &alice, sighash::SIGHASH_DEFAULT
+		).expect("valid input of the transaction");
+		let sig = secp256k1.sign_schnorr_with_aux_rand(
+			&Message::from_digest(sighash),
+			keypair,
+			aux_rand
+		);
+		tx.input[i].witness[0] = sig.as_ref().to_vec();
+	}
+
+	Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+	use secp256k1::XOnlyPublicKey;
+	use secp256k1::schnorr::Signature;
+	use super::*;
+
+	fn secret(n: u8) -> SecretKey {
+		SecretKey::from_slice(&[n; 32]).unwrap()
+	}
+
+	fn utxos() -> Vec<RecoveryUtxo> {
+		vec!(
+			RecoveryUtxo{ outpoint: OutPoint{txid: [0xA1; 32], vout: 0}
+				    , amount_sat: 50000
+				    , alice: secret(1)
+				    , confirmation_height: Some(800000)
+				    },
+			RecoveryUtxo{ outpoint: OutPoint{txid: [0xA2; 32], vout: 3}
+				    , amount_sat: 25000
+				    , alice: secret(2)
+				    , confirmation_height: Some(800100)
+				    }
+		)
+	}
+
+	#[test]
+	fn test_build_alice_recovery() {
+		let secp256k1 = Secp256k1::new();
+		let bob = PublicKey::from_secret_key(&secp256k1, &secret(9));
+		let destination = [vec!(0x00, 0x14), vec!(0x33; 20)].concat();
+		let utxos = utxos();
+
+		/* The second output matures in block 804132.  */
+		let tx = build_alice_recovery( &secp256k1, &bob, &utxos, &destination
+					     , 2000, 804131, &[0; 32]
+					     ).unwrap();
+		assert_eq!(tx.version, 2);
+		assert_eq!(tx.lock_time, 804131);
+		assert_eq!(tx.output.len(), 1);
+		assert_eq!(tx.output[0].script_pubkey, destination);
+		let fee = 75000 - tx.output[0].value;
+		assert_eq!(fee, (2000 * tx.weight()).div_ceil(1000));
+
+		let prevouts: Vec<TxOut> = utxos.iter().map(|u| {
+			let alice = PublicKey::from_secret_key(&secp256k1, &u.alice);
+			TxOut{ value: u.amount_sat
+			     , script_pubkey: address::derive_taproot_scriptpubkey(&secp256k1, &alice, &bob).unwrap()
+			     }
+		}).collect();
+		for (i, utxo) in utxos.iter().enumerate() {
+			let alice = PublicKey::from_secret_key(&secp256k1, &utxo.alice);
+			let txin = &tx.input[i];
+			assert_eq!(txin.prevout, utxo.outpoint);
+			assert_eq!(txin.sequence, 4032);
+			assert!(txin.script_sig.is_empty());
+
+			let sig = &txin.witness[0];
+			assert_eq!(sig.len(), 64);
+			assert_eq!( Some(txin.witness.clone())
+				  , witness::witness_alice_recovery(&secp256k1, &alice, &bob, sig, None)
+				  );
+			let sighash = sighash::alice_recovery_sighash( &tx, i, &prevouts
+								     , &alice, sighash::SIGHASH_DEFAULT
+								     ).unwrap();
+			assert!(secp256k1.verify_schnorr( &Signature::from_slice(sig).unwrap()
+							, &Message::from_digest(sighash)
+							, &XOnlyPublicKey::from(alice)
+							).is_ok());
+		}
+	}
+
+	#[test]
+	fn test_build_alice_recovery_fails() {
+		let secp256k1 = Secp256k1::new();
+		let bob = PublicKey::from_secret_key(&secp256k1, &secret(9));
+		let destination = [vec!(0x00, 0x14), vec!(0x33; 20)].concat();
+		let utxos = utxos();
+
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &utxos, &destination
+						, 2000, 804130, &[0; 32]
+						)
+			  , Err(RecoveryError::Immature(OutPoint{txid: [0xA2; 32], vout: 3}))
+			  );
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &utxos[0..1], &destination
+						, 2000, 804030, &[0; 32]
+						)
+			  , Err(RecoveryError::Immature(OutPoint{txid: [0xA1; 32], vout: 0}))
+			  );
+		assert!(build_alice_recovery( &secp256k1, &bob, &utxos[0..1], &destination
+					    , 2000, 804031, &[0; 32]
+					    ).is_ok());
+
+		let mut unconfirmed = utxos.clone();
+		unconfirmed[0].confirmation_height = None;
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &unconfirmed, &destination
+						, 2000, 804131, &[0; 32]
+						)
+			  , Err(RecoveryError::Immature(OutPoint{txid: [0xA1; 32], vout: 0}))
+			  );
+
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &[], &destination
+						, 2000, 804131, &[0; 32]
+						)
+			  , Err(RecoveryError::NoInputs)
+			  );
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &utxos, &destination
+						, 200000, 804131, &[0; 32]
+						)
+			  , Err(RecoveryError::FeeTooHigh)
+			  );
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &utxos, &destination
+						, u64::MAX, 804131, &[0; 32]
+						)
+			  , Err(RecoveryError::FeeTooHigh)
+			  );
+		let mut huge = utxos.clone();
+		huge[1].amount_sat = u64::MAX;
+		assert_eq!( build_alice_recovery( &secp256k1, &bob, &huge, &destination
+						, 2000, 804131, &[0; 32]
+						)
+			  , Err(RecoveryError::Overflow)
+			  );
+	}
+}
@@ -6,14 +6,14 @@ use secp256k1::SecretKey;
 
 fn scalar_to_sk(a: &Scalar) -> SecretKey {
 	SecretKey::from_slice(
-		&a.clone().to_be_bytes()
+		&a.to_be_bytes()
 	).expect("only use this if you already know non-0")
 }
 
 pub(crate)
 fn scalar_plus(a: &Scalar, b: &Scalar) -> Scalar {
 	if a == &Scalar::ZERO {
-		return b.clone();
+		return *b;
 	}
 	let sk_a = scalar_to_sk(a);
 	let sum = sk_a.add_tweak(b);
@@ -26,15 +26,62 @@ fn scalar_plus(a: &Scalar, b: &Scalar) -> Scalar {
 pub(crate)
 fn scalar_negate(a: &Scalar) -> Scalar {
 	if a == &Scalar::ZERO {
-		return a.clone();
+		return *a;
 	}
 	let sk_a = scalar_to_sk(a);
 	Scalar::from(sk_a.negate())
 }
 
+pub(crate)
+fn scalar_times(a: &Scalar, b: &Scalar) -> Scalar {
+	if a == &Scalar::ZERO || b == &Scalar::ZERO {
+		return Scalar::ZERO;
+	}
+	let sk_a = scalar_to_sk(a);
+	/* n is prime, so the product of two non-0
+	scalars is never 0.
+	*/
+	let prod = sk_a.mul_tweak(b)
+	.expect("product of non-0 scalars is non-0");
+	Scalar::from(prod)
+}
+
+/* 2^256 - n, big-endian.  */
+const TWO_256_MINUS_N: [u8; 32] =
+[ 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01
+, 0x45, 0x51, 0x23, 0x19, 0x50, 0xB7, 0x5F, 0xC4
+, 0x40, 0x2D, 0xA1, 0x73, 0x2F, 0xC9, 0xBE, 0xBF
+];
+
+/* Interprets the 32 bytes as a big-endian integer and
+reduces it modulo n, i.e. the `int(x) mod n` that
+BIP-327 applies to hash outputs.
+*/
+pub(crate)
+fn scalar_from_bytes_mod_n(buf: [u8; 32]) -> Scalar {
+	if let Ok(s) = Scalar::from_be_bytes(buf) {
+		return s;
+	}
+	/* buf >= n, and n > 2^255, so buf - n < n.
+	Subtract n by adding 2^256 - n and dropping
+	the final carry.
+	*/
+	let mut rv: [u8; 32] = [0; 32];
+	let mut carry: u16 = 0;
+	for i in (0..32).rev() {
+		let sum = (buf[i] as u16)
+			+ (TWO_256_MINUS_N[i] as u16)
+			+ carry;
+		rv[i] = (sum & 0xFF) as u8;
+		carry = sum >> 8;
+	}
+	Scalar::from_be_bytes(rv)
+	.expect("reduced value is less than n")
+}
+
 #[cfg(test)]
 mod test {
-	use hex;
 	use super::*;
 
 	fn scalar(a: &str) -> Scalar {
@@ -129,4 +176,47 @@ mod test {
 			  , scalar("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD036413E")
 			  );
 	}
+
+	#[test]
+	fn test_scalar_times() {
+		/* 0 * 1 = 0 */
+		assert_eq!( scalar_times(&Scalar::ZERO, &Scalar::ONE)
+			  , Scalar::ZERO
+			  );
+		/* -1 * -1 = 1 */
+		assert_eq!( scalar_times(&Scalar::MAX, &Scalar::MAX)
+			  , Scalar::ONE
+			  );
+		/* 2 * -1 = -2 */
+		assert_eq!( scalar_times(&scalar("0000000000000000000000000000000000000000000000000000000000000002"), &Scalar::MAX)
+			  , scalar_negate(&scalar("0000000000000000000000000000000000000000000000000000000000000002"))
+			  );
+		/* 256 * 511 = 130816 */
+		assert_eq!( scalar_times(&scalar("0000000000000000000000000000000000000000000000000000000000000100"), &scalar("00000000000000000000000000000000000000000000000000000000000001FF"))
+			  , scalar("000000000000000000000000000000000000000000000000000000000001FF00")
+			  );
+	}
+
+	fn bytes(a: &str) -> [u8; 32] {
+		hex::decode(a)
+		.expect("Test input must be hex")
+		.try_into()
+		.expect("Test input must be 32 bytes")
+	}
+
+	#[test]
+	fn test_scalar_from_bytes_mod_n() {
+		/* n - 1 is unchanged */
+		assert_eq!( scalar_from_bytes_mod_n(bytes("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364140"))
+			  , Scalar::MAX
+			  );
+		/* n = 0 mod n */
+		assert_eq!( scalar_from_bytes_mod_n(bytes("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"))
+			  , Scalar::ZERO
+			  );
+		/* 2^256 - 1 = 2^256 - n - 1 mod n */
+		assert_eq!( scalar_from_bytes_mod_n(bytes("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"))
+			  , scalar("000000000000000000000000000000014551231950B75FC4402DA1732FC9BEBE")
+			  );
+	}
 }
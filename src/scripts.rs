@@ -1,10 +1,12 @@
+use hashes::sha2::sha256;
 use secp256k1::PublicKey;
+use super::bip340::lift_x;
 
 /* Structure for P0 and P1.  */
 pub(crate)
 struct P0P1 {
-	p0: PublicKey,
-	p1: PublicKey
+	pub(crate) p0: PublicKey,
+	pub(crate) p1: PublicKey
 }
 
 impl P0P1 {
@@ -81,9 +83,197 @@ fn tapleaf_alice_recovery(alice: &PublicKey) -> Vec<u8> {
 	rv
 }
 
+/* Returns the BOLT 3 anchor witness script of the given
+Bob public key, which is the plain 33-byte key, and may
+have an odd Y coordinate.
+*/
+pub(crate)
+fn anchor(bob: &PublicKey) -> Vec<u8> {
+	let mut rv = Vec::new();
+
+	rv.push(0x21); /* PUSH 33 bytes */
+	rv.extend_from_slice(&bob.serialize());
+	rv.push(0xAC); /* OP_CHECKSIG */
+	rv.push(0x73); /* OP_IFDUP */
+	rv.push(0x64); /* OP_NOTIF */
+	rv.push(0x60); /* OP_16 */
+	rv.push(0xB2); /* OP_CHECKSEQUENCEVERIFY */
+	rv.push(0x68); /* OP_ENDIF */
+
+	assert_eq!(rv.len(), 40);
+
+	rv
+}
+
+/* Returns the SegWit v0 P2WSH scriptPubKey of the given
+witness script.
+*/
+pub(crate)
+fn p2wsh(witness_script: &[u8]) -> Vec<u8> {
+	let mut rv = Vec::new();
+
+	rv.push(0x00); /* OP_0 */
+	rv.push(0x20); /* PUSH 32 bytes */
+	rv.extend_from_slice(&sha256::hash(witness_script).into_bytes());
+
+	rv
+}
+
+/* Reasons a script does not match one of the tapleaf
+templates above.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate)
+enum TapleafError {
+	/* The script ended before the template did.  */
+	Truncated,
+	/* The script continues after the template ended.  */
+	TrailingData,
+	/* An opcode or push does not match the template.  */
+	WrongOpcode,
+	/* A 32-byte push is not the X coordinate of a
+	point on the curve.
+	*/
+	InvalidKey,
+	/* The 2-of-2 keys are not in P[0] < P[1] order.  */
+	UnsortedKeys,
+	/* The 2-of-2 keys are the same key.  */
+	DuplicateKeys,
+	/* The CSV value is negative, or does not fit in
+	the 32-bit nSequence.
+	*/
+	CsvOutOfRange
+}
+
+/* Simple cursor over a script.  */
+struct ScriptReader<'a> {
+	script: &'a [u8],
+	pos: usize
+}
+impl<'a> ScriptReader<'a> {
+	fn new(script: &'a [u8]) -> Self {
+		ScriptReader{script, pos: 0}
+	}
+	fn read_byte(&mut self) -> Result<u8, TapleafError> {
+		if self.pos >= self.script.len() {
+			return Err(TapleafError::Truncated);
+		}
+		self.pos += 1;
+		Ok(self.script[self.pos - 1])
+	}
+	fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], TapleafError> {
+		if self.script.len() - self.pos < n {
+			return Err(TapleafError::Truncated);
+		}
+		self.pos += n;
+		Ok(&self.script[self.pos - n..self.pos])
+	}
+	fn expect_opcode(&mut self, opcode: u8) -> Result<(), TapleafError> {
+		if self.read_byte()? != opcode {
+			return Err(TapleafError::WrongOpcode);
+		}
+		Ok(())
+	}
+	/* A 32-byte push of an X coordinate.  */
+	fn read_xonly(&mut self) -> Result<[u8; 32], TapleafError> {
+		self.expect_opcode(0x20)?; /* PUSH 32 bytes */
+		let x: [u8; 32] = self.read_bytes(32)?
+			.try_into()
+			.expect("read exactly 32 bytes");
+		if lift_x(&x).is_none() {
+			return Err(TapleafError::InvalidKey);
+		}
+		Ok(x)
+	}
+	/* A script number, as accepted by
+	OP_CHECKSEQUENCEVERIFY (at most 5 bytes).
+
+	This does not require minimal number encoding, as
+	consensus does not: tapleaf_alice_recovery itself
+	encodes 4032 as 3 bytes where 2 would do.
+	*/
+	fn read_csv(&mut self) -> Result<u32, TapleafError> {
+		let opcode = self.read_byte()?;
+		let bytes = match opcode {
+			0x00 => { return Ok(0); }, /* OP_0 */
+			0x4F => { return Err(TapleafError::CsvOutOfRange); }, /* OP_1NEGATE */
+			0x51..=0x60 => { return Ok((opcode - 0x50) as u32); }, /* OP_1 to OP_16 */
+			0x01..=0x05 => self.read_bytes(opcode as usize)?,
+			_ => { return Err(TapleafError::WrongOpcode); }
+		};
+		/* Little-endian magnitude, with the sign in the
+		top bit of the last byte.
+		*/
+		let mut value: u64 = 0;
+		for (i, b) in bytes.iter().enumerate() {
+			value |= (*b as u64) << (8 * i);
+		}
+		let sign_bit = 0x80u64 << (8 * (bytes.len() - 1));
+		let negative = (value & sign_bit) != 0;
+		value &= !sign_bit;
+		if negative && value != 0 {
+			return Err(TapleafError::CsvOutOfRange);
+		}
+		value.try_into().map_err(|_| TapleafError::CsvOutOfRange)
+	}
+	fn expect_end(&self) -> Result<(), TapleafError> {
+		if self.pos != self.script.len() {
+			return Err(TapleafError::TrailingData);
+		}
+		Ok(())
+	}
+}
+
+/* Parses a script of the template
+`<P[0]> OP_CHECKSIGVERIFY <P[1]> OP_CHECKSIG` as built
+by tapleaf_cooperative, returning the X coordinates of
+P[0] and P[1].
+
+P[0] must be lexicographically less than P[1].
+*/
+pub(crate)
+fn parse_tapleaf_cooperative( script: &[u8]
+			    ) -> Result<([u8; 32], [u8; 32]), TapleafError> {
+	let mut reader = ScriptReader::new(script);
+	let p0 = reader.read_xonly()?;
+	reader.expect_opcode(0xAD)?; /* OP_CHECKSIGVERIFY */
+	let p1 = reader.read_xonly()?;
+	reader.expect_opcode(0xAC)?; /* OP_CHECKSIG */
+	reader.expect_end()?;
+
+	if p0 == p1 {
+		return Err(TapleafError::DuplicateKeys);
+	}
+	if p0 > p1 {
+		return Err(TapleafError::UnsortedKeys);
+	}
+	Ok((p0, p1))
+}
+
+/* Parses a script of the template
+`<csv> OP_CHECKSEQUENCEVERIFY OP_DROP <A> OP_CHECKSIG`
+as built by tapleaf_alice_recovery, returning the CSV
+value and the X coordinate of A.
+
+The CSV value is returned as-is; the caller has to
+check that it is the 4032 swap-in-potentiam uses.
+*/
+pub(crate)
+fn parse_tapleaf_alice_recovery( script: &[u8]
+			       ) -> Result<(u32, [u8; 32]), TapleafError> {
+	let mut reader = ScriptReader::new(script);
+	let csv = reader.read_csv()?;
+	reader.expect_opcode(0xB2)?; /* OP_CHECKSEQUENCEVERIFY */
+	reader.expect_opcode(0x75)?; /* OP_DROP */
+	let a = reader.read_xonly()?;
+	reader.expect_opcode(0xAC)?; /* OP_CHECKSIG */
+	reader.expect_end()?;
+
+	Ok((csv, a))
+}
+
 #[cfg(test)]
 mod tests {
-	use hex;
 	use super::*;
 
 	fn pubkey(h: &str) -> PublicKey {
@@ -140,4 +330,124 @@ mod tests {
 			    hex::decode("03c00f00b27520c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830aeAC")
 			    .expect("Test gives hex"));
 	}
+
+	#[test]
+	fn test_anchor() {
+		/* BOLT 3 to_local_anchor and to_remote_anchor.  */
+		assert_eq!( anchor(&pubkey("030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1")),
+			    hex::decode("21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1AC736460B268")
+			    .expect("Test gives hex"));
+	}
+
+	#[test]
+	fn test_parse_tapleaf_cooperative() {
+		let a = pubkey("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = pubkey("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let script = tapleaf_cooperative(&a, &b);
+		let (p0, p1) = parse_tapleaf_cooperative(&script)
+		.expect("Should parse");
+		assert_eq!(hex::encode(p0), "659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		assert_eq!(hex::encode(p1), "c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+
+		/* Unsorted.  */
+		let mut unsorted = Vec::new();
+		unsorted.extend_from_slice(&script[34..67]);
+		unsorted.push(0xAD);
+		unsorted.extend_from_slice(&script[0..33]);
+		unsorted.push(0xAC);
+		assert_eq!(parse_tapleaf_cooperative(&unsorted), Err(TapleafError::UnsortedKeys));
+
+		/* Same key twice.  */
+		let mut duplicate = script.clone();
+		duplicate[35..67].copy_from_slice(&script[1..33]);
+		assert_eq!(parse_tapleaf_cooperative(&duplicate), Err(TapleafError::DuplicateKeys));
+
+		/* OP_CHECKSIG instead of OP_CHECKSIGVERIFY.  */
+		let mut wrong = script.clone();
+		wrong[33] = 0xAC;
+		assert_eq!(parse_tapleaf_cooperative(&wrong), Err(TapleafError::WrongOpcode));
+		/* OP_CHECKSIGADD instead of OP_CHECKSIG.  */
+		let mut wrong = script.clone();
+		wrong[67] = 0xBA;
+		assert_eq!(parse_tapleaf_cooperative(&wrong), Err(TapleafError::WrongOpcode));
+		/* Compressed key push instead of x-only.  */
+		let mut wrong = vec!(0x21, 0x02);
+		wrong.extend_from_slice(&script[1..]);
+		assert_eq!(parse_tapleaf_cooperative(&wrong), Err(TapleafError::WrongOpcode));
+
+		/* Not on the curve (x = 5).  */
+		let mut invalid = script.clone();
+		invalid[1..33].copy_from_slice(&[0u8; 32]);
+		invalid[32] = 5;
+		assert_eq!(parse_tapleaf_cooperative(&invalid), Err(TapleafError::InvalidKey));
+
+		assert_eq!(parse_tapleaf_cooperative(&script[0..67]), Err(TapleafError::Truncated));
+		assert_eq!(parse_tapleaf_cooperative(&script[0..20]), Err(TapleafError::Truncated));
+		assert_eq!(parse_tapleaf_cooperative(&[]), Err(TapleafError::Truncated));
+		let mut trailing = script.clone();
+		trailing.push(0x51);
+		assert_eq!(parse_tapleaf_cooperative(&trailing), Err(TapleafError::TrailingData));
+
+		/* The other template.  */
+		assert_eq!(parse_tapleaf_cooperative(&tapleaf_alice_recovery(&a)), Err(TapleafError::WrongOpcode));
+	}
+
+	#[test]
+	fn test_parse_tapleaf_alice_recovery() {
+		let a = pubkey("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = pubkey("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let script = tapleaf_alice_recovery(&a);
+		let (csv, ax) = parse_tapleaf_alice_recovery(&script)
+		.expect("Should parse");
+		assert_eq!(csv, 4032);
+		assert_eq!(hex::encode(ax), "c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+
+		let with_csv = |csv: &[u8]| -> Vec<u8> {
+			let mut rv = csv.to_vec();
+			rv.extend_from_slice(&script[4..]);
+			rv
+		};
+		/* Other CSV values.  */
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x00])).map(|r| r.0), Ok(0));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x60])).map(|r| r.0), Ok(16));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x01, 0x11])).map(|r| r.0), Ok(17));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x02, 0x80, 0x00])).map(|r| r.0), Ok(128));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0x00])).map(|r| r.0), Ok(0xFFFFFFFF));
+		/* Non-minimal CSV encodings, including negative
+		 * zero.
+		 */
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x01, 0x05])).map(|r| r.0), Ok(5));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x01, 0x80])).map(|r| r.0), Ok(0));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x02, 0xC0, 0x0F])).map(|r| r.0), Ok(4032));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x04, 0xC0, 0x0F, 0x00, 0x00])).map(|r| r.0), Ok(4032));
+		/* Negative and too large CSV values.  */
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x4F])), Err(TapleafError::CsvOutOfRange));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x02, 0xC0, 0x8F])), Err(TapleafError::CsvOutOfRange));
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x05, 0x00, 0x00, 0x00, 0x00, 0x01])), Err(TapleafError::CsvOutOfRange));
+		/* Not a number push at all.  */
+		assert_eq!(parse_tapleaf_alice_recovery(&with_csv(&[0x06, 1, 2, 3, 4, 5, 6])), Err(TapleafError::WrongOpcode));
+
+		/* OP_CHECKLOCKTIMEVERIFY instead of CSV.  */
+		let mut wrong = script.clone();
+		wrong[4] = 0xB1;
+		assert_eq!(parse_tapleaf_alice_recovery(&wrong), Err(TapleafError::WrongOpcode));
+		/* Missing OP_DROP.  */
+		let mut wrong = script[0..5].to_vec();
+		wrong.extend_from_slice(&script[6..]);
+		assert_eq!(parse_tapleaf_alice_recovery(&wrong), Err(TapleafError::WrongOpcode));
+
+		let mut invalid = script.clone();
+		invalid[7..39].copy_from_slice(&[0u8; 32]);
+		invalid[38] = 5;
+		assert_eq!(parse_tapleaf_alice_recovery(&invalid), Err(TapleafError::InvalidKey));
+
+		assert_eq!(parse_tapleaf_alice_recovery(&script[0..39]), Err(TapleafError::Truncated));
+		assert_eq!(parse_tapleaf_alice_recovery(&script[0..2]), Err(TapleafError::Truncated));
+		let mut trailing = script.clone();
+		trailing.push(0xAC);
+		assert_eq!(parse_tapleaf_alice_recovery(&trailing), Err(TapleafError::TrailingData));
+
+		/* The other template.  */
+		assert_eq!(parse_tapleaf_alice_recovery(&tapleaf_cooperative(&a, &b)), Err(TapleafError::WrongOpcode));
+	}
 }
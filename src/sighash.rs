@@ -0,0 +1,376 @@
+/*!
+The `sighash` module computes the BIP-341 signature
+hashes that Alice and Bob sign when spending a
+swap-in-potentiam output, for each of the three ways it
+can be spent:
+
+* `keypath_sighash` for the MuSig2 keypath spend (see
+  `address::keypath_session_context`).
+* `cooperative_sighash` for the 2-of-2 tapleaf path.
+* `alice_recovery_sighash` for the timelock tapleaf
+  path.
+
+All of them take the transaction being signed, the
+index of the input being signed, and `prevouts`, the
+outputs spent by *every* input of the transaction, in
+input order, as BIP-341 commits to all of their amounts
+and scriptPubKeys.
+
+`anchor_sighash` computes the [BIP-143][] signature
+hash that Bob signs to spend the anchor output of a
+funding transaction, which is a SegWit v0 output.
+
+The BIP-341 ones fail (return None) if the `hash_type` is not one
+of the BIP-341 hash types, if `input_index` is out of
+range, if `prevouts` does not have one entry per input,
+or if `SIGHASH_SINGLE` is used for an input without a
+corresponding output.
+
+[BIP-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+*/
+use hashes::sha2::sha256;
+use secp256k1::PublicKey;
+use super::bip341;
+use super::scripts;
+use super::tx::Transaction;
+use super::tx::TxOut;
+
+pub const SIGHASH_DEFAULT: u8 = bip341::SIGHASH_DEFAULT;
+pub const SIGHASH_ALL: u8 = bip341::SIGHASH_ALL;
+pub const SIGHASH_NONE: u8 = bip341::SIGHASH_NONE;
+pub const SIGHASH_SINGLE: u8 = bip341::SIGHASH_SINGLE;
+pub const SIGHASH_ANYONECANPAY: u8 = bip341::SIGHASH_ANYONECANPAY;
+
+/* Neither tapleaf script uses OP_CODESEPARATOR.  */
+pub(crate)
+const NO_CODESEPARATOR: u32 = 0xFFFFFFFF;
+
+/**
+`keypath_sighash` computes the signature hash for a
+keypath spend of the input at `input_index`.
+*/
+pub
+fn keypath_sighash( tx: &Transaction
+		  , input_index: usize
+		  , prevouts: &[TxOut]
+		  , hash_type: u8
+		  ) -> Option<[u8; 32]>
+{
+	bip341::signature_hash( tx
+			      , input_index
+			      , prevouts
+			      , hash_type
+			      , None
+			      , None
+			      )
+}
+
+fn tapleaf_sighash( tx: &Transaction
+		  , input_index: usize
+		  , prevouts: &[TxOut]
+		  , script: &[u8]
+		  , hash_type: u8
+		  ) -> Option<[u8; 32]>
+{
+	let tapleaf_hash = bip341::tapleaf_hash(
+		bip341::TAPROOT_TAPLEAF_VERSION,
+		script
+	);
+	bip341::signature_hash( tx
+			      , input_index
+			      , prevouts
+			      , hash_type
+			      , None
+			      , Some(bip341::ScriptPath{
+					tapleaf_hash: &tapleaf_hash,
+					codesep_pos: NO_CODESEPARATOR
+				})
+			      )
+}
+
+/**
+`cooperative_sighash` computes the signature hash that
+both Alice and Bob sign for a 2-of-2 tapleaf path spend
+of the input at `input_index`, which must be a
+swap-in-potentiam output of the given `alice` and `bob`
+public keys.
+*/
+pub
+fn cooperative_sighash( tx: &Transaction
+		      , input_index: usize
+		      , prevouts: &[TxOut]
+		      , alice: &PublicKey
+		      , bob: &PublicKey
+		      , hash_type: u8
+		      ) -> Option<[u8; 32]>
+{
+	tapleaf_sighash( tx
+		       , input_index
+		       , prevouts
+		       , &scripts::tapleaf_cooperative(alice, bob)
+		       , hash_type
+		       )
+}
+
+/**
+`alice_recovery_sighash` computes the signature hash
+that Alice signs for a timelock tapleaf path spend of
+the input at `input_index`, which must be a
+swap-in-potentiam output of the given `alice` public
+key.
+*/
+pub
+fn alice_recovery_sighash( tx: &Transaction
+			 , input_index: usize
+			 , prevouts: &[TxOut]
+			 , alice: &PublicKey
+			 , hash_type: u8
+			 ) -> Option<[u8; 32]>
+{
+	tapleaf_sighash( tx
+		       , input_index
+		       , prevouts
+		       , &scripts::tapleaf_alice_recovery(alice)
+		       , hash_type
+		       )
+}
+
+fn sha256d(data: &[u8]) -> [u8; 32] {
+	let h = sha256::hash(data).into_bytes();
+	sha256::hash(&h).into_bytes()
+}
+
+/* BIP-143 signature hash, for SIGHASH_ALL only.  */
+fn segwit_v0_sighash_all( tx: &Transaction
+			, input_index: usize
+			, script_code: &[u8]
+			, amount: u64
+			) -> Option<[u8; 32]>
+{
+	let txin = tx.input.get(input_index)?;
+
+	let mut prevouts = Vec::new();
+	let mut sequences = Vec::new();
+	for i in tx.input.iter() {
+		prevouts.extend_from_slice(&i.prevout.txid);
+		prevouts.extend_from_slice(&i.prevout.vout.to_le_bytes());
+		sequences.extend_from_slice(&i.sequence.to_le_bytes());
+	}
+	let mut outputs = Vec::new();
+	for o in tx.output.iter() {
+		outputs.extend_from_slice(&o.value.to_le_bytes());
+		bip341::load_compactsize(&mut outputs, o.script_pubkey.len());
+		outputs.extend_from_slice(&o.script_pubkey);
+	}
+
+	let mut buf = Vec::new();
+	buf.extend_from_slice(&tx.version.to_le_bytes());
+	buf.extend_from_slice(&sha256d(&prevouts));
+	buf.extend_from_slice(&sha256d(&sequences));
+	buf.extend_from_slice(&txin.prevout.txid);
+	buf.extend_from_slice(&txin.prevout.vout.to_le_bytes());
+	bip341::load_compactsize(&mut buf, script_code.len());
+	buf.extend_from_slice(script_code);
+	buf.extend_from_slice(&amount.to_le_bytes());
+	buf.extend_from_slice(&txin.sequence.to_le_bytes());
+	buf.extend_from_slice(&sha256d(&outputs));
+	buf.extend_from_slice(&tx.lock_time.to_le_bytes());
+	buf.extend_from_slice(&(SIGHASH_ALL as u32).to_le_bytes());
+	Some(sha256d(&buf))
+}
+
+/**
+`anchor_sighash` computes the `SIGHASH_ALL` signature
+hash that Bob signs to spend the input at
+`input_index`, which must be the anchor output of the
+given `bob` public key, of `amount` satoshis.
+
+Fails (returns None) if `input_index` is out of range.
+*/
+pub
+fn anchor_sighash( tx: &Transaction
+		 , input_index: usize
+		 , bob: &PublicKey
+		 , amount: u64
+		 ) -> Option<[u8; 32]>
+{
+	segwit_v0_sighash_all( tx
+			     , input_index
+			     , &scripts::anchor(bob)
+			     , amount
+			     )
+}
+
+#[cfg(test)]
+mod tests {
+	use hex;
+	use super::*;
+	use super::super::tx::OutPoint;
+	use super::super::tx::TxIn;
+
+	fn point_txt(pk_s: &str) -> PublicKey {
+		let buf = hex::decode(pk_s)
+		.expect("Test iput must be hex");
+		PublicKey::from_slice(&buf)
+		.expect("Test input must be valid point")
+	}
+
+	/* Spends the swap-in-potentiam output of
+	 * swap-in-potentiam.md Address Generation Test
+	 * Vector 1.
+	 */
+	fn vector_tx() -> (Transaction, Vec<TxOut>) {
+		let tx = Transaction{
+			version: 2,
+			lock_time: 800000,
+			input: vec!(TxIn{
+				prevout: OutPoint{txid: [0x11; 32], vout: 1},
+				script_sig: Vec::new(),
+				sequence: 4032,
+				witness: Vec::new()
+			}),
+			output: vec!(TxOut{
+				value: 99000,
+				script_pubkey: hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6")
+				.expect("Test input must be hex")
+			})
+		};
+		let prevouts = vec!(TxOut{
+			value: 100000,
+			script_pubkey: hex::decode("51208e65ee023bbe8850816b833690b565c5edb5c7570724d65958620ea7f6846da5")
+			.expect("Test input must be hex")
+		});
+		(tx, prevouts)
+	}
+
+	/* The expected values were computed with
+	 * `SighashCache` of rust-bitcoin 0.31, as
+	 * `test_sighash_bitcoin` does with the `bitcoin`
+	 * feature.
+	 */
+	#[test]
+	fn test_sighash() {
+		let a = point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let (tx, prevouts) = vector_tx();
+
+		assert_eq!( hex::encode(keypath_sighash(&tx, 0, &prevouts, SIGHASH_DEFAULT).unwrap())
+			  , "94b04ab4c01b68fae6a979a3c2b4baad21c0403d4a0760f5b7f0bda37bd210cd"
+			  );
+		assert_eq!( hex::encode(cooperative_sighash(&tx, 0, &prevouts, &a, &b, SIGHASH_DEFAULT).unwrap())
+			  , "c413ebe0c2e59e824d56a8e40f8a777f4746ac930a20a8298b47f7ec71fc057e"
+			  );
+		assert_eq!( hex::encode(cooperative_sighash(&tx, 0, &prevouts, &a, &b, SIGHASH_ALL | SIGHASH_ANYONECANPAY).unwrap())
+			  , "6adfdb1178f1767f76fd57bb9b4cffe6eae7b385d38a6457d30f8a610e983b05"
+			  );
+		assert_eq!( hex::encode(alice_recovery_sighash(&tx, 0, &prevouts, &a, SIGHASH_DEFAULT).unwrap())
+			  , "42563df22815d4613031eb0a479d7f5fa0c9be461dc6994b564403f804fb7620"
+			  );
+		assert_eq!( hex::encode(alice_recovery_sighash(&tx, 0, &prevouts, &a, SIGHASH_ALL | SIGHASH_ANYONECANPAY).unwrap())
+			  , "7e6c8265bb78d563d13c4f7e0fabfb7d53795a76f16d98e274e8dd738a50f035"
+			  );
+
+		assert!(keypath_sighash(&tx, 1, &prevouts, SIGHASH_DEFAULT).is_none());
+		assert!(cooperative_sighash(&tx, 0, &prevouts, &a, &b, 0x04).is_none());
+	}
+
+	/* Recomputes the expected values of `test_sighash`
+	 * with the `bitcoin` crate, from the tapleaf scripts
+	 * of swap-in-potentiam.md Address Generation Test
+	 * Vector 1.
+	 */
+	#[cfg(feature = "bitcoin")]
+	#[test]
+	fn test_sighash_bitcoin() {
+		use bitcoin::ScriptBuf;
+		use bitcoin::TapLeafHash;
+		use bitcoin::hashes::Hash;
+		use bitcoin::sighash::Prevouts;
+		use bitcoin::sighash::SighashCache;
+		use bitcoin::sighash::TapSighashType;
+		use bitcoin::taproot::LeafVersion;
+		use super::super::bitcoin_interop;
+
+		let (tx, prevouts) = vector_tx();
+		let tx = bitcoin_interop::to_transaction(&tx).unwrap();
+		let prevouts: Vec<bitcoin::TxOut> = prevouts.iter().map(bitcoin_interop::to_txout).collect();
+		let prevouts = Prevouts::All(&prevouts);
+		let leaf = |script_s: &str| TapLeafHash::from_script(
+			&ScriptBuf::from_bytes(hex::decode(script_s).unwrap()),
+			LeafVersion::TapScript
+		);
+		let cooperative = leaf("20659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3ad20c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830aeac");
+		let alice_recovery = leaf("03c00f00b27520c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830aeac");
+		let mut cache = SighashCache::new(&tx);
+		let mut sighash = |leaf: Option<TapLeafHash>, hash_type| hex::encode(
+			cache.taproot_signature_hash(
+				0,
+				&prevouts,
+				None,
+				leaf.map(|leaf| (leaf, 0xFFFFFFFF)),
+				hash_type
+			).unwrap().to_byte_array()
+		);
+
+		assert_eq!( sighash(None, TapSighashType::Default)
+			  , "94b04ab4c01b68fae6a979a3c2b4baad21c0403d4a0760f5b7f0bda37bd210cd"
+			  );
+		assert_eq!( sighash(Some(cooperative), TapSighashType::Default)
+			  , "c413ebe0c2e59e824d56a8e40f8a777f4746ac930a20a8298b47f7ec71fc057e"
+			  );
+		assert_eq!( sighash(Some(cooperative), TapSighashType::AllPlusAnyoneCanPay)
+			  , "6adfdb1178f1767f76fd57bb9b4cffe6eae7b385d38a6457d30f8a610e983b05"
+			  );
+		assert_eq!( sighash(Some(alice_recovery), TapSighashType::Default)
+			  , "42563df22815d4613031eb0a479d7f5fa0c9be461dc6994b564403f804fb7620"
+			  );
+		assert_eq!( sighash(Some(alice_recovery), TapSighashType::AllPlusAnyoneCanPay)
+			  , "7e6c8265bb78d563d13c4f7e0fabfb7d53795a76f16d98e274e8dd738a50f035"
+			  );
+	}
+
+	/* BIP-143 Native P2WPKH example, whose scriptCode
+	 * is the P2PKH script of the key.
+	 */
+	#[test]
+	fn test_segwit_v0_sighash_all() {
+		let txid = |h: &str| {
+			let mut txid = [0u8; 32];
+			hex::decode_to_slice(h, &mut txid).unwrap();
+			txid
+		};
+		let tx = Transaction{
+			version: 1,
+			lock_time: 0x11,
+			input: vec!(
+				TxIn{
+					prevout: OutPoint{txid: txid("fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f"), vout: 0},
+					script_sig: Vec::new(),
+					sequence: 0xFFFFFFEE,
+					witness: Vec::new()
+				},
+				TxIn{
+					prevout: OutPoint{txid: txid("ef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a"), vout: 1},
+					script_sig: Vec::new(),
+					sequence: 0xFFFFFFFF,
+					witness: Vec::new()
+				}
+			),
+			output: vec!(
+				TxOut{
+					value: 112340000,
+					script_pubkey: hex::decode("76a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac").unwrap()
+				},
+				TxOut{
+					value: 223450000,
+					script_pubkey: hex::decode("76a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac").unwrap()
+				}
+			)
+		};
+		let script_code = hex::decode("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+		assert_eq!( hex::encode(segwit_v0_sighash_all(&tx, 1, &script_code, 600000000).unwrap())
+			  , "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+			  );
+		assert!(segwit_v0_sighash_all(&tx, 2, &script_code, 600000000).is_none());
+	}
+}
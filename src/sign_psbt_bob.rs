@@ -0,0 +1,551 @@
+/*!
+The `sign_psbt_bob` module implements the LSP side of
+`c=.sip.sign_psbt_bob`, where the LSP, as Bob, signs
+the 2-of-2 tapleaf path of every swap-in-potentiam
+input of a PSBT that has the LSP as Bob.
+
+The LSP only acts as a [BIP-174][] Signer: it adds a
+[BIP-371][] `PSBT_IN_TAP_SCRIPT_SIG` to each such input,
+and never finalizes any input.
+
+[BIP-174]: https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
+[BIP-371]: https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki
+*/
+use secp256k1::Keypair;
+use secp256k1::Message;
+use secp256k1::Secp256k1;
+use secp256k1::SecretKey;
+use secp256k1::Signing;
+use super::bip341;
+use super::psbt;
+use super::scripts;
+use super::sighash;
+use super::state::BatchError;
+use super::state::StateStore;
+use super::state::Transition;
+use super::tx::OutPoint;
+use super::tx::TxOut;
+
+/**
+`SignPsbtBobError` is the reason `sign_psbt_bob` failed.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum SignPsbtBobError {
+	/// `invalid_psbt`: the PSBT is not parseable as a
+	/// Base64 PSBT, or is otherwise invalid, including if a
+	/// swap-in-potentiam input cannot be signed (e.g. it
+	/// has an unsupported `PSBT_IN_SIGHASH_TYPE`, or the
+	/// `PSBT_IN_WITNESS_UTXO`s needed for the signature
+	/// hash are missing).
+	InvalidPsbt,
+	/// `unsupported_psbt_version`: the PSBT is of the
+	/// given version, which is not supported.
+	UnsupportedPsbtVersion(u32),
+	/// `utxo_not_valid`: one or more of the inputs to
+	/// be signed are in use for a 0-conf Lightning
+	/// operation.
+	UtxoNotValid,
+	/// The `StateStore` failed to persist the new
+	/// states.
+	/// This is not an error of the request, and is
+	/// reported to the client as a JSON-RPC internal
+	/// error.
+	StoreFailure
+}
+
+impl SignPsbtBobError {
+	/// `code` returns the JSON-RPC error `code` for the
+	/// error.
+	pub
+	fn code(&self) -> i64 {
+		match self {
+			SignPsbtBobError::InvalidPsbt => 1001,
+			SignPsbtBobError::UnsupportedPsbtVersion(_) => 1002,
+			SignPsbtBobError::UtxoNotValid => 1004,
+			SignPsbtBobError::StoreFailure => -32603
+		}
+	}
+}
+
+impl From<psbt::PsbtError> for SignPsbtBobError {
+	fn from(e: psbt::PsbtError) -> Self {
+		match e {
+			psbt::PsbtError::InvalidPsbt => SignPsbtBobError::InvalidPsbt,
+			psbt::PsbtError::UnsupportedPsbtVersion(v) => {
+				SignPsbtBobError::UnsupportedPsbtVersion(v)
+			}
+		}
+	}
+}
+
+/* Returns the 2-of-2 tapleaf script of the input, if it
+is a swap-in-potentiam input with Bob as P[0] or P[1].
+*/
+fn find_cooperative_script( psbt: &psbt::Psbt
+			  , input_index: usize
+			  , bob_xonly: &[u8; 32]
+			  ) -> Option<Vec<u8>>
+{
+	psbt.tap_leaf_scripts(input_index)?
+	.into_iter()
+	.filter(|l| l.leaf_version == bip341::TAPROOT_TAPLEAF_VERSION)
+	.find(|l| {
+		match scripts::parse_tapleaf_cooperative(&l.script) {
+			Ok((p0, p1)) => p0 == *bob_xonly || p1 == *bob_xonly,
+			Err(_) => false
+		}
+	})
+	.map(|l| l.script)
+}
+
+/* The PSBT_IN_SIGHASH_TYPE of the input, or
+SIGHASH_DEFAULT if it has none, failing if it is not a
+valid Taproot sighash type.
+
+The spec says to sign with SIGHASH_ALL if there is no
+PSBT_IN_SIGHASH_TYPE, and that SIGHASH_DEFAULT is
+treated as equivalent to SIGHASH_ALL.
+BIP-341 defines SIGHASH_DEFAULT as committing to the
+same data as SIGHASH_ALL, only with a 64-byte signature
+instead of 65, so Bob signs with it.
+*/
+fn get_hash_type( psbt: &psbt::Psbt
+		, input_index: usize
+		) -> Option<u8>
+{
+	match psbt.sighash_type(input_index) {
+		None => Some(bip341::SIGHASH_DEFAULT),
+		Some(t) => {
+			let h = u8::try_from(t).ok()?;
+			if !bip341::is_valid_hash_type(h) {
+				return None;
+			}
+			Some(h)
+		}
+	}
+}
+
+/* The outputs spent by all the inputs, in input order.
+With SIGHASH_ANYONECANPAY only the spent output of the
+input being signed is committed to, so the others may be
+missing.
+*/
+fn get_prevouts( psbt: &psbt::Psbt
+	       , input_index: usize
+	       , hash_type: u8
+	       ) -> Option<Vec<TxOut>>
+{
+	let anyonecanpay = (hash_type & bip341::SIGHASH_ANYONECANPAY) != 0;
+	let mut prevouts = Vec::new();
+	for i in 0..psbt.inputs.len() {
+		match psbt.witness_utxo(i) {
+			Some(prevout) => prevouts.push(prevout),
+			None if anyonecanpay && i != input_index => {
+				prevouts.push(TxOut{value: 0, script_pubkey: Vec::new()});
+			},
+			None => { return None; }
+		}
+	}
+	Some(prevouts)
+}
+
+/**
+`sign_psbt_bob` performs `c=.sip.sign_psbt_bob` on the
+given Base64 `psbt`, with `bob_secret_key` being the
+secret key of the LSP node ID, returning the Base64
+`signed_psbt`.
+
+For each input that is not finalized, and has a
+`PSBT_IN_TAP_LEAF_SCRIPT` for the 2-of-2 tapleaf with
+the LSP node ID as `P[0]` or `P[1]`, the output it
+spends is moved to `State::AliceMoved` in `store`, and
+a `PSBT_IN_TAP_SCRIPT_SIG` is added, signing with the
+`PSBT_IN_SIGHASH_TYPE` of the input, or
+`SIGHASH_DEFAULT` if it has none, which BIP-341 defines
+as `SIGHASH_ALL` with a 64-byte signature.
+The state changes of all the inputs are done in a
+single `StateStore::transition_all`, and only if every
+one of them is a valid transition; otherwise the `store`
+is unchanged.
+
+`aux_rand` is the BIP-340 auxiliary randomness for the
+signatures, and should be fresh randomness.
+
+Inputs that do not belong to the LSP are left as-is, so
+a PSBT without any swap-in-potentiam inputs is returned
+unchanged.
+*/
+pub
+fn sign_psbt_bob<C, S>( secp256k1: &Secp256k1<C>
+		      , psbt: &str
+		      , bob_secret_key: &SecretKey
+		      , store: &mut S
+		      , aux_rand: &[u8; 32]
+		      ) -> Result<String, SignPsbtBobError>
+	where C: Signing
+	    , S: StateStore
+{
+	use SignPsbtBobError::*;
+
+	let mut psbt = psbt::Psbt::from_base64(psbt)?;
+	let keypair = Keypair::from_secret_key(secp256k1, bob_secret_key);
+	let (bob_xonly, _) = keypair.x_only_public_key();
+	let bob_xonly = bob_xonly.serialize();
+
+	/* First find and compute the signature hashes of
+	all the inputs to sign, so that nothing is changed
+	if any of them cannot be signed.
+	*/
+	let tx = psbt.unsigned_tx().ok_or(InvalidPsbt)?;
+	let mut to_sign = Vec::new();
+	for i in 0..psbt.inputs.len() {
+		if psbt.is_finalized(i) {
+			continue;
+		}
+		let Some(script) = find_cooperative_script(&psbt, i, &bob_xonly) else {
+			continue;
+		};
+		let hash_type = get_hash_type(&psbt, i).ok_or(InvalidPsbt)?;
+		let prevouts = get_prevouts(&psbt, i, hash_type).ok_or(InvalidPsbt)?;
+		let leaf_hash = bip341::tapleaf_hash(
+			bip341::TAPROOT_TAPLEAF_VERSION,
+			&script
+		);
+		let sighash = bip341::signature_hash( &tx
+						    , i
+						    , &prevouts
+						    , hash_type
+						    , None
+						    , Some(bip341::ScriptPath{
+							tapleaf_hash: &leaf_hash,
+							codesep_pos: sighash::NO_CODESEPARATOR
+						      })
+						    ).ok_or(InvalidPsbt)?;
+		to_sign.push((i, leaf_hash, hash_type, sighash));
+	}
+
+	/* Then atomically move all of them to alice_moved.  */
+	let batch: Vec<(OutPoint, Transition)> = to_sign.iter()
+	.map(|(i, _, _, _)| (tx.input[*i].prevout.clone(), Transition::OnchainRequest))
+	.collect();
+	store.transition_all(&batch).map_err(|e| match e {
+		BatchError::Transition(_, _) => UtxoNotValid,
+		BatchError::Store => StoreFailure
+	})?;
+
+	/* Finally sign.  */
+	for (i, leaf_hash, hash_type, sighash) in to_sign {
+		let sig = secp256k1.sign_schnorr_with_aux_rand(
+			&Message::from_digest(sighash),
+			&keypair,
+			aux_rand
+		);
+		let mut sig = sig.as_ref().to_vec();
+		if hash_type != bip341::SIGHASH_DEFAULT {
+			sig.push(hash_type);
+		}
+		psbt.set_tap_script_sig(i, &psbt::TapScriptSig{
+			xonly_pubkey: bob_xonly,
+			leaf_hash,
+			sig
+		}).expect("valid signature for an existing input");
+	}
+
+	Ok(psbt.to_base64())
+}
+
+#[cfg(test)]
+mod tests {
+	use hex;
+	use secp256k1::PublicKey;
+	use secp256k1::XOnlyPublicKey;
+	use secp256k1::schnorr;
+	use std::collections::HashMap;
+	use super::*;
+	use super::super::address;
+	use super::super::state::State;
+
+	fn secret_txt(sk_s: &str) -> SecretKey {
+		let buf = hex::decode(sk_s)
+		.expect("Test input must be hex");
+		SecretKey::from_slice(&buf)
+		.expect("Test input must be valid secret key")
+	}
+
+	fn witness_utxo(value: u64, script_pubkey: &[u8]) -> Vec<u8> {
+		let mut buf = value.to_le_bytes().to_vec();
+		buf.push(script_pubkey.len() as u8);
+		buf.extend_from_slice(script_pubkey);
+		buf
+	}
+
+	/* A PSBT spending, in input 0, the swap-in-potentiam
+	output of `alice` and `bob`, and, in input 1, a
+	P2WPKH output.
+	*/
+	fn make_psbt( alice: &PublicKey
+		    , bob: &PublicKey
+		    ) -> psbt::Psbt
+	{
+		let s_ctx = Secp256k1::new();
+		let spk = address::derive_taproot_scriptpubkey(&s_ctx, alice, bob)
+		.expect("Should succeed");
+		let p2wpkh = hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6")
+		.expect("Test input must be hex");
+
+		let mut global = psbt::Map::new();
+		global.insert(psbt::PSBT_GLOBAL_TX_VERSION, Vec::new(), vec!(2, 0, 0, 0));
+		global.insert(psbt::PSBT_GLOBAL_INPUT_COUNT, Vec::new(), vec!(2));
+		global.insert(psbt::PSBT_GLOBAL_OUTPUT_COUNT, Vec::new(), vec!(1));
+		global.insert(psbt::PSBT_GLOBAL_VERSION, Vec::new(), vec!(2, 0, 0, 0));
+		let mut inputs = Vec::new();
+		for (i, (value, spk)) in [(100000, &spk), (50000, &p2wpkh)].into_iter().enumerate() {
+			let mut input = psbt::Map::new();
+			input.insert(psbt::PSBT_IN_PREVIOUS_TXID, Vec::new(), vec!(0x11 * (i as u8 + 1); 32));
+			input.insert(psbt::PSBT_IN_OUTPUT_INDEX, Vec::new(), vec!(1, 0, 0, 0));
+			input.insert(psbt::PSBT_IN_WITNESS_UTXO, Vec::new(), witness_utxo(value, spk));
+			inputs.push(input);
+		}
+		let mut output = psbt::Map::new();
+		output.insert(psbt::PSBT_OUT_AMOUNT, Vec::new(), 149000u64.to_le_bytes().to_vec());
+		output.insert(psbt::PSBT_OUT_SCRIPT, Vec::new(), p2wpkh);
+
+		let mut psbt = psbt::Psbt{global, inputs, outputs: vec!(output)};
+		address::fill_psbt_input(&s_ctx, &mut psbt, 0, alice, bob)
+		.expect("Should succeed");
+		psbt
+	}
+
+	fn check_signature( psbt: &psbt::Psbt
+			  , alice: &PublicKey
+			  , bob: &PublicKey
+			  , hash_type: u8
+			  )
+	{
+		let s_ctx = Secp256k1::new();
+		let sigs = psbt.tap_script_sigs(0).unwrap();
+		assert_eq!(sigs.len(), 1);
+		let (bob_xonly, _) = bob.x_only_public_key();
+		assert_eq!(sigs[0].xonly_pubkey, bob_xonly.serialize());
+		assert_eq!( sigs[0].leaf_hash
+			  , bip341::tapleaf_hash(0xC0, &scripts::tapleaf_cooperative(alice, bob))
+			  );
+		if hash_type == bip341::SIGHASH_DEFAULT {
+			assert_eq!(sigs[0].sig.len(), 64);
+		} else {
+			assert_eq!(sigs[0].sig.len(), 65);
+			assert_eq!(sigs[0].sig[64], hash_type);
+		}
+
+		let tx = psbt.unsigned_tx().unwrap();
+		let prevouts = [psbt.witness_utxo(0).unwrap(), psbt.witness_utxo(1).unwrap()];
+		let sighash = sighash::cooperative_sighash(&tx, 0, &prevouts, alice, bob, hash_type)
+		.unwrap();
+		s_ctx.verify_schnorr( &schnorr::Signature::from_slice(&sigs[0].sig[0..64]).unwrap()
+				    , &Message::from_digest(sighash)
+				    , &XOnlyPublicKey::from_slice(&bob_xonly.serialize()).unwrap()
+				    ).expect("Signature should be valid");
+
+		assert!(psbt.tap_script_sigs(1).unwrap().is_empty());
+		assert!(!psbt.is_finalized(0));
+	}
+
+	struct FailingStore;
+	impl StateStore for FailingStore {
+		fn get(&self, _: &OutPoint) -> Option<State> { None }
+		fn set_all(&mut self, _: &[(OutPoint, Option<State>)]) -> Option<()> { None }
+	}
+
+	#[test]
+	fn test_sign_psbt_bob() {
+		let s_ctx = Secp256k1::new();
+		let alice_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000003");
+		let alice = PublicKey::from_secret_key(&s_ctx, &alice_sk);
+		/* A node ID with an odd Y coordinate.  */
+		let bob_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000006");
+		let bob = PublicKey::from_secret_key(&s_ctx, &bob_sk);
+		assert_eq!(bob.serialize()[0], 0x03);
+		let rand = [0x42u8; 32];
+
+		let psbt = make_psbt(&alice, &bob);
+		let outpoint = psbt.previous_outpoint(0).unwrap();
+		let mut store = HashMap::new();
+
+		let signed = sign_psbt_bob(&s_ctx, &psbt.to_base64(), &bob_sk, &mut store, &rand)
+		.expect("Should succeed");
+		let signed = psbt::Psbt::from_base64(&signed).unwrap();
+		check_signature(&signed, &alice, &bob, bip341::SIGHASH_DEFAULT);
+		assert_eq!(store.len(), 1);
+		assert_eq!(store.get(&outpoint), Some(&State::AliceMoved));
+
+		/* alice_moved can move to alice_moved, so the
+		 * request can be repeated.
+		 */
+		for hash_type in [0x01, 0x02, 0x03, 0x81, 0x82, 0x83] {
+			let mut psbt = make_psbt(&alice, &bob);
+			psbt.inputs[0].insert(psbt::PSBT_IN_SIGHASH_TYPE, Vec::new(), vec!(hash_type, 0, 0, 0));
+			let signed = sign_psbt_bob(&s_ctx, &psbt.to_base64(), &bob_sk, &mut store, &rand)
+			.expect("Should succeed");
+			let signed = psbt::Psbt::from_base64(&signed).unwrap();
+			check_signature(&signed, &alice, &bob, hash_type);
+		}
+
+		/* The other inputs are only needed if not
+		 * SIGHASH_ANYONECANPAY.
+		 */
+		let mut psbt = make_psbt(&alice, &bob);
+		psbt.inputs[1].remove(psbt::PSBT_IN_WITNESS_UTXO, &[]);
+		psbt.inputs[0].insert(psbt::PSBT_IN_SIGHASH_TYPE, Vec::new(), vec!(0x81, 0, 0, 0));
+		assert!(sign_psbt_bob(&s_ctx, &psbt.to_base64(), &bob_sk, &mut store, &rand).is_ok());
+		psbt.inputs[0].insert(psbt::PSBT_IN_SIGHASH_TYPE, Vec::new(), vec!(0x01, 0, 0, 0));
+		assert_eq!( sign_psbt_bob(&s_ctx, &psbt.to_base64(), &bob_sk, &mut store, &rand)
+			  , Err(SignPsbtBobError::InvalidPsbt)
+			  );
+
+		/* Unsupported sighash types.  */
+		for t in [0x04u32, 0x80, 0x101] {
+			let mut psbt = make_psbt(&alice, &bob);
+			psbt.inputs[0].insert(psbt::PSBT_IN_SIGHASH_TYPE, Vec::new(), t.to_le_bytes().to_vec());
+			assert_eq!( sign_psbt_bob(&s_ctx, &psbt.to_base64(), &bob_sk, &mut store, &rand)
+				  , Err(SignPsbtBobError::InvalidPsbt)
+				  );
+		}
+	}
+
+	#[test]
+	fn test_sign_psbt_bob_default_sighash() {
+		let s_ctx = Secp256k1::new();
+		let alice_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000003");
+		let alice = PublicKey::from_secret_key(&s_ctx, &alice_sk);
+		let bob_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000006");
+		let bob = PublicKey::from_secret_key(&s_ctx, &bob_sk);
+		let rand = [0x42u8; 32];
+		let mut store = HashMap::new();
+		let sign = |hash_type: Option<u8>, store: &mut HashMap<OutPoint, State>| {
+			let mut psbt = make_psbt(&alice, &bob);
+			if let Some(h) = hash_type {
+				psbt.inputs[0].insert(psbt::PSBT_IN_SIGHASH_TYPE, Vec::new(), vec!(h, 0, 0, 0));
+			}
+			let signed = sign_psbt_bob(&s_ctx, &psbt.to_base64(), &bob_sk, store, &rand)
+			.unwrap();
+			let signed = psbt::Psbt::from_base64(&signed).unwrap();
+			signed.tap_script_sigs(0).unwrap()[0].sig.clone()
+		};
+
+		/* Without PSBT_IN_SIGHASH_TYPE, Bob signs
+		 * exactly as with an explicit SIGHASH_DEFAULT,
+		 * not with SIGHASH_ALL, whose signature has the
+		 * SIGHASH byte appended.
+		 */
+		let absent = sign(None, &mut store);
+		assert_eq!(absent.len(), 64);
+		assert_eq!(absent, sign(Some(bip341::SIGHASH_DEFAULT), &mut store));
+		let all = sign(Some(0x01), &mut store);
+		assert_eq!(all.len(), 65);
+		assert_ne!(absent[..], all[0..64]);
+	}
+
+	#[test]
+	fn test_sign_psbt_bob_skipped() {
+		let s_ctx = Secp256k1::new();
+		let alice_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000003");
+		let alice = PublicKey::from_secret_key(&s_ctx, &alice_sk);
+		let bob_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000004");
+		let bob = PublicKey::from_secret_key(&s_ctx, &bob_sk);
+		let other_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000005");
+		let rand = [0x42u8; 32];
+		let mut store = HashMap::new();
+
+		/* Not our input.  */
+		let psbt = make_psbt(&alice, &bob).to_base64();
+		assert_eq!( sign_psbt_bob(&s_ctx, &psbt, &other_sk, &mut store, &rand)
+			  , Ok(psbt)
+			  );
+		/* Only the recovery leaf is given, which Bob
+		 * cannot sign for.
+		 */
+		let mut psbt = make_psbt(&alice, &bob);
+		psbt.inputs[0].pairs.retain(|kv| {
+			kv.key_type != psbt::PSBT_IN_TAP_LEAF_SCRIPT
+			|| kv.value[0] != 0x20
+		});
+		assert_eq!(psbt.tap_leaf_scripts(0).unwrap().len(), 1);
+		let psbt = psbt.to_base64();
+		assert_eq!( sign_psbt_bob(&s_ctx, &psbt, &bob_sk, &mut store, &rand)
+			  , Ok(psbt)
+			  );
+		/* Finalized.  */
+		let mut psbt = make_psbt(&alice, &bob);
+		psbt.inputs[0].insert(psbt::PSBT_IN_FINAL_SCRIPTWITNESS, Vec::new(), vec!(0));
+		let psbt = psbt.to_base64();
+		assert_eq!( sign_psbt_bob(&s_ctx, &psbt, &bob_sk, &mut store, &rand)
+			  , Ok(psbt)
+			  );
+		assert!(store.is_empty());
+	}
+
+	#[test]
+	fn test_sign_psbt_bob_errors() {
+		let s_ctx = Secp256k1::new();
+		let alice_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000003");
+		let alice = PublicKey::from_secret_key(&s_ctx, &alice_sk);
+		let alice2_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000006");
+		let alice2 = PublicKey::from_secret_key(&s_ctx, &alice2_sk);
+		let bob_sk = secret_txt("0000000000000000000000000000000000000000000000000000000000000004");
+		let bob = PublicKey::from_secret_key(&s_ctx, &bob_sk);
+		let rand = [0x42u8; 32];
+
+		/* Both inputs are swap-in-potentiam, and the
+		 * second cannot move to alice_moved, so neither
+		 * changes state.
+		 */
+		let mut psbt = make_psbt(&alice, &bob);
+		let spk = address::derive_taproot_scriptpubkey(&s_ctx, &alice2, &bob).unwrap();
+		psbt.inputs[1].insert(psbt::PSBT_IN_WITNESS_UTXO, Vec::new(), witness_utxo(50000, &spk));
+		address::fill_psbt_input(&s_ctx, &mut psbt, 1, &alice2, &bob).unwrap();
+		let outpoint0 = psbt.previous_outpoint(0).unwrap();
+		let outpoint1 = psbt.previous_outpoint(1).unwrap();
+		let psbt = psbt.to_base64();
+
+		for (state, ok) in [ (None, true)
+				   , (Some(State::UnconfirmedAliceChange), false)
+				   , (Some(State::ConfirmedAliceChange), true)
+				   , (Some(State::AliceMoved), true)
+				   , (Some(State::BobProvisionallySecured), false)
+				   , (Some(State::BobSecured), false)
+				   , (Some(State::BobRetriable), true)
+				   ] {
+			let mut store = HashMap::new();
+			if let Some(state) = state {
+				store.insert(outpoint1.clone(), state);
+			}
+			let orig = store.clone();
+			let rv = sign_psbt_bob(&s_ctx, &psbt, &bob_sk, &mut store, &rand);
+			if ok {
+				let signed = psbt::Psbt::from_base64(&rv.unwrap()).unwrap();
+				assert_eq!(signed.tap_script_sigs(0).unwrap().len(), 1);
+				assert_eq!(signed.tap_script_sigs(1).unwrap().len(), 1);
+				assert_eq!(store.get(&outpoint0), Some(&State::AliceMoved));
+				assert_eq!(store.get(&outpoint1), Some(&State::AliceMoved));
+			} else {
+				assert_eq!(rv, Err(SignPsbtBobError::UtxoNotValid));
+				assert_eq!(store, orig);
+			}
+		}
+
+		assert_eq!( sign_psbt_bob(&s_ctx, &psbt, &bob_sk, &mut FailingStore, &rand)
+			  , Err(SignPsbtBobError::StoreFailure)
+			  );
+
+		/* BIP-174 valid test vector, which is version 0.  */
+		let psbt_v0 = "cHNidP8BAKACAAAAAqsJSaCMWvfEm4IS9Bfi8Vqz9cM9zxU4IagTn4d6W3vkAAAAAAD+////qwlJoIxa98SbghL0F+LxWrP1wz3PFTghqBOfh3pbe+QBAAAAAP7///8CYDvqCwAAAAAZdqkUdopAu9dAy+gdmI5x3ipNXHE5ax2IrI4kAAAAAAAAGXapFG9GILVT+glechue4O/p+gOcykWXiKwAAAAAAAEHakcwRAIgR1lmF5fAGwNrJZKJSGhiGDR9iYZLcZ4ff89X0eURZYcCIFMJ6r9Wqk2Ikf/REf3xM286KdqGbX+EhtdVRs7tr5MZASEDXNxh/HupccC1AaZGoqg7ECy0OIEhfKaC3Ibi1z+ogpIAAQEgAOH1BQAAAAAXqRQ1RebjO4MsRwUPJNPuuTycA5SLx4cBBBYAFIXRNTfy4mVAWjTbr6nj3aAfuCMIAAAA";
+		let rv = sign_psbt_bob(&s_ctx, psbt_v0, &bob_sk, &mut HashMap::new(), &rand);
+		assert_eq!(rv, Err(SignPsbtBobError::UnsupportedPsbtVersion(0)));
+		assert_eq!(rv.unwrap_err().code(), 1002);
+		let rv = sign_psbt_bob(&s_ctx, "bm90IGEgcHNidA==", &bob_sk, &mut HashMap::new(), &rand);
+		assert_eq!(rv, Err(SignPsbtBobError::InvalidPsbt));
+		assert_eq!(rv.unwrap_err().code(), 1001);
+		assert_eq!(SignPsbtBobError::UtxoNotValid.code(), 1004);
+	}
+}
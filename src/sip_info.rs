@@ -0,0 +1,539 @@
+/*!
+The `sip_info` module contains `SipOffchainInfo`, the
+`sip_offchain_info` object that the LSP returns from
+`c=.sip.get_sip_info`, and that the client gives back
+in `c=.sip.intend_to_fund_channel`.
+
+Deserializing a `SipOffchainInfo` validates it against
+the rules of the specification.
+The LSP may add other fields to the object; those are
+ignored when deserializing, and so are dropped when the
+client serializes the object back.
+*/
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use super::lsps0;
+use super::lsps0::Datetime;
+
+/**
+`MAX_DEADLINE` is the number of blocks after which the
+Alice recovery path of a swap-in-potentiam output is
+valid; every `max_deadline` must be lower.
+*/
+pub
+const MAX_DEADLINE: u32 = 4032;
+
+/**
+`MIN_FEERATE` is the lowest allowed `min_feerate`, in
+sat/kWU, which is the 1 sat/vbyte minimum relay fee.
+*/
+pub
+const MIN_FEERATE: u64 = 253;
+
+/**
+`MAX_PROMISE_LEN` is the maximum length of a `promise`,
+in bytes.
+*/
+pub
+const MAX_PROMISE_LEN: usize = 256;
+
+/**
+`SipInfoError` is the rule of the specification that a
+`sip_offchain_info` object breaks.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum SipInfoError {
+	/// `min_confirmations` is 0.
+	ZeroMinConfirmations,
+	/// The `onchain_fee_schedule` is not sorted on
+	/// `max_deadline` in ascending order.
+	UnsortedSchedule,
+	/// The `onchain_fee_schedule` has two entries
+	/// with the same `max_deadline`.
+	DuplicateMaxDeadline,
+	/// A `max_deadline` is 0, or not less than
+	/// `MAX_DEADLINE`.
+	InvalidMaxDeadline,
+	/// A `min_feerate` is less than `MIN_FEERATE`.
+	FeerateTooLow,
+	/// `min_offchain_size_sat` is greater than
+	/// `max_offchain_size_sat`.
+	InvalidSizeRange,
+	/// The `promise` is longer than `MAX_PROMISE_LEN`,
+	/// or has characters other than printable ASCII that
+	/// do not need a JSON escape.
+	InvalidPromise
+}
+
+impl fmt::Display for SipInfoError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			SipInfoError::ZeroMinConfirmations => "min_confirmations is zero",
+			SipInfoError::UnsortedSchedule => "onchain_fee_schedule is not sorted",
+			SipInfoError::DuplicateMaxDeadline => "duplicate max_deadline",
+			SipInfoError::InvalidMaxDeadline => "invalid max_deadline",
+			SipInfoError::FeerateTooLow => "min_feerate too low",
+			SipInfoError::InvalidSizeRange => "min_offchain_size_sat above max_offchain_size_sat",
+			SipInfoError::InvalidPromise => "invalid promise"
+		};
+		f.write_str(s)
+	}
+}
+
+/**
+`FeeScheduleEntry` is an entry of the
+`onchain_fee_schedule`: for deadlines of at least
+`max_deadline` blocks, the onchain feerate must be at
+least `min_feerate` sat/kWU.
+
+No other fields are allowed in the JSON object.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub
+struct FeeScheduleEntry {
+	pub max_deadline: u32,
+	pub min_feerate: u64
+}
+
+/**
+`SipOffchainInfo` is a `sip_offchain_info` object.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawSipOffchainInfo")]
+pub
+struct SipOffchainInfo {
+	pub min_confirmations: u32,
+	pub onchain_fee_schedule: Vec<FeeScheduleEntry>,
+	#[serde(with = "lsps0::sat")]
+	pub min_offchain_size_sat: u64,
+	#[serde(with = "lsps0::sat")]
+	pub max_offchain_size_sat: u64,
+	pub valid_until: Datetime,
+	pub promise: String
+}
+
+/* The JSON object before validation.  */
+#[derive(Deserialize)]
+struct RawSipOffchainInfo {
+	min_confirmations: u32,
+	onchain_fee_schedule: Vec<FeeScheduleEntry>,
+	#[serde(with = "lsps0::sat")]
+	min_offchain_size_sat: u64,
+	#[serde(with = "lsps0::sat")]
+	max_offchain_size_sat: u64,
+	valid_until: Datetime,
+	promise: String
+}
+
+impl TryFrom<RawSipOffchainInfo> for SipOffchainInfo {
+	type Error = SipInfoError;
+
+	fn try_from(raw: RawSipOffchainInfo) -> Result<Self, SipInfoError> {
+		let rv = SipOffchainInfo{ min_confirmations: raw.min_confirmations
+					, onchain_fee_schedule: raw.onchain_fee_schedule
+					, min_offchain_size_sat: raw.min_offchain_size_sat
+					, max_offchain_size_sat: raw.max_offchain_size_sat
+					, valid_until: raw.valid_until
+					, promise: raw.promise
+					};
+		rv.validate()?;
+		Ok(rv)
+	}
+}
+
+/**
+`is_valid_promise` returns true if `promise` follows the
+rules of the specification: at most `MAX_PROMISE_LEN`
+bytes, all in the ASCII range 32 to 126, except for `"`
+and `\`, which need a JSON escape.
+*/
+pub
+fn is_valid_promise(promise: &str) -> bool {
+	promise.len() <= MAX_PROMISE_LEN
+	&& promise.bytes().all(|c| (32..=126).contains(&c) && c != b'"' && c != b'\\')
+}
+
+/**
+`deadline` returns the number of blocks remaining, at
+`current_height`, before the recovery path of a
+swap-in-potentiam output confirmed at `conf_height`
+becomes valid: `conf_height + 4032 - current_height`.
+This is negative if it is already valid.
+*/
+pub
+fn deadline(conf_height: u32, current_height: u32) -> i64 {
+	conf_height as i64 + MAX_DEADLINE as i64 - current_height as i64
+}
+
+/**
+`FeerateError` is the reason the outputs cannot be
+used in a 0-conf Lightning operation.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum FeerateError {
+	/// `invalid_inputs_length`: there are no outputs,
+	/// while `inputs` MUST have at least one entry.
+	NoInputs,
+	/// `insufficient_confirms`: one or more of the
+	/// outputs has fewer than `min_confirmations`
+	/// confirmations.
+	InsufficientConfirms,
+	/// `deadline_too_near`: the deadline is lower than
+	/// the smallest `max_deadline`, or the
+	/// `onchain_fee_schedule` is empty.
+	DeadlineTooNear
+}
+
+impl FeerateError {
+	/// `code` returns the JSON-RPC error `code` of the
+	/// error in `c=.sip.sign_funding_bob`.
+	pub
+	fn code(&self) -> i64 {
+		match self {
+			FeerateError::NoInputs => 1202,
+			FeerateError::InsufficientConfirms => 1204,
+			FeerateError::DeadlineTooNear => 1205
+		}
+	}
+}
+
+impl SipOffchainInfo {
+	/// `validate` checks the rules of the specification.
+	pub
+	fn validate(&self) -> Result<(), SipInfoError> {
+		if self.min_confirmations == 0 {
+			return Err(SipInfoError::ZeroMinConfirmations);
+		}
+		for entry in self.onchain_fee_schedule.iter() {
+			if entry.max_deadline == 0 || entry.max_deadline >= MAX_DEADLINE {
+				return Err(SipInfoError::InvalidMaxDeadline);
+			}
+			if entry.min_feerate < MIN_FEERATE {
+				return Err(SipInfoError::FeerateTooLow);
+			}
+		}
+		for pair in self.onchain_fee_schedule.windows(2) {
+			if pair[0].max_deadline == pair[1].max_deadline {
+				return Err(SipInfoError::DuplicateMaxDeadline);
+			}
+			if pair[0].max_deadline > pair[1].max_deadline {
+				return Err(SipInfoError::UnsortedSchedule);
+			}
+		}
+		if self.min_offchain_size_sat > self.max_offchain_size_sat {
+			return Err(SipInfoError::InvalidSizeRange);
+		}
+		if !is_valid_promise(&self.promise) {
+			return Err(SipInfoError::InvalidPromise);
+		}
+		Ok(())
+	}
+
+	/// `required_feerate` returns the minimum onchain
+	/// feerate, in sat/kWU, of a 0-conf Lightning operation
+	/// spending swap-in-potentiam outputs confirmed at
+	/// `conf_heights`, at `current_height`.
+	///
+	/// The deadline of the operation is the lowest deadline
+	/// of its outputs, and the feerate is the `min_feerate`
+	/// of the entry with the highest `max_deadline` that is
+	/// not above the deadline.
+	/// There must be at least one output.
+	///
+	/// The specification conflicts on a deadline equal to a
+	/// `max_deadline`.
+	/// The example in "Swap-in-potentiam Transaction Output
+	/// Deadline" uses that entry ("288 to 575 blocks
+	/// (inclusive)" for a `max_deadline` of 288), and so
+	/// does `deadline_too_near`, which is for a deadline
+	/// "lower than the smallest `max_deadline`".
+	/// But the "expected minimum fee rate" in "Requesting
+	/// Bob-side Signatures To Fund 0-conf Channel" takes
+	/// "the highest `max_deadline` that is still lower than"
+	/// the deadline, which would use the previous entry.
+	/// This follows the example, until the specification
+	/// is fixed.
+	pub
+	fn required_feerate( &self
+			   , conf_heights: &[u32]
+			   , current_height: u32
+			   ) -> Result<u64, FeerateError>
+	{
+		if conf_heights.is_empty() {
+			return Err(FeerateError::NoInputs);
+		}
+		let mut min_deadline = MAX_DEADLINE as i64;
+		for conf_height in conf_heights.iter() {
+			if *conf_height > current_height
+			|| current_height - conf_height + 1 < self.min_confirmations {
+				return Err(FeerateError::InsufficientConfirms);
+			}
+			min_deadline = min_deadline.min(deadline(*conf_height, current_height));
+		}
+		self.onchain_fee_schedule.iter()
+		.rev()
+		.find(|e| e.max_deadline as i64 <= min_deadline)
+		.map(|e| e.min_feerate)
+		.ok_or(FeerateError::DeadlineTooNear)
+	}
+
+	/// `canonical_serialization` returns the fields other
+	/// than `promise`, serialized in a fixed order and with
+	/// fixed-width big-endian integers, so that two objects
+	/// have the same serialization only if they have the
+	/// same fields:
+	///
+	/// * `min_confirmations`, 4 bytes.
+	/// * The number of `onchain_fee_schedule` entries, 4
+	///   bytes, then for each entry `max_deadline`, 4 bytes,
+	///   and `min_feerate`, 8 bytes.
+	/// * `min_offchain_size_sat`, 8 bytes.
+	/// * `max_offchain_size_sat`, 8 bytes.
+	/// * `valid_until`, in milliseconds since the Unix
+	///   epoch, 8 bytes.
+	pub
+	fn canonical_serialization(&self) -> Vec<u8> {
+		let mut rv = Vec::new();
+		rv.extend_from_slice(&self.min_confirmations.to_be_bytes());
+		rv.extend_from_slice(&(self.onchain_fee_schedule.len() as u32).to_be_bytes());
+		for entry in self.onchain_fee_schedule.iter() {
+			rv.extend_from_slice(&entry.max_deadline.to_be_bytes());
+			rv.extend_from_slice(&entry.min_feerate.to_be_bytes());
+		}
+		rv.extend_from_slice(&self.min_offchain_size_sat.to_be_bytes());
+		rv.extend_from_slice(&self.max_offchain_size_sat.to_be_bytes());
+		rv.extend_from_slice(&self.valid_until.unix_millis.to_be_bytes());
+		rv
+	}
+
+	/// `from_json` parses and validates a JSON
+	/// `sip_offchain_info` object, returning None if it is
+	/// not valid.
+	pub
+	fn from_json(s: &str) -> Option<SipOffchainInfo> {
+		serde_json::from_str(s).ok()
+	}
+
+	/// `to_json` serializes the object, with only the
+	/// fields it recognizes.
+	pub
+	fn to_json(&self) -> String {
+		serde_json::to_string(self).expect("serializable")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/* From "Determining LSP Parameters For 0-Conf
+	 * Lightning" of the specification.
+	 */
+	const EXAMPLE: &str = r#"{
+		"min_confirmations": 3,
+		"onchain_fee_schedule": [
+			{
+				"max_deadline": 288,
+				"min_feerate": 50000
+			},
+			{
+				"max_deadline": 576,
+				"min_feerate": 25000
+			},
+			{
+				"max_deadline": 1008,
+				"min_feerate": 10000
+			}
+		],
+		"min_offchain_size_sat": "100000000",
+		"max_offchain_size_sat": "10000000000",
+		"valid_until": "2024-01-18T14:42:24.000Z",
+		"promise": "arbitrary-string-9999"
+	}"#;
+
+	fn example() -> SipOffchainInfo {
+		SipOffchainInfo::from_json(EXAMPLE).unwrap()
+	}
+
+	fn json_with(field: &str, value: serde_json::Value) -> String {
+		let mut v: serde_json::Value = serde_json::from_str(EXAMPLE).unwrap();
+		v[field] = value;
+		v.to_string()
+	}
+
+	fn error_of(info: &SipOffchainInfo) -> Option<SipInfoError> {
+		info.validate().err()
+	}
+
+	#[test]
+	fn test_example() {
+		let info = example();
+		assert_eq!(info.min_confirmations, 3);
+		assert_eq!( info.onchain_fee_schedule
+			  , vec!( FeeScheduleEntry{max_deadline: 288, min_feerate: 50000}
+				, FeeScheduleEntry{max_deadline: 576, min_feerate: 25000}
+				, FeeScheduleEntry{max_deadline: 1008, min_feerate: 10000}
+				)
+			  );
+		assert_eq!(info.min_offchain_size_sat, 100000000);
+		assert_eq!(info.max_offchain_size_sat, 10000000000);
+		assert_eq!(info.valid_until, Datetime::parse("2024-01-18T14:42:24.000Z").unwrap());
+		assert_eq!(info.promise, "arbitrary-string-9999");
+
+		let v: serde_json::Value = serde_json::from_str(&info.to_json()).unwrap();
+		let expected: serde_json::Value = serde_json::from_str(EXAMPLE).unwrap();
+		assert_eq!(v, expected);
+
+		/* An empty schedule disables 0-conf operations,
+		 * but is valid.
+		 */
+		let info = SipOffchainInfo::from_json(&json_with("onchain_fee_schedule", serde_json::json!([])));
+		assert!(info.unwrap().onchain_fee_schedule.is_empty());
+	}
+
+	#[test]
+	fn test_unknown_fields() {
+		let json = json_with("lsp_extension", serde_json::json!({"x": 1}));
+		let info = SipOffchainInfo::from_json(&json).unwrap();
+		assert_eq!(info, example());
+		let v: serde_json::Value = serde_json::from_str(&info.to_json()).unwrap();
+		assert!(v.get("lsp_extension").is_none());
+
+		/* But not in the schedule entries.  */
+		let json = json_with( "onchain_fee_schedule"
+				    , serde_json::json!([{"max_deadline": 288, "min_feerate": 50000, "x": 1}])
+				    );
+		assert_eq!(SipOffchainInfo::from_json(&json), None);
+	}
+
+	#[test]
+	fn test_validation() {
+		let mut info = example();
+		info.min_confirmations = 0;
+		assert_eq!(error_of(&info), Some(SipInfoError::ZeroMinConfirmations));
+
+		let mut info = example();
+		info.onchain_fee_schedule.swap(1, 2);
+		assert_eq!(error_of(&info), Some(SipInfoError::UnsortedSchedule));
+		info.onchain_fee_schedule[1].max_deadline = 576;
+		assert_eq!(error_of(&info), Some(SipInfoError::DuplicateMaxDeadline));
+
+		for (max_deadline, ok) in [(0, false), (1, true), (4031, true), (4032, false)] {
+			let mut info = example();
+			info.onchain_fee_schedule[2].max_deadline = max_deadline;
+			info.onchain_fee_schedule.drain(0..2);
+			assert_eq!(info.validate().is_ok(), ok, "{}", max_deadline);
+			if !ok {
+				assert_eq!(error_of(&info), Some(SipInfoError::InvalidMaxDeadline));
+			}
+		}
+
+		let mut info = example();
+		info.onchain_fee_schedule[2].min_feerate = 253;
+		assert_eq!(info.validate(), Ok(()));
+		info.onchain_fee_schedule[2].min_feerate = 252;
+		assert_eq!(error_of(&info), Some(SipInfoError::FeerateTooLow));
+
+		let mut info = example();
+		info.max_offchain_size_sat = info.min_offchain_size_sat;
+		assert_eq!(info.validate(), Ok(()));
+		info.max_offchain_size_sat -= 1;
+		assert_eq!(error_of(&info), Some(SipInfoError::InvalidSizeRange));
+
+		let mut info = example();
+		info.promise = "~".repeat(256);
+		assert_eq!(info.validate(), Ok(()));
+		info.promise = " !#[]".to_string();
+		assert_eq!(info.validate(), Ok(()));
+		for promise in ["~".repeat(257), "a\"b".to_string(), "a\\b".to_string()
+			       , "a\nb".to_string(), "\u{7f}".to_string(), "\u{e9}".to_string()
+			       ] {
+			info.promise = promise;
+			assert_eq!(error_of(&info), Some(SipInfoError::InvalidPromise));
+		}
+
+		/* Validated when deserializing.  */
+		let json = json_with("min_confirmations", serde_json::json!(0));
+		assert_eq!(SipOffchainInfo::from_json(&json), None);
+		let json = json_with("promise", serde_json::json!("a\"b"));
+		assert_eq!(SipOffchainInfo::from_json(&json), None);
+	}
+
+	#[test]
+	fn test_invalid_json() {
+		for (field, value) in [ ("min_confirmations", serde_json::json!(-1))
+				      , ("min_confirmations", serde_json::json!(3.5))
+				      , ("min_confirmations", serde_json::json!("3"))
+				      , ("min_offchain_size_sat", serde_json::json!(100000000))
+				      , ("min_offchain_size_sat", serde_json::json!("-1"))
+				      , ("valid_until", serde_json::json!("2024-01-18"))
+				      , ("promise", serde_json::json!(1))
+				      , ("onchain_fee_schedule", serde_json::json!({}))
+				      ] {
+			let json = json_with(field, value);
+			assert_eq!(SipOffchainInfo::from_json(&json), None, "{}", json);
+		}
+		let mut v: serde_json::Value = serde_json::from_str(EXAMPLE).unwrap();
+		v.as_object_mut().unwrap().remove("promise");
+		assert_eq!(SipOffchainInfo::from_json(&v.to_string()), None);
+	}
+
+	/* From "Swap-in-potentiam Transaction Output
+	 * Deadline" of the specification.
+	 */
+	#[test]
+	fn test_deadline() {
+		assert_eq!(deadline(100000, 100200), 3832);
+		assert_eq!(deadline(100000, 100000), 4032);
+		assert_eq!(deadline(100000, 104032), 0);
+		assert_eq!(deadline(100000, 104033), -1);
+	}
+
+	#[test]
+	fn test_required_feerate() {
+		let info = example();
+		let current = 200000;
+		let conf_for = |deadline: u32| current + deadline - MAX_DEADLINE;
+
+		for (d, rv) in [ (4030, Ok(10000)), (1008, Ok(10000))
+			       , (1007, Ok(25000)), (576, Ok(25000))
+			       , (575, Ok(50000)), (288, Ok(50000))
+			       , (287, Err(FeerateError::DeadlineTooNear))
+			       ] {
+			assert_eq!(info.required_feerate(&[conf_for(d)], current), rv, "{}", d);
+		}
+		/* The lowest deadline applies.  */
+		assert_eq!( info.required_feerate(&[conf_for(3000), conf_for(600), conf_for(2000)], current)
+			  , Ok(25000)
+			  );
+		assert_eq!( info.required_feerate(&[conf_for(3000), conf_for(100)], current)
+			  , Err(FeerateError::DeadlineTooNear)
+			  );
+		/* Confirmed more than 4032 blocks ago.  */
+		assert_eq!( info.required_feerate(&[current - 5000], current)
+			  , Err(FeerateError::DeadlineTooNear)
+			  );
+
+		/* min_confirmations is 3.  */
+		assert_eq!(info.required_feerate(&[current - 2], current), Ok(10000));
+		for conf_height in [current - 1, current, current + 1] {
+			assert_eq!( info.required_feerate(&[conf_for(1000), conf_height], current)
+				  , Err(FeerateError::InsufficientConfirms)
+				  );
+		}
+		assert_eq!(FeerateError::NoInputs.code(), 1202);
+		assert_eq!(FeerateError::InsufficientConfirms.code(), 1204);
+		assert_eq!(FeerateError::DeadlineTooNear.code(), 1205);
+
+		assert_eq!(info.required_feerate(&[], current), Err(FeerateError::NoInputs));
+		let mut info = info;
+		info.onchain_fee_schedule.clear();
+		assert_eq!( info.required_feerate(&[current - 10], current)
+			  , Err(FeerateError::DeadlineTooNear)
+			  );
+	}
+}
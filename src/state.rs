@@ -0,0 +1,515 @@
+/*!
+The `state` module contains the `state` that Bob keeps
+for each swap-in-potentiam transaction output it has
+been asked about, as described in "Bob Storage
+Requirements" of the specification, the transitions
+between them, and the `StateStore` trait that Bob
+persists them with.
+
+Each transition is a function from the current state
+to either the new state or the `TransitionError` the
+specification requires, and does not change anything
+itself: the caller applies the new states of all the
+outputs of a request with a single
+`StateStore::transition_all`.
+
+An output which is not in the mapping (the "Unknown"
+state) is represented as `None`.
+*/
+use std::collections::HashMap;
+use super::tx::OutPoint;
+
+/**
+`State` is the `state` of a transaction output in Bob's
+mapping.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub
+enum State {
+	/// A change output for Alice of a 0-conf Lightning
+	/// operation that is not yet deeply confirmed.
+	UnconfirmedAliceChange,
+	/// A change output for Alice of a 0-conf Lightning
+	/// operation that is deeply confirmed.
+	ConfirmedAliceChange,
+	/// Used by Alice in an onchain operation.
+	AliceMoved,
+	/// Used by Alice in a 0-conf Lightning operation
+	/// that has not yet completed.
+	BobProvisionallySecured,
+	/// Used by Alice in a 0-conf Lightning operation
+	/// that has completed.
+	BobSecured,
+	/// Used by Alice in a 0-conf Lightning operation
+	/// that was aborted.
+	BobRetriable
+}
+
+/**
+`TransitionError` is the reason a state transition of a
+transaction output is not done.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum TransitionError {
+	/// The output is the change of a 0-conf Lightning
+	/// operation that is not yet deeply confirmed, and the
+	/// request is rejected, since Alice could use it to pin
+	/// that operation.
+	Rejected,
+	/// The output has already been used by Alice in a
+	/// way that the request would conflict with, and the
+	/// request is disallowed.
+	Disallowed,
+	/// The event does not apply to an output in the
+	/// given state.
+	NotApplicable
+}
+
+/**
+`onchain_request` returns the new state of an output in
+the given `state` when Alice requests an onchain
+operation on it, or the reason the request must not be
+allowed.
+*/
+pub
+fn onchain_request(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		None => Ok(State::AliceMoved),
+		Some(State::UnconfirmedAliceChange) => Err(TransitionError::Rejected),
+		Some(State::ConfirmedAliceChange) => Ok(State::AliceMoved),
+		Some(State::AliceMoved) => Ok(State::AliceMoved),
+		Some(State::BobProvisionallySecured) => Err(TransitionError::Disallowed),
+		Some(State::BobSecured) => Err(TransitionError::Disallowed),
+		Some(State::BobRetriable) => Ok(State::AliceMoved)
+	}
+}
+
+/**
+`zeroconf_request` returns the new state of an output in
+the given `state` when Alice requests a 0-conf Lightning
+operation on it, or the reason the request must not be
+allowed.
+*/
+pub
+fn zeroconf_request(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		None => Ok(State::BobProvisionallySecured),
+		Some(State::UnconfirmedAliceChange) => Err(TransitionError::Rejected),
+		Some(State::ConfirmedAliceChange) => Ok(State::BobProvisionallySecured),
+		Some(State::AliceMoved) => Err(TransitionError::Disallowed),
+		Some(State::BobProvisionallySecured) => Err(TransitionError::Disallowed),
+		Some(State::BobSecured) => Err(TransitionError::Disallowed),
+		Some(State::BobRetriable) => Ok(State::BobProvisionallySecured)
+	}
+}
+
+/**
+`alice_change_created` returns the new state of the
+Alice-side change output of a 0-conf Lightning operation
+when Bob signs that operation.
+
+A new output cannot already be in the mapping, so this
+is only applicable to the Unknown state.
+*/
+pub
+fn alice_change_created(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		None => Ok(State::UnconfirmedAliceChange),
+		Some(_) => Err(TransitionError::NotApplicable)
+	}
+}
+
+/**
+`alice_change_confirmed` returns the new state of an
+output in the given `state` when the transaction it is
+an output of has confirmed deeply enough, i.e. to the
+`minimum_depth` Bob would use in `accept_channel`.
+*/
+pub
+fn alice_change_confirmed(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		Some(State::UnconfirmedAliceChange) => Ok(State::ConfirmedAliceChange),
+		_ => Err(TransitionError::NotApplicable)
+	}
+}
+
+/**
+`alice_change_unconfirmed` returns the new state of an
+output in the given `state` when a chain reorganization
+leaves the transaction it is an output of confirmed
+less deeply than the `minimum_depth`, or unconfirmed.
+
+The specification does not list this transition, but
+it is needed so that `alice_change_confirmed` holds
+only while the transaction is deeply confirmed.
+*/
+pub
+fn alice_change_unconfirmed(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		Some(State::ConfirmedAliceChange) => Ok(State::UnconfirmedAliceChange),
+		_ => Err(TransitionError::NotApplicable)
+	}
+}
+
+/**
+`zeroconf_aborted_input` returns the new state of an
+output in the given `state` when the 0-conf Lightning
+operation that spends it aborts.
+*/
+pub
+fn zeroconf_aborted_input(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		Some(State::BobProvisionallySecured) => Ok(State::BobRetriable),
+		_ => Err(TransitionError::NotApplicable)
+	}
+}
+
+/**
+`zeroconf_aborted_change` returns the new state of an
+output in the given `state` when the 0-conf Lightning
+operation that creates it aborts.
+The new state is always Unknown (`None`), i.e. the
+output is deleted from the mapping.
+*/
+pub
+fn zeroconf_aborted_change(state: Option<State>) -> Result<Option<State>, TransitionError> {
+	match state {
+		Some(State::UnconfirmedAliceChange) => Ok(None),
+		_ => Err(TransitionError::NotApplicable)
+	}
+}
+
+/**
+`zeroconf_completed` returns the new state of an output
+in the given `state` when the 0-conf Lightning operation
+that spends it completes, i.e. Bob receives a valid
+`c=.sip.sign_funding_alice` for it.
+*/
+pub
+fn zeroconf_completed(state: Option<State>) -> Result<State, TransitionError> {
+	match state {
+		Some(State::BobProvisionallySecured) => Ok(State::BobSecured),
+		_ => Err(TransitionError::NotApplicable)
+	}
+}
+
+/**
+`Transition` is one of the events of "Bob Storage
+Requirements" that change the state of a transaction
+output, each of which is done by one of the transition
+functions above.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub
+enum Transition {
+	/// `onchain_request`.
+	OnchainRequest,
+	/// `zeroconf_request`.
+	ZeroconfRequest,
+	/// `alice_change_created`.
+	AliceChangeCreated,
+	/// `alice_change_confirmed`.
+	AliceChangeConfirmed,
+	/// `alice_change_unconfirmed`.
+	AliceChangeUnconfirmed,
+	/// `zeroconf_aborted_input`.
+	ZeroconfAbortedInput,
+	/// `zeroconf_aborted_change`.
+	ZeroconfAbortedChange,
+	/// `zeroconf_completed`.
+	ZeroconfCompleted
+}
+
+impl Transition {
+	/// `apply` returns the new state of an output in the
+	/// given `state` after the transition, with `None`
+	/// being Unknown (i.e. the output is deleted from the
+	/// mapping).
+	pub
+	fn apply(&self, state: Option<State>) -> Result<Option<State>, TransitionError> {
+		match self {
+			Transition::OnchainRequest => onchain_request(state).map(Some),
+			Transition::ZeroconfRequest => zeroconf_request(state).map(Some),
+			Transition::AliceChangeCreated => alice_change_created(state).map(Some),
+			Transition::AliceChangeConfirmed => alice_change_confirmed(state).map(Some),
+			Transition::AliceChangeUnconfirmed => alice_change_unconfirmed(state).map(Some),
+			Transition::ZeroconfAbortedInput => zeroconf_aborted_input(state).map(Some),
+			Transition::ZeroconfAbortedChange => zeroconf_aborted_change(state),
+			Transition::ZeroconfCompleted => zeroconf_completed(state).map(Some)
+		}
+	}
+}
+
+/**
+`BatchError` is the reason `StateStore::transition_all`
+did not change any state.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub
+enum BatchError {
+	/// The transition at the given index of the batch
+	/// is not done, for the given reason.
+	Transition(usize, TransitionError),
+	/// The store failed to persist the new states.
+	Store
+}
+
+/**
+`StateStore` is Bob's persistent mapping from
+transaction outputs to their `State`.
+
+Implementations only need to provide `get` and
+`set_all`; the transitions of a request are validated
+and applied with `transition_all`.
+*/
+pub
+trait StateStore {
+	/// `get` returns the state of `outpoint`, or None if
+	/// it is not in the mapping.
+	fn get(&self, outpoint: &OutPoint) -> Option<State>;
+
+	/// `set_all` sets the states of all the given outputs,
+	/// in order, with `None` deleting the output from the
+	/// mapping.
+	/// It is atomic: either all of them are set and
+	/// persisted, or (on failure, returning None) none of
+	/// them are.
+	fn set_all(&mut self, changes: &[(OutPoint, Option<State>)]) -> Option<()>;
+
+	/// `transition_all` does all the transitions of the
+	/// `batch`, in order, atomically: if any of them is
+	/// not valid, or the new states cannot be persisted,
+	/// then none of them are done.
+	///
+	/// An output may appear more than once in the `batch`,
+	/// in which case each transition applies to the state
+	/// left by the previous one.
+	fn transition_all( &mut self
+			 , batch: &[(OutPoint, Transition)]
+			 ) -> Result<(), BatchError>
+	{
+		let mut changes: Vec<(OutPoint, Option<State>)> = Vec::new();
+		for (i, (outpoint, transition)) in batch.iter().enumerate() {
+			let state = match changes.iter().rev().find(|(o, _)| o == outpoint) {
+				Some((_, state)) => *state,
+				None => self.get(outpoint)
+			};
+			let new_state = transition.apply(state)
+			.map_err(|e| BatchError::Transition(i, e))?;
+			changes.push((outpoint.clone(), new_state));
+		}
+		if changes.is_empty() {
+			return Ok(());
+		}
+		self.set_all(&changes).ok_or(BatchError::Store)
+	}
+}
+
+/* A non-persistent store, for tests and for Bobs that
+persist the mapping by other means.
+*/
+impl StateStore for HashMap<OutPoint, State> {
+	fn get(&self, outpoint: &OutPoint) -> Option<State> {
+		HashMap::get(self, outpoint).copied()
+	}
+
+	fn set_all(&mut self, changes: &[(OutPoint, Option<State>)]) -> Option<()> {
+		for (outpoint, state) in changes.iter() {
+			match state {
+				Some(state) => { self.insert(outpoint.clone(), *state); },
+				None => { self.remove(outpoint); }
+			}
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const UNKNOWN: Option<State> = None;
+	const UAC: Option<State> = Some(State::UnconfirmedAliceChange);
+	const CAC: Option<State> = Some(State::ConfirmedAliceChange);
+	const AM: Option<State> = Some(State::AliceMoved);
+	const BPS: Option<State> = Some(State::BobProvisionallySecured);
+	const BS: Option<State> = Some(State::BobSecured);
+	const BR: Option<State> = Some(State::BobRetriable);
+
+	/* Applies `f` to every state, checking the result
+	against `table`, which must list every state.
+	*/
+	fn check_table<T>( f: fn(Option<State>) -> Result<T, TransitionError>
+			 , table: &[(Option<State>, Result<T, TransitionError>)]
+			 )
+		where T: std::fmt::Debug + PartialEq
+	{
+		let all = [UNKNOWN, UAC, CAC, AM, BPS, BS, BR];
+		assert_eq!(table.len(), all.len());
+		for state in all {
+			let (_, expected) = table.iter()
+			.find(|(s, _)| *s == state)
+			.expect("Table must list every state");
+			assert_eq!(f(state), *expected, "{:?}", state);
+		}
+	}
+
+	/* "Bob Storage Requirements": "When Alice requests
+	 * an onchain operation".
+	 */
+	#[test]
+	fn test_onchain_request() {
+		check_table(onchain_request, &[
+			(UNKNOWN, Ok(State::AliceMoved)),
+			(UAC, Err(TransitionError::Rejected)),
+			(CAC, Ok(State::AliceMoved)),
+			(AM, Ok(State::AliceMoved)),
+			(BPS, Err(TransitionError::Disallowed)),
+			(BS, Err(TransitionError::Disallowed)),
+			(BR, Ok(State::AliceMoved))
+		]);
+	}
+
+	/* "Bob Storage Requirements": "When Alice requests
+	 * a 0-conf Lightning operation".
+	 */
+	#[test]
+	fn test_zeroconf_request() {
+		check_table(zeroconf_request, &[
+			(UNKNOWN, Ok(State::BobProvisionallySecured)),
+			(UAC, Err(TransitionError::Rejected)),
+			(CAC, Ok(State::BobProvisionallySecured)),
+			(AM, Err(TransitionError::Disallowed)),
+			(BPS, Err(TransitionError::Disallowed)),
+			(BS, Err(TransitionError::Disallowed)),
+			(BR, Ok(State::BobProvisionallySecured))
+		]);
+	}
+
+	#[test]
+	fn test_chain_and_operation_events() {
+		let na = Err(TransitionError::NotApplicable);
+		check_table(alice_change_created, &[
+			(UNKNOWN, Ok(State::UnconfirmedAliceChange)),
+			(UAC, na), (CAC, na), (AM, na), (BPS, na), (BS, na), (BR, na)
+		]);
+		check_table(alice_change_confirmed, &[
+			(UAC, Ok(State::ConfirmedAliceChange)),
+			(UNKNOWN, na), (CAC, na), (AM, na), (BPS, na), (BS, na), (BR, na)
+		]);
+		check_table(alice_change_unconfirmed, &[
+			(CAC, Ok(State::UnconfirmedAliceChange)),
+			(UNKNOWN, na), (UAC, na), (AM, na), (BPS, na), (BS, na), (BR, na)
+		]);
+		check_table(zeroconf_aborted_input, &[
+			(BPS, Ok(State::BobRetriable)),
+			(UNKNOWN, na), (UAC, na), (CAC, na), (AM, na), (BS, na), (BR, na)
+		]);
+		check_table(zeroconf_completed, &[
+			(BPS, Ok(State::BobSecured)),
+			(UNKNOWN, na), (UAC, na), (CAC, na), (AM, na), (BS, na), (BR, na)
+		]);
+		let na = Err(TransitionError::NotApplicable);
+		check_table(zeroconf_aborted_change, &[
+			(UAC, Ok(None)),
+			(UNKNOWN, na), (CAC, na), (AM, na), (BPS, na), (BS, na), (BR, na)
+		]);
+	}
+
+	/* A 0-conf Lightning operation that aborts, is
+	 * retried, and completes, after which its change can
+	 * be spent onchain once deeply confirmed.
+	 */
+	#[test]
+	fn test_lifecycle() {
+		let input = zeroconf_request(None).unwrap();
+		let change = alice_change_created(None).unwrap();
+		let input = zeroconf_aborted_input(Some(input)).unwrap();
+		assert_eq!(zeroconf_aborted_change(Some(change)), Ok(None));
+
+		let input = zeroconf_request(Some(input)).unwrap();
+		let change = alice_change_created(None).unwrap();
+		assert_eq!(onchain_request(Some(change)), Err(TransitionError::Rejected));
+		let input = zeroconf_completed(Some(input)).unwrap();
+		assert_eq!(input, State::BobSecured);
+		assert_eq!(onchain_request(Some(input)), Err(TransitionError::Disallowed));
+
+		let change = alice_change_confirmed(Some(change)).unwrap();
+		let change = onchain_request(Some(change)).unwrap();
+		assert_eq!(change, State::AliceMoved);
+		assert_eq!(zeroconf_request(Some(change)), Err(TransitionError::Disallowed));
+	}
+
+	fn outpoint(n: u8) -> OutPoint {
+		OutPoint{txid: [n; 32], vout: n as u32}
+	}
+
+	/* A store that fails to persist.  */
+	struct FailingStore(HashMap<OutPoint, State>);
+	impl StateStore for FailingStore {
+		fn get(&self, outpoint: &OutPoint) -> Option<State> {
+			self.0.get(outpoint).copied()
+		}
+		fn set_all(&mut self, _: &[(OutPoint, Option<State>)]) -> Option<()> {
+			None
+		}
+	}
+
+	#[test]
+	fn test_transition_all() {
+		let mut store = HashMap::new();
+		store.insert(outpoint(1), State::BobRetriable);
+		store.insert(outpoint(2), State::UnconfirmedAliceChange);
+		store.insert(outpoint(3), State::BobProvisionallySecured);
+
+		/* The last transition fails, so none are done.  */
+		let orig = store.clone();
+		assert_eq!( store.transition_all(&[ (outpoint(1), Transition::ZeroconfRequest)
+						  , (outpoint(4), Transition::ZeroconfRequest)
+						  , (outpoint(2), Transition::ZeroconfRequest)
+						  ])
+			  , Err(BatchError::Transition(2, TransitionError::Rejected))
+			  );
+		assert_eq!(store, orig);
+
+		/* Abort of the operation of outpoint(3), which
+		 * created outpoint(2).
+		 */
+		assert_eq!( store.transition_all(&[ (outpoint(3), Transition::ZeroconfAbortedInput)
+						  , (outpoint(2), Transition::ZeroconfAbortedChange)
+						  ])
+			  , Ok(())
+			  );
+		assert_eq!(store.len(), 2);
+		assert_eq!(StateStore::get(&store, &outpoint(2)), None);
+		assert_eq!(StateStore::get(&store, &outpoint(3)), Some(State::BobRetriable));
+
+		/* Later transitions of the same output see the
+		 * earlier ones.
+		 */
+		assert_eq!( store.transition_all(&[ (outpoint(4), Transition::ZeroconfRequest)
+						  , (outpoint(4), Transition::ZeroconfRequest)
+						  ])
+			  , Err(BatchError::Transition(1, TransitionError::Disallowed))
+			  );
+		assert_eq!( store.transition_all(&[ (outpoint(4), Transition::ZeroconfRequest)
+						  , (outpoint(4), Transition::ZeroconfCompleted)
+						  , (outpoint(5), Transition::AliceChangeCreated)
+						  ])
+			  , Ok(())
+			  );
+		assert_eq!(StateStore::get(&store, &outpoint(4)), Some(State::BobSecured));
+		assert_eq!(StateStore::get(&store, &outpoint(5)), Some(State::UnconfirmedAliceChange));
+
+		assert_eq!(store.transition_all(&[]), Ok(()));
+
+		let mut store = FailingStore(store);
+		assert_eq!( store.transition_all(&[(outpoint(1), Transition::OnchainRequest)])
+			  , Err(BatchError::Store)
+			  );
+		assert_eq!( store.transition_all(&[(outpoint(4), Transition::OnchainRequest)])
+			  , Err(BatchError::Transition(0, TransitionError::Disallowed))
+			  );
+	}
+}
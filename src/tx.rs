@@ -0,0 +1,382 @@
+/*!
+The `tx` module contains a minimal representation of a
+Bitcoin transaction, with its consensus serialization,
+enough for computing signature hashes of
+swap-in-potentiam inputs, and for building and
+broadcasting the transactions that spend them.
+*/
+use hashes::sha2::sha256;
+use super::bip341;
+
+/**
+`OutPoint` refers to a specific output of a previous
+transaction.
+
+`txid` is in the byte order it is hashed and serialized
+in, i.e. the reverse of how it is usually displayed.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub
+struct OutPoint {
+	pub txid: [u8; 32],
+	pub vout: u32
+}
+
+/**
+`TxIn` is a transaction input.
+
+`witness` is the witness stack, from stack bottom to
+stack top.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct TxIn {
+	pub prevout: OutPoint,
+	pub script_sig: Vec<u8>,
+	pub sequence: u32,
+	pub witness: Vec<Vec<u8>>
+}
+
+/**
+`TxOut` is a transaction output, with the amount in
+satoshis.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct TxOut {
+	pub value: u64,
+	pub script_pubkey: Vec<u8>
+}
+
+/**
+`Transaction` is a Bitcoin transaction.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub
+struct Transaction {
+	pub version: u32,
+	pub lock_time: u32,
+	pub input: Vec<TxIn>,
+	pub output: Vec<TxOut>
+}
+
+/* Writes the transaction to `buf`, with the segwit
+marker, flag and witnesses if `with_witness`.
+*/
+fn write_tx(tx: &Transaction, buf: &mut Vec<u8>, with_witness: bool) {
+	buf.extend_from_slice(&tx.version.to_le_bytes());
+	if with_witness {
+		buf.push(0x00); /* marker */
+		buf.push(0x01); /* flag */
+	}
+	bip341::load_compactsize(buf, tx.input.len());
+	for txin in tx.input.iter() {
+		buf.extend_from_slice(&txin.prevout.txid);
+		buf.extend_from_slice(&txin.prevout.vout.to_le_bytes());
+		bip341::load_compactsize(buf, txin.script_sig.len());
+		buf.extend_from_slice(&txin.script_sig);
+		buf.extend_from_slice(&txin.sequence.to_le_bytes());
+	}
+	bip341::load_compactsize(buf, tx.output.len());
+	for txout in tx.output.iter() {
+		buf.extend_from_slice(&txout.value.to_le_bytes());
+		bip341::load_compactsize(buf, txout.script_pubkey.len());
+		buf.extend_from_slice(&txout.script_pubkey);
+	}
+	if with_witness {
+		for txin in tx.input.iter() {
+			bip341::load_compactsize(buf, txin.witness.len());
+			for item in txin.witness.iter() {
+				bip341::load_compactsize(buf, item.len());
+				buf.extend_from_slice(item);
+			}
+		}
+	}
+	buf.extend_from_slice(&tx.lock_time.to_le_bytes());
+}
+
+fn read_bytes<'a>(buf: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+	let end = pos.checked_add(len)?;
+	let rv = buf.get(*pos..end)?;
+	*pos = end;
+	Some(rv)
+}
+
+fn read_u32(buf: &[u8], pos: &mut usize) -> Option<u32> {
+	let bytes = read_bytes(buf, pos, 4)?;
+	Some(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+}
+
+fn read_u64(buf: &[u8], pos: &mut usize) -> Option<u64> {
+	let bytes = read_bytes(buf, pos, 8)?;
+	Some(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+}
+
+fn read_var_bytes(buf: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+	let len = bip341::read_compactsize(buf, pos)?;
+	let len = usize::try_from(len).ok()?;
+	Some(read_bytes(buf, pos, len)?.to_vec())
+}
+
+impl Transaction {
+	/// `serialize` returns the consensus serialization of
+	/// the transaction, which is the [BIP-144][] one, with
+	/// the witnesses, if any input has a witness, or else
+	/// the legacy one.
+	///
+	/// [BIP-144]: https://github.com/bitcoin/bips/blob/master/bip-0144.mediawiki
+	pub
+	fn serialize(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		write_tx(self, &mut buf, self.has_witness());
+		buf
+	}
+
+	/// `serialize_without_witness` returns the serialization
+	/// of the transaction without the witnesses, i.e. the
+	/// legacy serialization that the txid is the hash of.
+	pub
+	fn serialize_without_witness(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		write_tx(self, &mut buf, false);
+		buf
+	}
+
+	/// `deserialize` parses the consensus serialization of
+	/// a transaction, with or without witnesses.
+	///
+	/// Fails (returns None) if `buf` is not exactly one
+	/// transaction, including if it has the segwit marker
+	/// but an unknown flag, or no input with a witness, or
+	/// a compactsize is not minimally encoded.
+	pub
+	fn deserialize(buf: &[u8]) -> Option<Transaction> {
+		let mut pos = 0;
+		let version = read_u32(buf, &mut pos)?;
+		let with_witness = buf.get(pos) == Some(&0x00);
+		if with_witness {
+			if buf.get(pos + 1) != Some(&0x01) {
+				return None;
+			}
+			pos += 2;
+		}
+
+		let mut input = Vec::new();
+		let num_inputs = bip341::read_compactsize(buf, &mut pos)?;
+		for _ in 0..num_inputs {
+			let txid = read_bytes(buf, &mut pos, 32)?
+			.try_into().expect("32 bytes");
+			let vout = read_u32(buf, &mut pos)?;
+			let script_sig = read_var_bytes(buf, &mut pos)?;
+			let sequence = read_u32(buf, &mut pos)?;
+			input.push(TxIn{ prevout: OutPoint{txid, vout}
+				       , script_sig
+				       , sequence
+				       , witness: Vec::new()
+				       });
+		}
+
+		let mut output = Vec::new();
+		let num_outputs = bip341::read_compactsize(buf, &mut pos)?;
+		for _ in 0..num_outputs {
+			let value = read_u64(buf, &mut pos)?;
+			let script_pubkey = read_var_bytes(buf, &mut pos)?;
+			output.push(TxOut{ value
+					 , script_pubkey
+					 });
+		}
+
+		if with_witness {
+			for txin in input.iter_mut() {
+				let num_items = bip341::read_compactsize(buf, &mut pos)?;
+				for _ in 0..num_items {
+					txin.witness.push(read_var_bytes(buf, &mut pos)?);
+				}
+			}
+		}
+
+		let lock_time = read_u32(buf, &mut pos)?;
+		if pos != buf.len() {
+			return None;
+		}
+
+		let tx = Transaction{ version
+				    , lock_time
+				    , input
+				    , output
+				    };
+		/* The witnesses must not all be empty if the
+		segwit serialization is used.
+		*/
+		if with_witness && !tx.has_witness() {
+			return None;
+		}
+		Some(tx)
+	}
+
+	/// `has_witness` returns true if any input has a
+	/// non-empty witness.
+	pub
+	fn has_witness(&self) -> bool {
+		self.input.iter().any(|txin| !txin.witness.is_empty())
+	}
+
+	/// `weight` returns the weight of the transaction, in
+	/// weight units, with its witnesses as they are.
+	pub
+	fn weight(&self) -> u64 {
+		let base = self.serialize_without_witness().len() as u64;
+		let total = self.serialize().len() as u64;
+		base * 3 + total
+	}
+
+	/// `vsize` returns the virtual size of the transaction,
+	/// in vbytes, i.e. its weight divided by 4, rounded up.
+	pub
+	fn vsize(&self) -> u64 {
+		self.weight().div_ceil(4)
+	}
+
+	/// `txid` returns the txid of the transaction, in the
+	/// same byte order as `OutPoint::txid`.
+	pub
+	fn txid(&self) -> [u8; 32] {
+		sha256d(&self.serialize_without_witness())
+	}
+
+	/// `wtxid` returns the [BIP-141][] wtxid of the
+	/// transaction, in the same byte order as `txid`.
+	/// It is the txid if no input has a witness.
+	///
+	/// [BIP-141]: https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki
+	pub
+	fn wtxid(&self) -> [u8; 32] {
+		sha256d(&self.serialize())
+	}
+}
+
+fn sha256d(data: &[u8]) -> [u8; 32] {
+	let h = sha256::hash(data).into_bytes();
+	sha256::hash(&h).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/* The coinbase transaction of the genesis block.  */
+	#[test]
+	fn test_txid() {
+		let tx = Transaction{ version: 1
+				    , lock_time: 0
+				    , input: vec!(TxIn{ prevout: OutPoint{txid: [0; 32], vout: 0xFFFFFFFF}
+						      , script_sig: hex::decode("04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73").unwrap()
+						      , sequence: 0xFFFFFFFF
+						      , witness: Vec::new()
+						      })
+				    , output: vec!(TxOut{ value: 5000000000
+							, script_pubkey: hex::decode("4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac").unwrap()
+							})
+				    };
+		assert_eq!(tx.weight(), 204 * 4);
+		let mut txid = tx.txid();
+		txid.reverse();
+		assert_eq!( hex::encode(txid)
+			  , "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+			  );
+	}
+
+	#[test]
+	fn test_weight() {
+		let mut tx = Transaction{ version: 2
+					, lock_time: 0
+					, input: vec!(
+						TxIn{ prevout: OutPoint{txid: [1; 32], vout: 0}
+						    , script_sig: Vec::new()
+						    , sequence: 0xFFFFFFFD
+						    , witness: Vec::new()
+						    },
+						TxIn{ prevout: OutPoint{txid: [2; 32], vout: 1}
+						    , script_sig: Vec::new()
+						    , sequence: 0xFFFFFFFD
+						    , witness: Vec::new()
+						    }
+					  )
+					, output: vec!(TxOut{value: 1000, script_pubkey: vec!(0x51)})
+					};
+		/* 4 + 1 + 2 * 41 + 1 + 10 + 4 bytes.  */
+		assert_eq!(tx.weight(), 102 * 4);
+		tx.input[1].witness = vec!(vec!(0xAA; 64));
+		/* Marker, flag, an empty witness and a witness
+		 * with one 64-byte item.
+		 */
+		assert_eq!(tx.weight(), 102 * 4 + 2 + 1 + 1 + 1 + 64);
+	}
+
+	fn check_vector( tx_hex: &str
+		       , txid_hex: &str
+		       , wtxid_hex: &str
+		       , weight: u64
+		       , vsize: u64
+		       ) -> Transaction
+	{
+		let display = |mut h: [u8; 32]| { h.reverse(); hex::encode(h) };
+		let buf = hex::decode(tx_hex).unwrap();
+		let tx = Transaction::deserialize(&buf).unwrap();
+		assert_eq!(tx.serialize(), buf);
+		assert_eq!(display(tx.txid()), txid_hex);
+		assert_eq!(display(tx.wtxid()), wtxid_hex);
+		assert_eq!(tx.weight(), weight);
+		assert_eq!(tx.vsize(), vsize);
+		tx
+	}
+
+	/* Vectors from the tests of rust-bitcoin 0.31.  */
+	#[test]
+	fn test_serialization() {
+		let tx = check_vector(
+			"02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000",
+			"f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206",
+			"80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5",
+			442, 111
+		);
+		assert_eq!(tx.version, 2);
+		assert_eq!(tx.input[0].prevout.vout, 1);
+		assert_eq!(tx.input[0].witness.len(), 2);
+		assert_eq!(tx.output[0].value, 506078);
+		assert_eq!(tx.lock_time, 0);
+
+		let tx = check_vector(
+			"01000000010c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d000000006a4730440220503a696f55f2c00eee2ac5e65b17767cd88ed04866b5637d3c1d5d996a70656d02202c9aff698f343abb6d176704beda63fcdec503133ea4f6a5216b7f925fa9910c0121024d89b5a13d6521388969209df27a8469bd565aff10e8d42cef931fad5121bfb8ffffffff02b825b404000000001976a914ef79e7ee9fff98bcfd08473d2b76b02a48f8c69088ac0000000000000000296a2732363030393438363937313732333132373633313032313332353630353838373931323132373000000000",
+			"971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd",
+			"971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd",
+			241 * 4, 241
+		);
+		assert!(!tx.has_witness());
+		assert_eq!(tx.serialize(), tx.serialize_without_witness());
+	}
+
+	#[test]
+	fn test_deserialize_invalid() {
+		let buf = hex::decode("02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000").unwrap();
+		/* Truncated, or with trailing data.  */
+		for len in 0..buf.len() {
+			assert_eq!(Transaction::deserialize(&buf[..len]), None);
+		}
+		let mut longer = buf.clone();
+		longer.push(0);
+		assert_eq!(Transaction::deserialize(&longer), None);
+		/* Unknown flag.  */
+		let mut flag = buf.clone();
+		flag[5] = 0x02;
+		assert_eq!(Transaction::deserialize(&flag), None);
+
+		/* Segwit serialization with empty witnesses.  */
+		let mut tx = Transaction::deserialize(&buf).unwrap();
+		tx.input[0].witness.clear();
+		let mut empty = Vec::new();
+		write_tx(&tx, &mut empty, true);
+		assert_eq!(Transaction::deserialize(&empty), None);
+		assert_eq!(Transaction::deserialize(&tx.serialize()), Some(tx));
+	}
+}
@@ -0,0 +1,255 @@
+/*!
+The `witness` module assembles the complete witness
+stacks for the two tapleaf path spends of a
+swap-in-potentiam output:
+
+* `witness_cooperative` for the 2-of-2 tapleaf path,
+  which needs signatures from both Alice and Bob.
+* `witness_alice_recovery` for the timelock tapleaf
+  path, which needs only the signature from Alice, and
+  is only valid once the output has 4032 confirmations.
+
+The returned witness stack is ordered from stack bottom
+to stack top, which is also the order it is serialized
+in the transaction.
+
+Signatures are BIP-340 Schnorr signatures, either 64
+bytes (for `SIGHASH_DEFAULT`) or 65 bytes (with the
+sighash type byte appended, as per BIP-341).
+*/
+use secp256k1::PublicKey;
+use secp256k1::Secp256k1;
+use secp256k1::Verification;
+use super::address;
+use super::bip341;
+use super::scripts;
+
+/* Returns the signature as it should appear on the
+witness stack, with the sighash type byte appended if
+needed, or None if the signature or sighash type is
+invalid.
+*/
+fn witness_signature( sig: &[u8]
+		    , sighash: Option<u8>
+		    ) -> Option<Vec<u8>>
+{
+	/* BIP-341: an explicit SIGHASH_DEFAULT byte is
+	invalid.
+	*/
+	fn is_valid_hash_type(h: u8) -> bool {
+		h != bip341::SIGHASH_DEFAULT && bip341::is_valid_hash_type(h)
+	}
+
+	let mut rv = Vec::new();
+	match sig.len() {
+		64 => {
+			rv.extend_from_slice(sig);
+			match sighash {
+				None | Some(bip341::SIGHASH_DEFAULT) => { },
+				Some(h) => {
+					if !is_valid_hash_type(h) {
+						return None;
+					}
+					rv.push(h);
+				}
+			}
+		},
+		65 => {
+			let h = sig[64];
+			if !is_valid_hash_type(h) {
+				return None;
+			}
+			if let Some(expected) = sighash {
+				if expected != h {
+					return None;
+				}
+			}
+			rv.extend_from_slice(sig);
+		},
+		_ => { return None; }
+	}
+	Some(rv)
+}
+
+/**
+`witness_cooperative` generates the witness stack for
+spending a swap-in-potentiam output of the given
+`alice` and `bob` public keys via the 2-of-2 tapleaf
+path.
+
+`alice_sig` and `bob_sig` are the 64-byte or 65-byte
+Schnorr signatures of Alice and Bob respectively.
+If `sighash` is given, 64-byte signatures have it
+appended (unless it is `SIGHASH_DEFAULT`), and 65-byte
+signatures must already end with it.
+
+The stack is, from bottom to top, the signature from
+`P[1]`, the signature from `P[0]`, the 2-of-2 script,
+and the control block.
+
+Returns None if a signature has an invalid length or
+sighash type, or on the negligible-probability edge
+case where the Taproot address is the point at
+infinity.
+*/
+pub
+fn witness_cooperative<C>( secp256k1: &Secp256k1<C>
+			 , alice: &PublicKey
+			 , bob: &PublicKey
+			 , alice_sig: &[u8]
+			 , bob_sig: &[u8]
+			 , sighash: Option<u8>
+			 ) -> Option<Vec<Vec<u8>>>
+	where C: Verification
+{
+	let alice_sig = witness_signature(alice_sig, sighash)?;
+	let bob_sig = witness_signature(bob_sig, sighash)?;
+
+	let scripts::P0P1{p0: _, p1} = scripts::P0P1::new(*alice, *bob);
+	let (p1_sig, p0_sig) = if p1 == *alice {
+		(alice_sig, bob_sig)
+	} else {
+		(bob_sig, alice_sig)
+	};
+
+	let script = scripts::tapleaf_cooperative(alice, bob);
+	let control_block = address::control_block_cooperative(
+		secp256k1, alice, bob
+	)?;
+
+	Some(vec!( p1_sig
+		 , p0_sig
+		 , script
+		 , control_block.to_vec()
+		 ))
+}
+
+/**
+`witness_alice_recovery` generates the witness stack for
+spending a swap-in-potentiam output of the given
+`alice` and `bob` public keys via the timelock tapleaf
+path.
+
+`alice_sig` is the 64-byte or 65-byte Schnorr signature
+of Alice, and `sighash` is handled the same as in
+`witness_cooperative`.
+The spending input must have an `nSequence` of at least
+4032 for the script to succeed.
+
+The stack is, from bottom to top, the signature from
+Alice, the timelock script, and the control block.
+
+Returns None if the signature has an invalid length or
+sighash type, or on the negligible-probability edge
+case where the Taproot address is the point at
+infinity.
+*/
+pub
+fn witness_alice_recovery<C>( secp256k1: &Secp256k1<C>
+			    , alice: &PublicKey
+			    , bob: &PublicKey
+			    , alice_sig: &[u8]
+			    , sighash: Option<u8>
+			    ) -> Option<Vec<Vec<u8>>>
+	where C: Verification
+{
+	let alice_sig = witness_signature(alice_sig, sighash)?;
+
+	let script = scripts::tapleaf_alice_recovery(alice);
+	let control_block = address::control_block_alice_recovery(
+		secp256k1, alice, bob
+	)?;
+
+	Some(vec!( alice_sig
+		 , script
+		 , control_block.to_vec()
+		 ))
+}
+
+#[cfg(test)]
+mod tests {
+	use hex;
+	use super::*;
+
+	fn point_txt(pk_s: &str) -> PublicKey {
+		let buf = hex::decode(pk_s)
+		.expect("Test iput must be hex");
+		PublicKey::from_slice(&buf)
+		.expect("Test input must be valid point")
+	}
+
+	#[test]
+	fn test_witness_signature() {
+		let sig = [0x11u8; 64];
+		assert_eq!(witness_signature(&sig, None), Some(sig.to_vec()));
+		assert_eq!(witness_signature(&sig, Some(0x00)), Some(sig.to_vec()));
+
+		let mut sig_all = sig.to_vec();
+		sig_all.push(0x01);
+		assert_eq!(witness_signature(&sig, Some(0x01)), Some(sig_all.clone()));
+		assert_eq!(witness_signature(&sig_all, None), Some(sig_all.clone()));
+		assert_eq!(witness_signature(&sig_all, Some(0x01)), Some(sig_all.clone()));
+		/* Mismatched sighash byte.  */
+		assert_eq!(witness_signature(&sig_all, Some(0x83)), None);
+
+		/* Undefined hash types.  */
+		assert_eq!(witness_signature(&sig, Some(0x04)), None);
+		let mut sig_default = sig.to_vec();
+		sig_default.push(0x00);
+		assert_eq!(witness_signature(&sig_default, None), None);
+
+		/* Bad lengths.  */
+		assert_eq!(witness_signature(&sig[0..63], None), None);
+		assert_eq!(witness_signature(&[0x11u8; 66], None), None);
+	}
+
+	#[test]
+	fn test_witness_cooperative() {
+		let s_ctx = Secp256k1::new();
+		/* swap-in-potentiam.md Address Generation Test
+		 * Vector 1, where P[0] = B and P[1] = A.
+		 */
+		let a = point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let alice_sig = [0xAAu8; 64];
+		let bob_sig = [0xBBu8; 64];
+
+		let witness = witness_cooperative(&s_ctx, &a, &b, &alice_sig, &bob_sig, None)
+		.expect("Should succeed");
+		assert_eq!(witness.len(), 4);
+		assert_eq!(witness[0], alice_sig.to_vec());
+		assert_eq!(witness[1], bob_sig.to_vec());
+		assert_eq!(witness[2], scripts::tapleaf_cooperative(&a, &b));
+		assert_eq!(hex::encode(&witness[3]), "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c69b15e91cdc464dfdb2f19faa6fcb96d5fb58773452f6afcfe251ed1cf83f1576a");
+
+		/* Internal Public Key Derivation Test Vector 2,
+		 * where P[0] = A and P[1] = B.
+		 */
+		let a = point_txt("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5");
+		let b = point_txt("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+		let witness = witness_cooperative(&s_ctx, &a, &b, &alice_sig, &bob_sig, Some(0x83))
+		.expect("Should succeed");
+		assert_eq!(witness[0][0..64], bob_sig);
+		assert_eq!(witness[0][64], 0x83);
+		assert_eq!(witness[1][0..64], alice_sig);
+		assert_eq!(witness[1][64], 0x83);
+
+		assert!(witness_cooperative(&s_ctx, &a, &b, &alice_sig, &bob_sig[0..63], None).is_none());
+	}
+
+	#[test]
+	fn test_witness_alice_recovery() {
+		let s_ctx = Secp256k1::new();
+		let a = point_txt("02c6b754b20826eb925e052ee2c25285b162b51fdca732bcf67e39d647fb6830ae");
+		let b = point_txt("03659a69ea86e2f183895be58802e203eff51956e931c6282ed77ab4c4385711b3");
+		let alice_sig = [0xAAu8; 64];
+
+		let witness = witness_alice_recovery(&s_ctx, &a, &b, &alice_sig, Some(0x01))
+		.expect("Should succeed");
+		assert_eq!(witness.len(), 3);
+		assert_eq!(witness[0][0..64], alice_sig);
+		assert_eq!(witness[0][64], 0x01);
+		assert_eq!(witness[1], scripts::tapleaf_alice_recovery(&a));
+		assert_eq!(hex::encode(&witness[2]), "c06962aca1c57320eaa40f949928d3477f2eeb3ffdb7e3d7296c1f57608d2d2c696a3d6cef744256400f4a7ae5e5f72b04cb8668c117d47d6df325e5c13e8acef3");
+	}
+}